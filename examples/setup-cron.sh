@@ -2,6 +2,9 @@
 
 # Setup Cron Job for Brewup
 # This script helps you set up automated daily/weekly homebrew updates
+#
+# Superseded by `brewup schedule install`, which also supports systemd timers
+# and launchd; kept for installations of brewup without that subcommand.
 
 set -e
 
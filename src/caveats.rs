@@ -0,0 +1,235 @@
+//! Caveats Homebrew prints for upgraded packages.
+//!
+//! Caveats (PATH changes, keg-only notes, service instructions) are printed
+//! in the middle of the `brew upgrade` output, where they are easily missed.
+//! They are extracted from the upgrade transcript, completed with
+//! `brew info --json=v2` for upgraded packages whose transcript had none,
+//! and shown together in an "Action required" section at the end of a run.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::CommandFailure;
+use crate::runner::BrewRunner;
+
+/// The caveats of one package.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Caveat {
+    /// Package name
+    pub name: String,
+    /// The caveats text as printed by brew
+    pub text: String,
+}
+
+/// Extracts the caveats from the output of `brew upgrade`.
+///
+/// brew prints a `==> Caveats` section after each package it installs, and
+/// repeats all of them at the end when it upgraded several packages, each
+/// under a `==> <name>` heading. Caveats are attributed to the package last
+/// named in an `Upgrading`, `Installing` or `Pouring` heading, or in such a
+/// repeated heading. Only the first caveats of each package are kept.
+///
+/// # Arguments
+/// * `stdout` - The output of `brew upgrade`
+/// * `names` - The packages being upgraded
+///
+/// # Returns
+/// The caveats in the order they were printed.
+pub fn parse_transcript(stdout: &str, names: &[&str]) -> Vec<Caveat> {
+    let mut caveats = Vec::new();
+    let mut current: Option<&str> = None;
+    let mut section: Option<Option<&str>> = None;
+    let mut text: Vec<&str> = Vec::new();
+
+    for line in stdout.lines() {
+        let Some(heading) = line.strip_prefix("==> ") else {
+            if section.is_some() {
+                text.push(line);
+            }
+            continue;
+        };
+        if let Some(package) = section {
+            add(&mut caveats, package, &text);
+            text.clear();
+        }
+        let heading = heading.trim();
+        if heading == "Caveats" {
+            section = Some(current);
+        } else if section.is_some() && names.contains(&heading) {
+            section = Some(Some(heading));
+        } else {
+            section = None;
+            if let Some(name) = heading_package(heading, names) {
+                current = Some(name);
+            }
+        }
+    }
+    if let Some(package) = section {
+        add(&mut caveats, package, &text);
+    }
+    caveats
+}
+
+/// Returns the package a heading such as `Upgrading wget` is about.
+fn heading_package<'a>(heading: &str, names: &[&'a str]) -> Option<&'a str> {
+    let rest = ["Upgrading ", "Installing ", "Pouring "]
+        .iter()
+        .find_map(|prefix| heading.strip_prefix(prefix))?;
+    // Bottles are named `<name>--<version>.<platform>.bottle.tar.gz`
+    let word = rest.split_whitespace().next()?.split("--").next()?;
+    names.iter().copied().find(|name| *name == word)
+}
+
+/// Records the caveats of `package` unless it already has some.
+fn add(caveats: &mut Vec<Caveat>, package: Option<&str>, lines: &[&str]) {
+    let text = lines.join("\n").trim().to_string();
+    let Some(name) = package else {
+        return;
+    };
+    if text.is_empty() || caveats.iter().any(|c| c.name == name) {
+        return;
+    }
+    caveats.push(Caveat {
+        name: name.to_string(),
+        text,
+    });
+}
+
+/// Queries the caveats of packages with `brew info --json=v2`.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+/// * `names` - The packages to query, at least one
+///
+/// # Returns
+/// The packages that have caveats, or the failure if the query failed.
+pub fn info_caveats(
+    runner: &dyn BrewRunner,
+    names: &[&str],
+) -> Result<Vec<Caveat>, CommandFailure> {
+    let mut command = vec!["info", "--json=v2"];
+    command.extend(names);
+    let output = runner.capture(&command).map_err(|e| CommandFailure {
+        message: format!("Failed to execute command: {}", e),
+        output: None,
+    })?;
+    if !output.is_success() {
+        return Err(CommandFailure {
+            message: output.stderr.trim().to_string(),
+            output: Some(output),
+        });
+    }
+    parse_info(&output.stdout).map_err(|message| CommandFailure {
+        message,
+        output: Some(output),
+    })
+}
+
+/// Parses the caveats out of `brew info --json=v2` output.
+///
+/// # Arguments
+/// * `json` - The JSON document printed by brew
+///
+/// # Returns
+/// The formulae and casks that have caveats, in listing order.
+pub fn parse_info(json: &str) -> Result<Vec<Caveat>, String> {
+    let root: Value =
+        serde_json::from_str(json).map_err(|e| format!("Invalid brew info output: {}", e))?;
+    let mut caveats = Vec::new();
+    // Casks are identified by their token
+    for (key, id) in [("formulae", "name"), ("casks", "token")] {
+        for entry in root[key].as_array().into_iter().flatten() {
+            let (Some(name), Some(text)) = (entry[id].as_str(), entry["caveats"].as_str()) else {
+                continue;
+            };
+            add(&mut caveats, Some(name), &[text]);
+        }
+    }
+    Ok(caveats)
+}
+
+/// Adds the caveats of packages that have none yet.
+///
+/// # Arguments
+/// * `caveats` - The caveats collected so far
+/// * `more` - Caveats from another source
+pub fn merge(caveats: &mut Vec<Caveat>, more: Vec<Caveat>) {
+    for caveat in more {
+        if !caveats.iter().any(|c| c.name == caveat.name) {
+            caveats.push(caveat);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSCRIPT: &str = "\
+==> Upgrading 2 outdated packages:
+postgresql@14 14.10 -> 14.12
+openssl@3 3.2.1 -> 3.3.0
+==> Upgrading postgresql@14
+  14.10 -> 14.12
+==> Pouring postgresql@14--14.12.arm64_sonoma.bottle.tar.gz
+==> Caveats
+This formula has created a default database cluster with:
+  initdb --locale=C -E UTF-8 /opt/homebrew/var/postgresql@14
+
+To restart postgresql@14 after an upgrade:
+  brew services restart postgresql@14
+==> Summary
+🍺  /opt/homebrew/Cellar/postgresql@14/14.12: 3,333 files, 45.8MB
+==> Upgrading openssl@3
+  3.2.1 -> 3.3.0
+==> Pouring openssl@3--3.3.0.arm64_sonoma.bottle.tar.gz
+==> Summary
+🍺  /opt/homebrew/Cellar/openssl@3/3.3.0: 6,977 files, 32.4MB
+==> Caveats
+==> postgresql@14
+This formula has created a default database cluster with:
+  initdb --locale=C -E UTF-8 /opt/homebrew/var/postgresql@14
+==> openssl@3
+A CA file has been bootstrapped using certificates from the system
+keychain.
+";
+
+    /// Tests that caveats are attributed to their package, including the
+    /// repeated section at the end.
+    #[test]
+    fn test_parse_transcript() {
+        let caveats = parse_transcript(TRANSCRIPT, &["postgresql@14", "openssl@3"]);
+        assert_eq!(caveats.len(), 2);
+        assert_eq!(caveats[0].name, "postgresql@14");
+        assert!(caveats[0].text.starts_with("This formula has created"));
+        assert!(caveats[0]
+            .text
+            .ends_with("brew services restart postgresql@14"));
+        assert_eq!(caveats[1].name, "openssl@3");
+        assert!(caveats[1].text.ends_with("keychain."));
+
+        assert!(parse_transcript("==> Upgrading wget\n", &["wget"]).is_empty());
+    }
+
+    /// Tests that `brew info` caveats fill in packages without transcript caveats.
+    #[test]
+    fn test_info_caveats() {
+        let info = parse_info(
+            r#"{"formulae": [
+                {"name": "postgresql@14", "caveats": "From brew info"},
+                {"name": "wget", "caveats": null}
+            ], "casks": [
+                {"token": "docker", "caveats": "Docker needs your password"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(info.len(), 2);
+        assert_eq!(info[1].name, "docker");
+
+        let mut caveats = parse_transcript(TRANSCRIPT, &["postgresql@14"]);
+        merge(&mut caveats, info);
+        let names: Vec<_> = caveats.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["postgresql@14", "docker"]);
+        assert!(caveats[0].text.starts_with("This formula"));
+    }
+}
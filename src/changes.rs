@@ -0,0 +1,206 @@
+//! Version changes between the start and the end of a run.
+//!
+//! The upgrade step lists the installed packages before the first upgrade
+//! and the summary step lists them again at the end. [`diff`] compares both
+//! listings, so the summary can show every package that was upgraded, newly
+//! installed (usually as a dependency) or removed, including changes brew
+//! made on its own. Major-version bumps are flagged because they are the
+//! ones most likely to break something.
+
+use serde::Serialize;
+
+use crate::packages::{InstalledPackage, PackageKind};
+
+/// How a package changed during a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// Installed at another version than before
+    Upgraded,
+    /// Not installed before, e.g. a new dependency
+    Installed,
+    /// No longer installed
+    Removed,
+}
+
+/// A package whose installed version changed during a run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionChange {
+    /// Package name
+    pub name: String,
+    /// Whether this is a formula or a cask
+    pub kind: PackageKind,
+    /// How the package changed
+    pub change: ChangeKind,
+    /// Version installed before the run, `None` if it was not installed
+    pub from: Option<String>,
+    /// Version installed after the run, `None` if it was removed
+    pub to: Option<String>,
+    /// Whether the major version went up
+    pub major: bool,
+}
+
+/// Compares the installed packages before and after a run.
+///
+/// # Arguments
+/// * `before` - The packages installed before the first upgrade
+/// * `after` - The packages installed at the end of the run
+///
+/// # Returns
+/// Upgraded, then installed, then removed packages, each sorted by name.
+pub fn diff(before: &[InstalledPackage], after: &[InstalledPackage]) -> Vec<VersionChange> {
+    let find = |packages: &'_ [InstalledPackage], package: &InstalledPackage| {
+        packages
+            .iter()
+            .find(|p| p.name == package.name && p.kind == package.kind)
+            .map(|p| p.version().to_string())
+    };
+    let mut changes: Vec<VersionChange> = after
+        .iter()
+        .filter_map(|new| {
+            let to = new.version().to_string();
+            let change = match find(before, new) {
+                Some(from) if from == to => return None,
+                Some(from) => VersionChange {
+                    name: new.name.clone(),
+                    kind: new.kind,
+                    change: ChangeKind::Upgraded,
+                    major: is_major_bump(&from, &to),
+                    from: Some(from),
+                    to: Some(to),
+                },
+                None => VersionChange {
+                    name: new.name.clone(),
+                    kind: new.kind,
+                    change: ChangeKind::Installed,
+                    from: None,
+                    to: Some(to),
+                    major: false,
+                },
+            };
+            Some(change)
+        })
+        .collect();
+    changes.extend(
+        before
+            .iter()
+            .filter(|old| find(after, old).is_none())
+            .map(|old| VersionChange {
+                name: old.name.clone(),
+                kind: old.kind,
+                change: ChangeKind::Removed,
+                from: Some(old.version().to_string()),
+                to: None,
+                major: false,
+            }),
+    );
+    changes.sort_by(|a, b| a.change.cmp(&b.change).then_with(|| a.name.cmp(&b.name)));
+    changes
+}
+
+/// Returns `true` if `to` has a higher major version than `from`.
+///
+/// The major version is the number a version starts with, e.g. `22` in
+/// `22.2.0` or `3` in `3.3.0_1`; versions that do not start with a number
+/// are never major bumps.
+///
+/// # Arguments
+/// * `from` - The old version
+/// * `to` - The new version
+pub fn is_major_bump(from: &str, to: &str) -> bool {
+    match (major_version(from), major_version(to)) {
+        (Some(from), Some(to)) => to > from,
+        _ => false,
+    }
+}
+
+/// Returns the leading number of a version.
+fn major_version(version: &str) -> Option<u64> {
+    let digits: String = version
+        .trim_start_matches(['v', 'V'])
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formula(name: &str, version: &str) -> InstalledPackage {
+        InstalledPackage {
+            name: name.to_string(),
+            kind: PackageKind::Formula,
+            versions: vec![version.to_string()],
+        }
+    }
+
+    /// Tests that upgrades, new dependencies and removals are found in
+    /// that order, and unchanged packages left out.
+    #[test]
+    fn test_diff() {
+        let before = [
+            formula("wget", "1.24.4"),
+            formula("node", "21.7.1"),
+            formula("git", "2.45.1"),
+            formula("python@3.11", "3.11.9"),
+        ];
+        let after = [
+            formula("git", "2.45.1"),
+            formula("libuv", "1.48.0"),
+            formula("node", "22.2.0"),
+            formula("wget", "1.24.5"),
+        ];
+        let changes: Vec<_> = diff(&before, &after)
+            .into_iter()
+            .map(|c| (c.name, c.change, c.from, c.to, c.major))
+            .collect();
+        let version = |v: &str| Some(v.to_string());
+        assert_eq!(
+            changes,
+            [
+                (
+                    "node".to_string(),
+                    ChangeKind::Upgraded,
+                    version("21.7.1"),
+                    version("22.2.0"),
+                    true
+                ),
+                (
+                    "wget".to_string(),
+                    ChangeKind::Upgraded,
+                    version("1.24.4"),
+                    version("1.24.5"),
+                    false
+                ),
+                (
+                    "libuv".to_string(),
+                    ChangeKind::Installed,
+                    None,
+                    version("1.48.0"),
+                    false
+                ),
+                (
+                    "python@3.11".to_string(),
+                    ChangeKind::Removed,
+                    version("3.11.9"),
+                    None,
+                    false
+                ),
+            ]
+        );
+        assert!(diff(&after, &after).is_empty());
+    }
+
+    /// Tests that only a higher leading number counts as a major bump.
+    #[test]
+    fn test_is_major_bump() {
+        assert!(is_major_bump("3.2.1", "4.0.0"));
+        assert!(is_major_bump("v1.9", "v2.0"));
+        assert!(is_major_bump("9.1_2", "10.0"));
+        assert!(!is_major_bump("3.2.1", "3.3.0_1"));
+        assert!(!is_major_bump("4.0", "3.9"));
+        assert!(!is_major_bump("latest", "1.0"));
+    }
+}
@@ -0,0 +1,707 @@
+//! Parsing of `brew cleanup` output for BrewUp.
+//!
+//! This module understands the entries listed by `brew cleanup` (both the
+//! `--dry-run` preview and a real run) and the disk space summary line, and
+//! measures the Homebrew cache and Cellar so that the space a cleanup
+//! actually reclaimed is known even when brew does not say. It also parses
+//! `brew autoremove`, protects exempt formulae from both commands and finds
+//! the old versions beyond the number of versions kept per formula.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::CommandFailure;
+use crate::runner::{BrewRunner, CommandInput};
+
+/// Number of the largest cache files reported after a cleanup.
+pub const LARGEST_CACHE_ENTRIES: usize = 5;
+
+/// Variable listing the formulae brew never cleans up or autoremoves.
+pub const NO_CLEANUP_VAR: &str = "HOMEBREW_NO_CLEANUP_FORMULAE";
+
+/// Environment variable that stops `brew upgrade` from cleaning up by itself.
+pub const NO_INSTALL_CLEANUP_VAR: &str = "HOMEBREW_NO_INSTALL_CLEANUP";
+
+/// Entries and space reported by a `brew cleanup` invocation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanupReport {
+    /// Paths that were (or would be) removed
+    pub entries: Vec<String>,
+    /// Bytes freed (or that would be freed), if brew reported it
+    pub freed_bytes: Option<u64>,
+}
+
+/// Parses the output of `brew cleanup`, with or without `--dry-run`.
+///
+/// # Arguments
+/// * `stdout` - The standard output of the cleanup command
+///
+/// # Returns
+/// The removed entries and the reported amount of freed space.
+pub fn parse_cleanup_output(stdout: &str) -> CleanupReport {
+    let mut report = CleanupReport::default();
+
+    for line in stdout.lines().map(str::trim) {
+        if let Some(entry) = line
+            .strip_prefix("Would remove: ")
+            .or_else(|| line.strip_prefix("Removing: "))
+        {
+            report.entries.push(entry.to_string());
+        } else if let Some(rest) = line
+            .split("free approximately ")
+            .nth(1)
+            .or_else(|| line.split("freed approximately ").nth(1))
+        {
+            report.freed_bytes = rest.split_whitespace().next().and_then(parse_size);
+        }
+    }
+
+    report
+}
+
+/// Parses the output of `brew autoremove`, with or without `--dry-run`.
+///
+/// brew lists the formulae under an `Autoremoving 2 unneeded formulae:` (or
+/// `Would autoremove ...`) heading, one per line.
+///
+/// # Arguments
+/// * `stdout` - The standard output of the autoremove command
+///
+/// # Returns
+/// The formulae that were (or would be) uninstalled.
+pub fn parse_autoremove_output(stdout: &str) -> Vec<String> {
+    let mut removed = Vec::new();
+    let mut listing = false;
+    for line in stdout.lines().map(str::trim) {
+        if let Some(heading) = line.strip_prefix("==> ") {
+            listing =
+                heading.starts_with("Autoremoving") || heading.starts_with("Would autoremove");
+        } else if listing && !line.is_empty() && !line.starts_with("Uninstalling") {
+            removed.push(line.to_string());
+        }
+    }
+    removed
+}
+
+/// Builds the value of `HOMEBREW_NO_CLEANUP_FORMULAE` that protects the
+/// exempt formulae.
+///
+/// # Arguments
+/// * `exempt` - The formulae whose old versions are kept
+/// * `current` - The value already set in the environment, if any
+///
+/// # Returns
+/// The comma-separated formulae from both, or `None` if nothing is exempt.
+pub fn no_cleanup_formulae(exempt: &[String], current: Option<&str>) -> Option<String> {
+    if exempt.is_empty() {
+        return None;
+    }
+    let mut formulae: Vec<&str> = current
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .collect();
+    for formula in exempt {
+        if !formulae.contains(&formula.as_str()) {
+            formulae.push(formula);
+        }
+    }
+    Some(formulae.join(","))
+}
+
+/// Parses a Homebrew human-readable size such as `1.5GB` or `512B`.
+///
+/// Homebrew uses binary multiples, so `1KB` is 1024 bytes.
+///
+/// # Arguments
+/// * `text` - The size text
+///
+/// # Returns
+/// The size in bytes, or `None` if the text is not a size.
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let split = text.find(|c: char| c.is_ascii_alphabetic())?;
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.trim().parse().ok()?;
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "B" => 1u64,
+        "KB" => 1 << 10,
+        "MB" => 1 << 20,
+        "GB" => 1 << 30,
+        "TB" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * multiplier as f64).round() as u64)
+}
+
+/// Formats a byte count the way Homebrew does, e.g. `1.5GB`.
+///
+/// # Arguments
+/// * `bytes` - The number of bytes
+///
+/// # Returns
+/// The human-readable size.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [(u64, &str); 4] = [
+        (1 << 40, "TB"),
+        (1 << 30, "GB"),
+        (1 << 20, "MB"),
+        (1 << 10, "KB"),
+    ];
+    for (size, unit) in UNITS {
+        if bytes >= size {
+            let value = bytes as f64 / size as f64;
+            let text = format!("{:.1}", value);
+            return format!("{}{}", text.trim_end_matches(".0"), unit);
+        }
+    }
+    format!("{}B", bytes)
+}
+
+/// A file in the Homebrew cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// Path of the file
+    pub path: String,
+    /// Size of the file in bytes
+    pub bytes: u64,
+}
+
+/// Disk space used by the Homebrew cache and Cellar at one point in time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// Size of `brew --cache` in bytes
+    pub cache_bytes: u64,
+    /// Size of `brew --cellar` in bytes
+    pub cellar_bytes: u64,
+    /// The largest files in the cache, largest first
+    pub largest: Vec<CacheEntry>,
+}
+
+impl DiskUsage {
+    /// Returns the combined size of the cache and the Cellar.
+    pub fn total(&self) -> u64 {
+        self.cache_bytes + self.cellar_bytes
+    }
+}
+
+/// Asks brew where its cache and Cellar are.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+///
+/// # Returns
+/// The cache and Cellar directories, or the failure of the query.
+pub fn brew_dirs(runner: &dyn BrewRunner) -> Result<(PathBuf, PathBuf), CommandFailure> {
+    Ok((brew_dir(runner, "--cache")?, brew_dir(runner, "--cellar")?))
+}
+
+/// Asks brew for its prefix, which holds the `opt` links and the pins.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+///
+/// # Returns
+/// The prefix, or the failure of the query.
+pub fn brew_prefix(runner: &dyn BrewRunner) -> Result<PathBuf, CommandFailure> {
+    brew_dir(runner, "--prefix")
+}
+
+/// Runs `brew <flag>` and returns the directory it prints.
+fn brew_dir(runner: &dyn BrewRunner, flag: &str) -> Result<PathBuf, CommandFailure> {
+    let output = runner.capture(&[flag]).map_err(|e| CommandFailure {
+        message: format!("Failed to execute command: {}", e),
+        output: None,
+    })?;
+    if !output.is_success() {
+        return Err(CommandFailure {
+            message: output.stderr.trim().to_string(),
+            output: Some(output),
+        });
+    }
+    Ok(PathBuf::from(output.stdout.trim()))
+}
+
+/// Measures the cache and the Cellar.
+///
+/// Symbolic links are not followed, so the links brew keeps next to cached
+/// downloads and the `opt` links of kegs are not counted twice. Files that
+/// disappear while they are measured are skipped.
+///
+/// # Arguments
+/// * `cache` - The Homebrew cache directory
+/// * `cellar` - The Homebrew Cellar directory
+/// * `largest` - How many of the largest non-empty cache files to keep
+///
+/// # Returns
+/// The disk usage, or the error if a directory cannot be read at all.
+pub fn measure(cache: &Path, cellar: &Path, largest: usize) -> io::Result<DiskUsage> {
+    let mut files = Vec::new();
+    let cache_bytes = dir_size(cache, &mut |path, bytes| {
+        files.push(CacheEntry {
+            path: path.display().to_string(),
+            bytes,
+        })
+    })?;
+    let cellar_bytes = dir_size(cellar, &mut |_, _| {})?;
+    files.retain(|file| file.bytes > 0);
+    files.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+    files.truncate(largest);
+    Ok(DiskUsage {
+        cache_bytes,
+        cellar_bytes,
+        largest: files,
+    })
+}
+
+/// Adds up the sizes of the files below `dir`, calling `on_file` for each.
+fn dir_size(dir: &Path, on_file: &mut dyn FnMut(&Path, u64)) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(dir)? {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            total += dir_size(&path, on_file).unwrap_or(0);
+        } else if metadata.is_file() {
+            on_file(&path, metadata.len());
+            total += metadata.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Builds the input that exempts formulae from a single brew command.
+///
+/// brew only reads exemptions from [`NO_CLEANUP_VAR`], so the variable is
+/// passed to that one command rather than set on the whole process.
+///
+/// # Arguments
+/// * `formulae` - The formulae whose old versions brew must keep
+/// * `current` - The value already set in the environment, if any
+///
+/// # Returns
+/// The input, without variables if nothing is exempt.
+pub fn no_cleanup_input(formulae: &[String], current: Option<&str>) -> CommandInput {
+    match no_cleanup_formulae(formulae, current) {
+        Some(value) => CommandInput::env(NO_CLEANUP_VAR, &value),
+        None => CommandInput::default(),
+    }
+}
+
+/// The Homebrew directories that old versions are looked up in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KegDirs {
+    /// The Homebrew prefix, which holds the `opt` links and the pins
+    pub prefix: PathBuf,
+    /// The Cellar, which holds the kegs
+    pub cellar: PathBuf,
+    /// The cache, which holds the downloads
+    pub cache: PathBuf,
+}
+
+/// An installed version of a formula beyond the versions kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OldVersion {
+    /// Formula name
+    pub name: String,
+    /// The old version
+    pub version: String,
+    /// Its keg in the Cellar
+    pub keg: PathBuf,
+    /// Its downloads in the cache, including brew's links to them
+    pub downloads: Vec<PathBuf>,
+}
+
+impl fmt::Display for OldVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.version)
+    }
+}
+
+/// Lists the installed versions of every formula in the Cellar.
+///
+/// # Arguments
+/// * `cellar` - The Homebrew Cellar directory
+///
+/// # Returns
+/// The versions by formula name, newest first, or the error if the Cellar
+/// cannot be read.
+pub fn kegs(cellar: &Path) -> io::Result<BTreeMap<String, Vec<String>>> {
+    let mut kegs = BTreeMap::new();
+    for formula in fs::read_dir(cellar)? {
+        let formula = formula?;
+        if !formula.file_type()?.is_dir() {
+            continue;
+        }
+        let mut versions: Vec<String> = fs::read_dir(formula.path())?
+            .filter_map(Result::ok)
+            .filter(|keg| keg.file_type().is_ok_and(|t| t.is_dir()))
+            .map(|keg| keg.file_name().to_string_lossy().into_owned())
+            .collect();
+        versions.sort_by(|a, b| compare_versions(b, a));
+        kegs.insert(formula.file_name().to_string_lossy().into_owned(), versions);
+    }
+    Ok(kegs)
+}
+
+/// Finds the versions beyond the newest `keep` of each formula.
+///
+/// Versions are ranked by number, not by what is in use, so the kegs brew
+/// has linked or points its `opt` link at are never old, and neither are
+/// any versions of pinned and exempt formulae.
+///
+/// # Arguments
+/// * `kegs` - The installed versions by formula, newest first, see [`kegs`]
+/// * `dirs` - The Homebrew directories
+/// * `keep` - How many versions of each formula to keep
+/// * `exempt` - The formulae whose old versions are all kept
+///
+/// # Returns
+/// The old versions, by formula name and then newest first.
+pub fn old_versions(
+    kegs: &BTreeMap<String, Vec<String>>,
+    dirs: &KegDirs,
+    keep: usize,
+    exempt: &[String],
+) -> Vec<OldVersion> {
+    let KegDirs {
+        prefix,
+        cellar,
+        cache,
+    } = dirs;
+    let cached: Vec<PathBuf> = [cache.to_path_buf(), cache.join("downloads")]
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect();
+    let cached = &cached;
+    kegs.iter()
+        .filter(|(name, _)| !exempt.contains(name) && !is_pinned(prefix, name))
+        .flat_map(|(name, versions)| {
+            let in_use = kegs_in_use(prefix, name);
+            versions
+                .iter()
+                .skip(keep.max(1))
+                .filter(move |version| !in_use.contains(version))
+                .map(move |version| OldVersion {
+                    name: name.clone(),
+                    version: version.clone(),
+                    keg: cellar.join(name).join(version),
+                    downloads: cached
+                        .iter()
+                        .filter(|path| {
+                            path.file_name().is_some_and(|file| {
+                                is_download_of(&file.to_string_lossy(), name, version)
+                            })
+                        })
+                        .cloned()
+                        .collect(),
+                })
+        })
+        .collect()
+}
+
+/// Returns `true` if `name` is pinned with `brew pin`.
+fn is_pinned(prefix: &Path, name: &str) -> bool {
+    fs::symlink_metadata(prefix.join("var/homebrew/pinned").join(name)).is_ok()
+}
+
+/// Returns the versions of `name` that its `opt` link and its link into the
+/// prefix point at.
+fn kegs_in_use(prefix: &Path, name: &str) -> Vec<String> {
+    [
+        prefix.join("opt").join(name),
+        prefix.join("var/homebrew/linked").join(name),
+    ]
+    .iter()
+    .filter_map(|link| fs::read_link(link).ok())
+    .filter_map(|keg| Some(keg.file_name()?.to_string_lossy().into_owned()))
+    .collect()
+}
+
+/// Removes an old version's keg and its cached downloads.
+///
+/// # Arguments
+/// * `old` - The old version
+///
+/// # Returns
+/// `Ok(())` once everything is gone, the first error otherwise.
+pub fn remove_old_version(old: &OldVersion) -> io::Result<()> {
+    fs::remove_dir_all(&old.keg)?;
+    for download in &old.downloads {
+        fs::remove_file(download)?;
+    }
+    Ok(())
+}
+
+/// Returns `true` if a cache file name is a download of `name` at `version`.
+///
+/// Downloads are named like `<sha256>--wget--1.24.5.arm64_sonoma.bottle.tar.gz`
+/// or `<sha256>--wget_bottle_manifest--1.24.5`, and brew links them as
+/// `wget--1.24.5.arm64_sonoma.bottle.tar.gz`. A version must not be followed
+/// by another number, so that `1.24` does not match `1.24.5`.
+fn is_download_of(file: &str, name: &str, version: &str) -> bool {
+    let file = match file.split_once("--") {
+        Some((hash, rest)) if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            rest
+        }
+        _ => file,
+    };
+    let Some(rest) = file
+        .strip_prefix(name)
+        .and_then(|rest| {
+            rest.strip_prefix("--")
+                .or_else(|| rest.strip_prefix("_bottle_manifest--"))
+        })
+        .and_then(|rest| rest.strip_prefix(version))
+    else {
+        return false;
+    };
+    rest.is_empty()
+        || rest
+            .strip_prefix('.')
+            .is_some_and(|tail| !tail.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// Orders two versions by their numeric and other parts, so that `1.10`
+/// comes after `1.9` and `1.24.5_1` after `1.24.5`.
+///
+/// # Arguments
+/// * `a` - The first version
+/// * `b` - The second version
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |version: &str| -> Vec<(bool, String)> {
+        let mut parts: Vec<(bool, String)> = Vec::new();
+        for c in version.chars() {
+            let numeric = c.is_ascii_digit();
+            match parts.last_mut() {
+                Some((last, part)) if *last == numeric => part.push(c),
+                _ => parts.push((numeric, c.to_string())),
+            }
+        }
+        parts
+    };
+    for (a, b) in parts(a).iter().zip(&parts(b)) {
+        let order = match (a, b) {
+            ((true, a), (true, b)) => {
+                let (a, b) = (a.trim_start_matches('0'), b.trim_start_matches('0'));
+                a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+            }
+            ((_, a), (_, b)) => a.cmp(b),
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    parts(a).len().cmp(&parts(b).len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests parsing of a dry-run preview with its summary line.
+    #[test]
+    fn test_parse_dry_run_output() {
+        let report = parse_cleanup_output(
+            "Would remove: /Library/Caches/Homebrew/wget--1.24.4.bottle.tar.gz (1.5MB)\n\
+             Would remove: /opt/homebrew/Cellar/wget/1.24.4 (92 files, 4.2MB)\n\
+             ==> This operation would free approximately 5.7MB of disk space.\n",
+        );
+        assert_eq!(report.entries.len(), 2);
+        assert_eq!(report.freed_bytes, Some(5976883));
+    }
+
+    /// Tests parsing of a real cleanup run.
+    #[test]
+    fn test_parse_real_output() {
+        let report = parse_cleanup_output(
+            "Removing: /opt/homebrew/Cellar/node/21.7.1... (2,120 files, 70.1MB)\n\
+             ==> This operation has freed approximately 1.2GB of disk space.\n",
+        );
+        assert_eq!(
+            report.entries,
+            ["/opt/homebrew/Cellar/node/21.7.1... (2,120 files, 70.1MB)"]
+        );
+        assert_eq!(report.freed_bytes, Some(1288490189));
+    }
+
+    /// Tests that autoremoved formulae are listed and exemptions added to
+    /// the ones already in the environment.
+    #[test]
+    fn test_autoremove_and_exemptions() {
+        let removed = parse_autoremove_output(
+            "==> Autoremoving 2 unneeded formulae:\n\
+             libidn2\n\
+             python@3.11\n\
+             Uninstalling /opt/homebrew/Cellar/libidn2/2.3.7... (80 files, 1MB)\n",
+        );
+        assert_eq!(removed, ["libidn2", "python@3.11"]);
+        let preview =
+            parse_autoremove_output("==> Would autoremove 1 unneeded formula:\nlibidn2\n");
+        assert_eq!(preview, ["libidn2"]);
+        assert!(parse_autoremove_output("").is_empty());
+
+        let exempt = vec!["postgresql@14".to_string(), "node".to_string()];
+        assert_eq!(
+            no_cleanup_formulae(&exempt, Some("node, llvm")).as_deref(),
+            Some("node,llvm,postgresql@14")
+        );
+        assert_eq!(no_cleanup_formulae(&[], Some("node")), None);
+    }
+
+    /// Tests size parsing and formatting round trips.
+    #[test]
+    fn test_sizes() {
+        assert_eq!(parse_size("512B"), Some(512));
+        assert_eq!(parse_size("2KB"), Some(2048));
+        assert_eq!(parse_size("disk"), None);
+        assert_eq!(format_size(512), "512B");
+        assert_eq!(format_size(1536), "1.5KB");
+        assert_eq!(format_size(3 << 30), "3GB");
+    }
+
+    /// Tests that the cache and Cellar are measured without following
+    /// links, keeping the largest cache files.
+    #[test]
+    fn test_measure() {
+        let dir = std::env::temp_dir().join(format!("brewup-cleanup-{}", std::process::id()));
+        let downloads = dir.join("cache").join("downloads");
+        let keg = dir.join("Cellar").join("wget").join("1.24.5");
+        fs::create_dir_all(&downloads).unwrap();
+        fs::create_dir_all(&keg).unwrap();
+        fs::write(downloads.join("abc--node.tar.gz"), vec![0; 300]).unwrap();
+        fs::write(downloads.join("def--wget.tar.gz"), vec![0; 100]).unwrap();
+        fs::write(dir.join("cache").join("api.json"), vec![0; 200]).unwrap();
+        fs::write(keg.join("wget"), vec![0; 50]).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(
+            downloads.join("abc--node.tar.gz"),
+            dir.join("cache").join("node.tar.gz"),
+        )
+        .unwrap();
+
+        let usage = measure(&dir.join("cache"), &dir.join("Cellar"), 2).unwrap();
+        assert_eq!(usage.cache_bytes, 600);
+        assert_eq!(usage.cellar_bytes, 50);
+        assert_eq!(usage.total(), 650);
+        let largest: Vec<_> = usage.largest.iter().map(|e| e.bytes).collect();
+        assert_eq!(largest, [300, 200]);
+        assert!(usage.largest[0].path.ends_with("abc--node.tar.gz"));
+        assert!(measure(&dir.join("missing"), &dir.join("Cellar"), 2).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Tests that versions are compared by their numeric parts.
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.24.5_1", "1.24.5"), Ordering::Greater);
+        assert_eq!(compare_versions("3.3.0", "3.3.0"), Ordering::Equal);
+        assert_eq!(compare_versions("22.2.0", "9.11.2"), Ordering::Greater);
+    }
+
+    /// Tests that only the versions beyond the newest kept ones are old,
+    /// together with their downloads.
+    #[test]
+    fn test_old_versions() {
+        let dir = std::env::temp_dir().join(format!("brewup-kegs-{}", std::process::id()));
+        let (cache, cellar) = (dir.join("cache"), dir.join("Cellar"));
+        for version in ["1.9", "1.24.5", "1.24"] {
+            fs::create_dir_all(cellar.join("wget").join(version)).unwrap();
+        }
+        fs::create_dir_all(cellar.join("git").join("2.45.1")).unwrap();
+        fs::create_dir_all(cache.join("downloads")).unwrap();
+        let hash = "a".repeat(64);
+        for file in [
+            format!("downloads/{}--wget--1.9.arm64_sonoma.bottle.tar.gz", hash),
+            format!("downloads/{}--wget_bottle_manifest--1.9", hash),
+            "wget--1.9.arm64_sonoma.bottle.tar.gz".to_string(),
+            "wget--1.24.5.arm64_sonoma.bottle.tar.gz".to_string(),
+        ] {
+            fs::write(cache.join(file), "").unwrap();
+        }
+
+        let dirs = KegDirs {
+            prefix: dir.clone(),
+            cellar: cellar.clone(),
+            cache: cache.clone(),
+        };
+        let kegs = kegs(&cellar).unwrap();
+        assert_eq!(kegs["wget"], ["1.24.5", "1.24", "1.9"]);
+        let old = old_versions(&kegs, &dirs, 2, &[]);
+        assert_eq!(old.len(), 1);
+        assert_eq!(old[0].version, "1.9");
+        assert_eq!(old[0].keg, cellar.join("wget").join("1.9"));
+        assert_eq!(old[0].downloads.len(), 3);
+        assert_eq!(old_versions(&kegs, &dirs, 1, &[]).len(), 2);
+        assert!(old_versions(&kegs, &dirs, 1, &["wget".to_string()]).is_empty());
+        assert!(!is_download_of(
+            "wget--1.24.5.arm64_sonoma.bottle.tar.gz",
+            "wget",
+            "1.24"
+        ));
+
+        remove_old_version(&old[0]).unwrap();
+        assert!(!old[0].keg.exists());
+        assert_eq!(fs::read_dir(cache.join("downloads")).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Tests that the linked and `opt` kegs and pinned formulae are never
+    /// old, whatever their version.
+    #[test]
+    fn test_old_versions_keeps_kegs_in_use() {
+        let dir = std::env::temp_dir().join(format!("brewup-in-use-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cellar = dir.join("Cellar");
+        for (name, version) in [
+            ("node", "22.2.0"),
+            ("node", "20.12.2"),
+            ("node", "18.20.3"),
+            ("wget", "1.24.5"),
+            ("wget", "1.24.4"),
+        ] {
+            fs::create_dir_all(cellar.join(name).join(version)).unwrap();
+        }
+        for link in ["opt", "var/homebrew/linked", "var/homebrew/pinned"] {
+            fs::create_dir_all(dir.join(link)).unwrap();
+        }
+        // A rollback left node 18 linked and wget pinned at 1.24.4
+        std::os::unix::fs::symlink("../Cellar/node/18.20.3", dir.join("opt/node")).unwrap();
+        std::os::unix::fs::symlink(
+            "../../../Cellar/node/18.20.3",
+            dir.join("var/homebrew/linked/node"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(
+            "../../../Cellar/wget/1.24.4",
+            dir.join("var/homebrew/pinned/wget"),
+        )
+        .unwrap();
+        let dirs = KegDirs {
+            prefix: dir.clone(),
+            cellar: cellar.clone(),
+            cache: dir.join("cache"),
+        };
+
+        let old = old_versions(&kegs(&cellar).unwrap(), &dirs, 1, &[]);
+        let old: Vec<String> = old.iter().map(ToString::to_string).collect();
+        assert_eq!(old, ["node 20.12.2"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
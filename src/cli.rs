@@ -3,7 +3,15 @@
 //! This module defines the CLI structure, argument parsing, and help text
 //! for the BrewUp application using the `clap` crate.
 
+use clap::builder::{PossibleValue, PossibleValuesParser, TypedValueParser};
 use clap::{Arg, Command};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+use crate::history::HistoryFilter;
+use crate::packages::GreedyMode;
+use crate::schedule::{Backend, Frequency, TimeOfDay, Weekday};
+use crate::steps;
 
 /// Defines and builds the CLI argument parser.
 ///
@@ -19,10 +27,12 @@ pub fn build_cli() -> Command {
             packages and cleaning up cache in one operation.\n\n\
             It performs the following steps:\n\
             • Updates Homebrew itself\n\
-            • Upgrades all installed packages\n\
+            • Upgrades outdated formulae, then casks\n\
             • Cleans up cache and old versions\n\
             • Shows a summary of installed packages\n\n\
-            By default, BrewUp shows detailed output of all operations.",
+            By default, BrewUp shows the progress of each step and its results; \
+            --quiet reduces this to errors and a one-line summary, --verbose adds \
+            the brew commands, their full output and step timings.",
         )
         .after_help(
             "EXAMPLES:\n\
@@ -36,18 +46,74 @@ pub fn build_cli() -> Command {
             Upgrade packages but skip cleanup step:\n\
               $ brewup --skip-cleanup\n\
             \n\
-            Show verbose output (redundant as default is already verbose):\n\
+            Only update Homebrew and show the summary:\n\
+              $ brewup --only update --only summary\n\
+            \n\
+            Show the brew commands, their full output and step timings:\n\
               $ brewup --verbose\n\
             \n\
+            From cron: print only errors and a one-line summary:\n\
+              $ brewup --quiet\n\
+            \n\
+            Keep colors when piping into a pager, but use ASCII symbols:\n\
+              $ brewup --color=always --no-emoji | less -R\n\
+            \n\
             Combine flags:\n\
-              $ brewup --dry-run --skip-cleanup",
+              $ brewup --dry-run --skip-cleanup\n\
+            \n\
+            Never upgrade node or any postgresql version:\n\
+              $ brewup --exclude node --exclude 'postgresql@*'\n\
+            \n\
+            Also upgrade self-updating casks such as browsers:\n\
+              $ brewup --greedy\n\
+            \n\
+            Upgrade packages one at a time and keep going past failures:\n\
+              $ brewup --isolate\n\
+            \n\
+            Upgrade, but leave restarting services such as postgresql to me:\n\
+              $ brewup --no-restart\n\
+            \n\
+            Emit a machine-readable JSON report for automation:\n\
+              $ brewup --output json\n\
+            \n\
+            From cron: wait for a manual run to finish instead of skipping:\n\
+              $ brewup --wait\n\
+            \n\
+            Use a shared team configuration file:\n\
+              $ brewup --config ~/team/brewup.toml\n\
+            \n\
+            Find out when openssl was last upgraded on this machine:\n\
+              $ brewup history --package 'openssl*'\n\
+            \n\
+            Undo the last upgrade as far as possible:\n\
+              $ brewup rollback\n\
+            \n\
+            Run brewup every Sunday at 10:00 without cleanup:\n\
+              $ brewup schedule install --frequency weekly --weekday sunday --at 10:00 -- --skip-cleanup\n\
+            \n\
+            CONFIGURATION:\n\
+            \n\
+            Settings are merged from, in increasing precedence: built-in defaults,\n\
+            ~/.config/brewup/config.toml (or $XDG_CONFIG_HOME/brewup/config.toml),\n\
+            ./.brewup.toml, --config <FILE> (or $BREWUP_CONFIG), BREWUP_* environment\n\
+            variables and command-line flags.",
         )
         .arg(
             Arg::new("verbose")
                 .short('v')
                 .long("verbose")
-                .help("Show verbose output (redundant - default is already verbose)")
-                .action(clap::ArgAction::SetTrue),
+                .help("Show brew commands, their full output and timings; -vv also makes brew verbose")
+                .action(clap::ArgAction::Count)
+                .global(true),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Print only errors and a one-line summary")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("verbose")
+                .global(true),
         )
         .arg(
             Arg::new("dry-run")
@@ -58,18 +124,529 @@ pub fn build_cli() -> Command {
         .arg(
             Arg::new("skip-cleanup")
                 .long("skip-cleanup")
-                .help("Skip the cleanup step (brew cleanup --prune=all)")
+                .help("Skip the cleanup step (same as --skip cleanup)")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("only")
+                .long("only")
+                .value_name("STEP")
+                .help("Run only STEP (repeatable); the other steps are skipped")
+                .value_parser(step_names())
+                .action(clap::ArgAction::Append)
+                .conflicts_with("skip"),
+        )
+        .arg(
+            Arg::new("skip")
+                .long("skip")
+                .value_name("STEP")
+                .help("Skip STEP (repeatable)")
+                .value_parser(step_names())
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read configuration from FILE on top of the user and project files")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("max-packages")
+                .long("max-packages")
+                .value_name("N")
+                .help("Maximum number of packages to list in the summary")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .value_name("PATTERN")
+                .help("Never upgrade packages matching PATTERN (repeatable, * and ? allowed)")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("isolate")
+                .long("isolate")
+                .help("Upgrade packages one at a time (or in configured batches) and continue past failures")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-restart")
+                .long("no-restart")
+                .help("Only report running services of upgraded formulae instead of restarting them")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("greedy")
+                .long("greedy")
+                .help("Also upgrade casks that update themselves or use version :latest")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("greedy-auto-updates"),
+        )
+        .arg(
+            Arg::new("greedy-auto-updates")
+                .long("greedy-auto-updates")
+                .help("Also upgrade casks that update themselves (auto_updates true)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("wait")
+                .long("wait")
+                .help("Wait for another running brewup to finish instead of exiting with code 8")
+                .action(clap::ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .value_name("WHEN")
+                .help("Color output: auto (terminals only, honoring NO_COLOR), always or never")
+                .value_parser(["auto", "always", "never"])
+                .global(true),
+        )
+        .arg(
+            Arg::new("no-emoji")
+                .long("no-emoji")
+                .help("Use ASCII symbols instead of emoji")
+                .action(clap::ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .value_name("FORMAT")
+                .help("Output format: human-readable text or a single JSON report")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .global(true),
+        )
+        .subcommand(
+            Command::new("history")
+                .about("List past runs recorded in the history file")
+                .args_conflicts_with_subcommands(true)
+                .arg(
+                    Arg::new("package")
+                        .long("package")
+                        .short('p')
+                        .value_name("NAME")
+                        .help("Only runs that upgraded NAME (* and ? allowed)"),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .value_name("DATE")
+                        .help("Only runs started on or after DATE (YYYY, YYYY-MM or YYYY-MM-DD, UTC)")
+                        .value_parser(parse_date),
+                )
+                .arg(
+                    Arg::new("until")
+                        .long("until")
+                        .value_name("DATE")
+                        .help("Only runs started on or before DATE (YYYY, YYYY-MM or YYYY-MM-DD, UTC)")
+                        .value_parser(parse_date),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .short('n')
+                        .value_name("N")
+                        .help("Show at most N runs, most recent first (0 for all)")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("20"),
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("Show everything recorded about one run")
+                        .arg(
+                            Arg::new("id")
+                                .value_name("ID")
+                                .help("Run number from `brewup history`")
+                                .required(true)
+                                .value_parser(clap::value_parser!(u64)),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("rollback")
+                .about("Restore the package versions of a pre-upgrade snapshot")
+                .long_about(
+                    "Restore the package versions recorded before an upgrade.\n\n\
+                    Formulae are reinstalled from cached bottles (and pinned) or from \
+                    versioned formulae; removed packages are reinstalled from the \
+                    snapshot's Brewfile. Anything that cannot be restored is reported.",
+                )
+                .arg(
+                    Arg::new("snapshot")
+                        .value_name("SNAPSHOT")
+                        .help("Snapshot to restore, from `brewup rollback --list` (default: the latest)"),
+                )
+                .arg(
+                    Arg::new("list")
+                        .long("list")
+                        .help("List the stored snapshots")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with_all(["snapshot", "dry-run"]),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Show what would be restored without changing anything")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("schedule")
+                .about("Run brewup periodically with cron, a systemd timer or launchd")
+                .long_about(
+                    "Generate, install, list and remove a schedule entry that runs brewup \
+                    periodically.\n\n\
+                    The backend defaults to launchd on macOS, a systemd user timer where \
+                    systemd runs and the user's crontab otherwise. Options override the \
+                    [schedule] configuration table.",
+                )
+                .subcommand_required(true)
+                .subcommand(schedule_options(
+                    Command::new("generate")
+                        .about("Print the schedule files without installing them")
+                        .arg(
+                            Arg::new("dir")
+                                .long("dir")
+                                .value_name("DIR")
+                                .help("Write the files to DIR instead of printing them")
+                                .value_parser(clap::value_parser!(PathBuf)),
+                        ),
+                ))
+                .subcommand(schedule_options(
+                    Command::new("install").about("Install or replace the schedule and enable it"),
+                ))
+                .subcommand(Command::new("list").about("List installed schedules"))
+                .subcommand(
+                    Command::new("remove")
+                        .about("Disable and remove installed schedules")
+                        .arg(backend_arg().help("Only remove the schedule of BACKEND")),
+                ),
+        )
+}
+
+/// Adds the options describing a schedule to a `schedule` subcommand.
+fn schedule_options(command: Command) -> Command {
+    command
+        .arg(backend_arg().help("Scheduler to use (default: detected)"))
+        .arg(
+            Arg::new("frequency")
+                .long("frequency")
+                .value_name("FREQUENCY")
+                .help("How often brewup runs")
+                .value_parser(
+                    PossibleValuesParser::new(Frequency::ALL.map(Frequency::name))
+                        .map(|name| Frequency::from_name(&name).unwrap_or_default()),
+                ),
+        )
+        .arg(
+            Arg::new("at")
+                .long("at")
+                .value_name("HH:MM")
+                .help("Start time; hourly runs only use the minute")
+                .value_parser(TimeOfDay::parse),
+        )
+        .arg(
+            Arg::new("weekday")
+                .long("weekday")
+                .value_name("DAY")
+                .help("Day of weekly runs")
+                .value_parser(
+                    PossibleValuesParser::new(Weekday::ALL.map(Weekday::name))
+                        .map(|name| Weekday::from_name(&name).unwrap_or_default()),
+                ),
+        )
+        .arg(
+            Arg::new("window")
+                .long("window")
+                .value_name("MINUTES")
+                .help("Start within MINUTES after the start time, to spread load")
+                .value_parser(clap::value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("args")
+                .value_name("ARGS")
+                .help("Arguments for the scheduled runs, after --")
+                .num_args(1..)
+                .last(true)
+                .allow_hyphen_values(true),
+        )
+}
+
+/// Builds the `--backend` option of the `schedule` subcommands.
+fn backend_arg() -> Arg {
+    Arg::new("backend")
+        .long("backend")
+        .value_name("BACKEND")
+        .value_parser(
+            PossibleValuesParser::new(Backend::ALL.map(Backend::name))
+                .map(|name| Backend::from_name(&name).unwrap_or(Backend::Cron)),
+        )
+}
+
+/// Validates a `YYYY`, `YYYY-MM` or `YYYY-MM-DD` date.
+fn parse_date(value: &str) -> Result<String, String> {
+    let valid = matches!(value.len(), 4 | 7 | 10)
+        && value.char_indices().all(|(i, c)| match i {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
+        });
+    if valid {
+        Ok(value.to_string())
+    } else {
+        Err("expected YYYY, YYYY-MM or YYYY-MM-DD".to_string())
+    }
+}
+
+/// Parses the output level from `-q` and the number of `-v` flags.
+///
+/// # Arguments
+/// * `matches` - The parsed command line
+pub fn parse_verbosity(matches: &clap::ArgMatches) -> Verbosity {
+    if matches.get_flag("quiet") {
+        return Verbosity::Quiet;
+    }
+    match matches.get_count("verbose") {
+        0 => Verbosity::Normal,
+        1 => Verbosity::Verbose,
+        _ => Verbosity::VeryVerbose,
+    }
+}
+
+/// Parses the subcommand, if any, from the matches of [`build_cli`].
+///
+/// # Arguments
+/// * `matches` - The parsed command line
+///
+/// # Returns
+/// The subcommand, or `None` for a regular run.
+pub fn parse_subcommand(matches: &clap::ArgMatches) -> Option<Subcommand> {
+    match matches.subcommand()? {
+        ("history", history) => Some(Subcommand::History(match history.subcommand() {
+            Some(("show", show)) => HistoryCommand::Show(*show.get_one::<u64>("id")?),
+            _ => HistoryCommand::List(HistoryFilter {
+                package: history.get_one::<String>("package").cloned(),
+                since: history.get_one::<String>("since").cloned(),
+                until: history.get_one::<String>("until").cloned(),
+                limit: history.get_one::<usize>("limit").copied().unwrap_or(20),
+            }),
+        })),
+        ("rollback", rollback) if rollback.get_flag("list") => {
+            Some(Subcommand::Rollback(RollbackCommand::List))
+        }
+        ("rollback", rollback) => Some(Subcommand::Rollback(RollbackCommand::Restore {
+            snapshot: rollback.get_one::<String>("snapshot").cloned(),
+            dry_run: rollback.get_flag("dry-run"),
+        })),
+        ("schedule", schedule) => {
+            let (name, options) = schedule.subcommand()?;
+            let action = match name {
+                "generate" => ScheduleAction::Generate {
+                    dir: options.get_one::<PathBuf>("dir").cloned(),
+                },
+                "install" => ScheduleAction::Install,
+                "list" => ScheduleAction::List,
+                _ => ScheduleAction::Remove,
+            };
+            // `list` and `remove` do not define every option
+            fn option<T: Clone + Send + Sync + 'static>(
+                options: &clap::ArgMatches,
+                id: &str,
+            ) -> Option<T> {
+                options.try_get_one::<T>(id).ok().flatten().cloned()
+            }
+            Some(Subcommand::Schedule(ScheduleCommand {
+                action,
+                backend: option(options, "backend"),
+                frequency: option(options, "frequency"),
+                at: option(options, "at"),
+                weekday: option(options, "weekday"),
+                window: option(options, "window"),
+                args: options
+                    .try_get_many::<String>("args")
+                    .ok()
+                    .flatten()
+                    .map(|args| args.cloned().collect()),
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// A subcommand run instead of the upgrade workflow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subcommand {
+    /// `brewup history`
+    History(HistoryCommand),
+    /// `brewup rollback`
+    Rollback(RollbackCommand),
+    /// `brewup schedule`
+    Schedule(ScheduleCommand),
+}
+
+/// What `brewup history` does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryCommand {
+    /// List the runs matching the filter
+    List(HistoryFilter),
+    /// Show the details of the run with this number
+    Show(u64),
+}
+
+/// What `brewup rollback` does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RollbackCommand {
+    /// List the stored snapshots
+    List,
+    /// Restore a snapshot
+    Restore {
+        /// Snapshot id; `None` restores the most recent one
+        snapshot: Option<String>,
+        /// Only show what would be restored
+        dry_run: bool,
+    },
+}
+
+/// What `brewup schedule` does and the schedule options given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleCommand {
+    /// The action to perform
+    pub action: ScheduleAction,
+    /// `--backend`
+    pub backend: Option<Backend>,
+    /// `--frequency`
+    pub frequency: Option<Frequency>,
+    /// `--at`
+    pub at: Option<TimeOfDay>,
+    /// `--weekday`
+    pub weekday: Option<Weekday>,
+    /// `--window`
+    pub window: Option<u32>,
+    /// Arguments for scheduled runs, given after `--`
+    pub args: Option<Vec<String>>,
+}
+
+/// Action of `brewup schedule`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleAction {
+    /// Render the schedule files, printing them or writing them to `dir`
+    Generate {
+        /// Directory to write the files to
+        dir: Option<PathBuf>,
+    },
+    /// Install the schedule
+    Install,
+    /// List installed schedules
+    List,
+    /// Remove installed schedules
+    Remove,
+}
+
+/// Builds the value parser accepting the name of any registered step.
+fn step_names() -> PossibleValuesParser {
+    PossibleValuesParser::new(
+        steps::registry()
+            .iter()
+            .map(|step| PossibleValue::new(step.name()).help(step.description())),
+    )
+}
+
+/// Format of the run output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Colored, human-readable progress output
+    #[default]
+    Text,
+    /// A single JSON report printed when the run ends
+    Json,
+}
+
+/// How much a run prints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Verbosity {
+    /// Only errors and a one-line summary (`-q`)
+    Quiet,
+    /// Step progress, brew's `==>` headings and warnings, and the results
+    #[default]
+    Normal,
+    /// Also the brew commands, their full output and step timings (`-v`)
+    Verbose,
+    /// Also brew's own verbose output, by running brew with `--verbose` (`-vv`)
+    VeryVerbose,
+}
+
+impl Verbosity {
+    /// Returns `true` at `-v` and above.
+    pub fn is_verbose(self) -> bool {
+        self >= Verbosity::Verbose
+    }
+}
+
+/// When output is colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    /// Color terminals, unless `NO_COLOR` or `CLICOLOR=0` say otherwise
+    #[default]
+    Auto,
+    /// Always color, even when writing to a file or pipe
+    Always,
+    /// Never color
+    Never,
+}
+
+impl ColorChoice {
+    /// Parses a `--color` value.
+    ///
+    /// # Arguments
+    /// * `value` - `auto`, `always` or `never`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
 }
 
 /// Represents the parsed command-line arguments.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct CliArgs {
-    /// Whether to show verbose output
-    pub verbose: bool,
+    /// How much output to print (`-q`, `-v`, `-vv`)
+    pub verbosity: Verbosity,
     /// Whether to run in dry-run mode (preview only)
     pub dry_run: bool,
     /// Whether to skip the cleanup step
     pub skip_cleanup: bool,
+    /// Steps to run exclusively (`--only`), empty to run all
+    pub only: Vec<String>,
+    /// Steps to skip (`--skip`)
+    pub skip: Vec<String>,
+    /// Explicit configuration file to load
+    pub config: Option<PathBuf>,
+    /// Override for the number of packages listed in the summary
+    pub max_packages: Option<usize>,
+    /// Additional exclude patterns for the upgrade step
+    pub exclude: Vec<String>,
+    /// Whether to upgrade packages in isolation
+    pub isolate: bool,
+    /// Whether to leave services of upgraded formulae running (`--no-restart`)
+    pub no_restart: bool,
+    /// Greedy cask mode from `--greedy` / `--greedy-auto-updates`
+    pub greedy: Option<GreedyMode>,
+    /// Whether to wait for another run holding the lock
+    pub wait: bool,
+    /// Format of the run output
+    pub output: OutputFormat,
+    /// When to color output (`--color`), if given
+    pub color: Option<ColorChoice>,
+    /// Whether to use ASCII symbols instead of emoji (`--no-emoji`)
+    pub no_emoji: bool,
+    /// Subcommand to run instead of the upgrade workflow
+    pub command: Option<Subcommand>,
 }
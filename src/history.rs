@@ -0,0 +1,525 @@
+//! Run history for BrewUp.
+//!
+//! Every run is appended to a history file in JSON Lines format, one
+//! [`HistoryEntry`] per line. `brewup history` lists, filters and shows
+//! past runs from that file.
+
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::{
+    caveats::Caveat,
+    cleanup,
+    config::constants,
+    error::BrewupError,
+    report::{
+        CleanupSummary, HeldBackReport, PackageChange, PackageFailure, RunReport, StepReport,
+        StepStatus,
+    },
+    utils::{self, say},
+};
+
+/// One recorded run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Sequential run number, stable across pruning
+    pub id: u64,
+    /// Start time in RFC 3339 format (UTC)
+    pub started_at: String,
+    /// Name of the machine the run happened on
+    pub host: String,
+    /// Command-line arguments the run was started with
+    pub args: Vec<String>,
+    /// Whether the run was a dry run
+    pub dry_run: bool,
+    /// Whether the run completed without a fatal error
+    pub success: bool,
+    /// Process exit code of the run
+    pub exit_code: i32,
+    /// The fatal error that stopped the run, if any
+    pub error: Option<String>,
+    /// Total wall-clock duration of the run
+    pub duration_ms: u64,
+    /// Per-step results in execution order
+    pub steps: Vec<StepReport>,
+    /// Packages upgraded
+    pub upgraded: Vec<PackageChange>,
+    /// Packages whose upgrade failed
+    pub failed: Vec<PackageFailure>,
+    /// Outdated packages that were held back
+    pub held_back: Vec<HeldBackReport>,
+    /// Cleanup results, if cleanup ran
+    pub cleanup: Option<CleanupSummary>,
+    /// Id of the snapshot taken before upgrading, if one was taken
+    #[serde(default)]
+    pub snapshot: Option<String>,
+    /// Caveats brew printed for upgraded packages
+    #[serde(default)]
+    pub caveats: Vec<Caveat>,
+}
+
+impl HistoryEntry {
+    /// Builds the history entry for a finished run.
+    ///
+    /// The installed package list is left out to keep the history small.
+    ///
+    /// # Arguments
+    /// * `report` - The finished run report
+    /// * `host` - Name of this machine
+    /// * `args` - Command-line arguments of the run
+    pub fn from_report(report: &RunReport, host: String, args: Vec<String>) -> Self {
+        Self {
+            id: 0,
+            started_at: report.started_at.clone(),
+            host,
+            args,
+            dry_run: report.dry_run,
+            success: report.success,
+            exit_code: report.exit_code,
+            error: report.error.clone(),
+            duration_ms: report.duration_ms,
+            steps: report.steps.clone(),
+            upgraded: report.upgraded.clone(),
+            failed: report.failed.clone(),
+            held_back: report.held_back.clone(),
+            cleanup: report.cleanup.clone(),
+            snapshot: report.snapshot.clone(),
+            caveats: report.caveats.clone(),
+        }
+    }
+
+    /// Returns the changes for packages matching `pattern`, failed ones included.
+    fn changes_matching<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = &'a PackageChange> {
+        self.upgraded
+            .iter()
+            .chain(self.failed.iter().map(|f| &f.change))
+            .filter(move |c| utils::glob_match(pattern, &c.name))
+    }
+}
+
+/// Which past runs `brewup history` lists.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryFilter {
+    /// Only runs that upgraded (or failed to upgrade) a matching package
+    pub package: Option<String>,
+    /// Only runs started on or after this date (`YYYY`, `YYYY-MM` or `YYYY-MM-DD`)
+    pub since: Option<String>,
+    /// Only runs started on or before this date
+    pub until: Option<String>,
+    /// Maximum number of runs listed, most recent first; 0 lists all
+    pub limit: usize,
+}
+
+impl HistoryFilter {
+    /// Returns `true` if `entry` passes the filter.
+    ///
+    /// Dates are compared on the prefix of the start time they cover, so
+    /// `--until 2024-05` includes every run in May 2024. Dry runs never
+    /// match a package since they changed nothing.
+    ///
+    /// # Arguments
+    /// * `entry` - The run to check
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        let started = |date: &str| &entry.started_at[..date.len().min(entry.started_at.len())];
+        if let Some(since) = &self.since {
+            if started(since) < since.as_str() {
+                return false;
+            }
+        }
+        if let Some(until) = &self.until {
+            if started(until) > until.as_str() {
+                return false;
+            }
+        }
+        match &self.package {
+            Some(pattern) => !entry.dry_run && entry.changes_matching(pattern).next().is_some(),
+            None => true,
+        }
+    }
+}
+
+/// Reads every entry of a history file.
+///
+/// A missing file is an empty history. Lines that cannot be parsed (for
+/// example from an interrupted write) are skipped.
+///
+/// # Arguments
+/// * `path` - The history file
+///
+/// # Returns
+/// The entries in file order (oldest first), or `BrewupError::Storage`.
+pub fn load(path: &Path) -> Result<Vec<HistoryEntry>, BrewupError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(storage_error(path, e)),
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Appends a run to the history file, keeping at most `max_entries` runs.
+///
+/// The entry gets the next run number. The file is rewritten through a
+/// temporary file so that a crash never leaves it half-written.
+///
+/// # Arguments
+/// * `path` - The history file, created along with its directory if needed
+/// * `entry` - The run to record; its `id` is assigned here
+/// * `max_entries` - Number of runs to keep
+///
+/// # Returns
+/// The assigned run number, or `BrewupError::Storage`.
+pub fn append(
+    path: &Path,
+    mut entry: HistoryEntry,
+    max_entries: usize,
+) -> Result<u64, BrewupError> {
+    let mut entries = load(path)?;
+    entry.id = entries.last().map_or(1, |last| last.id + 1);
+    let id = entry.id;
+    entries.push(entry);
+    let start = entries.len().saturating_sub(max_entries.max(1));
+
+    let mut contents = String::new();
+    for entry in &entries[start..] {
+        let line = serde_json::to_string(entry).map_err(|e| storage_error(path, e))?;
+        contents.push_str(&line);
+        contents.push('\n');
+    }
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| storage_error(path, e))?;
+    }
+    let temp = path.with_extension("jsonl.tmp");
+    fs::write(&temp, contents).map_err(|e| storage_error(&temp, e))?;
+    fs::rename(&temp, path).map_err(|e| storage_error(path, e))?;
+    Ok(id)
+}
+
+/// Builds a `BrewupError::Storage` for `path`.
+fn storage_error(path: &Path, error: impl std::fmt::Display) -> BrewupError {
+    BrewupError::Storage {
+        path: path.to_path_buf(),
+        message: error.to_string(),
+    }
+}
+
+/// Selects the runs to list, most recent first.
+///
+/// # Arguments
+/// * `entries` - All recorded runs, oldest first
+/// * `filter` - Which runs to keep
+///
+/// # Returns
+/// The matching runs, newest first, limited to `filter.limit`.
+pub fn select<'a>(entries: &'a [HistoryEntry], filter: &HistoryFilter) -> Vec<&'a HistoryEntry> {
+    let limit = if filter.limit == 0 {
+        usize::MAX
+    } else {
+        filter.limit
+    };
+    entries
+        .iter()
+        .rev()
+        .filter(|e| filter.matches(e))
+        .take(limit)
+        .collect()
+}
+
+/// Formats an RFC 3339 start time for display, e.g. `2024-05-01 09:00:12`.
+fn display_time(started_at: &str) -> String {
+    started_at.trim_end_matches('Z').replacen('T', " ", 1)
+}
+
+/// Returns the colored one-word result of a run.
+fn run_result(entry: &HistoryEntry) -> ColoredString {
+    if !entry.success {
+        "failed".red()
+    } else if entry.dry_run {
+        "dry run".yellow()
+    } else {
+        "ok".green()
+    }
+}
+
+/// Displays past runs as a table.
+///
+/// With a package filter, each run also shows the matching version changes.
+///
+/// # Arguments
+/// * `entries` - The runs to show, in display order
+/// * `filter` - The filter the runs were selected with
+pub fn show_list(entries: &[&HistoryEntry], filter: &HistoryFilter) {
+    let arrow = utils::symbol(constants::SYMBOL_ARROW);
+    if entries.is_empty() {
+        utils::show_info(
+            constants::EMOJI_HISTORY,
+            "No matching runs recorded",
+            colored::Color::Yellow,
+        );
+        return;
+    }
+    let id_width = entries
+        .iter()
+        .map(|e| e.id.to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max(2);
+    let host_width = entries
+        .iter()
+        .map(|e| e.host.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    say!(
+        "{}",
+        format!(
+            "{:>id_width$}  {:<19}  {:<host_width$}  {:<7}  {:>8}  {:>8}",
+            "ID", "Started (UTC)", "Host", "Result", "Upgraded", "Duration"
+        )
+        .bold()
+    );
+    for entry in entries {
+        say!(
+            "{:>id_width$}  {:<19}  {:<host_width$}  {:<7}  {:>8}  {:>8}",
+            entry.id,
+            display_time(&entry.started_at),
+            entry.host,
+            run_result(entry),
+            entry.upgraded.len(),
+            utils::format_duration(entry.duration_ms)
+        );
+        if let Some(pattern) = &filter.package {
+            for change in entry.changes_matching(pattern) {
+                let failed = entry.failed.iter().any(|f| &f.change == change);
+                say!(
+                    "{:>id_width$}  {} {} {arrow} {}{}",
+                    "",
+                    change.name.bold(),
+                    change.from.dimmed(),
+                    change.to,
+                    if failed {
+                        " (failed)".red().to_string()
+                    } else {
+                        String::new()
+                    }
+                );
+            }
+        }
+    }
+}
+
+/// Displays everything recorded about one run.
+///
+/// # Arguments
+/// * `entry` - The run to show
+pub fn show_details(entry: &HistoryEntry) {
+    let arrow = utils::symbol(constants::SYMBOL_ARROW);
+    say!(
+        "{}",
+        format!(
+            "{} Run {}",
+            utils::symbol(constants::EMOJI_HISTORY),
+            entry.id
+        )
+        .bold()
+    );
+    say!("   Started:  {} UTC", display_time(&entry.started_at));
+    say!("   Host:     {}", entry.host);
+    let command = std::iter::once("brewup")
+        .chain(entry.args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ");
+    say!("   Command:  {}", command);
+    say!(
+        "   Result:   {} (exit code {})",
+        run_result(entry),
+        entry.exit_code
+    );
+    if let Some(error) = &entry.error {
+        say!("   Error:    {}", error.red());
+    }
+    say!("   Duration: {}", utils::format_duration(entry.duration_ms));
+    if let Some(snapshot) = &entry.snapshot {
+        say!("   Snapshot: {} (`brewup rollback {}`)", snapshot, snapshot);
+    }
+
+    say!("\n{}", "Steps:".bold());
+    for step in &entry.steps {
+        let status = match step.status {
+            StepStatus::Ok => "ok".green(),
+            StepStatus::Warning => "warning".yellow(),
+            StepStatus::Failed => "failed".red(),
+            StepStatus::Skipped => "skipped".dimmed(),
+            StepStatus::DryRun => "dry run".yellow(),
+        };
+        let exit_code = step
+            .exit_code
+            .map(|c| format!(" (exit code {})", c))
+            .unwrap_or_default();
+        say!(
+            "   {:<8} {:<7}  {}{}",
+            step.name,
+            status,
+            utils::format_duration(step.duration_ms).dimmed(),
+            exit_code.dimmed()
+        );
+    }
+
+    if !entry.upgraded.is_empty() {
+        say!("\n{}", "Upgraded:".bold());
+        for change in &entry.upgraded {
+            say!(
+                "   {} {} {arrow} {}",
+                change.name,
+                change.from.dimmed(),
+                change.to
+            );
+        }
+    }
+    if !entry.failed.is_empty() {
+        say!("\n{}", "Failed:".bold());
+        for failure in &entry.failed {
+            say!(
+                "   {} {} {arrow} {}  {}",
+                failure.change.name,
+                failure.change.from.dimmed(),
+                failure.change.to.dimmed(),
+                failure.error.lines().last().unwrap_or_default().red()
+            );
+        }
+    }
+    if !entry.held_back.is_empty() {
+        say!("\n{}", "Held back:".bold());
+        for held in &entry.held_back {
+            say!(
+                "   {} {} {arrow} {} {}",
+                held.change.name,
+                held.change.from.dimmed(),
+                held.change.to.dimmed(),
+                format!("({})", held.reason).yellow()
+            );
+            if let Some(version) = &held.upgraded_to {
+                say!("{}", format!("     upgraded anyway to {}", version).red());
+            }
+        }
+    }
+    if let Some(cleanup) = &entry.cleanup {
+        let reclaimed = cleanup
+            .reclaimed()
+            .map(cleanup::format_size)
+            .unwrap_or_else(|| "an unknown amount".to_string());
+        let cache = cleanup
+            .cache_bytes
+            .map(|bytes| format!(", {} left in the cache", cleanup::format_size(bytes)))
+            .unwrap_or_default();
+        say!(
+            "\n{} {} entries removed, {} reclaimed{}",
+            "Cleanup:".bold(),
+            cleanup.entries_removed,
+            reclaimed,
+            cache
+        );
+    }
+    if !entry.caveats.is_empty() {
+        say!("\n{}", "Caveats:".bold());
+        for caveat in &entry.caveats {
+            say!("   {}", caveat.name);
+            for line in caveat.text.lines() {
+                say!("      {}", line.dimmed());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packages::PackageKind;
+    use std::path::PathBuf;
+
+    fn entry(started_at: &str, upgraded: &[(&str, &str, &str)]) -> HistoryEntry {
+        let mut report = RunReport::new(false);
+        report.started_at = started_at.to_string();
+        report.upgraded = upgraded
+            .iter()
+            .map(|(name, from, to)| PackageChange {
+                name: name.to_string(),
+                kind: PackageKind::Formula,
+                from: from.to_string(),
+                to: to.to_string(),
+            })
+            .collect();
+        report.finish(None);
+        HistoryEntry::from_report(&report, "mbp".to_string(), vec!["--isolate".to_string()])
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("brewup-history-{}-{}", std::process::id(), name))
+            .join("history.jsonl")
+    }
+
+    /// Tests that appended runs get sequential ids and old runs are pruned.
+    #[test]
+    fn test_append_assigns_ids_and_prunes() {
+        let path = temp_path("append");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+
+        for day in 1..=4 {
+            let started = format!("2024-05-0{}T09:00:00Z", day);
+            append(&path, entry(&started, &[]), 3).unwrap();
+        }
+        let entries = load(&path).unwrap();
+        let ids: Vec<u64> = entries.iter().map(|e| e.id).collect();
+        assert_eq!(ids, [2, 3, 4]);
+        assert_eq!(entries[0].host, "mbp");
+        assert_eq!(entries[0].args, ["--isolate"]);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert!(load(&path).unwrap().is_empty());
+    }
+
+    /// Tests filtering runs by package pattern and date range.
+    #[test]
+    fn test_filter_by_package_and_date() {
+        let entries = vec![
+            entry("2024-04-28T09:00:00Z", &[("openssl@3", "3.2.1", "3.3.0")]),
+            entry("2024-05-02T09:00:00Z", &[("wget", "1.24.4", "1.24.5")]),
+            entry("2024-05-30T09:00:00Z", &[("openssl@3", "3.3.0", "3.3.1")]),
+        ];
+
+        let openssl = HistoryFilter {
+            package: Some("openssl*".to_string()),
+            ..Default::default()
+        };
+        let runs: Vec<_> = select(&entries, &openssl)
+            .iter()
+            .map(|e| &e.started_at[..10])
+            .collect();
+        assert_eq!(runs, ["2024-05-30", "2024-04-28"]);
+
+        let may = HistoryFilter {
+            since: Some("2024-05".to_string()),
+            until: Some("2024-05-02".to_string()),
+            ..Default::default()
+        };
+        let runs = select(&entries, &may);
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].upgraded[0].name, "wget");
+
+        let latest = HistoryFilter {
+            limit: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            select(&entries, &latest)[0].started_at,
+            "2024-05-30T09:00:00Z"
+        );
+    }
+}
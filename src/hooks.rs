@@ -0,0 +1,447 @@
+//! User hooks around the steps of a BrewUp run.
+//!
+//! Hooks are shell commands from the `[hooks]` table, keyed by when they run:
+//!
+//! - `pre_run` before the first step, `post_run` after the last one
+//! - `pre_<step>` and `post_<step>` around each step that runs, e.g.
+//!   `pre_update` or `post_upgrade`
+//! - `on_failure` when the run fails, before `post_run`
+//!
+//! Each hook runs with `sh -c` and receives the run context as environment
+//! variables:
+//!
+//! | Variable              | Value                                            |
+//! |-----------------------|--------------------------------------------------|
+//! | `BREWUP_HOOK`         | Hook name, e.g. `post_upgrade`                   |
+//! | `BREWUP_CONTEXT`      | Path of a JSON file with the run report so far   |
+//! | `BREWUP_UPGRADED`     | Upgraded packages, space separated               |
+//! | `BREWUP_FAILED`       | Packages that failed to upgrade, space separated |
+//! | `BREWUP_STEP`         | Step name (step hooks)                           |
+//! | `BREWUP_STEP_STATUS`  | How the step ended (`post_<step>` hooks)         |
+//! | `BREWUP_SUCCESS`      | `1` or `0` (`on_failure` and `post_run`)         |
+//! | `BREWUP_EXIT_CODE`    | Exit code of the run (`on_failure` and `post_run`) |
+//! | `BREWUP_ERROR`        | The error that failed the run, if any            |
+//!
+//! Hooks are started through `env` so that the variables can be passed to
+//! any [`BrewRunner`]. The context file is created in a new directory that
+//! only the user can access, and removed with it once the hook is done. In
+//! dry-run mode hooks are only announced.
+//!
+//! [`BrewRunner`]: crate::runner::BrewRunner
+
+use colored::*;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    config::{constants, ConfigError, HooksConfig},
+    error::{BrewupError, CommandFailure},
+    pipeline::{FailurePolicy, StepContext},
+    report::{RunReport, StepStatus},
+    utils::{self, say},
+};
+
+/// Hook run before the first step.
+pub const PRE_RUN: &str = "pre_run";
+/// Hook run after the last step, whatever the outcome.
+pub const POST_RUN: &str = "post_run";
+/// Hook run when the run fails.
+pub const ON_FAILURE: &str = "on_failure";
+
+/// Returns the name of every hook available for these steps.
+///
+/// # Arguments
+/// * `steps` - Names of the steps that can be scheduled
+pub fn names(steps: &[&str]) -> Vec<String> {
+    let mut names = vec![PRE_RUN.to_string()];
+    for step in steps {
+        names.push(format!("pre_{}", step));
+        names.push(format!("post_{}", step));
+    }
+    names.extend([ON_FAILURE.to_string(), POST_RUN.to_string()]);
+    names
+}
+
+/// Checks that every configured hook exists.
+///
+/// # Arguments
+/// * `config` - The `[hooks]` configuration
+/// * `steps` - Names of the steps that can be scheduled
+///
+/// # Returns
+/// A `ConfigError` naming the first unknown hook.
+pub fn validate(config: &HooksConfig, steps: &[&str]) -> Result<(), ConfigError> {
+    let known = names(steps);
+    match config.commands.keys().find(|name| !known.contains(name)) {
+        Some(name) => Err(ConfigError {
+            origin: "hooks".to_string(),
+            message: format!(
+                "unknown hook `{}` (expected one of: {})",
+                name,
+                known.join(", ")
+            ),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Returns the failure policy for a failed hook.
+///
+/// An interrupted hook always aborts the run, whatever the configured
+/// `failure_policy`.
+///
+/// # Arguments
+/// * `config` - The `[hooks]` configuration
+/// * `error` - The error the hook failed with
+pub fn failure_policy(config: &HooksConfig, error: &BrewupError) -> FailurePolicy {
+    if *error == BrewupError::Interrupted {
+        FailurePolicy::Abort
+    } else {
+        config.failure_policy
+    }
+}
+
+/// Runs a hook if one is configured, recording it in the report.
+///
+/// # Arguments
+/// * `ctx` - The run context; hooks are started with `ctx.hook_runner`
+/// * `name` - Hook name, e.g. `pre_update`
+/// * `env` - Hook-specific variables, added to the common ones
+/// * `context` - The report written to the context file; `None` uses `ctx.report`
+///
+/// # Returns
+/// `Ok(())` if no hook is configured or it succeeded, `BrewupError::HookFailed`
+/// if it failed, or `BrewupError::Interrupted` if it was killed by a signal.
+pub fn run(
+    ctx: &mut StepContext<'_>,
+    name: &str,
+    env: &[(&str, String)],
+    context: Option<&RunReport>,
+) -> Result<(), BrewupError> {
+    let config = ctx.config;
+    let Some(command) = config.hooks.commands.get(name) else {
+        return Ok(());
+    };
+    let started = Instant::now();
+    if ctx.args.dry_run {
+        say!(
+            "{} {}",
+            utils::symbol(constants::EMOJI_HOOK).cyan(),
+            format!("Would run the {} hook: {}", name, command).cyan()
+        );
+        ctx.report
+            .record_hook(name, started, StepStatus::DryRun, None);
+        return Ok(());
+    }
+    say!(
+        "{} {}",
+        utils::symbol(constants::EMOJI_HOOK).cyan().bold(),
+        format!("Running the {} hook...", name).cyan().bold()
+    );
+
+    let report = context.unwrap_or(ctx.report);
+    let context_file = ContextFile::create(&std::env::temp_dir(), &report.to_json())?;
+    let names = |packages: Vec<&str>| packages.join(" ");
+    let mut vars = vec![
+        ("BREWUP_HOOK", name.to_string()),
+        ("BREWUP_CONTEXT", context_file.path.display().to_string()),
+        (
+            "BREWUP_UPGRADED",
+            names(report.upgraded.iter().map(|c| c.name.as_str()).collect()),
+        ),
+        (
+            "BREWUP_FAILED",
+            names(
+                report
+                    .failed
+                    .iter()
+                    .map(|f| f.change.name.as_str())
+                    .collect(),
+            ),
+        ),
+    ];
+    vars.extend(env.iter().cloned());
+
+    let mut args: Vec<String> = vars
+        .iter()
+        .map(|(var, value)| format!("{}={}", var, value))
+        .collect();
+    args.extend(["sh".to_string(), "-c".to_string(), command.clone()]);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = ctx
+        .hook_runner
+        .stream(&args, &mut utils::show_output_line)
+        .map_err(|e| CommandFailure {
+            message: format!("could not be started: {}", e),
+            output: None,
+        })
+        .and_then(|output| match output.code {
+            Some(0) => Ok(output),
+            Some(code) => Err(CommandFailure {
+                message: format!("exited with status {}", code),
+                output: Some(output),
+            }),
+            None => Err(CommandFailure {
+                message: "was terminated by a signal".to_string(),
+                output: Some(output),
+            }),
+        });
+    drop(context_file);
+
+    match result {
+        Ok(output) => {
+            ctx.report
+                .record_hook(name, started, StepStatus::Ok, Some(&output));
+            Ok(())
+        }
+        Err(failure) => {
+            ctx.report
+                .record_hook(name, started, StepStatus::Failed, failure.output.as_ref());
+            Err(failure.into_error(|failure| BrewupError::HookFailed {
+                hook: name.to_string(),
+                failure: CommandFailure {
+                    message: format!("`{}` {}", command, failure.message),
+                    ..failure
+                },
+            }))
+        }
+    }
+}
+
+/// The JSON file a hook reads the run context from.
+///
+/// The file lives alone in a directory created for it with a fresh name and
+/// mode 0700, so that other users can neither read it nor plant a file or
+/// link in its place. Both are removed when the value is dropped.
+struct ContextFile {
+    /// The private directory
+    dir: PathBuf,
+    /// The context file inside it
+    path: PathBuf,
+}
+
+impl ContextFile {
+    /// Writes `contents` to a new context file below `parent`.
+    ///
+    /// # Arguments
+    /// * `parent` - The directory the private directory is created in
+    /// * `contents` - The JSON run report
+    ///
+    /// # Returns
+    /// The context file, or `BrewupError::Storage` if it cannot be written.
+    fn create(parent: &Path, contents: &str) -> Result<Self, BrewupError> {
+        let dir = private_dir(parent).map_err(|e| BrewupError::Storage {
+            path: parent.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        let file = Self {
+            path: dir.join("context.json"),
+            dir,
+        };
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&file.path)
+            .and_then(|mut f| f.write_all(contents.as_bytes()))
+            .map_err(|e| BrewupError::Storage {
+                path: file.path.clone(),
+                message: e.to_string(),
+            })?;
+        Ok(file)
+    }
+}
+
+impl Drop for ContextFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Creates a new directory below `parent` that only the user can access.
+///
+/// An entry that already has the chosen name, including a planted link, is
+/// never reused; another name is tried instead.
+fn private_dir(parent: &Path) -> io::Result<PathBuf> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    let mut attempt = 0;
+    loop {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let dir = parent.join(format!(
+            "brewup-hook-{}-{:08x}{:02x}",
+            std::process::id(),
+            nanos,
+            attempt
+        ));
+        match builder.create(&dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 16 => attempt += 1,
+            result => return result.map(|()| dir),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::CliArgs;
+    use crate::config::Config;
+    use crate::packages::PackageKind;
+    use crate::report::PackageChange;
+    use crate::runner::{ProcessRunner, ScriptedRunner};
+
+    /// Runs `command` as the `post_update` hook with a real `sh`, returning
+    /// its result, the lines it printed and the report.
+    fn run_hook(command: &str, dry_run: bool) -> (Result<(), BrewupError>, Vec<String>, RunReport) {
+        let args = CliArgs {
+            dry_run,
+            ..CliArgs::default()
+        };
+        let mut config = Config::new();
+        config
+            .hooks
+            .commands
+            .insert("post_update".to_string(), command.to_string());
+        let mut report = RunReport::new(dry_run);
+        report.upgraded.push(PackageChange {
+            name: "wget".to_string(),
+            kind: PackageKind::Formula,
+            from: "1.24.4".to_string(),
+            to: "1.24.5".to_string(),
+        });
+        let mut result = Ok(());
+        let output = utils::capture_output(|| {
+            let mut ctx = StepContext {
+                args: &args,
+                config: &config,
+                runner: &ScriptedRunner::new(),
+                hook_runner: &ProcessRunner::with_program("env"),
+                report: &mut report,
+            };
+            result = run(
+                &mut ctx,
+                "post_update",
+                &[("BREWUP_STEP", "update".to_string())],
+                None,
+            );
+        });
+        let lines = output
+            .lines()
+            .filter_map(|line| {
+                line.trim()
+                    .strip_prefix(utils::symbol(constants::SYMBOL_GUTTER))
+            })
+            .map(str::trim)
+            .map(String::from)
+            .collect();
+        (result, lines, report)
+    }
+
+    /// Tests that every hook name is known and others are rejected.
+    #[test]
+    fn test_validate() {
+        assert_eq!(
+            names(&["update"]),
+            [
+                "pre_run",
+                "pre_update",
+                "post_update",
+                "on_failure",
+                "post_run"
+            ]
+        );
+        let mut config = HooksConfig::default();
+        config
+            .commands
+            .insert("post_update".to_string(), "true".to_string());
+        assert!(validate(&config, &["update"]).is_ok());
+        config
+            .commands
+            .insert("post_upgrade".to_string(), "true".to_string());
+        let err = validate(&config, &["update"]).unwrap_err();
+        assert!(err.message.starts_with("unknown hook `post_upgrade`"));
+    }
+
+    /// Tests that a hook receives the context as variables and as a private
+    /// JSON file that is removed afterwards.
+    #[test]
+    fn test_run_passes_context() {
+        let (result, lines, report) = run_hook(
+            "echo \"$BREWUP_HOOK $BREWUP_STEP $BREWUP_UPGRADED\"; \
+             ls -l \"$BREWUP_CONTEXT\" | cut -c1-10; \
+             grep -o '\"name\": *\"wget\"' \"$BREWUP_CONTEXT\" | head -n 1; \
+             echo \"$BREWUP_CONTEXT\"",
+            false,
+        );
+        result.unwrap();
+        assert_eq!(lines[0], "post_update update wget");
+        assert_eq!(lines[1], "-rw-------");
+        assert!(lines[2].ends_with("\"wget\""));
+        let context = Path::new(&lines[3]);
+        assert!(context.starts_with(std::env::temp_dir()));
+        assert!(!context.exists());
+        assert!(!context.parent().unwrap().exists());
+        assert_eq!(report.hooks[0].name, "post_update");
+        assert_eq!(report.hooks[0].status, StepStatus::Ok);
+    }
+
+    /// Tests that a failing hook is reported with its command and status,
+    /// and that dry runs only announce hooks.
+    #[test]
+    fn test_run_failures() {
+        let (result, _, report) = run_hook("echo broken >&2; exit 3", false);
+        match result {
+            Err(BrewupError::HookFailed { hook, failure }) => {
+                assert_eq!(hook, "post_update");
+                assert_eq!(
+                    failure.message,
+                    "`echo broken >&2; exit 3` exited with status 3"
+                );
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(report.hooks[0].status, StepStatus::Failed);
+        assert_eq!(report.hooks[0].exit_code, Some(3));
+        assert_eq!(report.hooks[0].stderr.as_deref(), Some("broken"));
+
+        let (result, _, _) = run_hook("kill -KILL $$", false);
+        assert_eq!(result, Err(BrewupError::Interrupted));
+
+        let (result, lines, report) = run_hook("echo ran", true);
+        result.unwrap();
+        assert!(lines.is_empty());
+        assert_eq!(report.hooks[0].status, StepStatus::DryRun);
+    }
+
+    /// Tests that interrupted hooks abort the run whatever the policy.
+    #[test]
+    fn test_failure_policy() {
+        let config = HooksConfig {
+            failure_policy: FailurePolicy::Continue,
+            ..HooksConfig::default()
+        };
+        let failed = BrewupError::HookFailed {
+            hook: "post_run".to_string(),
+            failure: CommandFailure {
+                message: "exited with status 1".to_string(),
+                output: None,
+            },
+        };
+        assert_eq!(failure_policy(&config, &failed), FailurePolicy::Continue);
+        assert_eq!(
+            failure_policy(&config, &BrewupError::Interrupted),
+            FailurePolicy::Abort
+        );
+        assert_eq!(
+            failure_policy(&HooksConfig::default(), &failed),
+            FailurePolicy::Abort
+        );
+    }
+}
@@ -2,16 +2,39 @@
 //!
 //! This crate provides the core functionality for the BrewUp CLI tool.
 //! It contains modules for command-line interface, configuration,
-//! command execution, and utilities.
+//! command execution, failure diagnoses and retries, the step pipeline, brew execution
+//! backends, run history, snapshots and rollback, version changes, upgrade caveats, scheduled runs, the
+//! single-instance lock, notifications, user hooks, Homebrew services, and utilities.
 
+pub mod caveats;
+pub mod changes;
+pub mod cleanup;
 pub mod cli;
 pub mod commands;
 pub mod config;
+pub mod diagnosis;
+pub mod error;
+pub mod history;
+pub mod hooks;
+pub mod lock;
+pub mod notify;
+pub mod packages;
+pub mod pipeline;
+pub mod report;
+pub mod retry;
+pub mod rollback;
+pub mod runner;
+pub mod schedule;
+pub mod services;
+pub mod snapshot;
+pub mod steps;
 pub mod utils;
 
 /// Re-exports commonly used items for easier access.
 pub mod prelude {
     pub use crate::cli::CliArgs;
     pub use crate::config::Config;
-    pub use crate::utils::{exit_with_error, show_success, show_warning};
+    pub use crate::error::BrewupError;
+    pub use crate::runner::{BrewOutput, BrewRunner, ProcessRunner, ScriptedRunner};
+    pub use crate::utils::{exit_with_error, exit_with_failure, show_success, show_warning};
 }
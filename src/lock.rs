@@ -0,0 +1,306 @@
+//! Single-instance lock for BrewUp runs.
+//!
+//! Two brewup runs upgrading at the same time (for example a scheduled run
+//! overlapping a manual one) make Homebrew fail on its own locks. Runs that
+//! change packages therefore hold an advisory lock file, `brewup.lock` in the
+//! state directory, recording who holds it:
+//!
+//! ```json
+//! {"pid": 4242, "started_at": "2024-05-01T09:00:00Z", "host": "mbp"}
+//! ```
+//!
+//! A lock whose process no longer runs on this host is stale (the run was
+//! killed) and is taken over, and so is a lock file that cannot be parsed
+//! (the run crashed while writing it).
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::{config::constants, error::BrewupError, utils};
+
+/// How often a waiting run checks whether the lock was released.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The contents of a lock file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockInfo {
+    /// Process ID of the run holding the lock
+    pub pid: u32,
+    /// When the run started (RFC 3339, UTC)
+    pub started_at: String,
+    /// Host the run is on
+    pub host: String,
+}
+
+impl LockInfo {
+    /// Describes the current process.
+    fn current() -> Self {
+        Self {
+            pid: process::id(),
+            started_at: utils::format_timestamp(SystemTime::now()),
+            host: utils::hostname(),
+        }
+    }
+
+    /// Returns `true` if the holder is known to be gone.
+    ///
+    /// Processes on other hosts (a state directory on a shared home) cannot be
+    /// checked and are assumed to be alive.
+    fn is_stale(&self) -> bool {
+        self.host == utils::hostname() && !process_exists(self.pid)
+    }
+}
+
+/// A held run lock; the lock file is removed when it is dropped.
+#[derive(Debug)]
+pub struct RunLock {
+    path: PathBuf,
+    info: LockInfo,
+}
+
+impl RunLock {
+    /// Acquires the run lock.
+    ///
+    /// Stale locks left by killed runs are removed. If another run holds the
+    /// lock, this either fails at once or, with `wait`, blocks until the lock
+    /// is released.
+    ///
+    /// # Arguments
+    /// * `path` - The lock file
+    /// * `wait` - Whether to wait for another run instead of failing
+    ///
+    /// # Returns
+    /// The held lock, `BrewupError::LockHeld` if another run holds it, or
+    /// `BrewupError::Storage` if the lock file cannot be written.
+    pub fn acquire(path: &Path, wait: bool) -> Result<Self, BrewupError> {
+        let info = LockInfo::current();
+        let mut announced = false;
+        loop {
+            match try_create(path, &info) {
+                Ok(()) => {
+                    return Ok(Self {
+                        path: path.to_path_buf(),
+                        info,
+                    })
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(storage_error(path, e)),
+            }
+
+            let contents = match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                // Released (or being rewritten) in the meantime; try again
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(storage_error(path, e)),
+            };
+            let Some(holder) = parse(&contents) else {
+                remove_if_unchanged(path, &contents)?;
+                utils::show_warning(&format!(
+                    "Removed an unreadable lock file: {}",
+                    path.display()
+                ));
+                continue;
+            };
+            if holder.is_stale() {
+                remove_if_unchanged(path, &contents)?;
+                utils::show_warning(&format!(
+                    "Removed a stale lock left by brewup (PID {}, started {})",
+                    holder.pid, holder.started_at
+                ));
+                continue;
+            }
+            if !wait {
+                return Err(BrewupError::LockHeld { pid: holder.pid });
+            }
+            if !announced {
+                utils::show_info(
+                    constants::EMOJI_LOCK,
+                    &format!(
+                        "Waiting for the brewup run started {} (PID {}) to finish...",
+                        holder.started_at, holder.pid
+                    ),
+                    colored::Color::Yellow,
+                );
+                announced = true;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // Leave the file alone if it was taken over in the meantime
+        let holder = fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|contents| parse(&contents));
+        if holder.as_ref() == Some(&self.info) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Creates the lock file with `info` unless it exists.
+///
+/// The contents are written to a private file first and hard-linked into
+/// place, so other runs never see a partly written lock.
+fn try_create(path: &Path, info: &LockInfo) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temp = path.with_extension(format!("lock.{}", info.pid));
+    let json = serde_json::to_string(info).map_err(std::io::Error::other)?;
+    fs::write(&temp, json + "\n")?;
+    let result = fs::hard_link(&temp, path);
+    let _ = fs::remove_file(&temp);
+    result
+}
+
+/// Parses the contents of a lock file.
+///
+/// # Returns
+/// The holder, or `None` if the contents are not a lock (e.g. truncated).
+fn parse(contents: &str) -> Option<LockInfo> {
+    serde_json::from_str(contents).ok()
+}
+
+/// Removes a stale lock unless another run replaced it since it was read.
+///
+/// # Arguments
+/// * `path` - The lock file
+/// * `stale` - The contents read from it
+fn remove_if_unchanged(path: &Path, stale: &str) -> Result<(), BrewupError> {
+    match fs::read_to_string(path) {
+        Ok(current) if current == stale => match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(storage_error(path, e)),
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Returns `true` if a process with this PID exists.
+///
+/// A process of another user (e.g. a run started by root's cron) cannot be
+/// signalled, so only "no such process" means that it is gone.
+fn process_exists(pid: u32) -> bool {
+    // `kill -0` checks for the process without signalling it
+    let output = process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .env("LC_ALL", "C")
+        .stdin(process::Stdio::null())
+        .output();
+    match output {
+        Ok(output) if !output.status.success() => {
+            !String::from_utf8_lossy(&output.stderr).contains("No such process")
+        }
+        // Running, or without `kill` there is no telling; keep the lock
+        _ => true,
+    }
+}
+
+/// Builds the error for a lock file that cannot be accessed.
+fn storage_error(path: &Path, error: std::io::Error) -> BrewupError {
+    BrewupError::Storage {
+        path: path.to_path_buf(),
+        message: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_lock(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("brewup-lock-{}-{}", name, process::id()))
+            .join("brewup.lock")
+    }
+
+    /// Tests that a held lock is reported and released on drop.
+    #[test]
+    fn test_lock_is_exclusive() {
+        let path = temp_lock("exclusive");
+        let lock = RunLock::acquire(&path, false).unwrap();
+        assert_eq!(
+            parse(&fs::read_to_string(&path).unwrap()).unwrap().pid,
+            process::id()
+        );
+
+        let err = RunLock::acquire(&path, false).unwrap_err();
+        assert_eq!(err, BrewupError::LockHeld { pid: process::id() });
+        assert_eq!(err.exit_code(), 8);
+
+        drop(lock);
+        assert!(!path.exists());
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    /// Tests that a lock left by a process that no longer runs is taken over.
+    #[test]
+    fn test_stale_lock_is_taken_over() {
+        let path = temp_lock("stale");
+        let mut child = process::Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+        let stale = LockInfo {
+            pid: dead,
+            started_at: "2024-05-01T09:00:00Z".to_string(),
+            host: utils::hostname(),
+        };
+        try_create(&path, &stale).unwrap();
+
+        let lock = RunLock::acquire(&path, false).unwrap();
+        assert_eq!(
+            parse(&fs::read_to_string(&path).unwrap()).unwrap().pid,
+            process::id()
+        );
+        drop(lock);
+
+        // Locks of other hosts cannot be checked and are respected
+        let remote = LockInfo {
+            host: "elsewhere".to_string(),
+            ..stale
+        };
+        try_create(&path, &remote).unwrap();
+        assert_eq!(
+            RunLock::acquire(&path, false).unwrap_err(),
+            BrewupError::LockHeld { pid: dead }
+        );
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    /// Tests that a lock file that cannot be parsed is taken over.
+    #[test]
+    fn test_unreadable_lock_is_taken_over() {
+        let path = temp_lock("corrupt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "{\"pid\": 42, \"start").unwrap();
+
+        let lock = RunLock::acquire(&path, false).unwrap();
+        assert_eq!(
+            parse(&fs::read_to_string(&path).unwrap()).unwrap().pid,
+            process::id()
+        );
+        drop(lock);
+        assert!(!path.exists());
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    /// Tests that only a process that is gone counts as not existing, and
+    /// one that cannot be signalled (PID 1, unless running as root) exists.
+    #[test]
+    fn test_process_exists() {
+        let mut child = process::Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+        assert!(!process_exists(dead));
+        assert!(process_exists(process::id()));
+        assert!(process_exists(1));
+    }
+}
@@ -7,11 +7,28 @@
 //! # Features
 //!
 //! - **One-command workflow**: Updates, upgrades, and cleans up in a single operation
-//! - **Detailed output**: Shows progress and results of each step by default
+//! - **Output levels**: Progress and results by default, `-q` for errors and one line, `-v`/`-vv` for everything
 //! - **Dry-run mode**: Preview changes without executing them
 //! - **Flexible cleanup**: Option to skip cleanup step when needed
+//! - **Step pipeline**: Pick steps with `--only` / `--skip` and set failure policies
 //! - **Colored output**: Visual feedback with emojis and colors
 //! - **Package summary**: Shows installed packages and their versions
+//! - **Cleanup policy**: Prune age, scrub, exempt formulae, versions kept and `brew autoremove` in `[cleanup]`
+//! - **Disk space report**: Cleanup reports the space it reclaimed and the largest cache files
+//! - **Version changes**: A table of the packages upgraded, installed and removed during the run
+//! - **Layered configuration**: TOML files, `BREWUP_*` variables and flags
+//! - **JSON report**: `--output json` prints one machine-readable run report
+//! - **Run history**: Every run is recorded; `brewup history` searches past runs
+//! - **Snapshots**: Installed versions are saved before upgrading; `brewup rollback` restores them
+//! - **Scheduling**: `brewup schedule` installs a crontab entry, systemd timer or launchd agent
+//! - **Single-instance lock**: Overlapping runs exit with code 8, or wait with `--wait`
+//! - **Notifications**: Desktop notifications and Slack, Discord or JSON webhooks
+//! - **Hooks**: User scripts run before and after each step with the run context
+//! - **Service restarts**: Running services of upgraded formulae are restarted
+//! - **Caveats summary**: Caveats printed during upgrades are listed at the end
+//! - **Failure diagnoses**: Known brew errors are shown with a suggested fix
+//! - **Retries**: Network failures and held brew locks are retried with backoff
+//! - **Plain output**: No colors or emoji in logs and pipes, `NO_COLOR`, `--color`, `--no-emoji`
 //!
 //! # Usage
 //!
@@ -25,6 +42,21 @@
 //! # Skip cleanup step
 //! brewup --skip-cleanup
 //!
+//! # Only update Homebrew and show the summary
+//! brewup --only update --only summary
+//!
+//! # Errors and a one-line summary only, e.g. for cron mail
+//! brewup --quiet
+//!
+//! # When was openssl last upgraded?
+//! brewup history --package 'openssl*'
+//!
+//! # Undo the last upgrade as far as possible
+//! brewup rollback
+//!
+//! # Run brewup every day at 09:00 with the native scheduler
+//! brewup schedule install
+//!
 //! # Show help
 //! brewup --help
 //! ```
@@ -36,47 +68,218 @@
 //! - `cli`: Command-line interface and argument parsing
 //! - `commands`: Core application logic and workflow
 //! - `config`: Configuration management and constants
+//! - `history`: The run history file and `brewup history`
+//! - `snapshot`: Pre-upgrade snapshots of the installed packages
+//! - `rollback`: `brewup rollback`, restoring a snapshot
+//! - `schedule`: `brewup schedule`, rendering and installing scheduler entries
+//! - `lock`: The single-instance lock file
+//! - `notify`: Desktop and webhook notifications about finished runs
+//! - `hooks`: User hooks run around the steps
+//! - `services`: Running Homebrew services of upgraded formulae
+//! - `caveats`: Caveats printed for upgraded packages
+//! - `changes`: Version changes between the start and end of a run
+//! - `cleanup`: `brew cleanup` output and the sizes of the cache and Cellar
+//! - `diagnosis`: Classification of brew failures with remediations
+//! - `retry`: Retries of brew commands after transient failures
+//! - `pipeline`: The `Step` trait and the engine that runs steps in order
+//! - `runner`: Backends that execute `brew` (real process or scripted)
+//! - `steps`: The update, upgrade, cleanup and summary steps
 //! - `utils`: Utility functions and helpers
 //!
-//! Each module is designed to be self-contained and testable.
-
-mod cli;
-mod commands;
-mod config;
-mod utils;
+//! Each module is designed to be self-contained and testable. The modules
+//! live in the `brewup` library crate; this binary only parses arguments and
+//! wires in the real process runner.
 
 use clap::error::ErrorKind;
+use std::io::IsTerminal;
+use std::path::PathBuf;
 
-use crate::cli::CliArgs;
-use crate::commands::execute_brewup;
+use brewup::cleanup;
+use brewup::cli::{self, CliArgs, ColorChoice, OutputFormat, Subcommand, Verbosity};
+use brewup::commands::{execute_brewup, execute_history, execute_rollback, execute_schedule};
+use brewup::config::Config;
+use brewup::error::{BrewupError, EXIT_USAGE};
+use brewup::history::{self, HistoryEntry};
+use brewup::notify;
+use brewup::packages::GreedyMode;
+use brewup::report::RunReport;
+use brewup::runner::ProcessRunner;
+use brewup::schedule::Locations;
+use brewup::utils::{self, OutputStyle};
 
 /// Main entry point for the BrewUp application.
 ///
 /// This function:
 /// 1. Parses command-line arguments
-/// 2. Executes the main BrewUp workflow
-/// 3. Handles any errors and displays appropriate messages
-/// 4. Returns the appropriate exit code
+/// 2. Loads the layered configuration
+/// 3. Executes the main BrewUp workflow
+/// 4. Records the run in the history file
+/// 5. Sends the configured notifications
+/// 6. Handles any errors and displays appropriate messages
+/// 7. Returns the appropriate exit code
 ///
 /// # Exit Codes
 ///
-/// - `0`: Success
-/// - `1`: Error (Homebrew not found, update/upgrade failed)
+/// - `0`: Success (also for `--help` and `--version`)
 /// - `2`: Command-line argument error
+/// - `3`: Invalid configuration
+/// - `4`: Homebrew not found
+/// - `5`: `brew update` failed
+/// - `6`: Upgrading packages failed
+/// - `7`: Cleanup failed
+/// - `8`: Another brewup run holds the lock
+/// - `9`: A brewup file (such as the history) is inaccessible
+/// - `10`: A rollback left packages unrestored
+/// - `11`: A scheduler command failed
+/// - `12`: A user hook failed
+/// - `130`: Interrupted
+///
+/// See the `error` module for the full taxonomy.
 fn main() {
     // Parse command-line arguments
     let args = match parse_args() {
         Ok(args) => args,
         Err(e) => {
             // Handle argument parsing errors
+            let code = if e.use_stderr() { EXIT_USAGE } else { 0 };
             handle_arg_error(e);
-            std::process::exit(2);
+            std::process::exit(code);
         }
     };
 
+    // Load configuration files, environment overrides and flags
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => utils::exit_with_failure(&BrewupError::from(e)),
+    };
+
+    OutputStyle::detect(
+        &config.display,
+        |name| std::env::var(name).ok(),
+        std::io::stdout().is_terminal(),
+    )
+    .apply();
+
+    let brew = brew_runner(&config);
+
+    // JSON mode prints nothing but the final report on stdout
+    if args.output == OutputFormat::Json {
+        utils::set_silent(true);
+    }
+
+    // Subcommands replace the upgrade workflow
+    if let Some(command) = &args.command {
+        let result = match command {
+            Subcommand::History(history) => execute_history(history, &config, args.output),
+            Subcommand::Rollback(rollback) => execute_rollback(rollback, &args, &config, &brew),
+            Subcommand::Schedule(schedule) => match Locations::from_env() {
+                Some(locations) => {
+                    execute_schedule(&schedule.action, &config, args.output, &locations, &|b| {
+                        Box::new(ProcessRunner::with_program(b.tool()))
+                    })
+                }
+                None => Err(BrewupError::Storage {
+                    path: PathBuf::from("~"),
+                    message: "no home directory to keep schedules in".to_string(),
+                }),
+            },
+        };
+        if let Err(e) = result {
+            utils::exit_with_failure(&e);
+        }
+        return;
+    }
+
+    // Quiet mode prints nothing but a one-line summary of the run; the
+    // subcommands above print what was asked for whatever the verbosity
+    if args.verbosity == Verbosity::Quiet {
+        utils::set_silent(true);
+    }
+
     // Execute the main workflow
-    if execute_brewup(&args).is_err() {
-        std::process::exit(1);
+    let mut report = RunReport::new(args.dry_run);
+    let result = execute_brewup(
+        &args,
+        &config,
+        &brew,
+        &ProcessRunner::with_program("env"),
+        &mut report,
+    );
+    report.finish(result.as_ref().err());
+    if config.history.enabled {
+        record_history(&config, &report);
+    }
+    send_notifications(&config, &report);
+
+    if args.output == OutputFormat::Json {
+        println!("{}", report.to_json());
+    } else if args.verbosity == Verbosity::Quiet {
+        println!("{}", report.summary_line());
+    }
+    if let Err(e) = result {
+        utils::exit_with_failure(&e);
+    }
+}
+
+/// Creates the runner for brew commands, with the environment the cleanup
+/// policy needs.
+///
+/// Exempt formulae are protected from every brew command, including the
+/// cleanup `brew upgrade` runs by itself. With `keep_versions` above 1,
+/// `brew upgrade` does not clean up at all, so that the old versions to
+/// keep survive until the cleanup step decides.
+///
+/// # Arguments
+/// * `config` - Application configuration
+fn brew_runner(config: &Config) -> ProcessRunner {
+    let mut runner = ProcessRunner::new();
+    let no_cleanup = std::env::var(cleanup::NO_CLEANUP_VAR).ok();
+    if let Some(formulae) =
+        cleanup::no_cleanup_formulae(&config.cleanup.exempt, no_cleanup.as_deref())
+    {
+        runner = runner.with_env(cleanup::NO_CLEANUP_VAR, &formulae);
+    }
+    if config.cleanup.keep_versions > 1 {
+        runner = runner.with_env(cleanup::NO_INSTALL_CLEANUP_VAR, "1");
+    }
+    runner
+}
+
+/// Appends the finished run to the history file.
+///
+/// A history that cannot be written only produces a warning; it never
+/// changes the outcome of the run.
+///
+/// # Arguments
+/// * `config` - Application configuration
+/// * `report` - The finished run report
+fn record_history(config: &Config, report: &RunReport) {
+    let Some(path) = config.history.path() else {
+        return;
+    };
+    let entry = HistoryEntry::from_report(
+        report,
+        utils::hostname(),
+        std::env::args().skip(1).collect(),
+    );
+    if let Err(e) = history::append(&path, entry, config.history.max_entries) {
+        utils::show_warning(&format!("Could not record this run in the history: {}", e));
+    }
+}
+
+/// Sends the notifications configured under `[notify]` for the finished run.
+///
+/// Notifications that cannot be delivered only produce warnings.
+///
+/// # Arguments
+/// * `config` - Application configuration
+/// * `report` - The finished run report
+fn send_notifications(config: &Config, report: &RunReport) {
+    let failures = notify::send(&config.notify, report, &utils::hostname(), &|program| {
+        Box::new(ProcessRunner::with_program(program))
+    });
+    for failure in failures {
+        utils::show_warning(&format!("Could not send a notification: {}", failure));
     }
 }
 
@@ -90,9 +293,42 @@ fn parse_args() -> Result<CliArgs, clap::Error> {
 
     // Extract the flags we care about
     Ok(CliArgs {
-        verbose: true, // Always verbose by default
+        verbosity: cli::parse_verbosity(&matches),
         dry_run: matches.get_flag("dry-run"),
         skip_cleanup: matches.get_flag("skip-cleanup"),
+        only: matches
+            .get_many::<String>("only")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        skip: matches
+            .get_many::<String>("skip")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        config: matches.get_one::<PathBuf>("config").cloned(),
+        max_packages: matches.get_one::<usize>("max-packages").copied(),
+        exclude: matches
+            .get_many::<String>("exclude")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        isolate: matches.get_flag("isolate"),
+        no_restart: matches.get_flag("no-restart"),
+        greedy: if matches.get_flag("greedy") {
+            Some(GreedyMode::All)
+        } else if matches.get_flag("greedy-auto-updates") {
+            Some(GreedyMode::AutoUpdates)
+        } else {
+            None
+        },
+        wait: matches.get_flag("wait"),
+        output: match matches.get_one::<String>("output").map(String::as_str) {
+            Some("json") => OutputFormat::Json,
+            _ => OutputFormat::Text,
+        },
+        color: matches
+            .get_one::<String>("color")
+            .and_then(|value| ColorChoice::parse(value)),
+        no_emoji: matches.get_flag("no-emoji"),
+        command: cli::parse_subcommand(&matches),
     })
 }
 
@@ -140,15 +376,31 @@ mod tests {
     fn test_module_access() {
         // This test ensures all modules can be accessed and compiled together
         let _cli = cli::build_cli();
-        let _config = config::Config::new();
+        let _config = brewup::config::Config::new();
         // Test that we can create a default CliArgs instance
-        let _args = CliArgs {
-            verbose: true,
-            dry_run: false,
-            skip_cleanup: false,
+        let args = CliArgs {
+            verbosity: cli::Verbosity::Verbose,
+            ..Default::default()
         };
 
         // If we get here, all modules are accessible
-        assert!(true);
+        assert!(!args.dry_run);
+    }
+
+    /// Tests that `-q` and repeated `-v` select the output level.
+    #[test]
+    fn test_verbosity_flags() {
+        let verbosity = |args: &[&str]| {
+            let matches = cli::build_cli().try_get_matches_from(args).unwrap();
+            cli::parse_verbosity(&matches)
+        };
+        assert_eq!(verbosity(&["brewup"]), Verbosity::Normal);
+        assert_eq!(verbosity(&["brewup", "-q"]), Verbosity::Quiet);
+        assert_eq!(verbosity(&["brewup", "--verbose"]), Verbosity::Verbose);
+        assert_eq!(verbosity(&["brewup", "-vv"]), Verbosity::VeryVerbose);
+        assert_eq!(verbosity(&["brewup", "history", "-v"]), Verbosity::Verbose);
+        assert!(cli::build_cli()
+            .try_get_matches_from(["brewup", "-q", "-v"])
+            .is_err());
     }
 }
@@ -0,0 +1,523 @@
+//! Notifications about finished runs.
+//!
+//! Unattended runs (see `brewup schedule`) report their outcome through the
+//! channels configured in the `[notify]` table:
+//!
+//! - a desktop notification (`notify-send` on Linux, `osascript` on macOS)
+//! - webhooks, posted with `curl` as a generic JSON document, or formatted for
+//!   Slack or Discord incoming webhooks
+//!
+//! A notification that cannot be delivered only produces a warning; it
+//! never changes the outcome of the run.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{constants, NotifyConfig},
+    report::RunReport,
+    runner::{BrewRunner, CommandInput},
+    utils,
+};
+
+/// Maximum number of packages named per list in a message.
+const MAX_PACKAGES_LISTED: usize = 10;
+
+/// Discord rejects messages longer than this many characters.
+const DISCORD_MAX_CHARS: usize = 2000;
+
+/// Seconds a webhook may take before it is abandoned.
+const WEBHOOK_TIMEOUT_SECS: &str = "10";
+
+/// Which runs trigger a notification.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyWhen {
+    /// Runs that failed or could not upgrade some packages
+    #[default]
+    Failure,
+    /// Runs that upgraded at least one package
+    Upgrades,
+    /// Every run
+    Always,
+}
+
+/// Payload format of a webhook.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    /// A JSON document with the message and the full run report
+    #[default]
+    Json,
+    /// A Slack incoming webhook message
+    Slack,
+    /// A Discord webhook message
+    Discord,
+}
+
+/// A webhook notified about runs (`[[notify.webhook]]`).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    /// URL the payload is posted to
+    pub url: String,
+    /// Payload format
+    #[serde(default)]
+    pub format: WebhookFormat,
+}
+
+/// The text of a notification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// One-line summary, e.g. `BrewUp on mbp: upgraded 3 packages`
+    pub title: String,
+    /// Upgraded and failed packages and the error, one item per line
+    pub body: String,
+    /// Whether the run succeeded
+    pub success: bool,
+}
+
+impl Message {
+    /// Summarizes a finished run.
+    ///
+    /// # Arguments
+    /// * `report` - The finished run report
+    /// * `host` - Name of the machine the run was on
+    pub fn from_report(report: &RunReport, host: &str) -> Self {
+        let success = report.success && report.failed.is_empty();
+        let title = if !success {
+            format!("BrewUp on {} failed", host)
+        } else if report.upgraded.is_empty() {
+            format!("BrewUp on {}: everything is up to date", host)
+        } else {
+            format!(
+                "BrewUp on {}: upgraded {} package{}",
+                host,
+                report.upgraded.len(),
+                if report.upgraded.len() == 1 { "" } else { "s" }
+            )
+        };
+
+        let mut lines = Vec::new();
+        if !report.upgraded.is_empty() {
+            let arrow = utils::symbol(constants::SYMBOL_ARROW);
+            lines.push(format!(
+                "Upgraded: {}",
+                list(
+                    report
+                        .upgraded
+                        .iter()
+                        .map(|c| format!("{} {} {arrow} {}", c.name, c.from, c.to))
+                )
+            ));
+        }
+        if !report.failed.is_empty() {
+            lines.push(format!(
+                "Failed: {}",
+                list(report.failed.iter().map(|f| f.change.name.clone()))
+            ));
+        }
+        if !report.caveats.is_empty() {
+            lines.push(format!(
+                "Caveats: {}",
+                list(report.caveats.iter().map(|c| c.name.clone()))
+            ));
+        }
+        if let Some(error) = &report.error {
+            lines.push(format!("Error: {}", error));
+        }
+        if lines.is_empty() {
+            lines.push("No packages were upgraded.".to_string());
+        }
+
+        Self {
+            title,
+            body: lines.join("\n"),
+            success,
+        }
+    }
+}
+
+/// Joins names, naming at most [`MAX_PACKAGES_LISTED`] of them.
+fn list(items: impl ExactSizeIterator<Item = String>) -> String {
+    let total = items.len();
+    let mut text = items
+        .take(MAX_PACKAGES_LISTED)
+        .collect::<Vec<_>>()
+        .join(", ");
+    if total > MAX_PACKAGES_LISTED {
+        text.push_str(&format!(" and {} more", total - MAX_PACKAGES_LISTED));
+    }
+    text
+}
+
+/// Returns `true` if the run should be notified about.
+///
+/// Dry runs never are.
+///
+/// # Arguments
+/// * `when` - Which runs trigger a notification
+/// * `report` - The finished run report
+pub fn should_notify(when: NotifyWhen, report: &RunReport) -> bool {
+    if report.dry_run {
+        return false;
+    }
+    match when {
+        NotifyWhen::Failure => !report.success || !report.failed.is_empty(),
+        NotifyWhen::Upgrades => !report.upgraded.is_empty(),
+        NotifyWhen::Always => true,
+    }
+}
+
+/// The document posted to generic JSON webhooks.
+#[derive(Serialize)]
+struct JsonPayload<'a> {
+    event: &'static str,
+    host: &'a str,
+    title: &'a str,
+    text: &'a str,
+    report: &'a RunReport,
+}
+
+/// Builds the payload posted to a webhook.
+///
+/// # Arguments
+/// * `format` - Payload format
+/// * `message` - The notification text
+/// * `report` - The run report (included in generic JSON payloads)
+/// * `host` - Name of the machine the run was on
+///
+/// # Returns
+/// The payload as a JSON string.
+pub fn payload(format: WebhookFormat, message: &Message, report: &RunReport, host: &str) -> String {
+    let value = match format {
+        WebhookFormat::Json => serde_json::to_value(JsonPayload {
+            event: if message.success {
+                "success"
+            } else {
+                "failure"
+            },
+            host,
+            title: &message.title,
+            text: &message.body,
+            report,
+        })
+        .unwrap_or_default(),
+        WebhookFormat::Slack => serde_json::json!({
+            "text": format!("*{}*\n{}", message.title, message.body),
+        }),
+        WebhookFormat::Discord => serde_json::json!({
+            "content": format!("**{}**\n{}", message.title, message.body)
+                .chars()
+                .take(DISCORD_MAX_CHARS)
+                .collect::<String>(),
+        }),
+    };
+    value.to_string()
+}
+
+/// Sends the notifications configured for a finished run.
+///
+/// # Arguments
+/// * `config` - The `[notify]` configuration
+/// * `report` - The finished run report
+/// * `host` - Name of the machine the run was on
+/// * `tool` - Returns a runner for an external program (`curl`, `notify-send`, `osascript`)
+///
+/// # Returns
+/// A description of every channel that could not be notified.
+pub fn send(
+    config: &NotifyConfig,
+    report: &RunReport,
+    host: &str,
+    tool: &dyn Fn(&str) -> Box<dyn BrewRunner>,
+) -> Vec<String> {
+    if !should_notify(config.on, report) {
+        return Vec::new();
+    }
+    let message = Message::from_report(report, host);
+    let mut failures = Vec::new();
+
+    if config.desktop {
+        let (program, args) = desktop_command(&message);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        if let Err(e) = run(tool(program).as_ref(), &args, &CommandInput::default()) {
+            failures.push(format!("desktop notification ({}): {}", program, e));
+        }
+    }
+    for webhook in &config.webhooks {
+        let body = payload(webhook.format, &message, report, host);
+        // The URL is a secret and the body can be large, so both go to curl
+        // as a config on standard input rather than on its command line
+        let input = CommandInput::stdin(&format!(
+            "url = {}\ndata-binary = {}\n",
+            curl_quote(&webhook.url),
+            curl_quote(&body)
+        ));
+        let args = [
+            "--config",
+            "-",
+            "--fail",
+            "--silent",
+            "--show-error",
+            "--max-time",
+            WEBHOOK_TIMEOUT_SECS,
+            "--header",
+            "Content-Type: application/json",
+        ];
+        if let Err(e) = run(tool("curl").as_ref(), &args, &input) {
+            failures.push(format!("webhook {}: {}", redact(&webhook.url), e));
+        }
+    }
+    failures
+}
+
+/// Returns the program and arguments showing a desktop notification.
+fn desktop_command(message: &Message) -> (&'static str, Vec<String>) {
+    if cfg!(target_os = "macos") {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let script = format!(
+            "display notification {} with title {}",
+            quote(&message.body),
+            quote(&message.title)
+        );
+        ("osascript", vec!["-e".to_string(), script])
+    } else {
+        let urgency = if message.success {
+            "normal"
+        } else {
+            "critical"
+        };
+        (
+            "notify-send",
+            vec![
+                "--app-name=BrewUp".to_string(),
+                format!("--urgency={}", urgency),
+                message.title.clone(),
+                message.body.clone(),
+            ],
+        )
+    }
+}
+
+/// Quotes a value for a curl config file.
+fn curl_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Runs a notification program and describes why it failed.
+fn run(tool: &dyn BrewRunner, args: &[&str], input: &CommandInput) -> Result<(), String> {
+    let output = tool.capture_with(args, input).map_err(|e| e.to_string())?;
+    if output.is_success() {
+        return Ok(());
+    }
+    let stderr = output.stderr.trim();
+    Err(match output.code {
+        Some(code) if stderr.is_empty() => format!("exited with code {}", code),
+        Some(code) => format!("exited with code {}: {}", code, stderr),
+        None => "terminated by a signal".to_string(),
+    })
+}
+
+/// Shortens a webhook URL to its scheme and host; the path often holds a secret.
+fn redact(url: &str) -> String {
+    match url.split_once("://") {
+        Some((scheme, rest)) => format!(
+            "{}://{}/{}",
+            scheme,
+            rest.split('/').next().unwrap_or(""),
+            utils::symbol(constants::SYMBOL_ELLIPSIS)
+        ),
+        None => "<invalid URL>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{BrewupError, CommandFailure};
+    use crate::packages::PackageKind;
+    use crate::report::{PackageChange, PackageFailure};
+    use crate::runner::{BrewOutput, ScriptedRunner};
+    use std::rc::Rc;
+
+    fn change(name: &str) -> PackageChange {
+        PackageChange {
+            name: name.to_string(),
+            kind: PackageKind::Formula,
+            from: "1.0".to_string(),
+            to: "2.0".to_string(),
+        }
+    }
+
+    fn failed_report() -> RunReport {
+        let mut report = RunReport::new(false);
+        report.upgraded = vec![change("wget")];
+        report.failed = vec![PackageFailure {
+            change: change("postgresql@14"),
+            exit_code: Some(1),
+            error: "checksum mismatch".to_string(),
+            diagnosis: None,
+        }];
+        report.finish(Some(&BrewupError::UpgradeFailed {
+            packages: vec!["postgresql@14".to_string()],
+            failure: CommandFailure {
+                message: "brew exited with code 1".to_string(),
+                output: None,
+            },
+        }));
+        report
+    }
+
+    /// Tests that the message names upgraded and failed packages and the error.
+    #[test]
+    fn test_message_summarizes_run() {
+        let arrow = utils::symbol(constants::SYMBOL_ARROW);
+        let message = Message::from_report(&failed_report(), "mbp");
+        assert_eq!(message.title, "BrewUp on mbp failed");
+        assert_eq!(
+            message.body,
+            format!(
+                "Upgraded: wget 1.0 {arrow} 2.0\nFailed: postgresql@14\n\
+                 Error: Failed to upgrade postgresql@14: brew exited with code 1"
+            )
+        );
+        assert!(!message.success);
+
+        let mut report = RunReport::new(false);
+        report.upgraded = (0..12).map(|i| change(&format!("p{}", i))).collect();
+        report.finish(None);
+        let message = Message::from_report(&report, "mbp");
+        assert_eq!(message.title, "BrewUp on mbp: upgraded 12 packages");
+        assert!(message
+            .body
+            .ends_with(&format!("p9 1.0 {arrow} 2.0 and 2 more")));
+    }
+
+    /// Tests which runs trigger notifications.
+    #[test]
+    fn test_should_notify() {
+        let failed = failed_report();
+        let mut quiet = RunReport::new(false);
+        quiet.finish(None);
+        let mut dry = failed_report();
+        dry.dry_run = true;
+
+        assert!(should_notify(NotifyWhen::Failure, &failed));
+        assert!(!should_notify(NotifyWhen::Failure, &quiet));
+        assert!(should_notify(NotifyWhen::Upgrades, &failed));
+        assert!(!should_notify(NotifyWhen::Upgrades, &quiet));
+        assert!(should_notify(NotifyWhen::Always, &quiet));
+        assert!(!should_notify(NotifyWhen::Always, &dry));
+    }
+
+    /// Tests the Slack, Discord and generic JSON payloads.
+    #[test]
+    fn test_payloads() {
+        let report = failed_report();
+        let message = Message::from_report(&report, "mbp");
+        let parse = |format| -> serde_json::Value {
+            serde_json::from_str(&payload(format, &message, &report, "mbp")).unwrap()
+        };
+
+        let slack = parse(WebhookFormat::Slack);
+        assert!(slack["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("*BrewUp on mbp failed*\nUpgraded: wget"));
+        let discord = parse(WebhookFormat::Discord);
+        assert!(discord["content"]
+            .as_str()
+            .unwrap()
+            .starts_with("**BrewUp on mbp failed**\n"));
+        let json = parse(WebhookFormat::Json);
+        assert_eq!(json["event"], "failure");
+        assert_eq!(json["host"], "mbp");
+        assert_eq!(json["report"]["exit_code"], 6);
+        assert_eq!(json["report"]["failed"][0]["name"], "postgresql@14");
+    }
+
+    /// Tests that values are quoted for a curl config.
+    #[test]
+    fn test_curl_quote() {
+        assert_eq!(
+            curl_quote("{\"text\":\"a\\nb\"}\t"),
+            "\"{\\\"text\\\":\\\"a\\\\nb\\\"}\\t\""
+        );
+    }
+
+    /// Tests that webhooks are posted with curl and failures are reported.
+    #[test]
+    fn test_send_posts_webhooks() {
+        let report = failed_report();
+        let message = Message::from_report(&report, "mbp");
+        let body = payload(WebhookFormat::Json, &message, &report, "mbp");
+        let args = [
+            "--config",
+            "-",
+            "--fail",
+            "--silent",
+            "--show-error",
+            "--max-time",
+            "10",
+            "--header",
+            "Content-Type: application/json",
+        ];
+        let curl = Rc::new(
+            ScriptedRunner::new()
+                .respond(&args, BrewOutput::success("ok"))
+                .respond(&args, BrewOutput::failure(22, "HTTP 404")),
+        );
+        let config = NotifyConfig {
+            on: NotifyWhen::Failure,
+            desktop: false,
+            webhooks: vec![
+                Webhook {
+                    url: "https://hooks.slack.com/services/T0/B0/secret".to_string(),
+                    format: WebhookFormat::Slack,
+                },
+                Webhook {
+                    url: "https://example.com/hook/secret".to_string(),
+                    format: WebhookFormat::Json,
+                },
+            ],
+        };
+
+        let tool = |program: &str| -> Box<dyn BrewRunner> {
+            assert_eq!(program, "curl");
+            Box::new(Rc::clone(&curl))
+        };
+        let failures = send(&config, &report, "mbp", &tool);
+        assert_eq!(curl.calls().len(), 2);
+        assert!(curl.calls().iter().all(|call| !call.contains("secret")));
+        let config_text = curl.input(&args.join(" ")).unwrap().stdin.unwrap();
+        assert_eq!(
+            config_text,
+            format!(
+                "url = \"https://example.com/hook/secret\"\ndata-binary = {}\n",
+                curl_quote(&body)
+            )
+        );
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].starts_with(&format!(
+            "webhook https://example.com/{}: ",
+            utils::symbol(constants::SYMBOL_ELLIPSIS)
+        )));
+
+        let mut quiet = RunReport::new(false);
+        quiet.finish(None);
+        assert!(send(&config, &quiet, "mbp", &tool).is_empty());
+        assert_eq!(curl.calls().len(), 2);
+    }
+}
@@ -0,0 +1,504 @@
+//! Homebrew package queries and upgrade selection for BrewUp.
+//!
+//! This module parses the output of `brew outdated` and `brew list`, and
+//! decides which outdated packages are upgraded and which are held back.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::CommandFailure;
+use crate::runner::BrewRunner;
+use crate::utils;
+
+/// Kind of Homebrew package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageKind {
+    /// A formula (command-line package)
+    Formula,
+    /// A cask (application)
+    Cask,
+}
+
+impl PackageKind {
+    /// Returns the brew flag that restricts a command to this kind.
+    pub fn flag(self) -> &'static str {
+        match self {
+            PackageKind::Formula => "--formula",
+            PackageKind::Cask => "--cask",
+        }
+    }
+
+    /// Returns the plural noun for this kind, e.g. `formulae`.
+    pub fn plural(self) -> &'static str {
+        match self {
+            PackageKind::Formula => "formulae",
+            PackageKind::Cask => "casks",
+        }
+    }
+
+    /// Returns the noun for a number of packages of this kind.
+    ///
+    /// # Arguments
+    /// * `count` - How many packages are counted
+    ///
+    /// # Returns
+    /// `formula` or `cask` for one package, the plural otherwise.
+    pub fn noun(self, count: usize) -> &'static str {
+        match (self, count) {
+            (PackageKind::Formula, 1) => "formula",
+            (PackageKind::Cask, 1) => "cask",
+            _ => self.plural(),
+        }
+    }
+}
+
+/// Which casks `brew outdated` and `brew upgrade` consider outdated.
+///
+/// Casks that update themselves (`auto_updates true`) or use
+/// `version :latest` are skipped by brew unless asked to be greedy.
+/// The variants are ordered from least to most greedy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GreedyMode {
+    /// Only casks brew upgrades by default
+    #[default]
+    Off,
+    /// Also casks with `auto_updates true` (`--greedy-auto-updates`)
+    AutoUpdates,
+    /// Also `version :latest` casks (`--greedy`)
+    All,
+}
+
+impl GreedyMode {
+    /// Returns the brew flag for this mode, `None` for `Off`.
+    pub fn flag(self) -> Option<&'static str> {
+        match self {
+            GreedyMode::Off => None,
+            GreedyMode::AutoUpdates => Some("--greedy-auto-updates"),
+            GreedyMode::All => Some("--greedy"),
+        }
+    }
+}
+
+/// A package with a newer version available.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutdatedPackage {
+    /// Package name, e.g. `postgresql@14`
+    pub name: String,
+    /// Whether this is a formula or a cask
+    pub kind: PackageKind,
+    /// Currently installed versions
+    pub installed_versions: Vec<String>,
+    /// Version that would be installed by an upgrade
+    pub current_version: String,
+    /// Whether the formula is pinned with `brew pin`
+    pub pinned: bool,
+    /// Least greedy mode in which brew considers this cask outdated
+    pub requires_greedy: GreedyMode,
+}
+
+impl OutdatedPackage {
+    /// Returns the most recent installed version, or an empty string.
+    pub fn installed_version(&self) -> &str {
+        self.installed_versions
+            .last()
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+}
+
+/// An installed package as listed by `brew list --versions`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstalledPackage {
+    /// Package name
+    pub name: String,
+    /// Whether this is a formula or a cask
+    pub kind: PackageKind,
+    /// Installed versions
+    pub versions: Vec<String>,
+}
+
+impl InstalledPackage {
+    /// Returns the most recent installed version, or an empty string.
+    pub fn version(&self) -> &str {
+        self.versions.last().map(String::as_str).unwrap_or_default()
+    }
+}
+
+/// An outdated package that will not be upgraded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeldPackage {
+    /// The package that is held back
+    pub package: OutdatedPackage,
+    /// Human-readable reason, e.g. "matches exclude pattern `node`"
+    pub reason: String,
+    /// Whether it is only held back because it is a cask needing a
+    /// greedier mode, which brew itself skips too
+    pub greedy_only: bool,
+}
+
+/// Queries Homebrew for outdated formulae and casks.
+///
+/// Casks that only a greedy query lists are added too, marked with the
+/// mode they require, so that the caller can upgrade the ones configured
+/// as greedy and mention the rest. Telling auto-updating casks apart from
+/// `version :latest` ones takes an extra query, made only when
+/// `auto_updates` is set. The greedy queries are best-effort: if one fails,
+/// a warning is shown and the casks it would have added are left out.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+/// * `auto_updates` - Whether to classify casks needing `--greedy-auto-updates`
+///
+/// # Returns
+/// The outdated packages, or the failure if the plain query failed.
+pub fn outdated_packages(
+    runner: &dyn BrewRunner,
+    auto_updates: bool,
+) -> Result<Vec<OutdatedPackage>, CommandFailure> {
+    let mut packages = query_outdated(runner, GreedyMode::Off)?;
+    let modes: &[GreedyMode] = if auto_updates {
+        &[GreedyMode::AutoUpdates, GreedyMode::All]
+    } else {
+        &[GreedyMode::All]
+    };
+    for &mode in modes {
+        let greedy = match query_outdated(runner, mode) {
+            Ok(greedy) => greedy,
+            Err(e) => {
+                utils::show_warning(&format!(
+                    "Could not list casks that need a greedy upgrade: {}",
+                    e
+                ));
+                break;
+            }
+        };
+        for package in greedy {
+            let known = packages
+                .iter()
+                .any(|p| p.kind == package.kind && p.name == package.name);
+            if package.kind == PackageKind::Cask && !known {
+                packages.push(OutdatedPackage {
+                    requires_greedy: mode,
+                    ..package
+                });
+            }
+        }
+    }
+    Ok(packages)
+}
+
+/// Runs one `brew outdated --json=v2` query.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+/// * `greedy` - Which casks the query includes
+///
+/// # Returns
+/// The outdated packages, or the failure if the query failed.
+fn query_outdated(
+    runner: &dyn BrewRunner,
+    greedy: GreedyMode,
+) -> Result<Vec<OutdatedPackage>, CommandFailure> {
+    let mut command = vec!["outdated", "--json=v2"];
+    command.extend(greedy.flag());
+    let output = runner.capture(&command).map_err(|e| CommandFailure {
+        message: format!("Failed to execute command: {}", e),
+        output: None,
+    })?;
+    if !output.is_success() {
+        return Err(CommandFailure {
+            message: output.stderr.trim().to_string(),
+            output: Some(output),
+        });
+    }
+    parse_outdated(&output.stdout).map_err(|message| CommandFailure {
+        message,
+        output: Some(output),
+    })
+}
+
+/// Parses the output of `brew outdated --json=v2`.
+///
+/// # Arguments
+/// * `json` - The JSON document printed by brew
+///
+/// # Returns
+/// The outdated formulae followed by the outdated casks.
+pub fn parse_outdated(json: &str) -> Result<Vec<OutdatedPackage>, String> {
+    let root: Value =
+        serde_json::from_str(json).map_err(|e| format!("Invalid brew outdated output: {}", e))?;
+
+    let mut packages = Vec::new();
+    for (key, kind) in [
+        ("formulae", PackageKind::Formula),
+        ("casks", PackageKind::Cask),
+    ] {
+        for entry in root[key].as_array().into_iter().flatten() {
+            let Some(name) = entry["name"].as_str() else {
+                continue;
+            };
+            // Older brew versions report a single string for casks
+            let installed_versions = match &entry["installed_versions"] {
+                Value::Array(versions) => versions
+                    .iter()
+                    .filter_map(|v| v.as_str().map(String::from))
+                    .collect(),
+                Value::String(version) => vec![version.clone()],
+                _ => Vec::new(),
+            };
+            packages.push(OutdatedPackage {
+                name: name.to_string(),
+                kind,
+                installed_versions,
+                current_version: entry["current_version"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                pinned: entry["pinned"].as_bool().unwrap_or(false),
+                requires_greedy: GreedyMode::Off,
+            });
+        }
+    }
+    Ok(packages)
+}
+
+/// Lists the installed formulae and casks with `brew list --versions`.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+///
+/// # Returns
+/// The installed formulae followed by the installed casks, or the failure
+/// if a listing failed.
+pub fn installed_packages(
+    runner: &dyn BrewRunner,
+) -> Result<Vec<InstalledPackage>, CommandFailure> {
+    let mut packages = Vec::new();
+    for kind in [PackageKind::Formula, PackageKind::Cask] {
+        let output = runner
+            .capture(&["list", kind.flag(), "--versions"])
+            .map_err(|e| CommandFailure {
+                message: format!("Failed to execute command: {}", e),
+                output: None,
+            })?;
+        if !output.is_success() {
+            return Err(CommandFailure {
+                message: output.stderr.trim().to_string(),
+                output: Some(output),
+            });
+        }
+        packages.extend(parse_installed(&output.stdout, kind));
+    }
+    Ok(packages)
+}
+
+/// Parses the output of `brew list --formula --versions` or `--cask --versions`.
+///
+/// # Arguments
+/// * `stdout` - One package per line: the name followed by its versions
+/// * `kind` - The kind of package that was listed
+///
+/// # Returns
+/// The installed packages in listing order.
+pub fn parse_installed(stdout: &str, kind: PackageKind) -> Vec<InstalledPackage> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?;
+            Some(InstalledPackage {
+                name: name.to_string(),
+                kind,
+                versions: fields.map(String::from).collect(),
+            })
+        })
+        .collect()
+}
+
+/// Splits outdated packages into those to upgrade and those held back.
+///
+/// A package is held back when it is pinned with `brew pin`, its name
+/// matches one of the exclude patterns (`*` and `?` wildcards allowed), or
+/// it is a cask that needs a greedier mode than configured for it.
+///
+/// # Arguments
+/// * `outdated` - The outdated packages
+/// * `exclude` - Exclude patterns from the configuration
+/// * `greedy` - Returns the greedy mode configured for a cask
+///
+/// # Returns
+/// A tuple of (packages to upgrade, held packages).
+pub fn select_upgrades(
+    outdated: Vec<OutdatedPackage>,
+    exclude: &[String],
+    greedy: impl Fn(&str) -> GreedyMode,
+) -> (Vec<OutdatedPackage>, Vec<HeldPackage>) {
+    let mut upgrades = Vec::new();
+    let mut held = Vec::new();
+
+    for package in outdated {
+        let greedy_only = !package.pinned
+            && !exclude
+                .iter()
+                .any(|pattern| utils::glob_match(pattern, &package.name))
+            && package.requires_greedy > greedy(&package.name);
+        let reason = if package.pinned {
+            Some("pinned with `brew pin`".to_string())
+        } else if let Some(pattern) = exclude
+            .iter()
+            .find(|pattern| utils::glob_match(pattern, &package.name))
+        {
+            Some(format!("matches exclude pattern `{}`", pattern))
+        } else if greedy_only {
+            Some(
+                match package.requires_greedy {
+                    GreedyMode::AutoUpdates => "auto-updating cask, needs greedy `auto_updates`",
+                    _ => "auto-updating or `version :latest` cask, needs greedy `all`",
+                }
+                .to_string(),
+            )
+        } else {
+            None
+        };
+
+        match reason {
+            Some(reason) => held.push(HeldPackage {
+                package,
+                reason,
+                greedy_only,
+            }),
+            None => upgrades.push(package),
+        }
+    }
+
+    (upgrades, held)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{BrewOutput, ScriptedRunner};
+
+    const OUTDATED: &str = r#"{
+        "formulae": [
+            {"name": "node", "installed_versions": ["21.7.1"], "current_version": "22.2.0", "pinned": false, "pinned_version": null},
+            {"name": "postgresql@14", "installed_versions": ["14.10"], "current_version": "14.12", "pinned": false, "pinned_version": null},
+            {"name": "python@3.12", "installed_versions": ["3.12.2"], "current_version": "3.12.3", "pinned": false, "pinned_version": null},
+            {"name": "openssl@3", "installed_versions": ["3.2.1"], "current_version": "3.3.0", "pinned": true, "pinned_version": "3.2.1"}
+        ],
+        "casks": [
+            {"name": "firefox", "installed_versions": "125.0", "current_version": "126.0"}
+        ]
+    }"#;
+
+    /// Tests that a count of one takes the singular noun.
+    #[test]
+    fn test_noun() {
+        assert_eq!(PackageKind::Formula.noun(1), "formula");
+        assert_eq!(PackageKind::Formula.noun(0), "formulae");
+        assert_eq!(PackageKind::Cask.noun(1), "cask");
+        assert_eq!(PackageKind::Cask.noun(2), "casks");
+    }
+
+    /// Tests parsing of formulae and casks, including legacy cask versions.
+    #[test]
+    fn test_parse_outdated() {
+        let packages = parse_outdated(OUTDATED).unwrap();
+        assert_eq!(packages.len(), 5);
+        assert_eq!(packages[1].installed_version(), "14.10");
+        assert!(packages[3].pinned);
+        assert_eq!(packages[4].kind, PackageKind::Cask);
+        assert_eq!(packages[4].installed_versions, vec!["125.0"]);
+    }
+
+    /// Tests parsing of `brew list --versions` output.
+    #[test]
+    fn test_parse_installed() {
+        let installed = parse_installed(
+            "git 2.45.1\npython@3.12 3.12.2 3.12.3\n\n",
+            PackageKind::Formula,
+        );
+        assert_eq!(installed.len(), 2);
+        assert_eq!(installed[0].kind, PackageKind::Formula);
+        assert_eq!(installed[1].name, "python@3.12");
+        assert_eq!(installed[1].versions, ["3.12.2", "3.12.3"]);
+    }
+
+    /// Tests that excluded and pinned packages are held back with a reason.
+    #[test]
+    fn test_select_upgrades() {
+        let exclude = vec!["node".to_string(), "postgresql@*".to_string()];
+        let (upgrades, held) = select_upgrades(parse_outdated(OUTDATED).unwrap(), &exclude, |_| {
+            GreedyMode::Off
+        });
+
+        let names: Vec<_> = upgrades.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["python@3.12", "firefox"]);
+        assert_eq!(held.len(), 3);
+        assert_eq!(held[1].reason, "matches exclude pattern `postgresql@*`");
+        assert_eq!(held[2].reason, "pinned with `brew pin`");
+        assert!(held.iter().all(|h| !h.greedy_only));
+    }
+
+    /// Tests that casks only listed by greedy queries are classified and held back.
+    #[test]
+    fn test_greedy_casks() {
+        let casks = |names: &[&str]| {
+            let entries: Vec<String> = names
+                .iter()
+                .map(|n| {
+                    format!(
+                        r#"{{"name": "{}", "installed_versions": ["1"], "current_version": "2"}}"#,
+                        n
+                    )
+                })
+                .collect();
+            BrewOutput::success(&format!(
+                r#"{{"formulae": [], "casks": [{}]}}"#,
+                entries.join(",")
+            ))
+        };
+        let runner = ScriptedRunner::new()
+            .respond(&["outdated", "--json=v2"], casks(&["iterm2"]))
+            .respond(
+                &["outdated", "--json=v2", "--greedy-auto-updates"],
+                casks(&["iterm2", "firefox"]),
+            )
+            .respond(
+                &["outdated", "--json=v2", "--greedy"],
+                casks(&["iterm2", "firefox", "docker"]),
+            );
+        let outdated = outdated_packages(&runner, true).unwrap();
+        let modes: Vec<_> = outdated
+            .iter()
+            .map(|p| (p.name.as_str(), p.requires_greedy))
+            .collect();
+        assert_eq!(
+            modes,
+            [
+                ("iterm2", GreedyMode::Off),
+                ("firefox", GreedyMode::AutoUpdates),
+                ("docker", GreedyMode::All)
+            ]
+        );
+
+        let (upgrades, held) = select_upgrades(outdated, &[], |_| GreedyMode::AutoUpdates);
+        let names: Vec<_> = upgrades.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["iterm2", "firefox"]);
+        assert_eq!(held[0].package.name, "docker");
+        assert!(held[0].reason.contains("greedy `all`"));
+        assert!(held[0].greedy_only);
+
+        // A failing greedy query only loses the casks it would have added
+        let runner = ScriptedRunner::new()
+            .respond(&["outdated", "--json=v2"], casks(&["iterm2"]))
+            .respond(
+                &["outdated", "--json=v2", "--greedy"],
+                BrewOutput::failure(1, "Error: API unavailable"),
+            );
+        let outdated = outdated_packages(&runner, false).unwrap();
+        assert_eq!(outdated.len(), 1);
+    }
+}
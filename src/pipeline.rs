@@ -0,0 +1,688 @@
+//! Step pipeline engine for BrewUp.
+//!
+//! A run is a sequence of [`Step`]s executed in order by a [`Pipeline`].
+//! Each step declares its name, when it can run, what a failure means for
+//! the rest of the run and how it behaves in dry-run mode. The pipeline
+//! applies the `[pipeline]` configuration and the `--only` / `--skip` flags,
+//! and records every step into the run report. User hooks (see
+//! [`crate::hooks`]) run around the steps and at the end of the run.
+
+use colored::*;
+use serde::Deserialize;
+use std::time::Instant;
+
+use crate::{
+    cli::CliArgs,
+    config::{constants, Config, ConfigError},
+    error::BrewupError,
+    hooks,
+    report::{RunReport, StepStatus},
+    runner::{BrewOutput, BrewRunner},
+    steps,
+    utils::{self, say},
+};
+
+/// What a step failure means for the rest of the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Stop the run immediately and fail with the step's error
+    Abort,
+    /// Show a warning and carry on; the run still succeeds
+    Warn,
+    /// Carry on with the remaining steps, then fail the run with the step's error
+    Continue,
+}
+
+/// How a step behaves when BrewUp runs with `--dry-run`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DryRunBehavior {
+    /// The step runs and previews its changes itself
+    Preview,
+    /// The step only reads state and runs as usual
+    Run,
+    /// The step is not run at all
+    Skip,
+}
+
+/// Result of a step that completed.
+#[derive(Debug, Clone)]
+pub struct StepOutcome {
+    /// How the step ended
+    pub status: StepStatus,
+    /// Output of the brew command the step ran, if any
+    pub output: Option<BrewOutput>,
+}
+
+impl StepOutcome {
+    /// Creates an outcome.
+    ///
+    /// # Arguments
+    /// * `status` - How the step ended
+    /// * `output` - Output of the brew command the step ran, if any
+    pub fn new(status: StepStatus, output: Option<BrewOutput>) -> Self {
+        Self { status, output }
+    }
+}
+
+/// Everything a step needs while it runs.
+pub struct StepContext<'a> {
+    /// The parsed command-line arguments
+    pub args: &'a CliArgs,
+    /// Application configuration
+    pub config: &'a Config,
+    /// The backend used to invoke brew
+    pub runner: &'a dyn BrewRunner,
+    /// The backend used to start user hooks (through `env`)
+    pub hook_runner: &'a dyn BrewRunner,
+    /// The report that collects the results of the run
+    pub report: &'a mut RunReport,
+}
+
+/// One stage of a BrewUp run.
+pub trait Step {
+    /// Unique name used on the command line, in the configuration and in reports.
+    fn name(&self) -> &'static str;
+
+    /// One-line description shown in `--help`.
+    fn description(&self) -> &'static str;
+
+    /// Checks whether the step can run.
+    ///
+    /// # Returns
+    /// `Ok(())` to run the step, `Err(reason)` to skip it.
+    fn precondition(&self, _ctx: &StepContext<'_>) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Failure policy used unless `[pipeline.on_failure]` overrides it.
+    fn default_policy(&self, _ctx: &StepContext<'_>) -> FailurePolicy {
+        FailurePolicy::Abort
+    }
+
+    /// How the step behaves in dry-run mode.
+    fn dry_run_behavior(&self) -> DryRunBehavior {
+        DryRunBehavior::Preview
+    }
+
+    /// Runs the step.
+    ///
+    /// # Returns
+    /// The outcome on success, the error the failure policy is applied to otherwise
+    fn run(&self, ctx: &mut StepContext<'_>) -> Result<StepOutcome, BrewupError>;
+}
+
+/// A step as scheduled for one run.
+struct Scheduled {
+    step: Box<dyn Step>,
+    policy: Option<FailurePolicy>,
+    skip_reason: Option<String>,
+}
+
+/// The ordered steps of one run.
+pub struct Pipeline {
+    steps: Vec<Scheduled>,
+}
+
+impl Pipeline {
+    /// Builds the pipeline from the registered steps.
+    ///
+    /// # Arguments
+    /// * `config` - Application configuration (`[pipeline]` table)
+    /// * `args` - The parsed command-line arguments (`--only`, `--skip`)
+    ///
+    /// # Returns
+    /// The pipeline, or a `ConfigError` if the configuration names an unknown step.
+    pub fn new(config: &Config, args: &CliArgs) -> Result<Self, ConfigError> {
+        Self::with_steps(steps::registry(), config, args)
+    }
+
+    /// Builds the pipeline from an explicit set of available steps.
+    ///
+    /// `pipeline.steps` selects and orders the steps; without it every
+    /// available step runs in the given order. Steps filtered out by
+    /// `--only` or `--skip` stay in the pipeline so the report shows them
+    /// as skipped.
+    ///
+    /// # Arguments
+    /// * `available` - The steps that can be scheduled
+    /// * `config` - Application configuration (`[pipeline]` table)
+    /// * `args` - The parsed command-line arguments (`--only`, `--skip`)
+    ///
+    /// # Returns
+    /// The pipeline, or a `ConfigError` if the configuration names an unknown
+    /// step or hook.
+    pub fn with_steps(
+        available: Vec<Box<dyn Step>>,
+        config: &Config,
+        args: &CliArgs,
+    ) -> Result<Self, ConfigError> {
+        let known: Vec<&str> = available.iter().map(|s| s.name()).collect();
+        let unknown = |origin: &str, name: &str| ConfigError {
+            origin: origin.to_string(),
+            message: format!(
+                "unknown step `{}` (expected one of: {})",
+                name,
+                known.join(", ")
+            ),
+        };
+        for name in config.pipeline.on_failure.keys() {
+            if !known.contains(&name.as_str()) {
+                return Err(unknown("pipeline.on_failure", name));
+            }
+        }
+        for name in config.retry.steps.keys() {
+            if !known.contains(&name.as_str()) {
+                return Err(unknown("retry", name));
+            }
+        }
+        hooks::validate(&config.hooks, &known)?;
+
+        let order: Vec<String> = match &config.pipeline.steps {
+            Some(names) => names.clone(),
+            None => known.iter().map(|n| n.to_string()).collect(),
+        };
+        let mut available: Vec<Option<Box<dyn Step>>> = available.into_iter().map(Some).collect();
+        let mut steps = Vec::new();
+        for name in &order {
+            let index = known
+                .iter()
+                .position(|n| n == name)
+                .ok_or_else(|| unknown("pipeline.steps", name))?;
+            let step = available[index].take().ok_or_else(|| ConfigError {
+                origin: "pipeline.steps".to_string(),
+                message: format!("step `{}` is listed more than once", name),
+            })?;
+
+            let skip_reason = if !args.only.is_empty() && !args.only.contains(name) {
+                Some("not selected by --only".to_string())
+            } else if args.skip.contains(name) {
+                Some(format!("--skip {}", name))
+            } else if args.skip_cleanup && name == "cleanup" {
+                Some("--skip-cleanup".to_string())
+            } else {
+                None
+            };
+            steps.push(Scheduled {
+                policy: config.pipeline.on_failure.get(name).copied(),
+                step,
+                skip_reason,
+            });
+        }
+        Ok(Self { steps })
+    }
+
+    /// Returns the names of the scheduled steps in execution order.
+    pub fn step_names(&self) -> Vec<&'static str> {
+        self.steps.iter().map(|s| s.step.name()).collect()
+    }
+
+    /// Runs every scheduled step in order and records it in the report.
+    ///
+    /// A failing step is handled according to its failure policy, a failing
+    /// hook according to `hooks.failure_policy`. An interrupted command always
+    /// aborts, whatever the policy. The `on_failure` and `post_run` hooks run
+    /// however the steps ended.
+    ///
+    /// # Arguments
+    /// * `ctx` - The run context shared by all steps
+    ///
+    /// # Returns
+    /// `Ok(())` if nothing failed the run, otherwise the first aborting or
+    /// continuing step's or hook's error
+    pub fn run(&self, ctx: &mut StepContext<'_>) -> Result<(), BrewupError> {
+        let result = self.run_steps(ctx);
+        finish(ctx, result)
+    }
+
+    /// Runs the steps with their hooks, without the final hooks.
+    fn run_steps(&self, ctx: &mut StepContext<'_>) -> Result<(), BrewupError> {
+        let mut deferred = None;
+        run_hook(ctx, hooks::PRE_RUN, &[], &mut deferred)?;
+        for scheduled in &self.steps {
+            let step = scheduled.step.as_ref();
+            let started = Instant::now();
+
+            let skip_reason = scheduled
+                .skip_reason
+                .clone()
+                .or_else(|| step.precondition(ctx).err())
+                .or_else(|| {
+                    (ctx.args.dry_run && step.dry_run_behavior() == DryRunBehavior::Skip)
+                        .then(|| "not run in dry-run mode".to_string())
+                });
+            if let Some(reason) = skip_reason {
+                utils::show_info(
+                    constants::EMOJI_SKIP,
+                    &format!("Skipping {} step ({})", step.name(), reason),
+                    colored::Color::Yellow,
+                );
+                ctx.report
+                    .record_step(step.name(), started, StepStatus::Skipped, None);
+                continue;
+            }
+
+            let step_env = [("BREWUP_STEP", step.name().to_string())];
+            run_hook(
+                ctx,
+                &format!("pre_{}", step.name()),
+                &step_env,
+                &mut deferred,
+            )?;
+
+            let started = Instant::now();
+            let status = match step.run(ctx) {
+                Ok(outcome) => {
+                    ctx.report.record_step(
+                        step.name(),
+                        started,
+                        outcome.status,
+                        outcome.output.as_ref(),
+                    );
+                    outcome.status
+                }
+                Err(error) => {
+                    let policy = if error == BrewupError::Interrupted {
+                        FailurePolicy::Abort
+                    } else {
+                        scheduled.policy.unwrap_or_else(|| step.default_policy(ctx))
+                    };
+                    match policy {
+                        FailurePolicy::Abort => {
+                            ctx.report.record_step(
+                                step.name(),
+                                started,
+                                StepStatus::Failed,
+                                error.output(),
+                            );
+                            return Err(error);
+                        }
+                        FailurePolicy::Warn => {
+                            utils::show_warning(&error.to_string());
+                            if let Some(diagnosis) = error.diagnosis() {
+                                utils::show_diagnosis(&diagnosis);
+                            }
+                            ctx.report.record_step(
+                                step.name(),
+                                started,
+                                StepStatus::Warning,
+                                error.output(),
+                            );
+                            StepStatus::Warning
+                        }
+                        FailurePolicy::Continue => {
+                            utils::show_warning(&format!(
+                                "{}; continuing with the remaining steps",
+                                error
+                            ));
+                            if let Some(diagnosis) = error.diagnosis() {
+                                utils::show_diagnosis(&diagnosis);
+                            }
+                            ctx.report.record_step(
+                                step.name(),
+                                started,
+                                StepStatus::Failed,
+                                error.output(),
+                            );
+                            deferred.get_or_insert(error);
+                            StepStatus::Failed
+                        }
+                    }
+                }
+            };
+
+            if ctx.args.verbosity.is_verbose() {
+                let elapsed = utils::format_duration(started.elapsed().as_millis() as u64);
+                say!(
+                    "{}",
+                    format!("   {} step finished in {}", step.name(), elapsed).dimmed()
+                );
+            }
+
+            let step_env = [
+                ("BREWUP_STEP", step.name().to_string()),
+                ("BREWUP_STEP_STATUS", status.name().to_string()),
+            ];
+            run_hook(
+                ctx,
+                &format!("post_{}", step.name()),
+                &step_env,
+                &mut deferred,
+            )?;
+        }
+        deferred.map_or(Ok(()), Err)
+    }
+}
+
+/// Runs a hook between steps and applies the hook failure policy.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `name` - Hook name
+/// * `env` - Hook-specific variables
+/// * `deferred` - The error the run fails with at the end, for `continue`
+///
+/// # Returns
+/// The deferred error, or else the hook's error, if the hook aborts the run;
+/// `Ok(())` otherwise
+fn run_hook(
+    ctx: &mut StepContext<'_>,
+    name: &str,
+    env: &[(&str, String)],
+    deferred: &mut Option<BrewupError>,
+) -> Result<(), BrewupError> {
+    let Err(error) = hooks::run(ctx, name, env, None) else {
+        return Ok(());
+    };
+    match hooks::failure_policy(&ctx.config.hooks, &error) {
+        // A step or hook that failed earlier stays the cause of the run's failure
+        FailurePolicy::Abort => match deferred.take() {
+            Some(first) => {
+                utils::show_warning(&error.to_string());
+                return Err(first);
+            }
+            None => return Err(error),
+        },
+        FailurePolicy::Warn => utils::show_warning(&error.to_string()),
+        FailurePolicy::Continue => {
+            utils::show_warning(&format!("{}; continuing with the remaining steps", error));
+            deferred.get_or_insert(error);
+        }
+    }
+    Ok(())
+}
+
+/// Runs the `on_failure` and `post_run` hooks once the steps are done.
+///
+/// The hooks see the run as it ended. A failing final hook only fails a run
+/// that otherwise succeeded, unless the hook failure policy is `warn`.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `result` - How the steps ended
+///
+/// # Returns
+/// The result of the run
+fn finish(ctx: &mut StepContext<'_>, result: Result<(), BrewupError>) -> Result<(), BrewupError> {
+    let mut context = ctx.report.clone();
+    context.finish(result.as_ref().err());
+    let env = [
+        (
+            "BREWUP_SUCCESS",
+            if context.success { "1" } else { "0" }.to_string(),
+        ),
+        ("BREWUP_EXIT_CODE", context.exit_code.to_string()),
+        ("BREWUP_ERROR", context.error.clone().unwrap_or_default()),
+    ];
+
+    let mut hook_result = Ok(());
+    if result.is_err() {
+        hook_result = hooks::run(ctx, hooks::ON_FAILURE, &env, Some(&context));
+    }
+    let post_run = hooks::run(ctx, hooks::POST_RUN, &env, Some(&context));
+    let hook_result = hook_result.and(post_run);
+
+    match (result, hook_result) {
+        (Err(error), Err(hook_error)) => {
+            utils::show_warning(&hook_error.to_string());
+            Err(error)
+        }
+        (Ok(()), Err(hook_error))
+            if hooks::failure_policy(&ctx.config.hooks, &hook_error) == FailurePolicy::Warn =>
+        {
+            utils::show_warning(&hook_error.to_string());
+            Ok(())
+        }
+        (result, hook_result) => result.and(hook_result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CommandFailure;
+    use crate::runner::ScriptedRunner;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A step that logs its name and fails with `UpdateFailed` if told to.
+    struct FakeStep {
+        name: &'static str,
+        fails: bool,
+        log: Rc<RefCell<Vec<&'static str>>>,
+    }
+
+    impl Step for FakeStep {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn description(&self) -> &'static str {
+            "fake"
+        }
+
+        fn run(&self, _ctx: &mut StepContext<'_>) -> Result<StepOutcome, BrewupError> {
+            self.log.borrow_mut().push(self.name);
+            if self.fails {
+                return Err(BrewupError::UpdateFailed(CommandFailure {
+                    message: format!("{} failed", self.name),
+                    output: Some(BrewOutput::failure(1, "boom")),
+                }));
+            }
+            Ok(StepOutcome::new(StepStatus::Ok, None))
+        }
+    }
+
+    fn fake_steps(failing: &str, log: &Rc<RefCell<Vec<&'static str>>>) -> Vec<Box<dyn Step>> {
+        ["one", "two", "three"]
+            .into_iter()
+            .map(|name| {
+                Box::new(FakeStep {
+                    name,
+                    fails: name == failing,
+                    log: Rc::clone(log),
+                }) as Box<dyn Step>
+            })
+            .collect()
+    }
+
+    fn run_pipeline(
+        config: &Config,
+        args: &CliArgs,
+        failing: &str,
+    ) -> (Result<(), BrewupError>, Vec<&'static str>, RunReport) {
+        run_with_hooks(config, args, failing, &ScriptedRunner::new())
+    }
+
+    fn run_with_hooks(
+        config: &Config,
+        args: &CliArgs,
+        failing: &str,
+        hook_runner: &ScriptedRunner,
+    ) -> (Result<(), BrewupError>, Vec<&'static str>, RunReport) {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let pipeline = Pipeline::with_steps(fake_steps(failing, &log), config, args).unwrap();
+        let mut report = RunReport::new(args.dry_run);
+        let result = pipeline.run(&mut StepContext {
+            args,
+            config,
+            runner: &ScriptedRunner::new(),
+            hook_runner,
+            report: &mut report,
+        });
+        let log = log.borrow().clone();
+        (result, log, report)
+    }
+
+    fn statuses(report: &RunReport) -> Vec<StepStatus> {
+        report.steps.iter().map(|s| s.status).collect()
+    }
+
+    /// Tests the abort, warn and continue failure policies.
+    #[test]
+    fn test_failure_policies() {
+        let args = CliArgs::default();
+        let mut config = Config::new();
+
+        let (result, log, report) = run_pipeline(&config, &args, "two");
+        assert!(matches!(result, Err(BrewupError::UpdateFailed(_))));
+        assert_eq!(log, ["one", "two"]);
+        assert_eq!(report.steps[1].exit_code, Some(1));
+
+        config
+            .pipeline
+            .on_failure
+            .insert("two".to_string(), FailurePolicy::Warn);
+        let (result, log, report) = run_pipeline(&config, &args, "two");
+        result.unwrap();
+        assert_eq!(log, ["one", "two", "three"]);
+        assert_eq!(
+            statuses(&report),
+            [StepStatus::Ok, StepStatus::Warning, StepStatus::Ok]
+        );
+
+        config
+            .pipeline
+            .on_failure
+            .insert("two".to_string(), FailurePolicy::Continue);
+        let (result, log, report) = run_pipeline(&config, &args, "two");
+        assert!(matches!(result, Err(BrewupError::UpdateFailed(_))));
+        assert_eq!(log, ["one", "two", "three"]);
+        assert_eq!(
+            statuses(&report),
+            [StepStatus::Ok, StepStatus::Failed, StepStatus::Ok]
+        );
+    }
+
+    /// Tests that hooks run around the steps and follow the hook failure policy.
+    #[test]
+    fn test_hooks() {
+        let args = CliArgs::default();
+        let mut config = Config::new();
+        for (hook, command) in [("pre_two", "prepare"), ("post_run", "report")] {
+            config
+                .hooks
+                .commands
+                .insert(hook.to_string(), command.to_string());
+        }
+        let hook_names = |runner: &ScriptedRunner| -> Vec<String> {
+            runner
+                .calls()
+                .iter()
+                .map(|call| call.split(' ').next().unwrap().to_string())
+                .collect()
+        };
+
+        // The scripted runner fails every hook: `abort` stops before the
+        // step, but the final hooks still run
+        let runner = ScriptedRunner::new();
+        let (result, log, report) = run_with_hooks(&config, &args, "", &runner);
+        match result {
+            Err(BrewupError::HookFailed { hook, .. }) => assert_eq!(hook, "pre_two"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(log, ["one"]);
+        assert_eq!(
+            hook_names(&runner),
+            ["BREWUP_HOOK=pre_two", "BREWUP_HOOK=post_run"]
+        );
+        assert!(runner.calls()[1].contains("BREWUP_SUCCESS=0"));
+        assert!(runner.calls()[1].ends_with("sh -c report"));
+        assert_eq!(report.hooks.len(), 2);
+
+        config.hooks.failure_policy = FailurePolicy::Warn;
+        let runner = ScriptedRunner::new();
+        let (result, log, report) = run_with_hooks(&config, &args, "", &runner);
+        result.unwrap();
+        assert_eq!(log, ["one", "two", "three"]);
+        assert!(runner.calls()[1].contains("BREWUP_SUCCESS=1"));
+        assert_eq!(report.hooks[0].status, StepStatus::Failed);
+
+        // Unknown hooks are configuration errors
+        config
+            .hooks
+            .commands
+            .insert("pre_four".to_string(), "x".to_string());
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let err = Pipeline::with_steps(fake_steps("", &log), &config, &args)
+            .err()
+            .unwrap();
+        assert_eq!(err.origin, "hooks");
+    }
+
+    /// Tests that a hook aborting the run after a continued step failure
+    /// keeps the step's error.
+    #[test]
+    fn test_hook_abort_keeps_continued_failure() {
+        let args = CliArgs::default();
+        let mut config = Config::new();
+        config
+            .pipeline
+            .on_failure
+            .insert("two".to_string(), FailurePolicy::Continue);
+        config
+            .hooks
+            .commands
+            .insert("post_two".to_string(), "notify".to_string());
+
+        // The scripted runner fails the hook, whose policy is `abort`
+        let (result, log, report) = run_with_hooks(&config, &args, "two", &ScriptedRunner::new());
+        match result {
+            Err(BrewupError::UpdateFailed(failure)) => assert_eq!(failure.message, "two failed"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(log, ["one", "two"]);
+        assert_eq!(report.hooks[0].status, StepStatus::Failed);
+    }
+
+    /// Tests that `--only`, `--skip` and `pipeline.steps` select and order steps.
+    #[test]
+    fn test_step_selection() {
+        let mut config = Config::new();
+        let only = CliArgs {
+            only: vec!["three".to_string(), "one".to_string()],
+            ..Default::default()
+        };
+        let (_, log, report) = run_pipeline(&config, &only, "");
+        assert_eq!(log, ["one", "three"]);
+        assert_eq!(report.steps[1].status, StepStatus::Skipped);
+
+        config.pipeline.steps = Some(vec!["three".to_string(), "two".to_string()]);
+        let skip = CliArgs {
+            skip: vec!["two".to_string()],
+            ..Default::default()
+        };
+        let (_, log, report) = run_pipeline(&config, &skip, "");
+        assert_eq!(log, ["three"]);
+        assert_eq!(report.steps.len(), 2);
+    }
+
+    /// Tests that the configuration may only name registered steps.
+    #[test]
+    fn test_unknown_steps_are_rejected() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut config = Config::new();
+        config.pipeline.steps = Some(vec!["one".to_string(), "four".to_string()]);
+        let err = Pipeline::with_steps(fake_steps("", &log), &config, &CliArgs::default())
+            .err()
+            .unwrap();
+        assert_eq!(err.origin, "pipeline.steps");
+        assert!(err.message.contains("`four`"), "{}", err.message);
+
+        config.pipeline.steps = Some(vec!["one".to_string(), "one".to_string()]);
+        let err = Pipeline::with_steps(fake_steps("", &log), &config, &CliArgs::default())
+            .err()
+            .unwrap();
+        assert!(err.message.contains("more than once"), "{}", err.message);
+    }
+
+    /// Tests the registered steps and their default order.
+    #[test]
+    fn test_registered_steps() {
+        let pipeline = Pipeline::new(&Config::new(), &CliArgs::default()).unwrap();
+        assert_eq!(
+            pipeline.step_names(),
+            ["update", "upgrade", "cleanup", "summary"]
+        );
+    }
+}
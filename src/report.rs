@@ -0,0 +1,499 @@
+//! Machine-readable run report for BrewUp.
+//!
+//! Every run records what each step did into a [`RunReport`], which is
+//! printed as a single JSON document with `--output json`.
+
+use serde::{Deserialize, Serialize};
+use std::time::{Instant, SystemTime};
+
+use crate::caveats::Caveat;
+use crate::changes::VersionChange;
+use crate::cleanup::{self, CacheEntry};
+use crate::diagnosis::{self, Diagnosis};
+use crate::error::BrewupError;
+use crate::packages::{HeldPackage, InstalledPackage, OutdatedPackage, PackageKind};
+use crate::runner::BrewOutput;
+use crate::utils;
+
+/// Maximum number of stderr lines kept per step.
+const STDERR_EXCERPT_LINES: usize = 20;
+
+/// Outcome of a single workflow step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    /// The step completed successfully
+    Ok,
+    /// The step failed but the run continued
+    Warning,
+    /// The step failed and aborted the run
+    Failed,
+    /// The step was skipped by request
+    Skipped,
+    /// The step was only previewed
+    DryRun,
+}
+
+impl StepStatus {
+    /// Returns the name used in the JSON report, e.g. `dry_run`.
+    pub fn name(self) -> &'static str {
+        match self {
+            StepStatus::Ok => "ok",
+            StepStatus::Warning => "warning",
+            StepStatus::Failed => "failed",
+            StepStatus::Skipped => "skipped",
+            StepStatus::DryRun => "dry_run",
+        }
+    }
+}
+
+/// Result of a single workflow step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepReport {
+    /// Step name, e.g. `update`
+    pub name: String,
+    /// How the step ended
+    pub status: StepStatus,
+    /// Wall-clock time spent in the step
+    pub duration_ms: u64,
+    /// Exit code of the brew command, if one was run
+    pub exit_code: Option<i32>,
+    /// Last lines brew wrote to stderr, if any
+    pub stderr: Option<String>,
+    /// Recognized cause of a failure, with a remediation
+    #[serde(default)]
+    pub diagnosis: Option<Diagnosis>,
+    /// Brew commands re-run after transient failures
+    #[serde(default)]
+    pub retries: u32,
+}
+
+/// A package moved from one version to another.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageChange {
+    /// Package name
+    pub name: String,
+    /// Whether this is a formula or a cask
+    pub kind: PackageKind,
+    /// Version before the run
+    pub from: String,
+    /// Version after the run
+    pub to: String,
+}
+
+impl From<&OutdatedPackage> for PackageChange {
+    fn from(package: &OutdatedPackage) -> Self {
+        Self {
+            name: package.name.clone(),
+            kind: package.kind,
+            from: package.installed_version().to_string(),
+            to: package.current_version.clone(),
+        }
+    }
+}
+
+/// An outdated package that was not upgraded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeldBackReport {
+    /// The version change that was skipped
+    #[serde(flatten)]
+    pub change: PackageChange,
+    /// Why the package was held back
+    pub reason: String,
+    /// Version it was upgraded to anyway, e.g. by brew as a dependency
+    #[serde(default)]
+    pub upgraded_to: Option<String>,
+}
+
+/// A package whose upgrade failed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageFailure {
+    /// The version change that was attempted
+    #[serde(flatten)]
+    pub change: PackageChange,
+    /// Exit code of the failed `brew upgrade`
+    pub exit_code: Option<i32>,
+    /// Last lines brew wrote to stderr, or the failure message
+    pub error: String,
+    /// Recognized cause of the failure, with a remediation
+    #[serde(default)]
+    pub diagnosis: Option<Diagnosis>,
+}
+
+/// What the cleanup step removed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CleanupSummary {
+    /// Number of files and directories removed
+    pub entries_removed: usize,
+    /// Disk space reclaimed as brew reported it
+    pub bytes_reclaimed: Option<u64>,
+    /// Disk space reclaimed as measured in the cache and Cellar
+    #[serde(default)]
+    pub measured_bytes_reclaimed: Option<u64>,
+    /// Size of the Homebrew cache after the cleanup, if it was measured
+    #[serde(default)]
+    pub cache_bytes: Option<u64>,
+    /// Size of the Cellar after the cleanup, if it was measured
+    #[serde(default)]
+    pub cellar_bytes: Option<u64>,
+    /// The largest files left in the cache, largest first
+    #[serde(default)]
+    pub largest_cache_entries: Vec<CacheEntry>,
+    /// Unneeded dependencies uninstalled by `brew autoremove`
+    #[serde(default)]
+    pub autoremoved: Vec<String>,
+    /// Versions beyond `keep_versions` removed by brewup, e.g. `wget 1.24.3`
+    #[serde(default)]
+    pub old_versions_removed: Vec<String>,
+}
+
+impl CleanupSummary {
+    /// Returns the disk space reclaimed, as measured or else as brew
+    /// reported it.
+    pub fn reclaimed(&self) -> Option<u64> {
+        self.measured_bytes_reclaimed.or(self.bytes_reclaimed)
+    }
+}
+
+/// What became of a running service whose formula was upgraded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartStatus {
+    /// The service was restarted with the new version
+    Restarted,
+    /// `brew services restart` failed
+    Failed,
+    /// The service still runs the old version and needs a manual restart
+    NeedsRestart,
+    /// The service would be restarted
+    DryRun,
+}
+
+/// A running service whose formula was upgraded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceRestart {
+    /// Service (formula) name
+    pub name: String,
+    /// What became of the service
+    pub status: RestartStatus,
+    /// Why the service was not restarted, or how the restart failed
+    pub detail: Option<String>,
+}
+
+/// Everything that happened during one BrewUp run.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    /// Start time in RFC 3339 format (UTC)
+    pub started_at: String,
+    /// Total wall-clock duration of the run
+    pub duration_ms: u64,
+    /// Whether the run completed without a fatal error
+    pub success: bool,
+    /// Whether the run was a dry run
+    pub dry_run: bool,
+    /// The fatal error that stopped the run, if any
+    pub error: Option<String>,
+    /// Process exit code of the run (see `error` module for the taxonomy)
+    pub exit_code: i32,
+    /// Recognized cause of the fatal error, with a remediation
+    pub diagnosis: Option<Diagnosis>,
+    /// Per-step results in execution order
+    pub steps: Vec<StepReport>,
+    /// Results of the user hooks that ran, in execution order
+    pub hooks: Vec<StepReport>,
+    /// Packages upgraded (or that would be, in dry-run mode)
+    pub upgraded: Vec<PackageChange>,
+    /// Packages whose upgrade failed (isolated upgrades only)
+    pub failed: Vec<PackageFailure>,
+    /// Outdated packages that were held back
+    pub held_back: Vec<HeldBackReport>,
+    /// Running services of upgraded formulae
+    pub services: Vec<ServiceRestart>,
+    /// Caveats brew printed for upgraded packages
+    pub caveats: Vec<Caveat>,
+    /// Cleanup results, if cleanup ran
+    pub cleanup: Option<CleanupSummary>,
+    /// Id of the snapshot taken before upgrading, if one was taken
+    pub snapshot: Option<String>,
+    /// Installed packages after the run
+    pub installed: Vec<InstalledPackage>,
+    /// Packages upgraded, installed or removed during the run
+    pub changes: Vec<VersionChange>,
+    /// Installed packages before the first upgrade, if they were listed
+    #[serde(skip)]
+    pub(crate) installed_before: Option<Vec<InstalledPackage>>,
+    /// Monotonic start time used to compute durations
+    #[serde(skip)]
+    started: Instant,
+    /// Retries of the running step, moved to its report when it ends
+    #[serde(skip)]
+    pending_retries: u32,
+}
+
+impl RunReport {
+    /// Creates an empty report for a run starting now.
+    ///
+    /// # Arguments
+    /// * `dry_run` - Whether the run is a dry run
+    pub fn new(dry_run: bool) -> Self {
+        Self {
+            started_at: utils::format_timestamp(SystemTime::now()),
+            duration_ms: 0,
+            success: false,
+            dry_run,
+            error: None,
+            exit_code: 0,
+            diagnosis: None,
+            steps: Vec::new(),
+            hooks: Vec::new(),
+            upgraded: Vec::new(),
+            failed: Vec::new(),
+            held_back: Vec::new(),
+            services: Vec::new(),
+            caveats: Vec::new(),
+            cleanup: None,
+            snapshot: None,
+            installed: Vec::new(),
+            changes: Vec::new(),
+            installed_before: None,
+            started: Instant::now(),
+            pending_retries: 0,
+        }
+    }
+
+    /// Records the result of a step.
+    ///
+    /// # Arguments
+    /// * `name` - Step name
+    /// * `started` - When the step started
+    /// * `status` - How the step ended
+    /// * `output` - Output of the brew command the step ran, if any
+    pub fn record_step(
+        &mut self,
+        name: &str,
+        started: Instant,
+        status: StepStatus,
+        output: Option<&BrewOutput>,
+    ) {
+        let failed = matches!(status, StepStatus::Failed | StepStatus::Warning);
+        self.steps.push(StepReport {
+            name: name.to_string(),
+            status,
+            duration_ms: started.elapsed().as_millis() as u64,
+            exit_code: output.and_then(|o| o.code),
+            stderr: output.and_then(|o| stderr_excerpt(&o.stderr)),
+            diagnosis: output.filter(|_| failed).and_then(diagnosis::classify),
+            retries: std::mem::take(&mut self.pending_retries),
+        });
+    }
+
+    /// Counts a retry of a brew command in the running step.
+    pub fn record_retry(&mut self) {
+        self.pending_retries += 1;
+    }
+
+    /// Returns the number of retries across all steps.
+    pub fn retries(&self) -> u32 {
+        self.steps.iter().map(|s| s.retries).sum()
+    }
+
+    /// Records the result of a user hook.
+    ///
+    /// # Arguments
+    /// * `name` - Hook name, e.g. `pre_update`
+    /// * `started` - When the hook started
+    /// * `status` - How the hook ended
+    /// * `output` - Output of the hook command, if it ran
+    pub fn record_hook(
+        &mut self,
+        name: &str,
+        started: Instant,
+        status: StepStatus,
+        output: Option<&BrewOutput>,
+    ) {
+        self.hooks.push(StepReport {
+            name: name.to_string(),
+            status,
+            duration_ms: started.elapsed().as_millis() as u64,
+            exit_code: output.and_then(|o| o.code),
+            stderr: output.and_then(|o| stderr_excerpt(&o.stderr)),
+            diagnosis: None,
+            retries: 0,
+        });
+    }
+
+    /// Records the packages held back by the upgrade step.
+    ///
+    /// # Arguments
+    /// * `held` - The held packages
+    pub fn record_held(&mut self, held: &[HeldPackage]) {
+        self.held_back = held
+            .iter()
+            .map(|h| HeldBackReport {
+                change: PackageChange::from(&h.package),
+                reason: h.reason.clone(),
+                upgraded_to: None,
+            })
+            .collect();
+    }
+
+    /// Completes the report once the run has ended.
+    ///
+    /// # Arguments
+    /// * `error` - The fatal error that stopped the run, if any
+    pub fn finish(&mut self, error: Option<&BrewupError>) {
+        self.duration_ms = self.started.elapsed().as_millis() as u64;
+        self.success = error.is_none();
+        self.error = error.map(|e| e.to_string());
+        self.diagnosis = error.and_then(BrewupError::diagnosis);
+        self.exit_code = error.map_or(0, BrewupError::exit_code);
+    }
+
+    /// Summarizes the finished run in one line, as printed by `--quiet`.
+    ///
+    /// # Returns
+    /// A line such as `BrewUp: upgraded 2 packages (node, wget), freed 4.2MB in 1m 12s`.
+    pub fn summary_line(&self) -> String {
+        let duration = utils::format_duration(self.duration_ms);
+        if !self.success {
+            return format!(
+                "BrewUp failed after {} (exit code {})",
+                duration, self.exit_code
+            );
+        }
+
+        let names: Vec<&str> = self.upgraded.iter().map(|c| c.name.as_str()).collect();
+        let mut parts = vec![match (names.len(), self.dry_run) {
+            (0, _) => "everything is up to date".to_string(),
+            (n, dry_run) => format!(
+                "{} {} package{} ({})",
+                if dry_run { "would upgrade" } else { "upgraded" },
+                n,
+                if n == 1 { "" } else { "s" },
+                names.join(", ")
+            ),
+        }];
+        if !self.failed.is_empty() {
+            parts.push(format!("{} failed", self.failed.len()));
+        }
+        if let Some(bytes) = self.cleanup.as_ref().and_then(CleanupSummary::reclaimed) {
+            if bytes > 0 {
+                parts.push(format!("freed {}", cleanup::format_size(bytes)));
+            }
+        }
+        let warnings: Vec<&str> = self
+            .steps
+            .iter()
+            .filter(|s| s.status == StepStatus::Warning)
+            .map(|s| s.name.as_str())
+            .collect();
+        if !warnings.is_empty() {
+            parts.push(format!("warnings in {}", warnings.join(", ")));
+        }
+        format!("BrewUp: {} in {}", parts.join(", "), duration)
+    }
+
+    /// Serializes the report as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_else(|e| {
+            format!(
+                "{{\"success\": false, \"error\": \"failed to serialize report: {}\"}}",
+                e
+            )
+        })
+    }
+}
+
+/// Returns the last lines of `stderr`, or `None` if it is blank.
+pub(crate) fn stderr_excerpt(stderr: &str) -> Option<String> {
+    let lines: Vec<&str> = stderr.trim_end().lines().collect();
+    if lines.iter().all(|l| l.trim().is_empty()) {
+        return None;
+    }
+    let start = lines.len().saturating_sub(STDERR_EXCERPT_LINES);
+    Some(lines[start..].join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that steps record exit codes and only the tail of stderr.
+    #[test]
+    fn test_record_step_keeps_stderr_tail() {
+        let mut report = RunReport::new(false);
+        let stderr: Vec<String> = (1..=30).map(|i| format!("line {}", i)).collect();
+        report.record_step(
+            "update",
+            Instant::now(),
+            StepStatus::Failed,
+            Some(&BrewOutput::failure(1, &stderr.join("\n"))),
+        );
+        report.record_step("cleanup", Instant::now(), StepStatus::Skipped, None);
+        report.finish(Some(&BrewupError::BrewNotFound));
+
+        let step = &report.steps[0];
+        assert_eq!(step.exit_code, Some(1));
+        let excerpt = step.stderr.as_deref().unwrap();
+        assert!(excerpt.starts_with("line 11\n"));
+        assert!(excerpt.ends_with("line 30"));
+        assert!(!report.success);
+        assert_eq!(report.exit_code, 4);
+    }
+
+    /// Tests the JSON shape of a report.
+    #[test]
+    fn test_report_json() {
+        let mut report = RunReport::new(true);
+        report.upgraded.push(PackageChange {
+            name: "wget".to_string(),
+            kind: PackageKind::Formula,
+            from: "1.24.4".to_string(),
+            to: "1.24.5".to_string(),
+        });
+        report.record_step("upgrade", Instant::now(), StepStatus::DryRun, None);
+        report.finish(None);
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["success"], true);
+        assert_eq!(json["steps"][0]["status"], "dry_run");
+        assert_eq!(json["upgraded"][0]["kind"], "formula");
+        assert_eq!(json["upgraded"][0]["to"], "1.24.5");
+        assert!(json.get("started").is_none());
+    }
+
+    /// Tests the one-line summary printed by `--quiet`.
+    #[test]
+    fn test_summary_line() {
+        let mut report = RunReport::new(false);
+        report.upgraded = ["node", "wget"]
+            .iter()
+            .map(|name| PackageChange {
+                name: name.to_string(),
+                kind: PackageKind::Formula,
+                from: "1.0".to_string(),
+                to: "2.0".to_string(),
+            })
+            .collect();
+        report.cleanup = Some(CleanupSummary {
+            entries_removed: 3,
+            bytes_reclaimed: Some(4_404_019),
+            ..Default::default()
+        });
+        report.record_step("cleanup", Instant::now(), StepStatus::Warning, None);
+        report.finish(None);
+        report.duration_ms = 72_000;
+        assert_eq!(
+            report.summary_line(),
+            "BrewUp: upgraded 2 packages (node, wget), freed 4.2MB, warnings in cleanup in 1m 12s"
+        );
+
+        let mut report = RunReport::new(false);
+        report.finish(Some(&BrewupError::BrewNotFound));
+        report.duration_ms = 850;
+        assert_eq!(
+            report.summary_line(),
+            "BrewUp failed after 850ms (exit code 4)"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Retries of brew commands that failed for a transient reason.
+//!
+//! A flaky network or another brew process holding a lock makes a command
+//! fail although nothing is wrong with the installation. Steps run their
+//! brew commands through [`run_brew_command`], which re-runs a command whose
+//! failure is diagnosed as transient (see
+//! [`FailureKind::is_transient`](crate::diagnosis::FailureKind::is_transient)),
+//! waiting with exponential backoff and jitter between attempts as set by the
+//! step's [`RetryPolicy`]. Every retry is counted in the step's report.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    config::{constants, RetryPolicy},
+    diagnosis,
+    error::CommandFailure,
+    pipeline::StepContext,
+    runner::{BrewOutput, CommandInput},
+    utils,
+};
+
+/// Executes a brew command for a step, retrying transient failures.
+///
+/// Failures that are not transient, interrupted commands and the last
+/// attempt's failure are returned as they are.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `step` - Name of the running step, which selects the retry policy
+/// * `args` - Arguments to pass to brew
+///
+/// # Returns
+/// The output of the first successful attempt, or the failure of the last one
+pub fn run_brew_command(
+    ctx: &mut StepContext<'_>,
+    step: &str,
+    args: &[&str],
+) -> Result<BrewOutput, CommandFailure> {
+    run_brew_command_with(ctx, step, args, &CommandInput::default())
+}
+
+/// Executes a brew command for a step with extra input, retrying transient
+/// failures like [`run_brew_command`].
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `step` - Name of the running step, which selects the retry policy
+/// * `args` - Arguments to pass to brew
+/// * `input` - Environment variables and standard input for this command
+///
+/// # Returns
+/// The output of the first successful attempt, or the failure of the last one
+pub fn run_brew_command_with(
+    ctx: &mut StepContext<'_>,
+    step: &str,
+    args: &[&str],
+    input: &CommandInput,
+) -> Result<BrewOutput, CommandFailure> {
+    let policy = ctx.config.retry.policy(step);
+    let mut attempt = 1;
+    loop {
+        let failure =
+            match utils::run_brew_command_with(ctx.runner, args, input, ctx.args.verbosity) {
+                Ok(output) => return Ok(output),
+                Err(failure) => failure,
+            };
+        let kind = failure
+            .output
+            .as_ref()
+            .and_then(diagnosis::classify)
+            .map(|d| d.kind)
+            .filter(|kind| kind.is_transient());
+        let Some(kind) = kind else {
+            return Err(failure);
+        };
+        if attempt >= policy.attempts || failure.was_interrupted() {
+            return Err(failure);
+        }
+
+        let delay = backoff(&policy, attempt, jitter());
+        utils::show_info(
+            constants::EMOJI_RETRY,
+            &format!(
+                "Attempt {}/{} of `brew {}` failed ({}), retrying in {:.1}s",
+                attempt,
+                policy.attempts,
+                args.join(" "),
+                kind.name(),
+                delay.as_secs_f64()
+            ),
+            colored::Color::Yellow,
+        );
+        std::thread::sleep(delay);
+        ctx.report.record_retry();
+        attempt += 1;
+    }
+}
+
+/// Computes the wait before a retry.
+///
+/// The delay doubles with each retry up to `max_delay`; jitter then picks a
+/// wait between half and all of it, so that machines started by the same
+/// schedule do not retry in lockstep.
+///
+/// # Arguments
+/// * `policy` - The retry policy
+/// * `retry` - Number of the retry, starting at 1
+/// * `jitter` - Random fraction in `[0, 1)`
+///
+/// # Returns
+/// The time to wait before running the command again.
+pub fn backoff(policy: &RetryPolicy, retry: u32, jitter: f64) -> Duration {
+    let factor = 2u64.saturating_pow(retry.saturating_sub(1));
+    let delay = policy.delay.saturating_mul(factor).min(policy.max_delay);
+    Duration::from_secs(delay).mul_f64(0.5 + jitter.clamp(0.0, 1.0) / 2.0)
+}
+
+/// Returns a pseudo-random fraction in `[0, 1)` for jitter.
+///
+/// The sub-second clock is random enough to spread retries apart.
+fn jitter() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    f64::from(nanos) / 1e9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that delays double, stop at the maximum and are scaled by
+    /// jitter to between half and all of the delay.
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            attempts: 6,
+            delay: 2,
+            max_delay: 10,
+        };
+        for (retry, delay) in (1..=5).zip([2.0, 4.0, 8.0, 10.0, 10.0]) {
+            assert_eq!(backoff(&policy, retry, 0.0).as_secs_f64(), delay * 0.5);
+            for jitter in [0.25, 0.5, 0.999_999] {
+                let wait = backoff(&policy, retry, jitter).as_secs_f64();
+                assert!(
+                    (delay * 0.5..delay).contains(&wait),
+                    "retry {} with jitter {} waited {}s",
+                    retry,
+                    jitter,
+                    wait
+                );
+            }
+        }
+        assert_eq!(backoff(&policy, 64, 1.0), Duration::from_secs(10));
+
+        let immediate = RetryPolicy { delay: 0, ..policy };
+        assert_eq!(backoff(&immediate, 3, 0.5), Duration::ZERO);
+    }
+}
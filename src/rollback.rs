@@ -0,0 +1,803 @@
+//! Rollback to a pre-upgrade snapshot for BrewUp.
+//!
+//! `brewup rollback` compares the installed versions with a [`Snapshot`] and
+//! restores what Homebrew allows:
+//!
+//! - a formula whose snapshotted version is still in the download cache is
+//!   reinstalled from that bottle and pinned, so the next run keeps it;
+//! - a formula that moved to a new major version gets the matching
+//!   versioned formula (e.g. `node@20`) installed alongside, which counts
+//!   as restored;
+//! - removed packages are reinstalled from the snapshot's Brewfile, or one
+//!   by one when the snapshot has none.
+//!
+//! The installed versions are listed again afterwards, so the result reports
+//! what actually matches the snapshot rather than which commands succeeded.
+//!
+//! Cached bottles rarely survive until a rollback: the cleanup step of the
+//! run that upgraded them (`brew cleanup --prune=all` by default) empties
+//! the download cache.
+
+use colored::*;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    cli::Verbosity,
+    config::constants,
+    error::{BrewupError, CommandFailure},
+    packages::{self, InstalledPackage, PackageKind},
+    runner::BrewRunner,
+    snapshot::{self, Snapshot},
+    utils::{self, say},
+};
+
+/// A package whose installed version differs from the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionDiff {
+    /// Package name
+    pub name: String,
+    /// Whether this is a formula or a cask
+    pub kind: PackageKind,
+    /// Version installed now, `None` if the package was removed
+    pub current: Option<String>,
+    /// Version recorded in the snapshot
+    pub snapshot: String,
+}
+
+/// How a package is brought back to its snapshotted version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoreMethod {
+    /// Install the bottle of the snapshotted version from the Homebrew cache
+    CachedBottle(PathBuf),
+    /// Install the versioned formula of the snapshotted major version
+    VersionedFormula(String),
+    /// Reinstall the removed package from the snapshot's Brewfile
+    Brewfile,
+    /// Reinstall the removed package at the version Homebrew offers now
+    Install,
+    /// The package cannot be restored, for the given reason
+    Unavailable(String),
+}
+
+impl RestoreMethod {
+    /// Returns a short description for plans and reports.
+    fn describe(&self) -> String {
+        match self {
+            RestoreMethod::CachedBottle(_) => "reinstall the cached bottle and pin it".to_string(),
+            RestoreMethod::VersionedFormula(formula) => format!("install {}", formula),
+            RestoreMethod::Brewfile => "reinstall from the snapshot's Brewfile".to_string(),
+            RestoreMethod::Install => "reinstall the current version".to_string(),
+            RestoreMethod::Unavailable(reason) => reason.clone(),
+        }
+    }
+}
+
+/// A package that matches the snapshot again (or would, in dry-run mode).
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoredPackage {
+    /// The version change that was undone
+    #[serde(flatten)]
+    pub diff: VersionDiff,
+    /// How it was restored
+    pub method: String,
+}
+
+/// A package that still differs from the snapshot.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnrestoredPackage {
+    /// The version change that remains
+    #[serde(flatten)]
+    pub diff: VersionDiff,
+    /// Why it could not be restored
+    pub reason: String,
+}
+
+/// Outcome of `brewup rollback`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RollbackReport {
+    /// Id of the snapshot rolled back to
+    pub snapshot: String,
+    /// Whether this was only a preview
+    pub dry_run: bool,
+    /// Packages restored to their snapshotted version
+    pub restored: Vec<RestoredPackage>,
+    /// Packages that still differ from the snapshot
+    pub not_restored: Vec<UnrestoredPackage>,
+    /// Packages installed since the snapshot; rollback leaves them in place
+    pub added: Vec<String>,
+}
+
+/// Compares the installed packages with a snapshot.
+///
+/// # Arguments
+/// * `snapshot` - The snapshot to compare with
+/// * `installed` - The packages installed now
+///
+/// # Returns
+/// The snapshotted packages that were removed or are at another version.
+pub fn diff(snapshot: &Snapshot, installed: &[InstalledPackage]) -> Vec<VersionDiff> {
+    snapshot
+        .packages
+        .iter()
+        .filter_map(|old| {
+            let current = installed
+                .iter()
+                .find(|p| p.kind == old.kind && p.name == old.name)
+                .map(|p| p.version().to_string());
+            (current.as_deref() != Some(old.version())).then(|| VersionDiff {
+                name: old.name.clone(),
+                kind: old.kind,
+                current,
+                snapshot: old.version().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Finds the bottle of a formula version in the Homebrew download cache.
+///
+/// Bottles are named `<formula>--<version>.<platform>.bottle.tar.gz`, in the
+/// cache root or, prefixed with a hash, in its `downloads` directory.
+///
+/// # Arguments
+/// * `cache` - The Homebrew cache (`brew --cache`)
+/// * `name` - Formula name
+/// * `version` - Version to look for
+///
+/// # Returns
+/// The path of the bottle, or `None` if it is not cached.
+pub fn find_bottle(cache: &Path, name: &str, version: &str) -> Option<PathBuf> {
+    let prefix = format!("{}--{}.", name, version);
+    let mut bottles: Vec<PathBuf> = [cache.to_path_buf(), cache.join("downloads")]
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let file = entry.file_name().to_string_lossy().into_owned();
+            let file = match file.split_once("--") {
+                Some((hash, rest)) if hash.len() == 64 && rest.starts_with(&prefix) => rest,
+                _ => &file,
+            };
+            file.starts_with(&prefix) && file.contains(".bottle.") && file.ends_with(".tar.gz")
+        })
+        .map(|entry| entry.path())
+        .collect();
+    bottles.sort();
+    bottles.into_iter().next()
+}
+
+/// Decides how each differing package is restored.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+/// * `diffs` - The packages that differ from the snapshot
+/// * `has_brewfile` - Whether the snapshot has a Brewfile
+///
+/// # Returns
+/// The restore method for each package, in the same order.
+fn plan(runner: &dyn BrewRunner, diffs: &[VersionDiff], has_brewfile: bool) -> Vec<RestoreMethod> {
+    let cache = diffs
+        .iter()
+        .any(|d| d.kind == PackageKind::Formula)
+        .then(|| runner.capture(&["--cache"]).ok())
+        .flatten()
+        .filter(|o| o.is_success())
+        .map(|o| PathBuf::from(o.stdout.trim()));
+
+    diffs
+        .iter()
+        .map(|diff| {
+            let bottle = cache
+                .as_deref()
+                .filter(|_| diff.kind == PackageKind::Formula)
+                .and_then(|cache| find_bottle(cache, &diff.name, &diff.snapshot));
+            if let Some(bottle) = bottle {
+                return RestoreMethod::CachedBottle(bottle);
+            }
+            let Some(current) = &diff.current else {
+                return if has_brewfile {
+                    RestoreMethod::Brewfile
+                } else {
+                    RestoreMethod::Install
+                };
+            };
+            if diff.kind == PackageKind::Cask {
+                return RestoreMethod::Unavailable(
+                    "Homebrew cannot install an older version of a cask".to_string(),
+                );
+            }
+            match versioned_formula(runner, &diff.name, current, &diff.snapshot) {
+                Some(formula) => RestoreMethod::VersionedFormula(formula),
+                None => RestoreMethod::Unavailable(format!(
+                    "no cached bottle of {} {} and no versioned formula",
+                    diff.name, diff.snapshot
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Returns the versioned formula (e.g. `node@20`) for an older major version.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+/// * `name` - Formula name
+/// * `current` - Installed version
+/// * `wanted` - Snapshotted version
+///
+/// # Returns
+/// The formula name if the major versions differ and Homebrew knows the formula.
+fn versioned_formula(
+    runner: &dyn BrewRunner,
+    name: &str,
+    current: &str,
+    wanted: &str,
+) -> Option<String> {
+    let major = |version: &str| {
+        version
+            .split(['.', '_'])
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    };
+    let wanted = major(wanted);
+    if name.contains('@') || wanted.is_empty() || wanted == major(current) {
+        return None;
+    }
+    let formula = format!("{}@{}", name, wanted);
+    runner
+        .capture(&["info", "--json=v2", "--formula", &formula])
+        .ok()
+        .filter(|o| o.is_success())
+        .map(|_| formula)
+}
+
+/// Rolls the installed packages back to a snapshot.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+/// * `dir` - The snapshot directory
+/// * `snapshot` - The snapshot to restore
+/// * `dry_run` - Only show what would be done
+/// * `verbosity` - How much of the brew commands to show
+///
+/// # Returns
+/// The report, whether or not every package was restored; `Err` if the
+/// installed packages could not be listed or brew was interrupted.
+pub fn rollback(
+    runner: &dyn BrewRunner,
+    dir: &Path,
+    snapshot: &Snapshot,
+    dry_run: bool,
+    verbosity: Verbosity,
+) -> Result<RollbackReport, BrewupError> {
+    let installed = list_installed(runner)?;
+    let mut report = RollbackReport {
+        snapshot: snapshot.id.clone(),
+        dry_run,
+        added: installed
+            .iter()
+            .filter(|p| snapshot.package(&p.name, p.kind).is_none())
+            .map(|p| p.name.clone())
+            .collect(),
+        ..Default::default()
+    };
+    let diffs = diff(snapshot, &installed);
+    if diffs.is_empty() {
+        return Ok(report);
+    }
+    let brewfile = snapshot::brewfile_path(dir, snapshot);
+    let methods = plan(runner, &diffs, brewfile.is_some());
+
+    if dry_run {
+        for (diff, method) in diffs.into_iter().zip(methods) {
+            match method {
+                RestoreMethod::Unavailable(reason) => {
+                    report.not_restored.push(UnrestoredPackage { diff, reason })
+                }
+                method => report.restored.push(RestoredPackage {
+                    diff,
+                    method: method.describe(),
+                }),
+            }
+        }
+        return Ok(report);
+    }
+
+    // Failure messages and notes by package, in the order of `diffs`
+    let mut notes: Vec<Option<String>> = vec![None; diffs.len()];
+    if let Some(brewfile) = brewfile.filter(|_| methods.contains(&RestoreMethod::Brewfile)) {
+        let file = format!("--file={}", brewfile.display());
+        if let Some(error) = run(
+            runner,
+            &["bundle", "install", "--no-upgrade", &file],
+            verbosity,
+        )? {
+            for (note, method) in notes.iter_mut().zip(&methods) {
+                if *method == RestoreMethod::Brewfile {
+                    *note = Some(error.clone());
+                }
+            }
+        }
+    }
+    for ((diff, method), note) in diffs.iter().zip(&methods).zip(notes.iter_mut()) {
+        *note = match method {
+            RestoreMethod::CachedBottle(bottle) => {
+                restore_bottle(runner, diff, bottle, verbosity)?.or(note.take())
+            }
+            RestoreMethod::VersionedFormula(formula) => {
+                Some(match run(runner, &["install", formula], verbosity)? {
+                    None => format!("installed {} alongside it", formula),
+                    Some(error) => error,
+                })
+            }
+            RestoreMethod::Install => run(
+                runner,
+                &["install", diff.kind.flag(), &diff.name],
+                verbosity,
+            )?,
+            RestoreMethod::Brewfile => note.take(),
+            RestoreMethod::Unavailable(reason) => Some(reason.clone()),
+        };
+    }
+
+    let installed = list_installed(runner)?;
+    let remaining = diff(snapshot, &installed);
+    for ((diff, method), note) in diffs.into_iter().zip(methods).zip(notes) {
+        // The formula itself stays at the new version; the old major
+        // version is restored once its versioned formula is installed
+        if let RestoreMethod::VersionedFormula(formula) = &method {
+            if installed
+                .iter()
+                .any(|p| p.kind == PackageKind::Formula && p.name == *formula)
+            {
+                report.restored.push(RestoredPackage {
+                    diff,
+                    method: note.unwrap_or_else(|| method.describe()),
+                });
+                continue;
+            }
+        }
+        match remaining
+            .iter()
+            .find(|d| d.kind == diff.kind && d.name == diff.name)
+        {
+            None => report.restored.push(RestoredPackage {
+                diff,
+                method: method.describe(),
+            }),
+            Some(now) => {
+                let reason = match (note, &now.current) {
+                    (Some(note), _) => note,
+                    (None, Some(version)) => format!("{} is installed instead", version),
+                    (None, None) => "still not installed".to_string(),
+                };
+                report.not_restored.push(UnrestoredPackage { diff, reason });
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Reinstalls a formula from a cached bottle and pins it.
+///
+/// The current version is unlinked first and linked again if the install
+/// fails, so a failed restore leaves the formula usable.
+///
+/// # Returns
+/// `None` on success, the failure message otherwise; `Err` if interrupted
+fn restore_bottle(
+    runner: &dyn BrewRunner,
+    diff: &VersionDiff,
+    bottle: &Path,
+    verbosity: Verbosity,
+) -> Result<Option<String>, BrewupError> {
+    let bottle = bottle.to_string_lossy();
+    if diff.current.is_some() {
+        if let Some(error) = run(runner, &["unlink", &diff.name], verbosity)? {
+            return Ok(Some(error));
+        }
+    }
+    if let Some(error) = run(runner, &["install", "--force", &bottle], verbosity)? {
+        if diff.current.is_some() {
+            run(runner, &["link", &diff.name], verbosity)?;
+        }
+        return Ok(Some(error));
+    }
+    if let Some(error) = run(runner, &["pin", &diff.name], verbosity)? {
+        utils::show_warning(&format!("Could not pin {}: {}", diff.name, error));
+    }
+    Ok(None)
+}
+
+/// Runs one brew command of the rollback.
+///
+/// # Returns
+/// `None` on success, the failure message otherwise; `Err` if interrupted
+fn run(
+    runner: &dyn BrewRunner,
+    args: &[&str],
+    verbosity: Verbosity,
+) -> Result<Option<String>, BrewupError> {
+    match utils::run_brew_command(runner, args, verbosity) {
+        Ok(_) => Ok(None),
+        Err(e) if e.was_interrupted() => Err(BrewupError::Interrupted),
+        Err(e) => Ok(Some(e.message)),
+    }
+}
+
+/// Lists the installed packages, failing the rollback if brew cannot.
+fn list_installed(runner: &dyn BrewRunner) -> Result<Vec<InstalledPackage>, BrewupError> {
+    packages::installed_packages(runner).map_err(|e| {
+        e.into_error(|failure| BrewupError::RollbackFailed {
+            packages: Vec::new(),
+            failure,
+        })
+    })
+}
+
+impl RollbackReport {
+    /// Converts packages left unrestored into an error.
+    ///
+    /// # Returns
+    /// `Ok(())` if every package was restored (or a dry run found nothing
+    /// unrestorable), `BrewupError::RollbackFailed` otherwise.
+    pub fn into_result(&self) -> Result<(), BrewupError> {
+        if self.not_restored.is_empty() {
+            return Ok(());
+        }
+        let total = self.restored.len() + self.not_restored.len();
+        Err(BrewupError::RollbackFailed {
+            packages: self
+                .not_restored
+                .iter()
+                .map(|p| p.diff.name.clone())
+                .collect(),
+            failure: CommandFailure {
+                message: format!(
+                    "{} of {} packages {} be restored",
+                    self.not_restored.len(),
+                    total,
+                    if self.dry_run { "cannot" } else { "could not" }
+                ),
+                output: None,
+            },
+        })
+    }
+}
+
+/// Displays the outcome (or, in dry-run mode, the plan) of a rollback.
+///
+/// # Arguments
+/// * `report` - The rollback report
+pub fn show_report(report: &RollbackReport) {
+    let arrow = utils::symbol(constants::SYMBOL_ARROW);
+    if report.restored.is_empty() && report.not_restored.is_empty() {
+        utils::show_success("Installed versions already match the snapshot");
+    }
+    let name_width = report
+        .restored
+        .iter()
+        .map(|p| &p.diff)
+        .chain(report.not_restored.iter().map(|p| &p.diff))
+        .map(|d| d.name.len())
+        .max()
+        .unwrap_or(0);
+    let change = |diff: &VersionDiff| {
+        format!(
+            "{} {arrow} {}",
+            diff.current.as_deref().unwrap_or("not installed"),
+            diff.snapshot
+        )
+    };
+    if !report.restored.is_empty() {
+        let title = if report.dry_run {
+            "Would restore:"
+        } else {
+            "Restored:"
+        };
+        say!("\n{}", title.bold());
+        for package in &report.restored {
+            say!(
+                "   {} {:<name_width$}  {}  {}",
+                utils::symbol(constants::SYMBOL_OK).green(),
+                package.diff.name,
+                change(&package.diff),
+                format!("({})", package.method).dimmed()
+            );
+        }
+    }
+    if !report.not_restored.is_empty() {
+        let title = if report.dry_run {
+            "Cannot restore:"
+        } else {
+            "Not restored:"
+        };
+        say!("\n{}", title.bold());
+        for package in &report.not_restored {
+            say!(
+                "   {} {:<name_width$}  {}  {}",
+                utils::symbol(constants::SYMBOL_FAILED).red(),
+                package.diff.name,
+                change(&package.diff),
+                package.reason.red()
+            );
+        }
+    }
+    if !report.added.is_empty() {
+        say!(
+            "\n{} {}",
+            "Installed since the snapshot (left in place):".bold(),
+            report.added.join(", ").dimmed()
+        );
+    }
+}
+
+/// Announces the snapshot a rollback restores.
+///
+/// # Arguments
+/// * `snapshot` - The snapshot
+/// * `dry_run` - Whether this is only a preview
+pub fn show_header(snapshot: &Snapshot, dry_run: bool) {
+    let verb = if dry_run {
+        "Previewing rollback"
+    } else {
+        "Rolling back"
+    };
+    utils::show_info(
+        constants::EMOJI_ROLLBACK,
+        &format!(
+            "{} to snapshot {} (taken {} on {})",
+            verb,
+            snapshot.id.bold(),
+            snapshot.created_at.replace('T', " ").trim_end_matches('Z'),
+            snapshot.host
+        ),
+        colored::Color::Blue,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{BrewOutput, ScriptedRunner};
+
+    fn snapshot() -> Snapshot {
+        let mut packages = packages::parse_installed(
+            "node 20.12.2\nwget 1.24.4\njq 1.7.1\ngit 2.45.1\n",
+            PackageKind::Formula,
+        );
+        packages.extend(packages::parse_installed(
+            "firefox 125.0\n",
+            PackageKind::Cask,
+        ));
+        Snapshot {
+            id: "20240501T090000Z".to_string(),
+            created_at: "2024-05-01T09:00:00Z".to_string(),
+            host: "mbp".to_string(),
+            packages,
+            brewfile: None,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("brewup-rollback-{}-{}", std::process::id(), name))
+    }
+
+    /// Tests that changed and removed packages are found, and new ones ignored.
+    #[test]
+    fn test_diff() {
+        let installed = packages::parse_installed(
+            "node 22.2.0\nwget 1.24.4 1.24.5\ngit 2.45.1\nripgrep 14.1.0\n",
+            PackageKind::Formula,
+        );
+        let diffs: Vec<_> = diff(&snapshot(), &installed)
+            .into_iter()
+            .map(|d| (d.name, d.current))
+            .collect();
+        assert_eq!(
+            diffs,
+            [
+                ("node".to_string(), Some("22.2.0".to_string())),
+                ("wget".to_string(), Some("1.24.5".to_string())),
+                ("jq".to_string(), None),
+                ("firefox".to_string(), None)
+            ]
+        );
+    }
+
+    /// Tests that bottles are found in the cache root and the downloads directory.
+    #[test]
+    fn test_find_bottle() {
+        let cache = temp_dir("cache");
+        let _ = fs::remove_dir_all(&cache);
+        fs::create_dir_all(cache.join("downloads")).unwrap();
+        let hash = "a".repeat(64);
+        for file in [
+            format!("{}--wget--1.24.4.arm64_sonoma.bottle.tar.gz", hash),
+            format!("{}--wget--1.24.4.arm64_sonoma.bottle_manifest.json", hash),
+            format!("{}--wget--1.24.41.arm64_sonoma.bottle.tar.gz", hash),
+        ] {
+            fs::write(cache.join("downloads").join(file), "").unwrap();
+        }
+        fs::write(cache.join("jq--1.7.1.arm64_sonoma.bottle.1.tar.gz"), "").unwrap();
+
+        let wget = find_bottle(&cache, "wget", "1.24.4").unwrap();
+        assert!(wget.ends_with(format!(
+            "downloads/{}--wget--1.24.4.arm64_sonoma.bottle.tar.gz",
+            hash
+        )));
+        assert!(find_bottle(&cache, "jq", "1.7.1").is_some());
+        assert!(find_bottle(&cache, "wget", "1.24.3").is_none());
+        assert!(find_bottle(&cache, "get", "1.24.4").is_none());
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    /// Tests a rollback restoring what it can and reporting the rest.
+    #[test]
+    fn test_rollback_restores_what_it_can() {
+        let cache = temp_dir("restore");
+        let _ = fs::remove_dir_all(&cache);
+        fs::create_dir_all(&cache).unwrap();
+        let bottle = cache.join("wget--1.24.4.arm64_sonoma.bottle.tar.gz");
+        fs::write(&bottle, "").unwrap();
+        let bottle = bottle.to_string_lossy().into_owned();
+
+        let before = "node 22.2.0\nwget 1.24.5\ngit 2.45.1\nripgrep 14.1.0\n";
+        let after = "node 22.2.0\nnode@20 20.12.2\nwget 1.24.4\njq 1.7.1\ngit 2.45.1\n";
+        let runner = ScriptedRunner::new()
+            .respond(
+                &["list", "--formula", "--versions"],
+                BrewOutput::success(before),
+            )
+            .respond(
+                &["list", "--formula", "--versions"],
+                BrewOutput::success(after),
+            )
+            .respond(
+                &["list", "--cask", "--versions"],
+                BrewOutput::success("firefox 126.0\n"),
+            )
+            .respond(
+                &["--cache"],
+                BrewOutput::success(&format!("{}\n", cache.display())),
+            )
+            .respond(
+                &["info", "--json=v2", "--formula", "node@20"],
+                BrewOutput::success("{}"),
+            )
+            .respond(&["unlink", "wget"], BrewOutput::success(""))
+            .respond(&["install", "--force", &bottle], BrewOutput::success(""))
+            .respond(&["pin", "wget"], BrewOutput::success(""))
+            .respond(&["install", "node@20"], BrewOutput::success(""))
+            .respond(&["install", "--formula", "jq"], BrewOutput::success(""));
+
+        let report = rollback(&runner, &cache, &snapshot(), false, Verbosity::Normal).unwrap();
+        let restored: Vec<_> = report
+            .restored
+            .iter()
+            .map(|p| (p.diff.name.as_str(), p.method.as_str()))
+            .collect();
+        assert_eq!(
+            restored,
+            [
+                ("node", "installed node@20 alongside it"),
+                ("wget", "reinstall the cached bottle and pin it"),
+                ("jq", "reinstall the current version")
+            ]
+        );
+        let not_restored: Vec<_> = report
+            .not_restored
+            .iter()
+            .map(|p| (p.diff.name.as_str(), p.reason.as_str()))
+            .collect();
+        assert_eq!(
+            not_restored,
+            [(
+                "firefox",
+                "Homebrew cannot install an older version of a cask"
+            )]
+        );
+        assert!(runner.calls().contains(&"pin wget".to_string()));
+        assert_eq!(report.added, ["ripgrep"]);
+        match report.into_result().unwrap_err() {
+            BrewupError::RollbackFailed { packages, failure } => {
+                assert_eq!(packages, ["firefox"]);
+                assert_eq!(failure.message, "1 of 4 packages could not be restored");
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    /// Tests that a rollback needing only a versioned formula succeeds once
+    /// it is installed, and fails if it could not be.
+    #[test]
+    fn test_rollback_versioned_formula() {
+        let mut snapshot = snapshot();
+        snapshot.packages.retain(|p| p.name == "node");
+        let runner = |after: &str, install: BrewOutput| {
+            ScriptedRunner::new()
+                .respond(
+                    &["list", "--formula", "--versions"],
+                    BrewOutput::success("node 22.2.0\n"),
+                )
+                .respond(
+                    &["list", "--formula", "--versions"],
+                    BrewOutput::success(after),
+                )
+                .respond(&["list", "--cask", "--versions"], BrewOutput::success(""))
+                .respond(&["--cache"], BrewOutput::failure(1, ""))
+                .respond(
+                    &["info", "--json=v2", "--formula", "node@20"],
+                    BrewOutput::success("{}"),
+                )
+                .respond(&["install", "node@20"], install)
+        };
+        let dir = temp_dir("versioned");
+
+        let installed = runner("node 22.2.0\nnode@20 20.12.2\n", BrewOutput::success(""));
+        let report = rollback(&installed, &dir, &snapshot, false, Verbosity::Normal).unwrap();
+        assert_eq!(report.restored[0].method, "installed node@20 alongside it");
+        assert!(report.into_result().is_ok());
+
+        let failed = runner("node 22.2.0\n", BrewOutput::failure(1, "Error: no bottle"));
+        let report = rollback(&failed, &dir, &snapshot, false, Verbosity::Normal).unwrap();
+        assert_eq!(report.not_restored[0].reason, "Error: no bottle");
+        assert!(report.into_result().is_err());
+    }
+
+    /// Tests that a dry run only plans, and a failed bottle install relinks the formula.
+    #[test]
+    fn test_rollback_dry_run_and_relink() {
+        let cache = temp_dir("relink");
+        let _ = fs::remove_dir_all(&cache);
+        fs::create_dir_all(&cache).unwrap();
+        let bottle = cache.join("wget--1.24.4.arm64_sonoma.bottle.tar.gz");
+        fs::write(&bottle, "").unwrap();
+        let bottle = bottle.to_string_lossy().into_owned();
+        let mut snapshot = snapshot();
+        snapshot.packages.retain(|p| p.name == "wget");
+
+        let runner = ScriptedRunner::new()
+            .respond(
+                &["list", "--formula", "--versions"],
+                BrewOutput::success("wget 1.24.5\n"),
+            )
+            .respond(&["list", "--cask", "--versions"], BrewOutput::success(""))
+            .respond(
+                &["--cache"],
+                BrewOutput::success(&cache.display().to_string()),
+            )
+            .respond(&["unlink", "wget"], BrewOutput::success(""))
+            .respond(
+                &["install", "--force", &bottle],
+                BrewOutput::failure(1, "Error: bottle is corrupt"),
+            )
+            .respond(&["link", "wget"], BrewOutput::success(""));
+
+        let plan = rollback(&runner, &cache, &snapshot, true, Verbosity::Normal).unwrap();
+        assert_eq!(
+            plan.restored[0].method,
+            "reinstall the cached bottle and pin it"
+        );
+        assert!(plan.into_result().is_ok());
+        assert_eq!(
+            runner.calls(),
+            [
+                "list --formula --versions",
+                "list --cask --versions",
+                "--cache"
+            ]
+        );
+
+        let report = rollback(&runner, &cache, &snapshot, false, Verbosity::Normal).unwrap();
+        assert!(runner.calls().contains(&"link wget".to_string()));
+        assert!(!runner.calls().contains(&"pin wget".to_string()));
+        assert_eq!(report.not_restored[0].reason, "Error: bottle is corrupt");
+        fs::remove_dir_all(&cache).unwrap();
+    }
+}
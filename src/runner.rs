@@ -0,0 +1,535 @@
+//! Execution backends for Homebrew commands.
+//!
+//! Every `brew` invocation made by BrewUp goes through the [`BrewRunner`]
+//! trait. [`ProcessRunner`] spawns the real `brew` binary, while
+//! [`ScriptedRunner`] replays canned responses so the whole workflow can be
+//! exercised on machines without Homebrew.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+
+/// Captured result of a finished brew command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BrewOutput {
+    /// Exit code of the process, `None` if it was terminated by a signal
+    pub code: Option<i32>,
+    /// Everything the command wrote to stdout
+    pub stdout: String,
+    /// Everything the command wrote to stderr
+    pub stderr: String,
+}
+
+impl BrewOutput {
+    /// Creates a successful output with the given stdout.
+    pub fn success(stdout: &str) -> Self {
+        Self {
+            code: Some(0),
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        }
+    }
+
+    /// Creates a failed output with the given exit code and stderr.
+    pub fn failure(code: i32, stderr: &str) -> Self {
+        Self {
+            code: Some(code),
+            stdout: String::new(),
+            stderr: stderr.to_string(),
+        }
+    }
+
+    /// Returns `true` if the command exited with status 0.
+    pub fn is_success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Environment variables and standard input for a single command.
+///
+/// Settings that concern one command are passed with it rather than set on
+/// the whole process, where they would leak into every other command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandInput {
+    /// Variables set for the command, on top of the runner's own
+    pub env: Vec<(String, String)>,
+    /// Text written to the command's standard input
+    pub stdin: Option<String>,
+}
+
+impl CommandInput {
+    /// Creates an input that sets one environment variable.
+    pub fn env(name: &str, value: &str) -> Self {
+        Self {
+            env: vec![(name.to_string(), value.to_string())],
+            stdin: None,
+        }
+    }
+
+    /// Creates an input that writes `text` to the command's standard input.
+    pub fn stdin(text: &str) -> Self {
+        Self {
+            env: Vec::new(),
+            stdin: Some(text.to_string()),
+        }
+    }
+}
+
+/// A single line of output produced while streaming a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputLine<'a> {
+    /// A line written to stdout
+    Stdout(&'a str),
+    /// A line written to stderr
+    Stderr(&'a str),
+}
+
+/// Backend used to invoke Homebrew.
+pub trait BrewRunner {
+    /// Runs `brew` with the given arguments, discarding its output.
+    ///
+    /// # Returns
+    /// `Ok(true)` if the command exited successfully, `Err` if it could not be started.
+    fn run(&self, args: &[&str]) -> io::Result<bool> {
+        self.capture(args).map(|output| output.is_success())
+    }
+
+    /// Runs `brew` with the given arguments and captures its output.
+    ///
+    /// # Returns
+    /// The captured output, `Err` if the command could not be started.
+    fn capture(&self, args: &[&str]) -> io::Result<BrewOutput> {
+        self.capture_with(args, &CommandInput::default())
+    }
+
+    /// Runs `brew` with the given arguments and input, capturing its output.
+    ///
+    /// # Returns
+    /// The captured output, `Err` if the command could not be started.
+    fn capture_with(&self, args: &[&str], input: &CommandInput) -> io::Result<BrewOutput>;
+
+    /// Runs `brew` with the given arguments, passing each output line to `on_line`.
+    ///
+    /// # Returns
+    /// The full transcript of the command, `Err` if it could not be started.
+    fn stream(
+        &self,
+        args: &[&str],
+        on_line: &mut dyn FnMut(OutputLine<'_>),
+    ) -> io::Result<BrewOutput> {
+        self.stream_with(args, &CommandInput::default(), on_line)
+    }
+
+    /// Runs `brew` with the given arguments and input, passing each output
+    /// line to `on_line`.
+    ///
+    /// # Returns
+    /// The full transcript of the command, `Err` if it could not be started.
+    fn stream_with(
+        &self,
+        args: &[&str],
+        input: &CommandInput,
+        on_line: &mut dyn FnMut(OutputLine<'_>),
+    ) -> io::Result<BrewOutput> {
+        let output = self.capture_with(args, input)?;
+        output
+            .stdout
+            .lines()
+            .for_each(|l| on_line(OutputLine::Stdout(l)));
+        output
+            .stderr
+            .lines()
+            .for_each(|l| on_line(OutputLine::Stderr(l)));
+        Ok(output)
+    }
+}
+
+/// Runner that spawns the real `brew` executable.
+#[derive(Debug, Clone)]
+pub struct ProcessRunner {
+    /// Program to execute
+    program: String,
+    /// Environment variables set for every command
+    env: Vec<(String, String)>,
+}
+
+impl Default for ProcessRunner {
+    fn default() -> Self {
+        Self::with_program("brew")
+    }
+}
+
+impl ProcessRunner {
+    /// Creates a runner that executes `brew` from the PATH.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a runner that executes the given program instead of `brew`.
+    pub fn with_program(program: &str) -> Self {
+        Self {
+            program: program.to_string(),
+            env: Vec::new(),
+        }
+    }
+
+    /// Sets an environment variable for every command the runner starts.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the variable
+    /// * `value` - Its value
+    pub fn with_env(mut self, name: &str, value: &str) -> Self {
+        self.env.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Builds the command for `args`, with the runner's and `input`'s environment.
+    fn command(&self, args: &[&str], input: &CommandInput) -> Command {
+        let mut command = Command::new(&self.program);
+        command
+            .args(args)
+            .envs(self.env.iter().chain(&input.env).map(|(k, v)| (k, v)));
+        command
+    }
+}
+
+impl BrewRunner for ProcessRunner {
+    fn run(&self, args: &[&str]) -> io::Result<bool> {
+        self.command(args, &CommandInput::default())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+    }
+
+    fn capture_with(&self, args: &[&str], input: &CommandInput) -> io::Result<BrewOutput> {
+        let mut command = self.command(args, input);
+        let output = match &input.stdin {
+            None => command.output()?,
+            Some(text) => {
+                let mut child = command
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()?;
+                // Written on its own thread so that a child that answers
+                // before reading all of its input cannot block on full pipes
+                let writer = child.stdin.take().map(|mut stdin| {
+                    let text = text.clone();
+                    thread::spawn(move || {
+                        let _ = stdin.write_all(text.as_bytes());
+                    })
+                });
+                let output = child.wait_with_output()?;
+                if let Some(writer) = writer {
+                    let _ = writer.join();
+                }
+                output
+            }
+        };
+        Ok(BrewOutput {
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+
+    fn stream_with(
+        &self,
+        args: &[&str],
+        input: &CommandInput,
+        on_line: &mut dyn FnMut(OutputLine<'_>),
+    ) -> io::Result<BrewOutput> {
+        if input.stdin.is_some() {
+            let output = self.capture_with(args, input)?;
+            output
+                .stdout
+                .lines()
+                .for_each(|l| on_line(OutputLine::Stdout(l)));
+            output
+                .stderr
+                .lines()
+                .for_each(|l| on_line(OutputLine::Stderr(l)));
+            return Ok(output);
+        }
+        let mut child = self
+            .command(args, input)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Both pipes are drained on their own threads so that neither can fill
+        // up and block the child; lines are handed back here in arrival order.
+        let (sender, receiver) = mpsc::channel();
+        let readers = [
+            child
+                .stdout
+                .take()
+                .map(|out| forward_lines(out, false, sender.clone())),
+            child
+                .stderr
+                .take()
+                .map(|err| forward_lines(err, true, sender)),
+        ];
+
+        let mut output = BrewOutput::default();
+        for (is_stderr, line) in receiver {
+            let transcript = if is_stderr {
+                on_line(OutputLine::Stderr(&line));
+                &mut output.stderr
+            } else {
+                on_line(OutputLine::Stdout(&line));
+                &mut output.stdout
+            };
+            transcript.push_str(&line);
+            transcript.push('\n');
+        }
+
+        for reader in readers.into_iter().flatten() {
+            let _ = reader.join();
+        }
+        output.code = child.wait()?.code();
+        Ok(output)
+    }
+}
+
+/// Reads `pipe` line by line on a new thread and sends each line to `sender`.
+fn forward_lines<R: Read + Send + 'static>(
+    pipe: R,
+    is_stderr: bool,
+    sender: mpsc::Sender<(bool, String)>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut buffer = Vec::new();
+        while let Ok(read) = reader.read_until(b'\n', &mut buffer) {
+            if read == 0 {
+                break;
+            }
+            let line = String::from_utf8_lossy(&buffer);
+            let line = line.trim_end_matches(['\n', '\r']).to_string();
+            if sender.send((is_stderr, line)).is_err() {
+                break;
+            }
+            buffer.clear();
+        }
+    })
+}
+
+/// In-memory runner that replays scripted responses.
+///
+/// Responses are matched on the exact argument list. When several responses
+/// are scripted for the same arguments they are returned in order, and the
+/// last one is repeated. Unscripted commands fail as if `brew` was missing.
+#[derive(Debug, Default)]
+pub struct ScriptedRunner {
+    /// Scripted responses keyed by argument list
+    responses: RefCell<Vec<(Vec<String>, VecDeque<BrewOutput>)>>,
+    /// Every invocation received, in order, with its input
+    calls: RefCell<Vec<(Vec<String>, CommandInput)>>,
+}
+
+impl ScriptedRunner {
+    /// Creates a runner with no scripted responses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a response for the given arguments.
+    ///
+    /// # Arguments
+    /// * `args` - The brew arguments the response applies to
+    /// * `output` - The output to return
+    pub fn respond(self, args: &[&str], output: BrewOutput) -> Self {
+        {
+            let mut responses = self.responses.borrow_mut();
+            match responses.iter_mut().find(|(a, _)| a == args) {
+                Some((_, queue)) => queue.push_back(output),
+                None => responses.push((to_owned_args(args), VecDeque::from([output]))),
+            }
+        }
+        self
+    }
+
+    /// Returns every command line received so far, e.g. `"upgrade"`.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls
+            .borrow()
+            .iter()
+            .map(|(args, _)| args.join(" "))
+            .collect()
+    }
+
+    /// Returns the input of the last call of `command`, e.g. `"cleanup"`.
+    pub fn input(&self, command: &str) -> Option<CommandInput> {
+        self.calls
+            .borrow()
+            .iter()
+            .rev()
+            .find(|(args, _)| args.join(" ") == command)
+            .map(|(_, input)| input.clone())
+    }
+}
+
+impl BrewRunner for ScriptedRunner {
+    fn capture_with(&self, args: &[&str], input: &CommandInput) -> io::Result<BrewOutput> {
+        self.calls
+            .borrow_mut()
+            .push((to_owned_args(args), input.clone()));
+
+        let mut responses = self.responses.borrow_mut();
+        let queue = responses
+            .iter_mut()
+            .find(|(a, _)| a == args)
+            .map(|(_, queue)| queue)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no scripted response for `brew {}`", args.join(" ")),
+                )
+            })?;
+
+        let output = if queue.len() > 1 {
+            queue.pop_front()
+        } else {
+            queue.front().cloned()
+        };
+        Ok(output.unwrap_or_default())
+    }
+}
+
+/// A shared runner, e.g. a `ScriptedRunner` whose calls are inspected after
+/// it was handed out as a `Box<dyn BrewRunner>`.
+impl<R: BrewRunner + ?Sized> BrewRunner for Rc<R> {
+    fn capture_with(&self, args: &[&str], input: &CommandInput) -> io::Result<BrewOutput> {
+        (**self).capture_with(args, input)
+    }
+
+    fn stream_with(
+        &self,
+        args: &[&str],
+        input: &CommandInput,
+        on_line: &mut dyn FnMut(OutputLine<'_>),
+    ) -> io::Result<BrewOutput> {
+        (**self).stream_with(args, input, on_line)
+    }
+}
+
+fn to_owned_args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that scripted responses are replayed in order and the last one repeats.
+    #[test]
+    fn test_scripted_runner_replays_responses() {
+        let runner = ScriptedRunner::new()
+            .respond(&["update"], BrewOutput::failure(1, "network down"))
+            .respond(&["update"], BrewOutput::success("Already up-to-date."));
+
+        assert!(!runner.capture(&["update"]).unwrap().is_success());
+        assert!(runner.capture(&["update"]).unwrap().is_success());
+        assert!(runner.run(&["update"]).unwrap());
+        assert_eq!(runner.calls(), vec!["update", "update", "update"]);
+    }
+
+    /// Tests that unscripted commands fail to start.
+    #[test]
+    fn test_scripted_runner_unknown_command() {
+        let runner = ScriptedRunner::new();
+        let err = runner.capture(&["doctor"]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    /// Tests that streaming forwards stdout and stderr lines.
+    #[test]
+    fn test_default_stream_forwards_lines() {
+        let runner = ScriptedRunner::new().respond(
+            &["upgrade"],
+            BrewOutput {
+                code: Some(0),
+                stdout: "==> Upgrading 1 outdated package\nwget 1.24.5".to_string(),
+                stderr: "Warning: something".to_string(),
+            },
+        );
+
+        let mut lines = Vec::new();
+        runner
+            .stream(&["upgrade"], &mut |line| lines.push(format!("{:?}", line)))
+            .unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2], "Stderr(\"Warning: something\")");
+    }
+
+    /// Tests that the process runner captures the output of a real program.
+    #[test]
+    fn test_process_runner_captures_output() {
+        let runner = ProcessRunner::with_program("sh");
+        let output = runner
+            .capture(&["-c", "echo out; echo err >&2; exit 3"])
+            .unwrap();
+        assert_eq!(output.code, Some(3));
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+    }
+
+    /// Tests that the process runner passes environment variables and
+    /// standard input to a single command only.
+    #[test]
+    fn test_process_runner_passes_input() {
+        let runner = ProcessRunner::with_program("sh").with_env("BREWUP_TEST_A", "runner");
+        let script = ["-c", "echo \"$BREWUP_TEST_A $BREWUP_TEST_B\"; cat"];
+        let input = CommandInput {
+            env: vec![("BREWUP_TEST_B".to_string(), "command".to_string())],
+            stdin: Some("from stdin\n".to_string()),
+        };
+        let output = runner.capture_with(&script, &input).unwrap();
+        assert_eq!(output.stdout, "runner command\nfrom stdin\n");
+
+        let output = runner.capture(&script).unwrap();
+        assert_eq!(output.stdout, "runner \n");
+        assert!(std::env::var_os("BREWUP_TEST_B").is_none());
+    }
+
+    /// Tests that the scripted runner records the input of each call.
+    #[test]
+    fn test_scripted_runner_records_input() {
+        let runner = ScriptedRunner::new().respond(&["cleanup"], BrewOutput::success(""));
+        runner.capture(&["cleanup"]).unwrap();
+        runner
+            .capture_with(&["cleanup"], &CommandInput::env("A", "1"))
+            .unwrap();
+        assert_eq!(runner.input("cleanup"), Some(CommandInput::env("A", "1")));
+        assert_eq!(runner.input("update"), None);
+    }
+
+    /// Tests that the process runner streams lines from both pipes.
+    #[test]
+    fn test_process_runner_streams_lines() {
+        let runner = ProcessRunner::with_program("sh");
+        let mut lines = Vec::new();
+        let output = runner
+            .stream(&["-c", "echo one; echo two >&2; echo three"], &mut |line| {
+                lines.push(match line {
+                    OutputLine::Stdout(l) => format!("out:{}", l),
+                    OutputLine::Stderr(l) => format!("err:{}", l),
+                })
+            })
+            .unwrap();
+
+        assert!(output.is_success());
+        assert_eq!(output.stdout, "one\nthree\n");
+        assert_eq!(output.stderr, "two\n");
+        let stdout_lines: Vec<_> = lines.iter().filter(|l| l.starts_with("out:")).collect();
+        assert_eq!(stdout_lines, ["out:one", "out:three"]);
+        assert!(lines.contains(&"err:two".to_string()));
+    }
+}
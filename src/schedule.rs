@@ -0,0 +1,1156 @@
+//! Scheduled runs for BrewUp.
+//!
+//! `brewup schedule` renders, installs, lists and removes a schedule entry
+//! for one of three backends:
+//!
+//! - `cron`: a line in the user's crontab, marked so it can be found again
+//! - `systemd`: a user `.service` and `.timer` pair
+//! - `launchd`: a user LaunchAgent plist
+//!
+//! Each backend is driven by a single tool (`crontab`, `systemctl` or
+//! `launchctl`), invoked through a [`BrewRunner`] so the install steps can
+//! be exercised without touching the real scheduler. Rendering needs no tool
+//! at all, so every unit file can be checked on any platform.
+
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    config::{self, constants, ScheduleConfig},
+    error::{BrewupError, CommandFailure},
+    runner::{BrewRunner, CommandInput},
+    utils::{self, say},
+};
+
+/// Marker line placed above the crontab entry managed by brewup.
+const CRON_MARKER: &str = "# brewup schedule (managed by `brewup schedule`, do not edit)";
+
+/// Label of the LaunchAgent.
+const LAUNCHD_LABEL: &str = "com.xcrong.brewup";
+
+/// Base name of the systemd units.
+const SYSTEMD_UNIT: &str = "brewup";
+
+/// Scheduler that runs BrewUp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// The user's crontab
+    Cron,
+    /// A systemd user timer
+    Systemd,
+    /// A launchd user agent (macOS)
+    Launchd,
+}
+
+impl Backend {
+    /// Every backend, in the order they are listed.
+    pub const ALL: [Backend; 3] = [Backend::Cron, Backend::Systemd, Backend::Launchd];
+
+    /// Returns the backend name used on the command line and in configuration.
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Cron => "cron",
+            Backend::Systemd => "systemd",
+            Backend::Launchd => "launchd",
+        }
+    }
+
+    /// Returns the backend with this name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|b| b.name() == name)
+    }
+
+    /// Returns the program that manages this backend's entries.
+    pub fn tool(self) -> &'static str {
+        match self {
+            Backend::Cron => "crontab",
+            Backend::Systemd => "systemctl",
+            Backend::Launchd => "launchctl",
+        }
+    }
+
+    /// Returns the native backend of this machine: launchd on macOS, a
+    /// systemd timer where systemd runs, cron otherwise.
+    pub fn detect() -> Self {
+        if cfg!(target_os = "macos") {
+            Backend::Launchd
+        } else if Path::new("/run/systemd/system").is_dir() {
+            Backend::Systemd
+        } else {
+            Backend::Cron
+        }
+    }
+}
+
+/// How often BrewUp runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    /// Every hour, at the configured minute
+    Hourly,
+    /// Every day, at the configured time
+    #[default]
+    Daily,
+    /// Once a week, on the configured weekday and time
+    Weekly,
+}
+
+impl Frequency {
+    /// Every frequency.
+    pub const ALL: [Frequency; 3] = [Frequency::Hourly, Frequency::Daily, Frequency::Weekly];
+
+    /// Returns the frequency name used on the command line and in configuration.
+    pub fn name(self) -> &'static str {
+        match self {
+            Frequency::Hourly => "hourly",
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly",
+        }
+    }
+
+    /// Returns the frequency with this name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.name() == name)
+    }
+}
+
+/// Day of the week for weekly runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    /// Sunday
+    Sunday,
+    /// Monday
+    #[default]
+    Monday,
+    /// Tuesday
+    Tuesday,
+    /// Wednesday
+    Wednesday,
+    /// Thursday
+    Thursday,
+    /// Friday
+    Friday,
+    /// Saturday
+    Saturday,
+}
+
+impl Weekday {
+    /// Every weekday, starting on Sunday as cron and launchd count them.
+    pub const ALL: [Weekday; 7] = [
+        Weekday::Sunday,
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+    ];
+
+    /// Returns the lowercase English name, e.g. `monday`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Weekday::Sunday => "sunday",
+            Weekday::Monday => "monday",
+            Weekday::Tuesday => "tuesday",
+            Weekday::Wednesday => "wednesday",
+            Weekday::Thursday => "thursday",
+            Weekday::Friday => "friday",
+            Weekday::Saturday => "saturday",
+        }
+    }
+
+    /// Returns the weekday with this name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|d| d.name() == name)
+    }
+
+    /// Returns the day number used by cron and launchd (Sunday is 0).
+    fn number(self) -> u32 {
+        self as u32
+    }
+
+    /// Returns the abbreviation used by systemd, e.g. `Mon`.
+    fn abbreviation(self) -> String {
+        let name = self.name();
+        name[..1].to_uppercase() + &name[1..3]
+    }
+}
+
+/// Time of day in 24-hour format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay {
+    /// Hour, 0 to 23
+    pub hour: u32,
+    /// Minute, 0 to 59
+    pub minute: u32,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self { hour: 9, minute: 0 }
+    }
+}
+
+impl TimeOfDay {
+    /// Parses a `HH:MM` time.
+    ///
+    /// # Arguments
+    /// * `text` - The time, e.g. `09:30` or `18:05`
+    ///
+    /// # Returns
+    /// The time, or a description of what is wrong with it.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("`{}` is not a valid time (expected HH:MM)", text);
+        let (hour, minute) = text.split_once(':').ok_or_else(invalid)?;
+        let hour: u32 = hour.parse().map_err(|_| invalid())?;
+        let minute: u32 = minute.parse().map_err(|_| invalid())?;
+        if hour > 23 || minute > 59 {
+            return Err(invalid());
+        }
+        Ok(Self { hour, minute })
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        Self::parse(&text)
+    }
+}
+
+impl std::fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+/// Where schedule entries and their logs live.
+#[derive(Debug, Clone)]
+pub struct Locations {
+    /// Directory of systemd user units
+    pub systemd_dir: PathBuf,
+    /// Directory of launchd user agents
+    pub launch_agents_dir: PathBuf,
+    /// Log file cron and launchd runs append to
+    pub log: PathBuf,
+}
+
+impl Locations {
+    /// Returns the standard locations for the current user.
+    ///
+    /// # Returns
+    /// The locations, or `None` if no home directory can be determined.
+    pub fn from_env() -> Option<Self> {
+        let home = PathBuf::from(std::env::var_os("HOME")?);
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .unwrap_or_else(|| home.join(".config"));
+        Some(Self {
+            systemd_dir: config_home.join("systemd").join("user"),
+            launch_agents_dir: home.join("Library").join("LaunchAgents"),
+            log: config::state_dir()?.join("brewup.log"),
+        })
+    }
+
+    /// Returns the path of the systemd unit with this extension.
+    fn systemd_unit(&self, extension: &str) -> PathBuf {
+        self.systemd_dir
+            .join(format!("{}.{}", SYSTEMD_UNIT, extension))
+    }
+
+    /// Returns the path of the LaunchAgent plist.
+    fn launchd_plist(&self) -> PathBuf {
+        self.launch_agents_dir
+            .join(format!("{}.plist", LAUNCHD_LABEL))
+    }
+}
+
+/// A fully resolved schedule, ready to be rendered.
+#[derive(Debug, Clone)]
+pub struct ScheduleSpec {
+    /// Scheduler that runs BrewUp
+    pub backend: Backend,
+    /// How often BrewUp runs
+    pub frequency: Frequency,
+    /// Start time (only the minute is used for hourly runs)
+    pub at: TimeOfDay,
+    /// Day of weekly runs
+    pub weekday: Weekday,
+    /// Minutes after `at` within which runs start
+    pub window: u32,
+    /// Fixed start delay used by backends without a randomized delay
+    pub splay: u32,
+    /// The brewup executable
+    pub program: PathBuf,
+    /// Arguments passed to brewup
+    pub args: Vec<String>,
+    /// `PATH` for the run, so that `brew` is found
+    pub path: String,
+    /// Log file cron and launchd runs append to
+    pub log: PathBuf,
+}
+
+impl ScheduleSpec {
+    /// Resolves the configured schedule for this machine.
+    ///
+    /// The backend defaults to [`Backend::detect`]. The program is the running
+    /// brewup; inside a Homebrew Cellar its stable `bin` link is used instead,
+    /// so the schedule survives brewup upgrades. Backends without a randomized
+    /// delay start at a fixed offset within the window, derived from the host
+    /// name so that machines sharing a configuration spread out.
+    ///
+    /// # Arguments
+    /// * `config` - The `[schedule]` configuration
+    /// * `locations` - Where the log file is kept
+    ///
+    /// # Returns
+    /// The schedule, or `BrewupError::Storage` if the executable cannot be found.
+    pub fn resolve(config: &ScheduleConfig, locations: &Locations) -> Result<Self, BrewupError> {
+        let program = std::env::current_exe().map_err(|e| BrewupError::Storage {
+            path: PathBuf::from("brewup"),
+            message: format!("cannot locate the brewup executable: {}", e),
+        })?;
+        let window = match config.frequency {
+            Frequency::Hourly => config.window.min(59),
+            _ => config.window,
+        };
+        Ok(Self {
+            backend: config.backend.unwrap_or_else(Backend::detect),
+            frequency: config.frequency,
+            at: config.at,
+            weekday: config.weekday,
+            window,
+            splay: splay(&utils::hostname(), window),
+            program: stable_program(&program),
+            args: config.args.clone(),
+            path: std::env::var("PATH").unwrap_or_default(),
+            log: locations.log.clone(),
+        })
+    }
+
+    /// Returns a human-readable description, e.g. `every Monday at 09:00`.
+    pub fn describe(&self) -> String {
+        let when = match self.frequency {
+            Frequency::Hourly => format!("hourly at minute {}", self.at.minute),
+            Frequency::Daily => format!("daily at {}", self.at),
+            Frequency::Weekly => {
+                format!("every {} at {}", capitalize(self.weekday.name()), self.at)
+            }
+        };
+        if self.window == 0 {
+            when
+        } else {
+            format!("{}, starting within {} minutes", when, self.window)
+        }
+    }
+
+    /// Returns the start time with the splay applied: weekday, hour, minute.
+    fn start(&self) -> (Weekday, u32, u32) {
+        let at = self.at.hour * 60 + self.at.minute + self.splay;
+        match self.frequency {
+            Frequency::Hourly => (
+                self.weekday,
+                self.at.hour,
+                (self.at.minute + self.splay) % 60,
+            ),
+            Frequency::Daily => (self.weekday, at / 60 % 24, at % 60),
+            Frequency::Weekly => {
+                let at = (self.weekday.number() * 1440 + at) % (7 * 1440);
+                (Weekday::ALL[(at / 1440) as usize], at % 1440 / 60, at % 60)
+            }
+        }
+    }
+
+    /// Returns the brewup command line, each part quoted with `quote`.
+    fn command(&self, quote: impl Fn(&str) -> String) -> String {
+        std::iter::once(self.program.to_string_lossy().into_owned())
+            .chain(self.args.iter().cloned())
+            .map(|part| quote(&part))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Returns the stable path of a brewup installed in a Homebrew Cellar.
+///
+/// `/opt/homebrew/Cellar/brewup/0.1.0/bin/brewup` becomes
+/// `/opt/homebrew/bin/brewup`; other paths are returned unchanged.
+fn stable_program(program: &Path) -> PathBuf {
+    let text = program.to_string_lossy();
+    match text.split_once("/Cellar/") {
+        Some((prefix, _)) => {
+            let linked = Path::new(prefix).join("bin").join("brewup");
+            if linked.exists() || !program.exists() {
+                linked
+            } else {
+                program.to_path_buf()
+            }
+        }
+        None => program.to_path_buf(),
+    }
+}
+
+/// Returns a fixed delay in `0..=window` minutes derived from `host`.
+fn splay(host: &str, window: u32) -> u32 {
+    if window == 0 {
+        return 0;
+    }
+    // FNV-1a, stable across builds unlike the standard library hasher
+    let hash = host.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    (hash % (u64::from(window) + 1)) as u32
+}
+
+/// Upper-cases the first letter of `text`.
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// A rendered schedule file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RenderedFile {
+    /// File name, e.g. `brewup.timer`
+    pub name: String,
+    /// File contents
+    pub contents: String,
+}
+
+/// Renders the files a backend needs for a schedule.
+///
+/// # Arguments
+/// * `spec` - The schedule
+///
+/// # Returns
+/// The crontab entry (`brewup.crontab`), the systemd service and timer, or
+/// the LaunchAgent plist.
+pub fn render(spec: &ScheduleSpec) -> Vec<RenderedFile> {
+    match spec.backend {
+        Backend::Cron => vec![RenderedFile {
+            name: "brewup.crontab".to_string(),
+            contents: format!("{}\n{}\n", CRON_MARKER, cron_line(spec)),
+        }],
+        Backend::Systemd => vec![
+            RenderedFile {
+                name: format!("{}.service", SYSTEMD_UNIT),
+                contents: systemd_service(spec),
+            },
+            RenderedFile {
+                name: format!("{}.timer", SYSTEMD_UNIT),
+                contents: systemd_timer(spec),
+            },
+        ],
+        Backend::Launchd => vec![RenderedFile {
+            name: format!("{}.plist", LAUNCHD_LABEL),
+            contents: launchd_plist(spec),
+        }],
+    }
+}
+
+/// Renders the crontab line of a schedule.
+fn cron_line(spec: &ScheduleSpec) -> String {
+    let (weekday, hour, minute) = spec.start();
+    let when = match spec.frequency {
+        Frequency::Hourly => format!("{} * * * *", minute),
+        Frequency::Daily => format!("{} {} * * *", minute, hour),
+        Frequency::Weekly => format!("{} {} * * {}", minute, hour, weekday.number()),
+    };
+    let command = format!(
+        "PATH={} {} >> {} 2>&1",
+        shell_quote(&spec.path),
+        spec.command(shell_quote),
+        shell_quote(&spec.log.to_string_lossy())
+    );
+    // cron turns unescaped `%` into newlines
+    format!("{} {}", when, command.replace('%', "\\%"))
+}
+
+/// Quotes `text` for a POSIX shell if it contains special characters.
+fn shell_quote(text: &str) -> String {
+    let plain = !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+".contains(c));
+    if plain {
+        text.to_string()
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    }
+}
+
+/// Quotes `text` for a systemd unit file.
+fn systemd_quote(text: &str) -> String {
+    let escaped = text.replace('%', "%%").replace('$', "$$");
+    if escaped.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\' || c == '\'') {
+        format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        escaped
+    }
+}
+
+/// Renders the systemd service unit.
+fn systemd_service(spec: &ScheduleSpec) -> String {
+    format!(
+        "[Unit]\n\
+         Description=BrewUp: update and upgrade Homebrew packages\n\
+         Documentation=https://github.com/xcrong/brewup\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         Environment={}\n\
+         ExecStart={}\n",
+        systemd_quote(&format!("PATH={}", spec.path)),
+        spec.command(systemd_quote)
+    )
+}
+
+/// Renders the systemd timer unit.
+fn systemd_timer(spec: &ScheduleSpec) -> String {
+    let at = spec.at;
+    let calendar = match spec.frequency {
+        Frequency::Hourly => format!("*-*-* *:{:02}:00", at.minute),
+        Frequency::Daily => format!("*-*-* {}:00", at),
+        Frequency::Weekly => format!("{} *-*-* {}:00", spec.weekday.abbreviation(), at),
+    };
+    let delay = match spec.window {
+        0 => String::new(),
+        window => format!("RandomizedDelaySec={}m\n", window),
+    };
+    format!(
+        "[Unit]\n\
+         Description=Run BrewUp {}\n\
+         \n\
+         [Timer]\n\
+         OnCalendar={}\n\
+         {}\
+         Persistent=true\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        spec.describe(),
+        calendar,
+        delay
+    )
+}
+
+/// Renders the LaunchAgent plist.
+fn launchd_plist(spec: &ScheduleSpec) -> String {
+    let (weekday, hour, minute) = spec.start();
+    let mut interval = format!(
+        "        <key>Minute</key>\n        <integer>{}</integer>\n",
+        minute
+    );
+    if spec.frequency != Frequency::Hourly {
+        interval.push_str(&format!(
+            "        <key>Hour</key>\n        <integer>{}</integer>\n",
+            hour
+        ));
+    }
+    if spec.frequency == Frequency::Weekly {
+        interval.push_str(&format!(
+            "        <key>Weekday</key>\n        <integer>{}</integer>\n",
+            weekday.number()
+        ));
+    }
+    let arguments: String = std::iter::once(spec.program.to_string_lossy().into_owned())
+        .chain(spec.args.iter().cloned())
+        .map(|arg| format!("        <string>{}</string>\n", xml_escape(&arg)))
+        .collect();
+    let log = xml_escape(&spec.log.to_string_lossy());
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n\
+         \x20   <key>Label</key>\n\
+         \x20   <string>{}</string>\n\
+         \x20   <key>ProgramArguments</key>\n\
+         \x20   <array>\n\
+         {}\
+         \x20   </array>\n\
+         \x20   <key>StartCalendarInterval</key>\n\
+         \x20   <dict>\n\
+         {}\
+         \x20   </dict>\n\
+         \x20   <key>EnvironmentVariables</key>\n\
+         \x20   <dict>\n\
+         \x20       <key>PATH</key>\n\
+         \x20       <string>{}</string>\n\
+         \x20   </dict>\n\
+         \x20   <key>StandardOutPath</key>\n\
+         \x20   <string>{}</string>\n\
+         \x20   <key>StandardErrorPath</key>\n\
+         \x20   <string>{}</string>\n\
+         </dict>\n\
+         </plist>\n",
+        LAUNCHD_LABEL,
+        arguments,
+        interval,
+        xml_escape(&spec.path),
+        log,
+        log
+    )
+}
+
+/// Escapes the XML special characters in `text`.
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Returns `crontab` with the brewup entry replaced by `entry`, or removed.
+///
+/// # Arguments
+/// * `crontab` - The current crontab
+/// * `entry` - The new crontab line; `None` only removes the old entry
+///
+/// # Returns
+/// The new crontab; other entries are kept as they are.
+pub fn edit_crontab(crontab: &str, entry: Option<&str>) -> String {
+    let mut lines = Vec::new();
+    let mut skip_next = false;
+    for line in crontab.lines() {
+        if skip_next {
+            skip_next = false;
+        } else if line == CRON_MARKER {
+            skip_next = true;
+        } else {
+            lines.push(line);
+        }
+    }
+    let mut crontab: String = lines.iter().map(|l| format!("{}\n", l)).collect();
+    if let Some(entry) = entry {
+        crontab.push_str(&format!("{}\n{}\n", CRON_MARKER, entry));
+    }
+    crontab
+}
+
+/// An installed schedule entry.
+#[derive(Debug, Clone, Serialize)]
+pub struct InstalledSchedule {
+    /// Scheduler holding the entry
+    pub backend: Backend,
+    /// Where the entry lives
+    pub location: String,
+    /// The schedule as written in the entry
+    pub schedule: String,
+    /// Whether the scheduler reports the entry as active, if it can tell
+    pub active: Option<bool>,
+}
+
+/// Installs (or replaces) the schedule and enables it.
+///
+/// # Arguments
+/// * `spec` - The schedule
+/// * `locations` - Where unit files are written
+/// * `tool` - Runner for the backend's tool (`crontab`, `systemctl` or `launchctl`)
+///
+/// # Returns
+/// The files written or `crontab`, `BrewupError::ScheduleFailed` if the tool
+/// failed, or `BrewupError::Storage` if a file could not be written.
+pub fn install(
+    spec: &ScheduleSpec,
+    locations: &Locations,
+    tool: &dyn BrewRunner,
+) -> Result<Vec<String>, BrewupError> {
+    let files = render(spec);
+    // cron and launchd only append to the log, they do not create its directory
+    if spec.backend != Backend::Systemd {
+        if let Some(dir) = spec.log.parent() {
+            fs::create_dir_all(dir).map_err(|e| BrewupError::Storage {
+                path: dir.to_path_buf(),
+                message: e.to_string(),
+            })?;
+        }
+    }
+    match spec.backend {
+        Backend::Cron => {
+            let crontab = edit_crontab(&read_crontab(tool)?, Some(&cron_line(spec)));
+            write_crontab(tool, &crontab)?;
+            Ok(vec!["crontab".to_string()])
+        }
+        Backend::Systemd => {
+            let mut written = Vec::new();
+            for file in &files {
+                let path = locations.systemd_dir.join(&file.name);
+                write_file(&path, &file.contents)?;
+                written.push(path.display().to_string());
+            }
+            run_tool(tool, &["--user", "daemon-reload"])?;
+            run_tool(
+                tool,
+                &[
+                    "--user",
+                    "enable",
+                    "--now",
+                    &format!("{}.timer", SYSTEMD_UNIT),
+                ],
+            )?;
+            Ok(written)
+        }
+        Backend::Launchd => {
+            let plist = locations.launchd_plist();
+            write_file(&plist, &files[0].contents)?;
+            let plist = plist.to_string_lossy();
+            // Unloading an agent that is not loaded fails harmlessly
+            let _ = tool.capture(&["unload", &plist]);
+            run_tool(tool, &["load", "-w", &plist])?;
+            Ok(vec![plist.into_owned()])
+        }
+    }
+}
+
+/// Returns the schedule installed for a backend, if any.
+///
+/// # Arguments
+/// * `backend` - The backend to look at
+/// * `locations` - Where unit files are written
+/// * `tool` - Runner for the backend's tool
+///
+/// # Returns
+/// The installed entry, `None` if there is none or the backend is not available here.
+pub fn installed(
+    backend: Backend,
+    locations: &Locations,
+    tool: &dyn BrewRunner,
+) -> Result<Option<InstalledSchedule>, BrewupError> {
+    match backend {
+        Backend::Cron => {
+            // Without a crontab program there is no crontab entry either
+            let crontab = match tool.capture(&["-l"]) {
+                Ok(output) if output.is_success() => output.stdout,
+                _ => return Ok(None),
+            };
+            let mut lines = crontab.lines();
+            Ok(lines
+                .by_ref()
+                .find(|l| *l == CRON_MARKER)
+                .and(lines.next())
+                .map(|line| InstalledSchedule {
+                    backend,
+                    location: "crontab".to_string(),
+                    schedule: line
+                        .split_whitespace()
+                        .take(5)
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    active: Some(true),
+                }))
+        }
+        Backend::Systemd => {
+            let timer = locations.systemd_unit("timer");
+            let Ok(contents) = fs::read_to_string(&timer) else {
+                return Ok(None);
+            };
+            let active = tool
+                .capture(&["--user", "is-active", &format!("{}.timer", SYSTEMD_UNIT)])
+                .ok()
+                .map(|o| o.stdout.trim() == "active");
+            Ok(Some(InstalledSchedule {
+                backend,
+                location: timer.display().to_string(),
+                schedule: contents
+                    .lines()
+                    .filter(|l| {
+                        l.starts_with("OnCalendar=") || l.starts_with("RandomizedDelaySec=")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                active,
+            }))
+        }
+        Backend::Launchd => {
+            let plist = locations.launchd_plist();
+            if !plist.is_file() {
+                return Ok(None);
+            }
+            let active = tool
+                .capture(&["list", LAUNCHD_LABEL])
+                .ok()
+                .map(|o| o.is_success());
+            Ok(Some(InstalledSchedule {
+                backend,
+                location: plist.display().to_string(),
+                schedule: "StartCalendarInterval".to_string(),
+                active,
+            }))
+        }
+    }
+}
+
+/// Disables and removes the schedule of a backend.
+///
+/// # Arguments
+/// * `backend` - The backend to remove the schedule from
+/// * `locations` - Where unit files are written
+/// * `tool` - Runner for the backend's tool
+///
+/// # Returns
+/// `true` if a schedule was removed, `false` if there was none.
+pub fn remove(
+    backend: Backend,
+    locations: &Locations,
+    tool: &dyn BrewRunner,
+) -> Result<bool, BrewupError> {
+    match backend {
+        Backend::Cron => {
+            if installed(backend, locations, tool)?.is_none() {
+                return Ok(false);
+            }
+            let crontab = edit_crontab(&read_crontab(tool)?, None);
+            write_crontab(tool, &crontab)?;
+            Ok(true)
+        }
+        Backend::Systemd => {
+            let units = [
+                locations.systemd_unit("timer"),
+                locations.systemd_unit("service"),
+            ];
+            if !units[0].exists() {
+                return Ok(false);
+            }
+            // A timer that was never enabled cannot be disabled; remove it anyway
+            let _ = tool.capture(&[
+                "--user",
+                "disable",
+                "--now",
+                &format!("{}.timer", SYSTEMD_UNIT),
+            ]);
+            for unit in &units {
+                remove_file(unit)?;
+            }
+            run_tool(tool, &["--user", "daemon-reload"])?;
+            Ok(true)
+        }
+        Backend::Launchd => {
+            let plist = locations.launchd_plist();
+            if !plist.exists() {
+                return Ok(false);
+            }
+            let _ = tool.capture(&["unload", "-w", &plist.to_string_lossy()]);
+            remove_file(&plist)?;
+            Ok(true)
+        }
+    }
+}
+
+/// Displays the installed schedules as a table.
+///
+/// # Arguments
+/// * `schedules` - The installed schedules
+pub fn show_list(schedules: &[InstalledSchedule]) {
+    if schedules.is_empty() {
+        utils::show_info(
+            constants::EMOJI_SCHEDULE,
+            "No schedule is installed (see `brewup schedule install`)",
+            colored::Color::Yellow,
+        );
+        return;
+    }
+    say!(
+        "{}",
+        format!(
+            "{:<8}  {:<8}  {:<40}  {}",
+            "Backend", "Active", "Schedule", "Location"
+        )
+        .bold()
+    );
+    for schedule in schedules {
+        say!(
+            "{:<8}  {:<8}  {:<40}  {}",
+            schedule.backend.name(),
+            match schedule.active {
+                Some(true) => "yes",
+                Some(false) => "no",
+                None => "unknown",
+            },
+            schedule.schedule,
+            schedule.location
+        );
+    }
+}
+
+/// Reads the user's crontab; a user without one has an empty crontab.
+fn read_crontab(tool: &dyn BrewRunner) -> Result<String, BrewupError> {
+    let output = tool
+        .capture(&["-l"])
+        .map_err(|e| tool_error("crontab", e))?;
+    if output.is_success() {
+        Ok(output.stdout)
+    } else if output.stderr.contains("no crontab") {
+        Ok(String::new())
+    } else {
+        Err(BrewupError::ScheduleFailed(CommandFailure {
+            message: format!("crontab -l failed: {}", output.stderr.trim()),
+            output: Some(output),
+        }))
+    }
+}
+
+/// Replaces the user's crontab, passing it to `crontab -` on standard input
+/// so that no file another user could swap out is involved.
+fn write_crontab(tool: &dyn BrewRunner, crontab: &str) -> Result<(), BrewupError> {
+    run_tool_with(tool, &["-"], &CommandInput::stdin(crontab))
+}
+
+/// Runs the backend tool and fails on a non-zero exit.
+fn run_tool(tool: &dyn BrewRunner, args: &[&str]) -> Result<(), BrewupError> {
+    run_tool_with(tool, args, &CommandInput::default())
+}
+
+/// Runs the backend tool with the given input and fails on a non-zero exit.
+fn run_tool_with(
+    tool: &dyn BrewRunner,
+    args: &[&str],
+    input: &CommandInput,
+) -> Result<(), BrewupError> {
+    let output = tool
+        .capture_with(args, input)
+        .map_err(|e| tool_error(&args.join(" "), e))?;
+    if output.is_success() {
+        return Ok(());
+    }
+    Err(BrewupError::ScheduleFailed(CommandFailure {
+        message: format!("`{}` failed: {}", args.join(" "), output.stderr.trim()),
+        output: Some(output),
+    }))
+}
+
+/// Builds the error for a tool that could not be started.
+fn tool_error(command: &str, error: std::io::Error) -> BrewupError {
+    BrewupError::ScheduleFailed(CommandFailure {
+        message: format!("could not run `{}`: {}", command, error),
+        output: None,
+    })
+}
+
+/// Writes a file, creating its directory.
+fn write_file(path: &Path, contents: &str) -> Result<(), BrewupError> {
+    let storage_error = |e: std::io::Error| BrewupError::Storage {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(storage_error)?;
+    }
+    fs::write(path, contents).map_err(storage_error)
+}
+
+/// Removes a file that may already be gone.
+fn remove_file(path: &Path) -> Result<(), BrewupError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(BrewupError::Storage {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{BrewOutput, ScriptedRunner};
+
+    fn spec(backend: Backend, frequency: Frequency) -> ScheduleSpec {
+        ScheduleSpec {
+            backend,
+            frequency,
+            at: TimeOfDay {
+                hour: 23,
+                minute: 50,
+            },
+            weekday: Weekday::Saturday,
+            window: 0,
+            splay: 0,
+            program: PathBuf::from("/opt/homebrew/bin/brewup"),
+            args: vec!["--exclude".to_string(), "node 50%".to_string()],
+            path: "/opt/homebrew/bin:/usr/bin".to_string(),
+            log: PathBuf::from("/Users/me/.local/state/brewup/brewup.log"),
+        }
+    }
+
+    fn locations(dir: &Path) -> Locations {
+        Locations {
+            systemd_dir: dir.join("systemd"),
+            launch_agents_dir: dir.join("LaunchAgents"),
+            log: dir.join("brewup.log"),
+        }
+    }
+
+    /// Tests `HH:MM` parsing and its bounds.
+    #[test]
+    fn test_time_of_day() {
+        assert_eq!(
+            TimeOfDay::parse("7:05"),
+            Ok(TimeOfDay { hour: 7, minute: 5 })
+        );
+        assert!(TimeOfDay::parse("24:00").is_err());
+        assert!(TimeOfDay::parse("09:60").is_err());
+        assert!(TimeOfDay::parse("0900").is_err());
+    }
+
+    /// Tests the crontab line, including the splay crossing into the next week.
+    #[test]
+    fn test_render_cron() {
+        let mut spec = spec(Backend::Cron, Frequency::Weekly);
+        spec.window = 30;
+        spec.splay = 15;
+        let files = render(&spec);
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            files[0].contents.lines().nth(1).unwrap(),
+            "5 0 * * 0 PATH=/opt/homebrew/bin:/usr/bin /opt/homebrew/bin/brewup --exclude \
+             'node 50\\%' >> /Users/me/.local/state/brewup/brewup.log 2>&1"
+        );
+        assert!(splay("host", 30) <= 30);
+        assert_eq!(splay("host", 0), 0);
+    }
+
+    /// Tests the systemd units of a weekly schedule with a start window.
+    #[test]
+    fn test_render_systemd() {
+        let mut spec = spec(Backend::Systemd, Frequency::Weekly);
+        spec.window = 30;
+        let files = render(&spec);
+        assert_eq!(files[0].name, "brewup.service");
+        assert!(files[0]
+            .contents
+            .contains("ExecStart=/opt/homebrew/bin/brewup --exclude \"node 50%%\"\n"));
+        assert_eq!(files[1].name, "brewup.timer");
+        assert!(files[1]
+            .contents
+            .contains("OnCalendar=Sat *-*-* 23:50:00\nRandomizedDelaySec=30m\n"));
+        assert!(files[1]
+            .contents
+            .contains("every Saturday at 23:50, starting within 30 minutes"));
+    }
+
+    /// Tests the launchd plist of an hourly schedule.
+    #[test]
+    fn test_render_launchd() {
+        let mut spec = spec(Backend::Launchd, Frequency::Hourly);
+        spec.args = vec!["--exclude".to_string(), "a&b".to_string()];
+        let plist = &render(&spec)[0];
+        assert_eq!(plist.name, "com.xcrong.brewup.plist");
+        assert!(plist.contents.contains("<string>a&amp;b</string>"));
+        assert!(plist
+            .contents
+            .contains("<key>Minute</key>\n        <integer>50</integer>\n    </dict>"));
+        assert!(!plist.contents.contains("<key>Hour</key>"));
+    }
+
+    /// Tests that the managed crontab entry is replaced and removed without touching others.
+    #[test]
+    fn test_edit_crontab() {
+        let crontab = "MAILTO=me\n0 1 * * * backup\n";
+        let installed = edit_crontab(crontab, Some("0 9 * * * brewup"));
+        let replaced = edit_crontab(&installed, Some("0 10 * * * brewup"));
+        assert_eq!(
+            replaced,
+            format!(
+                "MAILTO=me\n0 1 * * * backup\n{}\n0 10 * * * brewup\n",
+                CRON_MARKER
+            )
+        );
+        assert_eq!(edit_crontab(&replaced, None), crontab);
+    }
+
+    /// Tests installing, listing and removing the systemd timer.
+    #[test]
+    fn test_systemd_install_and_remove() {
+        let dir = std::env::temp_dir().join(format!("brewup-schedule-{}", std::process::id()));
+        let locations = locations(&dir);
+        let systemctl = ScriptedRunner::new()
+            .respond(&["--user", "daemon-reload"], BrewOutput::success(""))
+            .respond(
+                &["--user", "enable", "--now", "brewup.timer"],
+                BrewOutput::success(""),
+            )
+            .respond(
+                &["--user", "is-active", "brewup.timer"],
+                BrewOutput::success("active\n"),
+            )
+            .respond(
+                &["--user", "disable", "--now", "brewup.timer"],
+                BrewOutput::success(""),
+            );
+
+        let written = install(
+            &spec(Backend::Systemd, Frequency::Daily),
+            &locations,
+            &systemctl,
+        )
+        .unwrap();
+        assert_eq!(written.len(), 2);
+        let installed = installed(Backend::Systemd, &locations, &systemctl)
+            .unwrap()
+            .unwrap();
+        assert_eq!(installed.schedule, "OnCalendar=*-*-* 23:50:00");
+        assert_eq!(installed.active, Some(true));
+
+        assert!(remove(Backend::Systemd, &locations, &systemctl).unwrap());
+        assert!(!locations.systemd_unit("timer").exists());
+        assert!(!remove(Backend::Systemd, &locations, &systemctl).unwrap());
+        assert_eq!(
+            systemctl.calls(),
+            [
+                "--user daemon-reload",
+                "--user enable --now brewup.timer",
+                "--user is-active brewup.timer",
+                "--user disable --now brewup.timer",
+                "--user daemon-reload",
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Tests that the cron entry is added to the existing crontab, which is
+    /// written through standard input.
+    #[test]
+    fn test_cron_install() {
+        let dir = std::env::temp_dir().join(format!("brewup-cron-ok-{}", std::process::id()));
+        let mut spec = spec(Backend::Cron, Frequency::Daily);
+        spec.log = dir.join("brewup.log");
+        let crontab = ScriptedRunner::new()
+            .respond(&["-l"], BrewOutput::success("MAILTO=me\n"))
+            .respond(&["-"], BrewOutput::success(""));
+        assert_eq!(
+            install(&spec, &locations(&dir), &crontab).unwrap(),
+            ["crontab"]
+        );
+        assert_eq!(crontab.calls(), ["-l", "-"]);
+        let written = crontab.input("-").unwrap().stdin.unwrap();
+        assert!(written.starts_with("MAILTO=me\n"));
+        assert!(written.contains(CRON_MARKER));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Tests that a failing crontab write is reported as a schedule failure.
+    #[test]
+    fn test_cron_install_failure() {
+        let dir = std::env::temp_dir().join(format!("brewup-cron-{}", std::process::id()));
+        let mut spec = spec(Backend::Cron, Frequency::Daily);
+        spec.log = dir.join("brewup.log");
+        let crontab =
+            ScriptedRunner::new().respond(&["-l"], BrewOutput::failure(1, "no crontab for me"));
+        let err = install(&spec, &locations(&dir), &crontab).unwrap_err();
+        assert_eq!(err.exit_code(), 11);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,137 @@
+//! Homebrew services of upgraded formulae.
+//!
+//! A service started with `brew services` keeps running the old binary after
+//! its formula is upgraded. The upgrade step lists the running services with
+//! `brew services list --json` before upgrading and restarts the ones whose
+//! formula was upgraded afterwards, or only reports them with `--no-restart`.
+
+use serde::Deserialize;
+
+use crate::cli::Verbosity;
+use crate::error::CommandFailure;
+use crate::runner::{BrewOutput, BrewRunner};
+use crate::utils;
+
+/// A service as listed by `brew services list --json`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Service {
+    /// Formula the service belongs to, e.g. `postgresql@14`
+    pub name: String,
+    /// Service status, e.g. `started`, `stopped` or `none`
+    pub status: String,
+    /// User the service runs as, if it is loaded
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+impl Service {
+    /// Returns `true` if the service is running.
+    pub fn is_running(&self) -> bool {
+        self.status == "started"
+    }
+
+    /// Returns `true` if the service was started with `sudo brew services`.
+    ///
+    /// Such services can only be restarted as root, which brewup never is.
+    pub fn runs_as_root(&self) -> bool {
+        self.user.as_deref() == Some("root")
+    }
+
+    /// Returns the command that restarts the service by hand.
+    pub fn restart_command(&self) -> String {
+        let sudo = if self.runs_as_root() { "sudo " } else { "" };
+        format!("{}brew services restart {}", sudo, self.name)
+    }
+}
+
+/// Lists the running services.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+///
+/// # Returns
+/// The running services, or the failure if they could not be listed.
+pub fn running_services(runner: &dyn BrewRunner) -> Result<Vec<Service>, CommandFailure> {
+    let output = runner
+        .capture(&["services", "list", "--json"])
+        .map_err(|e| CommandFailure {
+            message: format!("Failed to execute command: {}", e),
+            output: None,
+        })?;
+    if !output.is_success() {
+        return Err(CommandFailure {
+            message: output.stderr.trim().to_string(),
+            output: Some(output),
+        });
+    }
+    let services = parse_services(&output.stdout).map_err(|message| CommandFailure {
+        message,
+        output: Some(output),
+    })?;
+    Ok(services.into_iter().filter(Service::is_running).collect())
+}
+
+/// Parses the output of `brew services list --json`.
+///
+/// # Arguments
+/// * `json` - The JSON document printed by brew
+///
+/// # Returns
+/// Every listed service in listing order.
+pub fn parse_services(json: &str) -> Result<Vec<Service>, String> {
+    // Without any services brew prints nothing at all
+    if json.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    serde_json::from_str(json).map_err(|e| format!("Invalid brew services output: {}", e))
+}
+
+/// Restarts a service with `brew services restart`.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+/// * `service` - The service to restart
+/// * `verbosity` - How much of brew's output to show
+///
+/// # Returns
+/// The output of brew, or the failure if the restart failed.
+pub fn restart(
+    runner: &dyn BrewRunner,
+    service: &Service,
+    verbosity: Verbosity,
+) -> Result<BrewOutput, CommandFailure> {
+    utils::run_brew_command(runner, &["services", "restart", &service.name], verbosity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::ScriptedRunner;
+
+    const SERVICES: &str = r#"[
+        {"name": "postgresql@14", "status": "started", "user": "me", "file": "/Users/me/Library/LaunchAgents/homebrew.mxcl.postgresql@14.plist", "exit_code": 0},
+        {"name": "redis", "status": "stopped", "user": null, "file": "/opt/homebrew/opt/redis/homebrew.mxcl.redis.plist", "exit_code": null},
+        {"name": "dnsmasq", "status": "started", "user": "root", "file": "/Library/LaunchDaemons/homebrew.mxcl.dnsmasq.plist", "exit_code": 0},
+        {"name": "unbound", "status": "none"}
+    ]"#;
+
+    /// Tests that only running services are returned, with their user.
+    #[test]
+    fn test_running_services() {
+        let runner = ScriptedRunner::new().respond(
+            &["services", "list", "--json"],
+            BrewOutput::success(SERVICES),
+        );
+        let running = running_services(&runner).unwrap();
+        let names: Vec<_> = running.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["postgresql@14", "dnsmasq"]);
+        assert!(!running[0].runs_as_root());
+        assert_eq!(
+            running[1].restart_command(),
+            "sudo brew services restart dnsmasq"
+        );
+
+        assert_eq!(parse_services("\n").unwrap(), []);
+        assert!(parse_services("Error: nope").is_err());
+    }
+}
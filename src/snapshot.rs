@@ -0,0 +1,355 @@
+//! Pre-upgrade snapshots for BrewUp.
+//!
+//! Before packages are upgraded, BrewUp records the exact installed versions
+//! (`brew list --versions`) and a Brewfile (`brew bundle dump`) in a
+//! [`Snapshot`]. Each snapshot is a directory holding `snapshot.json` and,
+//! when the dump succeeded, a `Brewfile`. Only the most recent snapshots are
+//! kept. `brewup rollback` restores them (see the `rollback` module).
+
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::{
+    config::constants,
+    error::{BrewupError, CommandFailure},
+    packages::{self, InstalledPackage, PackageKind},
+    runner::BrewRunner,
+    utils::{self, say},
+};
+
+/// Name of the metadata file inside a snapshot directory.
+const METADATA_FILE: &str = "snapshot.json";
+
+/// Name of the Brewfile inside a snapshot directory.
+const BREWFILE: &str = "Brewfile";
+
+/// The installed packages at one point in time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Identifier derived from the creation time, e.g. `20240501T093000Z`
+    pub id: String,
+    /// Creation time in RFC 3339 format (UTC)
+    pub created_at: String,
+    /// Name of the machine the snapshot was taken on
+    pub host: String,
+    /// Installed formulae and casks with their exact versions
+    pub packages: Vec<InstalledPackage>,
+    /// Contents of `brew bundle dump`, if it succeeded
+    #[serde(skip)]
+    pub brewfile: Option<String>,
+}
+
+impl Snapshot {
+    /// Returns the snapshotted package with this name and kind.
+    ///
+    /// # Arguments
+    /// * `name` - Package name
+    /// * `kind` - Whether the package is a formula or a cask
+    pub fn package(&self, name: &str, kind: PackageKind) -> Option<&InstalledPackage> {
+        self.packages
+            .iter()
+            .find(|p| p.kind == kind && p.name == name)
+    }
+}
+
+/// Records the installed packages and a Brewfile.
+///
+/// A failing `brew bundle dump` only leaves the Brewfile out; the exact
+/// versions are what a rollback needs most.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+///
+/// # Returns
+/// The snapshot (with an empty `id` until it is saved), or the failure if
+/// the installed packages could not be listed.
+pub fn capture(runner: &dyn BrewRunner) -> Result<Snapshot, CommandFailure> {
+    let packages = packages::installed_packages(runner)?;
+    let brewfile = match runner.capture(&["bundle", "dump", "--file=-"]) {
+        Ok(output) if output.is_success() => Some(output.stdout),
+        Ok(output) => {
+            utils::show_warning(&format!(
+                "Could not dump a Brewfile, keeping versions only: {}",
+                output.stderr.trim()
+            ));
+            None
+        }
+        Err(e) => {
+            utils::show_warning(&format!(
+                "Could not dump a Brewfile, keeping versions only: {}",
+                e
+            ));
+            None
+        }
+    };
+    Ok(Snapshot {
+        id: String::new(),
+        created_at: utils::format_timestamp(SystemTime::now()),
+        host: utils::hostname(),
+        packages,
+        brewfile,
+    })
+}
+
+/// Saves a snapshot and removes the oldest ones beyond `keep`.
+///
+/// The snapshot gets an id derived from its creation time. It is written to
+/// a temporary directory first and renamed into place, so a crash never
+/// leaves a half-written snapshot behind.
+///
+/// # Arguments
+/// * `dir` - The snapshot directory, created if needed
+/// * `snapshot` - The snapshot to save; its `id` is assigned here
+/// * `keep` - Number of snapshots to keep
+///
+/// # Returns
+/// The assigned id, or `BrewupError::Storage`.
+pub fn save(dir: &Path, mut snapshot: Snapshot, keep: usize) -> Result<String, BrewupError> {
+    fs::create_dir_all(dir).map_err(|e| storage_error(dir, e))?;
+    let base: String = snapshot
+        .created_at
+        .chars()
+        .filter(|c| !matches!(c, '-' | ':'))
+        .collect();
+    snapshot.id = (1..)
+        .map(|n| match n {
+            1 => base.clone(),
+            n => format!("{}-{}", base, n),
+        })
+        .find(|id| !dir.join(id).exists())
+        .unwrap_or(base);
+
+    let temp = dir.join(format!(".{}.tmp", snapshot.id));
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir(&temp).map_err(|e| storage_error(&temp, e))?;
+    let metadata = serde_json::to_string_pretty(&snapshot).map_err(|e| storage_error(dir, e))?;
+    fs::write(temp.join(METADATA_FILE), metadata).map_err(|e| storage_error(&temp, e))?;
+    if let Some(brewfile) = &snapshot.brewfile {
+        fs::write(temp.join(BREWFILE), brewfile).map_err(|e| storage_error(&temp, e))?;
+    }
+    let path = dir.join(&snapshot.id);
+    fs::rename(&temp, &path).map_err(|e| storage_error(&path, e))?;
+
+    let snapshots = list(dir)?;
+    for old in &snapshots[..snapshots.len().saturating_sub(keep.max(1))] {
+        let path = dir.join(&old.id);
+        fs::remove_dir_all(&path).map_err(|e| storage_error(&path, e))?;
+    }
+    Ok(snapshot.id)
+}
+
+/// Reads every snapshot in a directory.
+///
+/// A missing directory holds no snapshots. Directories without readable
+/// metadata are ignored.
+///
+/// # Arguments
+/// * `dir` - The snapshot directory
+///
+/// # Returns
+/// The snapshots, oldest first, or `BrewupError::Storage`.
+pub fn list(dir: &Path) -> Result<Vec<Snapshot>, BrewupError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(storage_error(dir, e)),
+    };
+    let mut snapshots: Vec<Snapshot> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|entry| load(&entry.path()))
+        .collect();
+    snapshots.sort_by(|a, b| {
+        (&a.created_at, a.id.len(), &a.id).cmp(&(&b.created_at, b.id.len(), &b.id))
+    });
+    Ok(snapshots)
+}
+
+/// Reads one snapshot directory, `None` if it holds no valid snapshot.
+fn load(path: &Path) -> Option<Snapshot> {
+    let metadata = fs::read_to_string(path.join(METADATA_FILE)).ok()?;
+    let mut snapshot: Snapshot = serde_json::from_str(&metadata).ok()?;
+    snapshot.id = path.file_name()?.to_string_lossy().into_owned();
+    snapshot.brewfile = fs::read_to_string(path.join(BREWFILE)).ok();
+    Some(snapshot)
+}
+
+/// Finds a snapshot by id, or the most recent one.
+///
+/// # Arguments
+/// * `dir` - The snapshot directory
+/// * `id` - The snapshot to find; `None` for the most recent one
+///
+/// # Returns
+/// The snapshot, or `BrewupError::Storage` if there is no such snapshot.
+pub fn find(dir: &Path, id: Option<&str>) -> Result<Snapshot, BrewupError> {
+    let mut snapshots = list(dir)?;
+    let index = match id {
+        Some(id) => snapshots.iter().position(|s| s.id == id),
+        None => snapshots.len().checked_sub(1),
+    };
+    match (index, id) {
+        (Some(index), _) => Ok(snapshots.swap_remove(index)),
+        (None, Some(id)) => Err(storage_error(dir, format!("no snapshot `{}`", id))),
+        (None, None) => Err(storage_error(dir, "no snapshots have been taken yet")),
+    }
+}
+
+/// Returns the path of a snapshot's Brewfile, if it has one.
+///
+/// # Arguments
+/// * `dir` - The snapshot directory
+/// * `snapshot` - The snapshot
+pub fn brewfile_path(dir: &Path, snapshot: &Snapshot) -> Option<PathBuf> {
+    snapshot
+        .brewfile
+        .as_ref()
+        .map(|_| dir.join(&snapshot.id).join(BREWFILE))
+}
+
+/// Builds a `BrewupError::Storage` for `path`.
+fn storage_error(path: &Path, error: impl std::fmt::Display) -> BrewupError {
+    BrewupError::Storage {
+        path: path.to_path_buf(),
+        message: error.to_string(),
+    }
+}
+
+/// Displays the stored snapshots as a table, most recent first.
+///
+/// # Arguments
+/// * `snapshots` - The snapshots, oldest first
+pub fn show_list(snapshots: &[Snapshot]) {
+    if snapshots.is_empty() {
+        utils::show_info(
+            constants::EMOJI_SNAPSHOT,
+            "No snapshots have been taken yet",
+            colored::Color::Yellow,
+        );
+        return;
+    }
+    let id_width = snapshots
+        .iter()
+        .map(|s| s.id.len())
+        .max()
+        .unwrap_or(0)
+        .max(8);
+    say!(
+        "{}",
+        format!(
+            "{:<id_width$}  {:<19}  {:>8}  {:>5}  {}",
+            "Snapshot", "Created (UTC)", "Formulae", "Casks", "Brewfile"
+        )
+        .bold()
+    );
+    for snapshot in snapshots.iter().rev() {
+        let count = |kind| snapshot.packages.iter().filter(|p| p.kind == kind).count();
+        say!(
+            "{:<id_width$}  {:<19}  {:>8}  {:>5}  {}",
+            snapshot.id,
+            snapshot.created_at.replace('T', " ").trim_end_matches('Z'),
+            count(PackageKind::Formula),
+            count(PackageKind::Cask),
+            if snapshot.brewfile.is_some() {
+                "yes"
+            } else {
+                "no"
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{BrewOutput, ScriptedRunner};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("brewup-snapshot-{}-{}", std::process::id(), name))
+    }
+
+    fn snapshot(created_at: &str) -> Snapshot {
+        Snapshot {
+            id: String::new(),
+            created_at: created_at.to_string(),
+            host: "mbp".to_string(),
+            packages: packages::parse_installed("wget 1.24.4\n", PackageKind::Formula),
+            brewfile: Some("brew \"wget\"\n".to_string()),
+        }
+    }
+
+    /// Tests that a snapshot records versions and a Brewfile, or only versions.
+    #[test]
+    fn test_capture() {
+        let runner = ScriptedRunner::new()
+            .respond(
+                &["list", "--formula", "--versions"],
+                BrewOutput::success("git 2.45.1\nwget 1.24.4\n"),
+            )
+            .respond(
+                &["list", "--cask", "--versions"],
+                BrewOutput::success("firefox 125.0\n"),
+            )
+            .respond(
+                &["bundle", "dump", "--file=-"],
+                BrewOutput::success("brew \"git\"\nbrew \"wget\"\ncask \"firefox\"\n"),
+            );
+        let snapshot = capture(&runner).unwrap();
+        assert_eq!(snapshot.packages.len(), 3);
+        assert_eq!(
+            snapshot
+                .package("firefox", PackageKind::Cask)
+                .unwrap()
+                .version(),
+            "125.0"
+        );
+        assert!(snapshot.brewfile.unwrap().contains("cask \"firefox\""));
+
+        let runner = ScriptedRunner::new()
+            .respond(
+                &["list", "--formula", "--versions"],
+                BrewOutput::success("wget 1.24.4\n"),
+            )
+            .respond(&["list", "--cask", "--versions"], BrewOutput::success(""))
+            .respond(
+                &["bundle", "dump", "--file=-"],
+                BrewOutput::failure(1, "Error: Unknown command: bundle"),
+            );
+        assert!(capture(&runner).unwrap().brewfile.is_none());
+    }
+
+    /// Tests that saved snapshots get unique ids and only the newest are kept.
+    #[test]
+    fn test_save_keeps_newest() {
+        let dir = temp_dir("save");
+        let _ = fs::remove_dir_all(&dir);
+
+        for created_at in [
+            "2024-05-01T09:00:00Z",
+            "2024-05-02T09:00:00Z",
+            "2024-05-03T09:00:00Z",
+            "2024-05-03T09:00:00Z",
+        ] {
+            save(&dir, snapshot(created_at), 3).unwrap();
+        }
+        let ids: Vec<String> = list(&dir).unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(
+            ids,
+            ["20240502T090000Z", "20240503T090000Z", "20240503T090000Z-2"]
+        );
+
+        let latest = find(&dir, None).unwrap();
+        assert_eq!(latest.id, "20240503T090000Z-2");
+        assert_eq!(latest.packages[0].version(), "1.24.4");
+        assert!(brewfile_path(&dir, &latest).unwrap().is_file());
+        assert_eq!(find(&dir, Some("20240502T090000Z")).unwrap().host, "mbp");
+        assert!(find(&dir, Some("20240501T090000Z")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(find(&dir, None).is_err());
+    }
+}
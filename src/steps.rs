@@ -0,0 +1,1461 @@
+//! The workflow steps of BrewUp.
+//!
+//! Each step implements [`Step`] and is listed in [`registry`], which
+//! defines the default order of a run and the names accepted by `--only`,
+//! `--skip` and the `[pipeline]` configuration.
+
+use std::path::{Path, PathBuf};
+
+use colored::*;
+
+use crate::{
+    caveats,
+    changes::{self, ChangeKind, VersionChange},
+    cleanup::{self, CleanupReport, DiskUsage},
+    config::{constants, Config},
+    diagnosis,
+    error::{BrewupError, CommandFailure},
+    packages::{self, GreedyMode, HeldPackage, InstalledPackage, OutdatedPackage, PackageKind},
+    pipeline::{DryRunBehavior, FailurePolicy, Step, StepContext, StepOutcome},
+    report::{
+        stderr_excerpt, CleanupSummary, PackageChange, PackageFailure, RestartStatus, RunReport,
+        ServiceRestart, StepStatus,
+    },
+    retry,
+    runner::{BrewRunner, CommandInput},
+    services::{self, Service},
+    snapshot,
+    utils::{self, say},
+};
+
+/// Returns every available step in default execution order.
+pub fn registry() -> Vec<Box<dyn Step>> {
+    vec![
+        Box::new(UpdateStep),
+        Box::new(UpgradeStep),
+        Box::new(CleanupStep),
+        Box::new(SummaryStep),
+    ]
+}
+
+/// Status of a step that completed, depending on dry-run mode.
+fn completed(ctx: &StepContext<'_>) -> StepStatus {
+    if ctx.args.dry_run {
+        StepStatus::DryRun
+    } else {
+        StepStatus::Ok
+    }
+}
+
+/// Updates Homebrew itself with `brew update`.
+pub struct UpdateStep;
+
+impl Step for UpdateStep {
+    fn name(&self) -> &'static str {
+        "update"
+    }
+
+    fn description(&self) -> &'static str {
+        "Update Homebrew and the package index"
+    }
+
+    fn run(&self, ctx: &mut StepContext<'_>) -> Result<StepOutcome, BrewupError> {
+        utils::show_info(
+            constants::EMOJI_DOWNLOAD,
+            "Updating Homebrew...",
+            colored::Color::Blue,
+        );
+        if ctx.args.dry_run {
+            say!("{}", "   Would run: brew update".dimmed());
+            return Ok(StepOutcome::new(StepStatus::DryRun, None));
+        }
+        let output = retry::run_brew_command(ctx, self.name(), &["update"])
+            .map_err(|e| e.into_error(BrewupError::UpdateFailed))?;
+        Ok(StepOutcome::new(StepStatus::Ok, Some(output)))
+    }
+}
+
+/// Upgrades outdated packages, holding back excluded and pinned ones.
+pub struct UpgradeStep;
+
+impl Step for UpgradeStep {
+    fn name(&self) -> &'static str {
+        "upgrade"
+    }
+
+    fn description(&self) -> &'static str {
+        "Upgrade outdated packages"
+    }
+
+    /// Isolated upgrades carry on past failures but still fail the run.
+    fn default_policy(&self, ctx: &StepContext<'_>) -> FailurePolicy {
+        if ctx.config.upgrade.isolate {
+            FailurePolicy::Continue
+        } else {
+            FailurePolicy::Abort
+        }
+    }
+
+    /// Queries the outdated packages first so the report knows what gets
+    /// upgraded, then upgrades formulae and casks in separate phases. Excluded
+    /// and pinned packages are held back, and casks needing a greedier mode
+    /// than configured only mentioned; excluded formulae are pinned while the
+    /// upgrades run, so brew does not upgrade them as dependencies, and checked
+    /// afterwards. The installed packages are recorded, and saved as a
+    /// snapshot, before the first upgrade; afterwards running services of
+    /// upgraded formulae are restarted and their caveats collected. In dry-run
+    /// mode the query is shown as a plan.
+    fn run(&self, ctx: &mut StepContext<'_>) -> Result<StepOutcome, BrewupError> {
+        utils::show_info(
+            constants::EMOJI_UPGRADE,
+            "Upgrading packages...",
+            colored::Color::Blue,
+        );
+        let config = ctx.config;
+        let auto_updates = config.upgrade.uses_greedy(GreedyMode::AutoUpdates);
+        // Without the query nothing is known about exclusions, pins and
+        // greedy casks, so no upgrade runs; the failure policy decides the rest
+        let outdated = packages::outdated_packages(ctx.runner, auto_updates).map_err(|e| {
+            e.into_error(|failure| BrewupError::UpgradeFailed {
+                packages: Vec::new(),
+                failure,
+            })
+        })?;
+        let (upgrades, held) =
+            packages::select_upgrades(outdated, &config.upgrade.exclude, |cask| {
+                config.upgrade.greedy_mode(cask)
+            });
+        // brew skips these casks by default, so they are not reported as held back
+        let (greedy_only, held): (Vec<_>, Vec<_>) = held.into_iter().partition(|h| h.greedy_only);
+        ctx.report.record_held(&held);
+        if ctx.args.dry_run {
+            show_upgrade_plan(&upgrades, &held);
+        } else {
+            for package in &held {
+                say!(
+                    "   {} {} ({})",
+                    "Holding back".yellow(),
+                    package.package.name.bold(),
+                    package.reason.dimmed()
+                );
+            }
+        }
+        show_greedy_only(ctx, &greedy_only);
+        if upgrades.is_empty() {
+            say!("{}", "   Nothing to upgrade".dimmed());
+            return Ok(StepOutcome::new(completed(ctx), None));
+        }
+        record_installed(ctx)?;
+        let pins = PinGuard::pin(ctx, &held)?;
+        let (formulae, casks): (Vec<_>, Vec<_>) = upgrades
+            .into_iter()
+            .partition(|p| p.kind == PackageKind::Formula);
+        let running = running_services(ctx, &formulae);
+        let result = if config.upgrade.isolate {
+            run_isolated_upgrades(ctx, &formulae, &casks)
+        } else {
+            run_upgrade_phases(ctx, &formulae, &casks)
+        };
+        drop(pins);
+        // Formulae upgraded before a failure still need their services restarted
+        if matches!(result, Err(BrewupError::Interrupted)) {
+            return result;
+        }
+        let restarts_failed = restart_services(ctx, &running)?;
+        collect_info_caveats(ctx);
+        let held_changed = check_held_back(ctx);
+        result.map(|mut outcome| {
+            if restarts_failed || held_changed {
+                outcome.status = StepStatus::Warning;
+            }
+            outcome
+        })
+    }
+}
+
+/// Mentions the outdated casks that only a greedy upgrade would upgrade.
+///
+/// One line lists them all; with `-v` each is shown with the mode it needs.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `casks` - The casks needing a greedier mode than configured
+fn show_greedy_only(ctx: &StepContext<'_>, casks: &[HeldPackage]) {
+    if casks.is_empty() {
+        return;
+    }
+    if ctx.args.verbosity.is_verbose() {
+        for cask in casks {
+            say!(
+                "   {} {} ({})",
+                "Not upgrading".dimmed(),
+                cask.package.name.bold(),
+                cask.reason.dimmed()
+            );
+        }
+        return;
+    }
+    let names: Vec<&str> = casks.iter().map(|c| c.package.name.as_str()).collect();
+    say!(
+        "{}",
+        format!("   Available with --greedy: {}", names.join(", ")).dimmed()
+    );
+}
+
+/// Formulae held back by an exclude pattern, pinned while upgrading.
+///
+/// `brew upgrade <names>` also upgrades the outdated dependencies of the
+/// named formulae, so an excluded dependency such as `openssl@3` would be
+/// upgraded anyway. Pinning makes brew leave it alone; the pins are removed
+/// again when the guard is dropped. A run that is killed outright leaves
+/// them pinned, which `brew unpin` undoes.
+struct PinGuard<'a> {
+    runner: &'a dyn BrewRunner,
+    names: Vec<String>,
+}
+
+impl<'a> PinGuard<'a> {
+    /// Pins the excluded formulae that are not pinned already.
+    ///
+    /// In dry-run mode it only says which formulae would be pinned.
+    ///
+    /// # Arguments
+    /// * `ctx` - The run context
+    /// * `held` - The packages held back by the upgrade step
+    ///
+    /// # Returns
+    /// The guard, `BrewupError::UpgradeFailed` if brew could not pin them,
+    /// so that nothing is upgraded without the protection.
+    fn pin(ctx: &StepContext<'a>, held: &[HeldPackage]) -> Result<Self, BrewupError> {
+        let names: Vec<String> = held
+            .iter()
+            .filter(|h| h.package.kind == PackageKind::Formula && !h.package.pinned)
+            .map(|h| h.package.name.clone())
+            .collect();
+        if names.is_empty() || ctx.args.dry_run {
+            if !names.is_empty() {
+                say!(
+                    "{}",
+                    format!("   Would pin {} during the upgrade", names.join(", ")).dimmed()
+                );
+            }
+            return Ok(Self {
+                runner: ctx.runner,
+                names: Vec::new(),
+            });
+        }
+        let mut command = vec!["pin"];
+        command.extend(names.iter().map(String::as_str));
+        utils::run_brew_command(ctx.runner, &command, ctx.args.verbosity).map_err(|e| {
+            e.into_error(|failure| BrewupError::UpgradeFailed {
+                packages: names.clone(),
+                failure,
+            })
+        })?;
+        say!(
+            "{}",
+            format!("   Pinned {} during the upgrade", names.join(", ")).dimmed()
+        );
+        Ok(Self {
+            runner: ctx.runner,
+            names,
+        })
+    }
+}
+
+impl Drop for PinGuard<'_> {
+    fn drop(&mut self) {
+        if self.names.is_empty() {
+            return;
+        }
+        let mut command = vec!["unpin"];
+        command.extend(self.names.iter().map(String::as_str));
+        let failure = match self.runner.capture(&command) {
+            Ok(output) if output.is_success() => return,
+            Ok(output) => output.stderr.trim().to_string(),
+            Err(e) => e.to_string(),
+        };
+        utils::show_warning(&format!(
+            "Could not unpin {} (run `brew unpin` yourself): {}",
+            self.names.join(", "),
+            failure
+        ));
+    }
+}
+
+/// Checks that no held back package changed version during the upgrades.
+///
+/// Compares the installed packages recorded before the first upgrade with
+/// a fresh listing. A package that was upgraded anyway is reported as a
+/// warning and recorded in its held back entry.
+///
+/// # Arguments
+/// * `ctx` - The run context
+///
+/// # Returns
+/// `true` if a held back package was upgraded anyway.
+fn check_held_back(ctx: &mut StepContext<'_>) -> bool {
+    let report = &mut *ctx.report;
+    let Some(before) = &report.installed_before else {
+        return false;
+    };
+    if report.held_back.is_empty() {
+        return false;
+    }
+    let after = match packages::installed_packages(ctx.runner) {
+        Ok(after) => after,
+        Err(e) => {
+            utils::show_warning(&format!(
+                "Could not check the versions of held back packages: {}",
+                e
+            ));
+            return false;
+        }
+    };
+    let version = |packages: &[InstalledPackage], change: &PackageChange| {
+        packages
+            .iter()
+            .find(|p| p.name == change.name && p.kind == change.kind)
+            .map(|p| p.version().to_string())
+    };
+    let mut changed = false;
+    for held in &mut report.held_back {
+        let now = version(&after, &held.change);
+        if now.is_none() || now == version(before, &held.change) {
+            continue;
+        }
+        utils::show_warning(&format!(
+            "{} was held back but brew upgraded it anyway to {}",
+            held.change.name,
+            now.as_deref().unwrap_or_default()
+        ));
+        held.upgraded_to = now;
+        changed = true;
+    }
+    changed
+}
+
+/// Upgrades formulae, then casks, with one `brew upgrade` each.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `formulae` - The formulae to upgrade
+/// * `casks` - The casks to upgrade
+///
+/// # Returns
+/// The outcome of the last upgrade, `Err` as soon as one fails
+fn run_upgrade_phases(
+    ctx: &mut StepContext<'_>,
+    formulae: &[OutdatedPackage],
+    casks: &[OutdatedPackage],
+) -> Result<StepOutcome, BrewupError> {
+    let by_name = !ctx.config.upgrade.exclude.is_empty();
+    let mut outcome = StepOutcome::new(completed(ctx), None);
+    for phase in [formulae, casks] {
+        if phase.is_empty() {
+            continue;
+        }
+        let command = upgrade_command(phase, by_name);
+        outcome = run_upgrade(ctx, &command, phase)?;
+    }
+    Ok(outcome)
+}
+
+/// Completes the caveats of upgraded packages with `brew info`.
+///
+/// Only packages whose upgrade output had no caveats are queried. A failed
+/// query only produces a warning.
+///
+/// # Arguments
+/// * `ctx` - The run context
+fn collect_info_caveats(ctx: &mut StepContext<'_>) {
+    let report = &*ctx.report;
+    let names: Vec<&str> = report
+        .upgraded
+        .iter()
+        .map(|c| c.name.as_str())
+        .filter(|name| !report.caveats.iter().any(|c| c.name == *name))
+        .collect();
+    if ctx.args.dry_run || names.is_empty() {
+        return;
+    }
+    match caveats::info_caveats(ctx.runner, &names) {
+        Ok(info) => caveats::merge(&mut ctx.report.caveats, info),
+        Err(e) => utils::show_warning(&format!("Could not look up caveats: {}", e)),
+    }
+}
+
+/// Records the caveats printed while upgrading `packages`.
+///
+/// # Arguments
+/// * `report` - The run report
+/// * `stdout` - The output of `brew upgrade`
+/// * `packages` - The packages the command upgraded
+fn record_caveats(report: &mut RunReport, stdout: &str, packages: &[OutdatedPackage]) {
+    let names: Vec<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+    caveats::merge(
+        &mut report.caveats,
+        caveats::parse_transcript(stdout, &names),
+    );
+}
+
+/// Lists the running services of formulae about to be upgraded.
+///
+/// A failed listing only produces a warning, since services are a side
+/// issue of the upgrade.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `formulae` - The formulae to upgrade
+///
+/// # Returns
+/// The running services belonging to `formulae`.
+fn running_services(ctx: &StepContext<'_>, formulae: &[OutdatedPackage]) -> Vec<Service> {
+    if formulae.is_empty() {
+        return Vec::new();
+    }
+    match services::running_services(ctx.runner) {
+        Ok(running) => running
+            .into_iter()
+            .filter(|service| formulae.iter().any(|f| f.name == service.name))
+            .collect(),
+        Err(e) => {
+            utils::show_warning(&format!("Could not list services: {}", e));
+            Vec::new()
+        }
+    }
+}
+
+/// Restarts the running services whose formula was upgraded.
+///
+/// Services are only reported when restarting is turned off
+/// (`--no-restart`) or when they run as root. Every service is recorded in
+/// the report.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `running` - Services that were running before the upgrade
+///
+/// # Returns
+/// `Ok(true)` if a restart failed, `Err` if brew was interrupted
+fn restart_services(ctx: &mut StepContext<'_>, running: &[Service]) -> Result<bool, BrewupError> {
+    let upgraded: Vec<&Service> = running
+        .iter()
+        .filter(|service| {
+            ctx.report
+                .upgraded
+                .iter()
+                .any(|c| c.kind == PackageKind::Formula && c.name == service.name)
+        })
+        .collect();
+    if upgraded.is_empty() {
+        return Ok(false);
+    }
+    let message = if ctx.config.upgrade.restart_services {
+        "Restarting services of upgraded formulae..."
+    } else {
+        "Services of upgraded formulae still run the old version:"
+    };
+    utils::show_info(constants::EMOJI_RESTART, message, colored::Color::Blue);
+    let mut failed = false;
+    for service in upgraded {
+        let (status, detail) = if !ctx.config.upgrade.restart_services {
+            (
+                RestartStatus::NeedsRestart,
+                Some(format!(
+                    "restarting is turned off; run `{}`",
+                    service.restart_command()
+                )),
+            )
+        } else if service.runs_as_root() {
+            (
+                RestartStatus::NeedsRestart,
+                Some(format!("runs as root; run `{}`", service.restart_command())),
+            )
+        } else if ctx.args.dry_run {
+            say!(
+                "{}",
+                format!("   Would run: {}", service.restart_command()).dimmed()
+            );
+            (RestartStatus::DryRun, None)
+        } else {
+            match services::restart(ctx.runner, service, ctx.args.verbosity) {
+                Ok(_) => (RestartStatus::Restarted, None),
+                Err(e) if e.was_interrupted() => return Err(BrewupError::Interrupted),
+                Err(e) => {
+                    utils::show_warning(&format!("Failed to restart {}: {}", service.name, e));
+                    failed = true;
+                    let error = e
+                        .output
+                        .as_ref()
+                        .and_then(|o| stderr_excerpt(&o.stderr))
+                        .unwrap_or(e.message);
+                    (RestartStatus::Failed, Some(error))
+                }
+            }
+        };
+        if let (RestartStatus::NeedsRestart, Some(detail)) = (status, &detail) {
+            say!(
+                "   {} {} ({})",
+                "Not restarting".yellow(),
+                service.name.bold(),
+                detail.dimmed()
+            );
+        }
+        ctx.report.services.push(ServiceRestart {
+            name: service.name.clone(),
+            status,
+            detail,
+        });
+    }
+    Ok(failed)
+}
+
+/// Records the installed packages before anything is upgraded, so that
+/// the summary can show what changed.
+///
+/// The listing is saved as a snapshot when snapshots are enabled. Without
+/// snapshots a failed listing only costs the changes table and is reported
+/// as a warning.
+///
+/// # Arguments
+/// * `ctx` - The run context
+///
+/// # Returns
+/// `Ok(())` unless the snapshot could not be taken.
+fn record_installed(ctx: &mut StepContext<'_>) -> Result<(), BrewupError> {
+    if ctx.args.dry_run || ctx.config.snapshot.enabled {
+        return take_snapshot(ctx);
+    }
+    match packages::installed_packages(ctx.runner) {
+        Ok(installed) => ctx.report.installed_before = Some(installed),
+        Err(e) => utils::show_warning(&format!(
+            "Could not list installed packages, version changes will not be shown: {}",
+            e
+        )),
+    }
+    Ok(())
+}
+
+/// Saves a snapshot of the installed packages before anything is upgraded.
+///
+/// Does nothing when snapshots are disabled; in dry-run mode it only says
+/// where the snapshot would be saved.
+///
+/// # Arguments
+/// * `ctx` - The run context
+///
+/// # Returns
+/// `Ok(())` once the snapshot is saved, `BrewupError::Storage` if it could
+/// not be taken, so that no upgrade runs without a way back.
+fn take_snapshot(ctx: &mut StepContext<'_>) -> Result<(), BrewupError> {
+    let config = &ctx.config.snapshot;
+    if !config.enabled {
+        return Ok(());
+    }
+    let dir = config.dir().ok_or_else(|| BrewupError::Storage {
+        path: "snapshots".into(),
+        message: "no home directory to keep snapshots in".to_string(),
+    })?;
+    if ctx.args.dry_run {
+        say!(
+            "{}",
+            format!("   Would save a snapshot to {}", dir.display()).dimmed()
+        );
+        return Ok(());
+    }
+    let taken = snapshot::capture(ctx.runner).map_err(|e| {
+        e.into_error(|failure| BrewupError::Storage {
+            path: dir.clone(),
+            message: format!("could not list installed packages: {}", failure),
+        })
+    })?;
+    ctx.report.installed_before = Some(taken.packages.clone());
+    let id = snapshot::save(&dir, taken, config.keep)?;
+    say!(
+        "   {} Saved snapshot {} (undo with `brewup rollback`)",
+        utils::symbol(constants::EMOJI_SNAPSHOT),
+        id.bold()
+    );
+    ctx.report.snapshot = Some(id);
+    Ok(())
+}
+
+/// Builds the `brew upgrade` command for packages of a single kind.
+///
+/// Packages are named when `by_name` is set or when a cask needs a greedy
+/// flag, so that the flag only applies to the casks that were selected.
+///
+/// # Arguments
+/// * `packages` - Packages of one kind, at least one
+/// * `by_name` - Whether to list the packages instead of upgrading all of the kind
+///
+/// # Returns
+/// The brew arguments, starting with `upgrade`.
+fn upgrade_command(packages: &[OutdatedPackage], by_name: bool) -> Vec<&str> {
+    let mut command = vec!["upgrade", packages[0].kind.flag()];
+    let greedy = packages
+        .iter()
+        .map(|p| p.requires_greedy)
+        .max()
+        .unwrap_or_default();
+    command.extend(greedy.flag());
+    if by_name || greedy != GreedyMode::Off {
+        command.extend(packages.iter().map(|p| p.name.as_str()));
+    }
+    command
+}
+
+/// Displays the planned version changes as a table.
+///
+/// # Arguments
+/// * `upgrades` - Packages that would be upgraded
+/// * `held` - Packages that would be held back
+fn show_upgrade_plan(upgrades: &[OutdatedPackage], held: &[HeldPackage]) {
+    let arrow = utils::symbol(constants::SYMBOL_ARROW);
+    if upgrades.is_empty() && held.is_empty() {
+        say!("{}", "   All packages are up to date".dimmed());
+        return;
+    }
+    let rows: Vec<(&OutdatedPackage, Option<&str>)> = upgrades
+        .iter()
+        .map(|p| (p, None))
+        .chain(held.iter().map(|h| (&h.package, Some(h.reason.as_str()))))
+        .collect();
+    let name_width = rows
+        .iter()
+        .map(|(p, _)| p.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(7);
+    let version_width = rows
+        .iter()
+        .map(|(p, _)| p.installed_version().len())
+        .max()
+        .unwrap_or(0)
+        .max(9);
+    say!(
+        "   {}",
+        format!(
+            "{:<name_width$}  {:<7}  {:<version_width$}    {}",
+            "Package", "Type", "Installed", "Available"
+        )
+        .bold()
+    );
+    for (package, reason) in rows {
+        let kind = match package.kind {
+            PackageKind::Formula => "formula",
+            PackageKind::Cask => "cask",
+        };
+        let line = format!(
+            "{:<name_width$}  {:<7}  {:<version_width$} {arrow}  {}",
+            package.name,
+            kind,
+            package.installed_version(),
+            package.current_version
+        );
+        match reason {
+            None => say!("   {}", line),
+            Some(reason) => say!(
+                "   {} {}",
+                line.dimmed(),
+                format!("(held: {})", reason).yellow()
+            ),
+        }
+    }
+    say!(
+        "{}",
+        "   Based on the current package index; `brew update` may find newer versions.".dimmed()
+    );
+}
+
+/// Runs (or previews in dry-run mode) a single `brew upgrade` command.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `command` - The brew arguments, starting with `upgrade`
+/// * `upgrades` - The packages the command is expected to upgrade
+///
+/// # Returns
+/// The step outcome on success, `Err` if the upgrade failed
+fn run_upgrade(
+    ctx: &mut StepContext<'_>,
+    command: &[&str],
+    upgrades: &[OutdatedPackage],
+) -> Result<StepOutcome, BrewupError> {
+    if ctx.args.dry_run {
+        say!(
+            "{}",
+            format!("   Would run: brew {}", command.join(" ")).dimmed()
+        );
+        ctx.report
+            .upgraded
+            .extend(upgrades.iter().map(PackageChange::from));
+        return Ok(StepOutcome::new(StepStatus::DryRun, None));
+    }
+    let output = retry::run_brew_command(ctx, UpgradeStep.name(), command).map_err(|e| {
+        e.into_error(|failure| BrewupError::UpgradeFailed {
+            packages: command[1..]
+                .iter()
+                .filter(|arg| !arg.starts_with("--"))
+                .map(|p| p.to_string())
+                .collect(),
+            failure,
+        })
+    })?;
+    ctx.report
+        .upgraded
+        .extend(upgrades.iter().map(PackageChange::from));
+    record_caveats(ctx.report, &output.stdout, upgrades);
+    Ok(StepOutcome::new(StepStatus::Ok, Some(output)))
+}
+
+/// Upgrades packages in batches, continuing past failed batches.
+///
+/// Formulae and casks are batched separately, formulae first. A failed
+/// batch of several packages is retried one package at a time so that each
+/// failure is attributed to a single package. Failures are recorded in the
+/// report and only turned into an error once every batch was attempted.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `formulae` - The formulae to upgrade
+/// * `casks` - The casks to upgrade
+///
+/// # Returns
+/// The step outcome if every package was upgraded, `Err` naming the failed
+/// packages otherwise
+fn run_isolated_upgrades(
+    ctx: &mut StepContext<'_>,
+    formulae: &[OutdatedPackage],
+    casks: &[OutdatedPackage],
+) -> Result<StepOutcome, BrewupError> {
+    let batch_size = ctx.config.upgrade.batch_size.max(1);
+    for batch in formulae.chunks(batch_size).chain(casks.chunks(batch_size)) {
+        upgrade_batch(ctx, batch)?;
+    }
+    let failed = &ctx.report.failed;
+    if failed.is_empty() {
+        return Ok(StepOutcome::new(completed(ctx), None));
+    }
+    Err(BrewupError::UpgradeFailed {
+        packages: failed.iter().map(|f| f.change.name.clone()).collect(),
+        failure: CommandFailure {
+            message: format!(
+                "{} of {} packages failed to upgrade",
+                failed.len(),
+                formulae.len() + casks.len()
+            ),
+            output: None,
+        },
+    })
+}
+
+/// Runs `brew upgrade` for one batch and records the outcome per package.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `batch` - The packages to upgrade together
+///
+/// # Returns
+/// `Ok(())` whether or not the upgrade succeeded, `Err` if brew was interrupted
+fn upgrade_batch(ctx: &mut StepContext<'_>, batch: &[OutdatedPackage]) -> Result<(), BrewupError> {
+    let command = upgrade_command(batch, true);
+    if ctx.args.dry_run {
+        say!(
+            "{}",
+            format!("   Would run: brew {}", command.join(" ")).dimmed()
+        );
+        ctx.report
+            .upgraded
+            .extend(batch.iter().map(PackageChange::from));
+        return Ok(());
+    }
+    match retry::run_brew_command(ctx, UpgradeStep.name(), &command) {
+        Ok(output) => {
+            ctx.report
+                .upgraded
+                .extend(batch.iter().map(PackageChange::from));
+            record_caveats(ctx.report, &output.stdout, batch);
+            Ok(())
+        }
+        Err(e) if e.was_interrupted() => Err(BrewupError::Interrupted),
+        Err(_) if batch.len() > 1 => {
+            utils::show_warning("Batch upgrade failed, retrying its packages one at a time");
+            for package in batch {
+                upgrade_batch(ctx, std::slice::from_ref(package))?;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            let package = &batch[0];
+            utils::show_warning(&format!("Failed to upgrade {}, continuing", package.name));
+            let diagnosis = e.output.as_ref().and_then(diagnosis::classify);
+            if let Some(diagnosis) = &diagnosis {
+                utils::show_diagnosis(diagnosis);
+            }
+            ctx.report.failed.push(PackageFailure {
+                change: PackageChange::from(package),
+                exit_code: e.output.as_ref().and_then(|o| o.code),
+                diagnosis,
+                error: e
+                    .output
+                    .as_ref()
+                    .and_then(|o| stderr_excerpt(&o.stderr))
+                    .unwrap_or(e.message),
+            });
+            Ok(())
+        }
+    }
+}
+
+/// Removes old versions and cached downloads with the configured cleanup command.
+pub struct CleanupStep;
+
+impl Step for CleanupStep {
+    fn name(&self) -> &'static str {
+        "cleanup"
+    }
+
+    fn description(&self) -> &'static str {
+        "Remove old versions and cached downloads"
+    }
+
+    fn precondition(&self, ctx: &StepContext<'_>) -> Result<(), String> {
+        if ctx.config.cleanup_args().is_empty() {
+            return Err("cleanup_args is empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// A failed cleanup leaves the packages themselves in good shape.
+    fn default_policy(&self, _ctx: &StepContext<'_>) -> FailurePolicy {
+        FailurePolicy::Warn
+    }
+
+    /// Runs `brew autoremove` and the cleanup command as configured. With
+    /// `keep_versions` above 1, formulae with several versions installed are
+    /// exempted from `brew cleanup` and brewup removes the versions beyond
+    /// the newest ones itself, with their downloads. The cache and Cellar
+    /// are measured before and after. In dry-run mode everything is only
+    /// previewed.
+    fn run(&self, ctx: &mut StepContext<'_>) -> Result<StepOutcome, BrewupError> {
+        utils::show_info(
+            constants::EMOJI_CLEANUP,
+            "Cleaning up cache and old versions...",
+            colored::Color::Blue,
+        );
+        let policy = &ctx.config.cleanup;
+        if !policy.exempt.is_empty() {
+            say!(
+                "   {} {}",
+                "Keeping old versions of".dimmed(),
+                policy.exempt.join(", ").dimmed()
+            );
+        }
+        if policy.keep_versions > 1 {
+            say!(
+                "{}",
+                format!(
+                    "   Keeping the newest {} versions of each formula",
+                    policy.keep_versions
+                )
+                .dimmed()
+            );
+        }
+        let dirs = if ctx.args.dry_run && policy.keep_versions == 1 {
+            None
+        } else {
+            match cleanup::brew_dirs(ctx.runner) {
+                Ok(dirs) => Some(dirs),
+                Err(e) => {
+                    if ctx.args.verbosity.is_verbose() {
+                        utils::show_warning(&format!("Could not locate the Homebrew cache: {}", e));
+                    }
+                    None
+                }
+            }
+        };
+        if ctx.args.dry_run {
+            let autoremoved = if policy.autoremove {
+                preview_autoremove(ctx.runner)
+            } else {
+                Vec::new()
+            };
+            let (kept, old) = find_old_versions(ctx, dirs.as_ref())?;
+            for version in &old {
+                say!(
+                    "   Would remove: {} ({} cached downloads)",
+                    version,
+                    version.downloads.len()
+                );
+            }
+            let exempt = no_cleanup_input(ctx, &kept);
+            let mut summary = preview_cleanup(ctx.config, ctx.runner, &exempt)
+                .as_ref()
+                .map(cleanup_summary);
+            if !autoremoved.is_empty() || !old.is_empty() {
+                let summary = summary.get_or_insert_with(Default::default);
+                summary.autoremoved = autoremoved;
+                summary.old_versions_removed = old.iter().map(ToString::to_string).collect();
+            }
+            ctx.report.cleanup = summary;
+            return Ok(StepOutcome::new(StepStatus::DryRun, None));
+        }
+        let before = dirs.as_ref().and_then(|dirs| disk_usage(ctx, dirs));
+        // Autoremove first, so that cleanup also drops the removed formulae's downloads
+        let mut autoremoved = Vec::new();
+        if policy.autoremove {
+            let output = retry::run_brew_command(ctx, self.name(), &["autoremove"])
+                .map_err(|e| e.into_error(BrewupError::CleanupFailed))?;
+            autoremoved = cleanup::parse_autoremove_output(&output.stdout);
+            if !autoremoved.is_empty() {
+                say!(
+                    "   Removed unneeded dependencies: {}",
+                    autoremoved.join(", ").bold()
+                );
+            }
+        }
+        let (kept, old) = find_old_versions(ctx, dirs.as_ref())?;
+        let removed = remove_old_versions(&old);
+        let exempt = no_cleanup_input(ctx, &kept);
+        let command = ctx.config.cleanup_args();
+        let args: Vec<&str> = command.iter().map(String::as_str).collect();
+        let output = retry::run_brew_command_with(ctx, self.name(), &args, &exempt)
+            .map_err(|e| e.into_error(BrewupError::CleanupFailed))?;
+        let mut summary = cleanup_summary(&cleanup::parse_cleanup_output(&output.stdout));
+        summary.autoremoved = autoremoved;
+        summary.old_versions_removed = removed;
+        let after = dirs.as_ref().and_then(|dirs| disk_usage(ctx, dirs));
+        if let (Some(before), Some(after), Some((cache, _))) = (before, after, &dirs) {
+            summary.measured_bytes_reclaimed = Some(before.total().saturating_sub(after.total()));
+            summary.cache_bytes = Some(after.cache_bytes);
+            summary.cellar_bytes = Some(after.cellar_bytes);
+            show_disk_usage(&before, &after, summary.bytes_reclaimed, cache);
+            summary.largest_cache_entries = after.largest;
+        }
+        ctx.report.cleanup = Some(summary);
+        Ok(StepOutcome::new(StepStatus::Ok, Some(output)))
+    }
+}
+
+/// Finds the installed versions beyond `[cleanup] keep_versions`.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `dirs` - The cache and Cellar directories, if they are known
+///
+/// # Returns
+/// The formulae with more than one version installed, which `brew cleanup`
+/// must leave alone, and the old versions to remove; nothing when only the
+/// current version is kept. `BrewupError::CleanupFailed` if the Cellar or
+/// the prefix cannot be read, so that neither brew nor brewup removes
+/// versions meant to be kept or in use.
+fn find_old_versions(
+    ctx: &StepContext<'_>,
+    dirs: Option<&(PathBuf, PathBuf)>,
+) -> Result<(Vec<String>, Vec<cleanup::OldVersion>), BrewupError> {
+    let keep = ctx.config.cleanup.keep_versions;
+    if keep == 1 {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let failure = |message: String| {
+        BrewupError::CleanupFailed(CommandFailure {
+            message: format!("cannot keep {} versions of each formula: {}", keep, message),
+            output: None,
+        })
+    };
+    let (cache, cellar) = dirs.ok_or_else(|| failure("the Cellar is unknown".to_string()))?;
+    let prefix = cleanup::brew_prefix(ctx.runner).map_err(|e| failure(e.message))?;
+    let kegs = cleanup::kegs(cellar).map_err(|e| failure(e.to_string()))?;
+    let kept = kegs
+        .iter()
+        .filter(|(_, versions)| versions.len() > 1)
+        .map(|(name, _)| name.clone())
+        .collect();
+    let dirs = cleanup::KegDirs {
+        prefix,
+        cellar: cellar.clone(),
+        cache: cache.clone(),
+    };
+    let old = cleanup::old_versions(&kegs, &dirs, keep, &ctx.config.cleanup.exempt);
+    Ok((kept, old))
+}
+
+/// Builds the input that keeps brew from removing the old versions of the
+/// exempt formulae and of `kept`.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `kept` - Formulae whose old versions brewup decides about itself
+fn no_cleanup_input(ctx: &StepContext<'_>, kept: &[String]) -> CommandInput {
+    let mut formulae = ctx.config.cleanup.exempt.clone();
+    formulae.extend(kept.iter().cloned());
+    let current = std::env::var(cleanup::NO_CLEANUP_VAR).ok();
+    cleanup::no_cleanup_input(&formulae, current.as_deref())
+}
+
+/// Removes old versions with their downloads.
+///
+/// A version that cannot be removed only produces a warning.
+///
+/// # Arguments
+/// * `old` - The old versions
+///
+/// # Returns
+/// The removed versions, e.g. `wget 1.24.3`.
+fn remove_old_versions(old: &[cleanup::OldVersion]) -> Vec<String> {
+    let mut removed = Vec::new();
+    for version in old {
+        match cleanup::remove_old_version(version) {
+            Ok(()) => removed.push(version.to_string()),
+            Err(e) => utils::show_warning(&format!("Could not remove {}: {}", version, e)),
+        }
+    }
+    if !removed.is_empty() {
+        say!("   Removed old versions: {}", removed.join(", ").bold());
+    }
+    removed
+}
+
+/// Converts parsed cleanup output into its report form.
+fn cleanup_summary(cleanup: &CleanupReport) -> CleanupSummary {
+    CleanupSummary {
+        entries_removed: cleanup.entries.len(),
+        bytes_reclaimed: cleanup.freed_bytes,
+        ..Default::default()
+    }
+}
+
+/// Measures the Homebrew cache and Cellar.
+///
+/// # Arguments
+/// * `ctx` - The run context
+/// * `dirs` - The cache and Cellar directories
+///
+/// # Returns
+/// The disk usage, or `None` (with a warning in verbose mode) if a
+/// directory could not be read.
+fn disk_usage(ctx: &StepContext<'_>, (cache, cellar): &(PathBuf, PathBuf)) -> Option<DiskUsage> {
+    match cleanup::measure(cache, cellar, cleanup::LARGEST_CACHE_ENTRIES) {
+        Ok(usage) => Some(usage),
+        Err(e) => {
+            if ctx.args.verbosity.is_verbose() {
+                utils::show_warning(&format!("Could not measure the Homebrew cache: {}", e));
+            }
+            None
+        }
+    }
+}
+
+/// Displays the space a cleanup reclaimed and the largest files left in
+/// the cache.
+///
+/// Brew's own figure is shown next to the measured one when they differ.
+///
+/// # Arguments
+/// * `before` - Disk usage before the cleanup
+/// * `after` - Disk usage after the cleanup
+/// * `reported` - The space brew reported as freed, if it did
+/// * `cache` - The cache directory, which entries are shown relative to
+fn show_disk_usage(before: &DiskUsage, after: &DiskUsage, reported: Option<u64>, cache: &Path) {
+    let arrow = utils::symbol(constants::SYMBOL_ARROW);
+    let measured = before.total().saturating_sub(after.total());
+    let reported = match reported {
+        Some(bytes) if bytes != measured => {
+            format!(", brew reported {}", cleanup::format_size(bytes))
+        }
+        _ => String::new(),
+    };
+    say!(
+        "   Reclaimed {}{reported} (cache {} {arrow} {}, Cellar {} {arrow} {})",
+        cleanup::format_size(measured).bold(),
+        cleanup::format_size(before.cache_bytes).dimmed(),
+        cleanup::format_size(after.cache_bytes),
+        cleanup::format_size(before.cellar_bytes).dimmed(),
+        cleanup::format_size(after.cellar_bytes)
+    );
+    if after.largest.is_empty() {
+        return;
+    }
+    say!("   {}", "Largest cache entries:".dimmed());
+    for entry in &after.largest {
+        let path = Path::new(&entry.path);
+        say!(
+            "   {:>8}  {}",
+            cleanup::format_size(entry.bytes),
+            path.strip_prefix(cache).unwrap_or(path).display()
+        );
+    }
+}
+
+/// Shows which dependencies `brew autoremove` would uninstall.
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+///
+/// # Returns
+/// The formulae that would be uninstalled, empty if none or unknown.
+fn preview_autoremove(runner: &dyn BrewRunner) -> Vec<String> {
+    say!("{}", "   Would run: brew autoremove".dimmed());
+    let output = match runner.capture(&["autoremove", "--dry-run"]) {
+        Ok(output) if output.is_success() => output,
+        Ok(output) => {
+            utils::show_warning(&format!(
+                "Could not preview autoremove: {}",
+                output.stderr.trim()
+            ));
+            return Vec::new();
+        }
+        Err(e) => {
+            utils::show_warning(&format!("Could not preview autoremove: {}", e));
+            return Vec::new();
+        }
+    };
+    let removed = cleanup::parse_autoremove_output(&output.stdout);
+    if removed.is_empty() {
+        say!("{}", "   No unneeded dependencies".dimmed());
+    } else {
+        say!("   {} {}", "Would autoremove:".dimmed(), removed.join(", "));
+    }
+    removed
+}
+
+/// Shows what the cleanup step would remove, using `brew cleanup --dry-run`.
+///
+/// # Arguments
+/// * `config` - Application configuration
+/// * `runner` - The backend used to invoke brew
+/// * `input` - The exemptions passed to brew
+///
+/// # Returns
+/// The parsed preview, or `None` if no preview was available.
+fn preview_cleanup(
+    config: &Config,
+    runner: &dyn BrewRunner,
+    input: &CommandInput,
+) -> Option<CleanupReport> {
+    let command = config.cleanup_args();
+    let mut command: Vec<&str> = command.iter().map(String::as_str).collect();
+    say!(
+        "{}",
+        format!("   Would run: brew {}", command.join(" ")).dimmed()
+    );
+    // Only `brew cleanup` itself knows how to preview its work
+    if command.first() != Some(&"cleanup") {
+        return None;
+    }
+    command.push("--dry-run");
+    let output = match runner.capture_with(&command, input) {
+        Ok(output) if output.is_success() => output,
+        Ok(output) => {
+            utils::show_warning(&format!(
+                "Could not preview cleanup: {}",
+                output.stderr.trim()
+            ));
+            return None;
+        }
+        Err(e) => {
+            utils::show_warning(&format!("Could not preview cleanup: {}", e));
+            return None;
+        }
+    };
+    let report = cleanup::parse_cleanup_output(&output.stdout);
+    if report.entries.is_empty() {
+        say!("{}", "   Nothing to clean up".dimmed());
+        return Some(report);
+    }
+    for entry in report.entries.iter().take(config.max_packages_display()) {
+        say!("   {} {}", "Would remove:".dimmed(), entry.dimmed());
+    }
+    if report.entries.len() > config.max_packages_display() {
+        say!(
+            "   {} (and {} more...)",
+            "...".dimmed(),
+            (report.entries.len() - config.max_packages_display())
+                .to_string()
+                .dimmed()
+        );
+    }
+    let freed = report
+        .freed_bytes
+        .map(cleanup::format_size)
+        .unwrap_or_else(|| "an unknown amount of".to_string());
+    let count = report.entries.len();
+    say!(
+        "   {} {} would be removed, freeing approximately {} of disk space",
+        count.to_string().bold(),
+        if count == 1 { "entry" } else { "entries" },
+        freed.bold()
+    );
+    Some(report)
+}
+
+/// Lists the installed formulae and casks along with version changes, upgrade results and
+/// held back packages.
+pub struct SummaryStep;
+
+impl Step for SummaryStep {
+    fn name(&self) -> &'static str {
+        "summary"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show installed formulae and casks, version changes and held back packages"
+    }
+
+    /// `brew list` changes nothing, so the summary also runs in dry-run mode.
+    fn dry_run_behavior(&self) -> DryRunBehavior {
+        DryRunBehavior::Run
+    }
+
+    fn run(&self, ctx: &mut StepContext<'_>) -> Result<StepOutcome, BrewupError> {
+        utils::show_info(
+            constants::EMOJI_SUMMARY,
+            "Getting package summary...",
+            colored::Color::Blue,
+        );
+        let config = ctx.config;
+        let mut outcome = StepOutcome::new(StepStatus::Ok, None);
+        let mut listings = Vec::new();
+        for kind in [PackageKind::Formula, PackageKind::Cask] {
+            match ctx.runner.capture(&["list", kind.flag(), "--versions"]) {
+                Ok(output) if output.is_success() => {
+                    let installed = packages::parse_installed(&output.stdout, kind);
+                    listings.push((kind, installed.len(), output.stdout.clone()));
+                    ctx.report.installed.extend(installed);
+                    if outcome.status == StepStatus::Ok {
+                        outcome.output = Some(output);
+                    }
+                }
+                Ok(output) => {
+                    utils::show_warning(&format!("Could not get the list of {}", kind.plural()));
+                    outcome = StepOutcome::new(StepStatus::Warning, Some(output));
+                }
+                Err(e) => {
+                    if ctx.args.verbosity.is_verbose() {
+                        utils::show_warning(&format!(
+                            "Error getting the list of {}: {}",
+                            kind.plural(),
+                            e
+                        ));
+                    }
+                    outcome = StepOutcome::new(StepStatus::Warning, None);
+                }
+            }
+        }
+        if let (Some(before), 2) = (&ctx.report.installed_before, listings.len()) {
+            ctx.report.changes = changes::diff(before, &ctx.report.installed);
+        }
+        // Always show package counts
+        if !listings.is_empty() {
+            let counts: Vec<String> = listings
+                .iter()
+                .map(|(kind, count, _)| {
+                    format!("{} {}", count.to_string().bold(), kind.noun(*count))
+                })
+                .collect();
+            say!(
+                "{} {} installed",
+                utils::symbol(constants::EMOJI_PACKAGE).green(),
+                counts.join(" and ")
+            );
+        }
+        // Package lists are only shown in verbose mode
+        let shown = if ctx.args.verbosity.is_verbose() {
+            listings.as_slice()
+        } else {
+            &[]
+        };
+        for (kind, count, stdout) in shown {
+            if *count == 0 {
+                continue;
+            }
+            say!("\n{}", format!("Installed {}:", kind.plural()).bold());
+            for line in stdout
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .take(config.max_packages_display())
+            {
+                say!("   {}", line.dimmed());
+            }
+            if *count > config.max_packages_display() {
+                say!(
+                    "   {} (and {} more...)",
+                    "...".dimmed(),
+                    (count - config.max_packages_display()).to_string().dimmed()
+                );
+            }
+        }
+        let report = &*ctx.report;
+        if config.upgrade.isolate && !(report.upgraded.is_empty() && report.failed.is_empty()) {
+            show_upgrade_results(report);
+        }
+        if !report.changes.is_empty() {
+            show_changes(&report.changes);
+        }
+        if !report.services.is_empty() {
+            show_services(report);
+        }
+        if !report.held_back.is_empty() {
+            say!("\n{}", "Held back:".bold());
+            for held in &report.held_back {
+                say!(
+                    "   {} {} {} {} {}",
+                    held.change.name,
+                    held.change.from.dimmed(),
+                    utils::symbol(constants::SYMBOL_ARROW),
+                    held.change.to.dimmed(),
+                    format!("({})", held.reason).yellow()
+                );
+                if let Some(version) = &held.upgraded_to {
+                    say!("{}", format!("     upgraded anyway to {}", version).red());
+                }
+            }
+        }
+        let retried: Vec<String> = report
+            .steps
+            .iter()
+            .filter(|s| s.retries > 0)
+            .map(|s| format!("{} x{}", s.name, s.retries))
+            .collect();
+        if !retried.is_empty() {
+            say!(
+                "\n{} Retried after transient failures: {}",
+                utils::symbol(constants::EMOJI_RETRY),
+                retried.join(", ")
+            );
+        }
+        Ok(outcome)
+    }
+}
+
+/// Displays the packages that changed during the run as a table, with
+/// major-version bumps highlighted.
+///
+/// # Arguments
+/// * `changes` - The version changes, at least one
+fn show_changes(changes: &[VersionChange]) {
+    let name_width = changes.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    let from_width = changes
+        .iter()
+        .map(|c| c.from.as_deref().unwrap_or_default().chars().count())
+        .max()
+        .unwrap_or(0);
+    let (upgraded, arrow) = (
+        utils::symbol(constants::SYMBOL_UPGRADED),
+        utils::symbol(constants::SYMBOL_ARROW),
+    );
+    // Keeps versions of new packages in line with the upgraded ones
+    let gap = " ".repeat(arrow.chars().count() + 2);
+    say!("\n{}", "Changes:".bold());
+    for change in changes {
+        let from = change.from.as_deref().unwrap_or_default();
+        let to = change.to.as_deref().unwrap_or_default();
+        match change.change {
+            ChangeKind::Upgraded if change.major => say!(
+                "   {} {:<name_width$}  {:>from_width$} {arrow} {}  {}",
+                upgraded.red(),
+                change.name.bold(),
+                from.dimmed(),
+                to.red().bold(),
+                "major".red()
+            ),
+            ChangeKind::Upgraded => say!(
+                "   {} {:<name_width$}  {:>from_width$} {arrow} {}",
+                upgraded.green(),
+                change.name,
+                from.dimmed(),
+                to
+            ),
+            ChangeKind::Installed => say!(
+                "   {} {:<name_width$}  {:>from_width$}{gap}{}  {}",
+                "+".cyan(),
+                change.name,
+                "",
+                to,
+                "installed".dimmed()
+            ),
+            ChangeKind::Removed => say!(
+                "   {} {:<name_width$}  {:>from_width$}  {}",
+                "-".yellow(),
+                change.name,
+                from.dimmed(),
+                "removed".dimmed()
+            ),
+        }
+    }
+}
+
+/// Displays what became of the running services of upgraded formulae.
+///
+/// # Arguments
+/// * `report` - The run report
+fn show_services(report: &RunReport) {
+    say!("\n{}", "Services:".bold());
+    for service in &report.services {
+        let detail = service.detail.as_deref().unwrap_or_default();
+        match service.status {
+            RestartStatus::Restarted => say!(
+                "   {} {} restarted",
+                utils::symbol(constants::SYMBOL_OK).green(),
+                service.name
+            ),
+            RestartStatus::DryRun => {
+                say!(
+                    "   {} {} would be restarted",
+                    utils::symbol(constants::SYMBOL_ARROW).cyan(),
+                    service.name
+                )
+            }
+            RestartStatus::NeedsRestart => say!(
+                "   {} {} still runs the old version: {}",
+                "!".yellow(),
+                service.name,
+                detail.yellow()
+            ),
+            RestartStatus::Failed => say!(
+                "   {} {} failed to restart: {}",
+                utils::symbol(constants::SYMBOL_FAILED).red(),
+                service.name,
+                detail.lines().last().unwrap_or_default().red()
+            ),
+        }
+    }
+}
+
+/// Displays the per-package results of isolated upgrades as a table.
+///
+/// # Arguments
+/// * `report` - The run report
+fn show_upgrade_results(report: &RunReport) {
+    let arrow = utils::symbol(constants::SYMBOL_ARROW);
+    let name_width = report
+        .upgraded
+        .iter()
+        .chain(report.failed.iter().map(|f| &f.change))
+        .map(|c| c.name.len())
+        .max()
+        .unwrap_or(0);
+    say!("\n{}", "Upgrade results:".bold());
+    for change in &report.upgraded {
+        say!(
+            "   {} {:<name_width$}  {} {arrow} {}",
+            utils::symbol(constants::SYMBOL_OK).green(),
+            change.name,
+            change.from.dimmed(),
+            change.to
+        );
+    }
+    for failure in &report.failed {
+        let reason = failure.error.lines().last().unwrap_or_default();
+        say!(
+            "   {} {:<name_width$}  {} {arrow} {}  {}",
+            utils::symbol(constants::SYMBOL_FAILED).red(),
+            failure.change.name,
+            failure.change.from.dimmed(),
+            failure.change.to.dimmed(),
+            reason.red()
+        );
+    }
+}
@@ -5,69 +5,282 @@
 
 use colored::*;
 use std::process;
-use std::process::{Command, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use crate::config::constants;
+use crate::cli::{ColorChoice, Verbosity};
+use crate::config::{constants, DisplayConfig};
+use crate::diagnosis::Diagnosis;
+use crate::error::{BrewupError, CommandFailure};
+use crate::runner::{BrewOutput, BrewRunner, CommandInput, OutputLine};
+
+/// Whether human-readable output on stdout is suppressed.
+static SILENT: AtomicBool = AtomicBool::new(false);
+
+/// Whether symbols are printed as emoji rather than ASCII.
+static EMOJI: AtomicBool = AtomicBool::new(true);
+
+/// Prints a line of human-readable output unless output is silenced.
+///
+/// Takes the same arguments as `println!`.
+macro_rules! say {
+    () => {
+        $crate::utils::say!("")
+    };
+    ($($arg:tt)*) => {
+        if !$crate::utils::is_silent() {
+            $crate::utils::print_line(format_args!($($arg)*));
+        }
+    };
+}
+pub(crate) use say;
+
+#[cfg(test)]
+thread_local! {
+    /// Output of the current test thread while [`capture_output`] runs.
+    static CAPTURED: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+    /// Emoji setting of the current test thread while [`with_emoji`] runs.
+    static THREAD_EMOJI: std::cell::Cell<Option<bool>> = const { std::cell::Cell::new(None) };
+}
+
+/// Prints one line of output for [`say!`].
+///
+/// # Arguments
+/// * `line` - The formatted line
+pub(crate) fn print_line(line: std::fmt::Arguments<'_>) {
+    #[cfg(test)]
+    if CAPTURED.with_borrow_mut(|captured| {
+        captured
+            .as_mut()
+            .map(|output| output.push_str(&format!("{}\n", line)))
+            .is_some()
+    }) {
+        return;
+    }
+    println!("{}", line);
+}
+
+/// Runs `f` and returns what it printed with [`say!`] instead of printing it.
+#[cfg(test)]
+pub(crate) fn capture_output(f: impl FnOnce()) -> String {
+    CAPTURED.set(Some(String::new()));
+    f();
+    CAPTURED.take().unwrap_or_default()
+}
+
+/// Runs `f` with emoji turned on or off for the current test thread only,
+/// so that tests running in parallel keep their own setting.
+#[cfg(test)]
+pub(crate) fn with_emoji<T>(emoji: bool, f: impl FnOnce() -> T) -> T {
+    let previous = THREAD_EMOJI.replace(Some(emoji));
+    let result = f();
+    THREAD_EMOJI.set(previous);
+    result
+}
+
+/// Suppresses or restores human-readable output on stdout.
+///
+/// Used by `--output json` so that only the report is printed. Errors are
+/// written to stderr and are never suppressed.
+///
+/// # Arguments
+/// * `silent` - Whether to suppress output
+pub fn set_silent(silent: bool) {
+    SILENT.store(silent, Ordering::Relaxed);
+}
+
+/// Returns `true` if human-readable output is suppressed.
+pub fn is_silent() -> bool {
+    SILENT.load(Ordering::Relaxed)
+}
+
+/// How the output of a run looks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputStyle {
+    /// Whether output is colored
+    pub color: bool,
+    /// Whether symbols are emoji rather than ASCII
+    pub emoji: bool,
+}
+
+impl OutputStyle {
+    /// Decides colors and symbols from the configuration, the environment
+    /// and the kind of output.
+    ///
+    /// With `color = "auto"`, a non-empty `NO_COLOR` or `CLICOLOR=0` turns
+    /// colors off and `CLICOLOR_FORCE` (other than `0`) turns them on;
+    /// otherwise only terminals get colors. Emoji are used on terminals
+    /// unless `emoji` is set.
+    ///
+    /// # Arguments
+    /// * `display` - The `[display]` settings, including `--color` and `--no-emoji`
+    /// * `var` - Looks up an environment variable
+    /// * `terminal` - Whether stdout is a terminal
+    pub fn detect(
+        display: &DisplayConfig,
+        var: impl Fn(&str) -> Option<String>,
+        terminal: bool,
+    ) -> Self {
+        let set = |name: &str| var(name).filter(|value| !value.is_empty());
+        let color = match display.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto if set("NO_COLOR").is_some() => false,
+            ColorChoice::Auto if set("CLICOLOR_FORCE").is_some_and(|v| v != "0") => true,
+            ColorChoice::Auto if set("CLICOLOR").is_some_and(|v| v == "0") => false,
+            ColorChoice::Auto => terminal,
+        };
+        Self {
+            color,
+            emoji: display.emoji.unwrap_or(terminal),
+        }
+    }
+
+    /// Makes all following output use this style.
+    pub fn apply(self) {
+        colored::control::set_override(self.color);
+        EMOJI.store(self.emoji, Ordering::Relaxed);
+    }
+}
+
+/// Returns the symbol to print for one of the `EMOJI_*` or `SYMBOL_*` constants.
+///
+/// # Arguments
+/// * `emoji` - The emoji or symbol constant
+///
+/// # Returns
+/// The emoji or symbol itself, or its ASCII replacement when emoji are
+/// turned off.
+pub fn symbol(emoji: &'static str) -> &'static str {
+    let enabled = EMOJI.load(Ordering::Relaxed);
+    #[cfg(test)]
+    let enabled = THREAD_EMOJI.get().unwrap_or(enabled);
+    if enabled {
+        return emoji;
+    }
+    constants::ASCII_SYMBOLS
+        .iter()
+        .find(|(e, _)| *e == emoji)
+        .map_or(emoji, |(_, ascii)| ascii)
+}
 
 /// Checks if Homebrew is available on the system.
 ///
 /// This function executes `brew --version` to verify that Homebrew
 /// is installed and accessible in the system PATH.
 ///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+///
 /// # Returns
 /// `true` if Homebrew is available, `false` otherwise.
-pub fn is_brew_available() -> bool {
-    Command::new("brew")
-        .arg("--version")
-        .output()
-        .map(|output| output.status.success())
-        .unwrap_or(false)
+pub fn is_brew_available(runner: &dyn BrewRunner) -> bool {
+    runner.run(&["--version"]).unwrap_or(false)
 }
 
 /// Executes a Homebrew command with the specified arguments.
 ///
+/// Output is rendered line by line as brew produces it, while the full
+/// transcript is kept for error reporting. At normal verbosity only brew's
+/// `==>` headings and stderr are shown; `-v` shows the command and all of
+/// its output, and `-vv` also runs brew with `--verbose`.
+///
 /// # Arguments
+/// * `runner` - The backend used to invoke brew
 /// * `args` - Slice of string arguments to pass to the brew command
-/// * `verbose` - Whether to show verbose output
+/// * `verbosity` - How much output to show
 ///
 /// # Returns
-/// `Ok(())` if the command succeeds, `Err(String)` with error message on failure
-pub fn run_brew_command(args: &[&str], verbose: bool) -> Result<(), String> {
-    if verbose {
-        println!(
+/// The command output on success, `Err(CommandFailure)` on failure
+pub fn run_brew_command(
+    runner: &dyn BrewRunner,
+    args: &[&str],
+    verbosity: Verbosity,
+) -> Result<BrewOutput, CommandFailure> {
+    run_brew_command_with(runner, args, &CommandInput::default(), verbosity)
+}
+
+/// Executes a Homebrew command with extra input, like [`run_brew_command`].
+///
+/// # Arguments
+/// * `runner` - The backend used to invoke brew
+/// * `args` - Slice of string arguments to pass to the brew command
+/// * `input` - Environment variables and standard input for this command
+/// * `verbosity` - How much output to show
+///
+/// # Returns
+/// The command output on success, `Err(CommandFailure)` on failure
+pub fn run_brew_command_with(
+    runner: &dyn BrewRunner,
+    args: &[&str],
+    input: &CommandInput,
+    verbosity: Verbosity,
+) -> Result<BrewOutput, CommandFailure> {
+    let mut args = args.to_vec();
+    if verbosity == Verbosity::VeryVerbose {
+        args.push("--verbose");
+    }
+    if verbosity.is_verbose() {
+        say!(
             "{} brew {}",
             "   Running:".dimmed(),
             args.join(" ").dimmed()
         );
     }
 
-    let mut command = Command::new("brew");
-    command.args(args);
+    let started = Instant::now();
+    let mut on_line = |line: OutputLine<'_>| {
+        let heading = matches!(line, OutputLine::Stdout(text) if text.starts_with("==> "));
+        if verbosity.is_verbose() || heading || matches!(line, OutputLine::Stderr(_)) {
+            show_output_line(line);
+        }
+    };
+    let output = runner
+        .stream_with(&args, input, &mut on_line)
+        .map_err(|e| CommandFailure {
+            message: format!("Failed to execute command: {}", e),
+            output: None,
+        })?;
 
-    let output = command
-        .output()
-        .map_err(|e| format!("Failed to execute command: {}", e))?;
-
-    if output.status.success() {
-        handle_command_success(&output, verbose);
-        Ok(())
+    if output.is_success() {
+        handle_command_success(started, verbosity);
+        Ok(output)
     } else {
-        handle_command_failure(&output)
+        handle_command_failure(output)
+    }
+}
+
+/// Renders a single line of streamed brew output.
+///
+/// Lines are indented under the step header; stderr lines are highlighted.
+///
+/// # Arguments
+/// * `line` - The output line to render
+pub fn show_output_line(line: OutputLine<'_>) {
+    match line {
+        OutputLine::Stdout(text) => {
+            say!("   {} {}", symbol(constants::SYMBOL_GUTTER).dimmed(), text)
+        }
+        OutputLine::Stderr(text) => say!(
+            "   {} {}",
+            symbol(constants::SYMBOL_GUTTER).yellow(),
+            text.yellow()
+        ),
     }
 }
 
 /// Handles successful command execution output.
 ///
 /// # Arguments
-/// * `output` - The command output
-/// * `verbose` - Whether to show verbose output
-fn handle_command_success(output: &Output, _verbose: bool) {
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    if !stdout.trim().is_empty() {
-        println!("{}", stdout);
+/// * `started` - When the command started
+/// * `verbosity` - How much output to show
+fn handle_command_success(started: Instant, verbosity: Verbosity) {
+    let done = format!("   {} Done", symbol(constants::SYMBOL_OK));
+    if verbosity.is_verbose() {
+        let elapsed = format_duration(started.elapsed().as_millis() as u64);
+        say!("{} {}", done.green(), format!("({})", elapsed).dimmed());
     } else {
-        // Show a simple progress indicator for silent operations
-        println!("{}", "   ✓ Done".green());
+        say!("{}", done.green());
     }
 }
 
@@ -77,28 +290,188 @@ fn handle_command_success(output: &Output, _verbose: bool) {
 /// * `output` - The command output containing error information
 ///
 /// # Returns
-/// `Err(String)` with the formatted error message
-fn handle_command_failure(output: &Output) -> Result<(), String> {
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    Err(stderr.to_string())
+/// `Err(CommandFailure)` with the formatted error message
+fn handle_command_failure(output: BrewOutput) -> Result<BrewOutput, CommandFailure> {
+    let message = if output.stderr.trim().is_empty() {
+        output.stdout.trim()
+    } else {
+        output.stderr.trim()
+    };
+
+    let message = match output.code {
+        Some(code) if message.is_empty() => format!("brew exited with status {}", code),
+        None if message.is_empty() => "brew was terminated by a signal".to_string(),
+        _ => message.to_string(),
+    };
+    Err(CommandFailure {
+        message,
+        output: Some(output),
+    })
+}
+
+/// Matches `text` against a shell-style glob `pattern`.
+///
+/// `*` matches any run of characters and `?` matches a single character.
+///
+/// # Arguments
+/// * `pattern` - The glob pattern
+/// * `text` - The text to match
+///
+/// # Returns
+/// `true` if the whole text matches the pattern.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` absorb one more character and retry
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Formats a point in time as an RFC 3339 UTC timestamp.
+///
+/// # Arguments
+/// * `time` - The time to format
+///
+/// # Returns
+/// A timestamp such as `2024-05-01T09:30:00Z`.
+pub fn format_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+/// Formats a duration in milliseconds for display, e.g. `1m 12s`.
+///
+/// # Arguments
+/// * `ms` - The duration in milliseconds
+///
+/// # Returns
+/// The human-readable duration.
+pub fn format_duration(ms: u64) -> String {
+    let secs = ms / 1_000;
+    match secs {
+        0 => format!("{}ms", ms),
+        1..=59 => format!("{}s", secs),
+        60..=3_599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3_600, secs % 3_600 / 60),
+    }
+}
+
+/// Returns the name of this machine, or `unknown` if it cannot be determined.
+pub fn hostname() -> String {
+    process::Command::new("hostname")
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
 }
 
 /// Displays a formatted error message and exits the application.
 ///
+/// Only `main` should call this; the workflow returns a `BrewupError` instead.
+///
 /// # Arguments
 /// * `message` - The error message to display
-/// * `exit_code` - The exit code to use (default: 1)
+/// * `exit_code` - The exit code to use (see `BrewupError::exit_code`)
 pub fn exit_with_error(message: &str, exit_code: i32) -> ! {
-    eprintln!("{} {}", constants::EMOJI_ERROR.red(), message.red());
+    eprintln!("{} {}", symbol(constants::EMOJI_ERROR).red(), message.red());
     process::exit(exit_code);
 }
 
+/// Prints a fatal error with its diagnosis to stderr and exits.
+///
+/// Only `main` should call this; the workflow returns a `BrewupError` instead.
+///
+/// # Arguments
+/// * `error` - The error that ended the run
+pub fn exit_with_failure(error: &BrewupError) -> ! {
+    eprintln!(
+        "{} {}",
+        symbol(constants::EMOJI_ERROR).red(),
+        error.to_string().red()
+    );
+    if let Some(diagnosis) = error.diagnosis() {
+        eprintln!(
+            "{} {}",
+            symbol(constants::EMOJI_DIAGNOSIS).cyan(),
+            diagnosis.summary.cyan()
+        );
+        eprintln!("   {} {}", "Fix:".bold(), diagnosis.remediation);
+    }
+    process::exit(error.exit_code());
+}
+
+/// Displays the diagnosis of a failure that did not end the run.
+///
+/// # Arguments
+/// * `diagnosis` - The diagnosis to display
+pub fn show_diagnosis(diagnosis: &Diagnosis) {
+    say!(
+        "{} {}",
+        symbol(constants::EMOJI_DIAGNOSIS).cyan(),
+        diagnosis.summary.cyan()
+    );
+    say!("   {} {}", "Fix:".bold(), diagnosis.remediation);
+}
+
 /// Displays a formatted warning message.
 ///
 /// # Arguments
 /// * `message` - The warning message to display
 pub fn show_warning(message: &str) {
-    println!("{} {}", constants::EMOJI_WARNING.yellow(), message.yellow());
+    say!(
+        "{} {}",
+        symbol(constants::EMOJI_WARNING).yellow(),
+        message.yellow()
+    );
 }
 
 /// Displays a formatted success message.
@@ -106,7 +479,11 @@ pub fn show_warning(message: &str) {
 /// # Arguments
 /// * `message` - The success message to display
 pub fn show_success(message: &str) {
-    println!("{} {}", constants::EMOJI_SUCCESS.green(), message.green());
+    say!(
+        "{} {}",
+        symbol(constants::EMOJI_SUCCESS).green(),
+        message.green()
+    );
 }
 
 /// Displays a formatted info message.
@@ -115,10 +492,142 @@ pub fn show_success(message: &str) {
 /// * `emoji` - The emoji to use for the message
 /// * `message` - The info message to display
 /// * `color` - The color to use for the message
-pub fn show_info(emoji: &str, message: &str, color: colored::Color) {
-    println!(
+pub fn show_info(emoji: &'static str, message: &str, color: colored::Color) {
+    say!(
         "{} {}",
-        emoji.color(color).bold(),
+        symbol(emoji).color(color).bold(),
         message.color(color).bold()
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::ScriptedRunner;
+
+    /// Tests RFC 3339 timestamp formatting.
+    #[test]
+    fn test_format_timestamp() {
+        use std::time::Duration;
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(1_709_251_199)),
+            "2024-02-29T23:59:59Z"
+        );
+    }
+
+    /// Tests duration formatting across units.
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(850), "850ms");
+        assert_eq!(format_duration(12_400), "12s");
+        assert_eq!(format_duration(72_000), "1m 12s");
+        assert_eq!(format_duration(3_720_000), "1h 2m");
+    }
+
+    /// Tests glob matching with wildcards.
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("node", "node"));
+        assert!(!glob_match("node", "node@20"));
+        assert!(glob_match("postgresql@*", "postgresql@14"));
+        assert!(glob_match("python@3.1?", "python@3.12"));
+        assert!(glob_match("*ssl*", "openssl@3"));
+        assert!(!glob_match("lib*z", "libzip"));
+        assert!(glob_match("*", ""));
+    }
+
+    /// Tests that failures report stderr, falling back to stdout and the exit code.
+    #[test]
+    fn test_failure_message_fallbacks() {
+        let runner = ScriptedRunner::new()
+            .respond(&["update"], BrewOutput::failure(1, "Error: no network\n"))
+            .respond(
+                &["upgrade"],
+                BrewOutput {
+                    code: Some(1),
+                    stdout: "Error: wget: checksum mismatch\n".to_string(),
+                    stderr: String::new(),
+                },
+            )
+            .respond(&["cleanup"], BrewOutput::failure(2, ""));
+
+        let failure = run_brew_command(&runner, &["update"], Verbosity::Normal).unwrap_err();
+        assert_eq!(failure.message, "Error: no network");
+        assert_eq!(failure.output.unwrap().code, Some(1));
+        assert_eq!(
+            run_brew_command(&runner, &["upgrade"], Verbosity::Normal)
+                .unwrap_err()
+                .message,
+            "Error: wget: checksum mismatch"
+        );
+        assert_eq!(
+            run_brew_command(&runner, &["cleanup"], Verbosity::Normal)
+                .unwrap_err()
+                .message,
+            "brew exited with status 2"
+        );
+        assert!(run_brew_command(&runner, &["doctor"], Verbosity::Normal)
+            .unwrap_err()
+            .output
+            .is_none());
+    }
+
+    /// Tests that `-vv` runs brew with `--verbose`.
+    #[test]
+    fn test_very_verbose_makes_brew_verbose() {
+        let runner = ScriptedRunner::new()
+            .respond(&["update"], BrewOutput::success(""))
+            .respond(&["update", "--verbose"], BrewOutput::success(""));
+        run_brew_command(&runner, &["update"], Verbosity::Verbose).unwrap();
+        run_brew_command(&runner, &["update"], Verbosity::VeryVerbose).unwrap();
+        assert_eq!(runner.calls(), vec!["update", "update --verbose"]);
+    }
+
+    /// Tests how colors and emoji follow the configuration, the environment
+    /// and the terminal.
+    #[test]
+    fn test_output_style() {
+        let style = |color, emoji, vars: &[(&str, &str)], terminal| {
+            let display = DisplayConfig { color, emoji };
+            let var = |name: &str| {
+                vars.iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| v.to_string())
+            };
+            let style = OutputStyle::detect(&display, var, terminal);
+            (style.color, style.emoji)
+        };
+        assert_eq!(style(ColorChoice::Auto, None, &[], true), (true, true));
+        assert_eq!(style(ColorChoice::Auto, None, &[], false), (false, false));
+        assert_eq!(
+            style(ColorChoice::Auto, None, &[("NO_COLOR", "1")], true),
+            (false, true)
+        );
+        assert_eq!(
+            style(ColorChoice::Auto, None, &[("NO_COLOR", "")], true),
+            (true, true)
+        );
+        assert_eq!(
+            style(ColorChoice::Auto, None, &[("CLICOLOR", "0")], true),
+            (false, true)
+        );
+        assert_eq!(
+            style(ColorChoice::Auto, None, &[("CLICOLOR_FORCE", "1")], false),
+            (true, false)
+        );
+        assert_eq!(
+            style(
+                ColorChoice::Always,
+                Some(false),
+                &[("NO_COLOR", "1")],
+                false
+            ),
+            (true, false)
+        );
+        assert_eq!(
+            style(ColorChoice::Never, Some(true), &[], true),
+            (false, true)
+        );
+    }
+}